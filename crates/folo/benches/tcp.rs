@@ -0,0 +1,179 @@
+//! End to end benchmarks of the TCP server: accepting connections via `TcpServerBuilder` and
+//! round-tripping small payloads over connections opened via `connect_loopback()`.
+//!
+//! * `accept_round_trip` opens many connections concurrently, each round-tripping one payload.
+//!   Criterion reports the throughput in connections per second.
+//! * `accept_round_trip_rss_query` is the same but against a server that queries the RSS processor
+//!   affinity of every connection, showing the cost of `TcpServerBuilder::query_rss_affinity()`.
+//! * `round_trip` round-trips one payload over an established connection. Criterion reports the
+//!   typical latency, after which the latency percentiles of a longer run are printed.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use folo::{
+    criterion::ComparativeAdapter,
+    io::{OperationResultExt, PinnedBuffer},
+    net::{
+        testing::{connect_loopback, echo, echo_server},
+        TcpConnection, TcpServerBuilder,
+    },
+};
+use futures::future::join_all;
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
+
+criterion_group!(benches, tcp);
+criterion_main!(benches);
+
+// How many connections `accept_round_trip` opens concurrently in each iteration.
+const CONCURRENT_CONNECTIONS: usize = 64;
+
+const PAYLOAD: &[u8] = b"0123456789abcdef";
+
+// How many round trips we measure to calculate the latency percentiles.
+const PERCENTILE_ROUND_TRIPS: usize = 10_000;
+
+// The payloads all execute on the same Folo worker, so this is where they keep their state.
+thread_local! {
+    static SERVER_PORT: Cell<u16> = const { Cell::new(0) };
+    static RSS_QUERY_SERVER_PORT: Cell<u16> = const { Cell::new(0) };
+    static CONNECTION: RefCell<Option<TcpConnection>> = const { RefCell::new(None) };
+}
+
+fn tcp(c: &mut Criterion) {
+    // We only benchmark Folo but the adapter requires a competitor.
+    let adapter = ComparativeAdapter::new(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    });
+
+    // The server and the connection used for round trips are shared by all iterations. The server
+    // keeps running after its handle is dropped, until the runtime stops.
+    adapter
+        .begin_folo(Box::new(|| {
+            Box::pin(async {
+                let server = echo_server().await.unwrap();
+                SERVER_PORT.set(server.local_port());
+
+                let rss_query_server = TcpServerBuilder::new()
+                    .ephemeral_port()
+                    .query_rss_affinity(true)
+                    .on_accept(echo)
+                    .build()
+                    .await
+                    .unwrap();
+                RSS_QUERY_SERVER_PORT.set(rss_query_server.local_port());
+
+                let connection = connect_loopback(server.local_port()).await.unwrap();
+                CONNECTION.set(Some(connection));
+            })
+        }))
+        .run();
+
+    let mut group = c.benchmark_group("tcp");
+
+    group.throughput(Throughput::Elements(CONCURRENT_CONNECTIONS as u64));
+    for (name, server_port) in [
+        ("accept_round_trip", &SERVER_PORT),
+        ("accept_round_trip_rss_query", &RSS_QUERY_SERVER_PORT),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    adapter.begin_folo(Box::new(move || {
+                        Box::pin(async move {
+                            let port = server_port.get();
+
+                            join_all((0..CONCURRENT_CONNECTIONS).map(|_| async move {
+                                let mut connection = connect_loopback(port).await.unwrap();
+                                round_trip(&mut connection).await;
+                                connection.shutdown().await.unwrap();
+                            }))
+                            .await;
+                        })
+                    }))
+                },
+                |prepared| prepared.run(),
+                BatchSize::PerIteration,
+            );
+        });
+    }
+
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("round_trip", |b| {
+        b.iter_batched(
+            || {
+                adapter.begin_folo(Box::new(|| {
+                    Box::pin(async {
+                        let mut connection = CONNECTION.take().unwrap();
+                        round_trip(&mut connection).await;
+                        CONNECTION.set(Some(connection));
+                    })
+                }))
+            },
+            |prepared| prepared.run(),
+            BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+
+    // Criterion reports averages, which hide the tail latency, so we measure that separately.
+    adapter
+        .begin_folo(Box::new(|| {
+            Box::pin(async {
+                let mut connection = CONNECTION.take().unwrap();
+                let mut latencies = Vec::with_capacity(PERCENTILE_ROUND_TRIPS);
+
+                for _ in 0..PERCENTILE_ROUND_TRIPS {
+                    let started = Instant::now();
+                    round_trip(&mut connection).await;
+                    latencies.push(started.elapsed());
+                }
+
+                connection.shutdown().await.unwrap();
+
+                print_percentiles(&mut latencies);
+            })
+        }))
+        .run();
+}
+
+async fn round_trip(connection: &mut TcpConnection) {
+    let mut buffer = PinnedBuffer::from_pool();
+    buffer
+        .as_mut_slice_with_len(PAYLOAD.len())
+        .copy_from_slice(PAYLOAD);
+    connection.send(buffer).await.into_inner().unwrap();
+
+    // The echo may arrive in multiple parts.
+    let mut received = 0;
+
+    while received < PAYLOAD.len() {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+        assert!(!buffer.is_empty(), "server closed the connection");
+
+        received += buffer.len();
+    }
+}
+
+fn print_percentiles(latencies: &mut [Duration]) {
+    latencies.sort_unstable();
+
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+    println!(
+        "tcp/round_trip latency over {} round trips: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        latencies.len(),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        latencies[latencies.len() - 1]
+    );
+}
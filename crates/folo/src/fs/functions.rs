@@ -121,6 +121,7 @@ async fn read_buffer_from_file(
 
     let mut operation = current_async_agent::with_io(|io| io.new_operation(buffer));
     operation.set_offset(offset);
+    operation.set_primitive(*file);
 
     // SAFETY: For safe usage of the I/O driver API, we are required to pass the `overlapped`
     // argument to a native I/O call under all circumstances, to trigger an I/O completion. We do.
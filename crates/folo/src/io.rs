@@ -4,8 +4,12 @@ mod driver;
 mod error;
 mod operation;
 mod operation_result;
+mod post_wakeup;
 mod primitive;
+mod read_buffer;
+mod wait_for_object;
 mod waker;
+mod wakeup_signal;
 
 pub use buffer::*;
 pub(crate) use completion_port::*;
@@ -14,5 +18,9 @@ pub use error::*;
 #[allow(unused_imports)] // Just WIP, shut up compiler.
 pub(crate) use operation::*;
 pub use operation_result::*;
+pub use post_wakeup::*;
 pub(crate) use primitive::*;
+pub use read_buffer::*;
+pub use wait_for_object::*;
 pub(crate) use waker::*;
+pub use wakeup_signal::*;
@@ -1,6 +1,6 @@
 use crate::{
     metrics::{Event, EventBuilder},
-    util::PinnedSlabChain,
+    util::{OptInCounter, PinnedSlabChain},
 };
 use core::slice;
 use negative_impl::negative_impl;
@@ -13,6 +13,22 @@ use std::{
     ptr,
 };
 
+// Buffer memory accounting is opt-in via `RuntimeBuilder::max_buffer_memory()`. Each thread has
+// its own pool but the accounting covers all of them.
+static POOLED_BYTES_IN_USE: OptInCounter = OptInCounter::new();
+
+/// Starts counting the bytes of pooled buffers in use across all threads of the process. Buffers
+/// taken from the pools before this are not counted, not even when they are dropped.
+pub(crate) fn enable_buffer_accounting() {
+    POOLED_BYTES_IN_USE.enable();
+}
+
+/// The number of bytes of pooled buffers in use across all threads of the process, if buffer
+/// accounting has been enabled.
+pub(crate) fn pooled_buffer_bytes() -> Option<usize> {
+    POOLED_BYTES_IN_USE.get()
+}
+
 /// A buffer of bytes for reading from or writing to as part of low level I/O operations. This is
 /// typically not visible to user code, rather it is used as the primitive inside the Folo I/O API.
 ///
@@ -30,6 +46,12 @@ use std::{
 ///
 /// This is a single threaded type - buffers cannot move between threads, all I/O stays within the
 /// same thread from start to finish.
+///
+/// A buffer returned by a completed I/O operation can be given directly to the next operation - it
+/// does not need to go back to the pool first. In a loop that receives and processes data, call
+/// `reset()` after processing to make the whole buffer available again, instead of taking a new
+/// buffer from the pool for every iteration. The backing storage stays the same (and stays at the
+/// same address) for as long as the buffer exists.
 #[derive(Debug)]
 pub struct PinnedBuffer {
     mode: Mode,
@@ -57,12 +79,21 @@ enum Mode {
         inner: Pin<&'static mut [u8]>,
 
         index_in_pool: usize,
+
+        // Whether the buffer is included in `POOLED_BYTES_IN_USE`, so we know whether to remove
+        // it from there.
+        counted: bool,
     },
     BoxedSlice {
         // We allow the caller to retrieve the inner value from the buffer via
         // `.into_inner_boxed_slice()` if they wish to reuse the storage later.
         inner: Pin<Box<[u8]>>,
     },
+    Static {
+        // Same as with boxed slices, the caller can get this back via
+        // `.into_inner_static_slice()` once they are done with the buffer.
+        inner: Pin<&'static mut [u8]>,
+    },
     Ptr {
         inner: *mut u8,
         capacity: usize,
@@ -77,6 +108,7 @@ impl fmt::Debug for Mode {
                 .field("index_in_pool", index_in_pool)
                 .finish(),
             Self::BoxedSlice { .. } => f.debug_struct("BoxedSlice").finish(),
+            Self::Static { .. } => f.debug_struct("Static").finish(),
             Self::Ptr { inner, capacity } => f
                 .debug_struct("Ptr")
                 .field("inner", &format_args!("{:p}", inner))
@@ -88,6 +120,11 @@ impl fmt::Debug for Mode {
 
 impl PinnedBuffer {
     /// Obtains a new buffer from the current thread's buffer pool.
+    ///
+    /// This never waits, even if the runtime has a buffer memory budget (see
+    /// `RuntimeBuilder::max_buffer_memory()`) and the budget has been reached. Backpressure is
+    /// instead applied by TCP servers, which stop accepting new connections until buffers are
+    /// released, so the work in progress can complete.
     pub fn from_pool() -> Self {
         POOL.with(|pool| {
             let mut pool = pool.borrow_mut();
@@ -108,6 +145,8 @@ impl PinnedBuffer {
 
             POOL_ALLOCATED.with(Event::observe_unit);
 
+            let counted = POOLED_BYTES_IN_USE.add(POOL_BUFFER_CAPACITY_BYTES);
+
             // SAFETY: The chain guarantees pinning, we just re-wrap Pin around the inner bytes.
             // We only ever hand out references derived from UnsafeCell, which are always valid
             // to hand out as long as we do not create multiple `&mut` references (which we do not
@@ -125,6 +164,7 @@ impl PinnedBuffer {
                 mode: Mode::Pooled {
                     inner,
                     index_in_pool: index,
+                    counted,
                 },
                 len,
                 start: 0,
@@ -148,16 +188,45 @@ impl PinnedBuffer {
         }
     }
 
+    /// Creates a new buffer from a slice of bytes that lives for the entire lifetime of the process
+    /// (e.g. a leaked allocation or a region of a long-lived ring buffer). Once the buffer has been
+    /// used up, the caller may get the inner slice back via `.into_inner_static_slice()`.
+    ///
+    /// This is a safe alternative to `from_ptr()` for caller-managed memory - the storage can never
+    /// be freed, so the operating system can never write into freed memory, and the exclusive
+    /// reference guarantees that nothing else accesses the storage while I/O is in progress.
+    pub fn from_static(slice: &'static mut [u8]) -> Self {
+        CALLER_BUFFERS_REFERENCED.with(Event::observe_unit);
+
+        let len = slice.len();
+
+        PinnedBuffer {
+            mode: Mode::Static {
+                inner: Pin::new(slice),
+            },
+            len,
+            start: 0,
+        }
+    }
+
     /// Creates a new buffer from a pinned pointer with a specified capacity.
     ///
+    /// This allows I/O to be performed directly into memory managed by the caller, without copying
+    /// via a pooled buffer. The buffer never frees the memory - when the buffer is dropped (e.g.
+    /// after being returned from a completed operation), the caller regains control of the memory.
+    ///
     /// # Safety
     ///
     /// The caller is responsible for ensuring that the provided pointer remains valid for the
     /// entire lifetime of the PinnedBuffer (including any I/O operations started that reference
     /// the PinnedBuffer, including after the operation is canceled, up to the moment the completion
-    /// or cancellation notification is received from the operating system).
+    /// or cancellation notification is received from the operating system). Dropping the future of
+    /// an operation does not end the operation - only receiving the buffer back does.
     ///
     /// The caller is responsible for ensuring that the pointer is actually to pinned memory.
+    ///
+    /// The caller is responsible for ensuring that nothing else reads or writes the memory while
+    /// the PinnedBuffer is alive, as the operating system may write to it at any time.
     pub unsafe fn from_ptr(ptr: *mut u8, capacity: usize) -> Self {
         CALLER_POINTERS_REFERENCED.with(Event::observe_unit);
 
@@ -171,14 +240,19 @@ impl PinnedBuffer {
         }
     }
 
+    /// The size of the backing storage of the buffer, regardless of the active region.
     pub fn capacity(&self) -> usize {
         match &self.mode {
             Mode::Pooled { inner, .. } => inner.len(),
             Mode::BoxedSlice { inner } => inner.len(),
+            Mode::Static { inner } => inner.len(),
             Mode::Ptr { capacity, .. } => *capacity,
         }
     }
 
+    /// The length of the active region of the buffer. When a buffer is returned from a completed
+    /// I/O operation, this is the number of bytes transferred by the operation (e.g. the number of
+    /// bytes received by a read).
     pub fn len(&self) -> usize {
         self.len
     }
@@ -228,11 +302,20 @@ impl PinnedBuffer {
         self
     }
 
+    /// Makes the entire buffer the active region again, as it was when the buffer was created, so
+    /// the buffer can be reused for the next I/O operation. Like `use_all()` but in place. The
+    /// contents of the buffer are not cleared and the backing storage is not reallocated or moved.
+    pub fn reset(&mut self) {
+        self.start = 0;
+        self.len = self.capacity();
+    }
+
     /// Obtains a mutable view over the contents of the buffer.
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
         match &mut self.mode {
             Mode::Pooled { inner, .. } => &mut inner[self.start..(self.start + self.len)],
             Mode::BoxedSlice { inner } => &mut inner[self.start..(self.start + self.len)],
+            Mode::Static { inner } => &mut inner[self.start..(self.start + self.len)],
             Mode::Ptr { inner, .. } => unsafe {
                 slice::from_raw_parts_mut(inner.add(self.start), self.len)
             },
@@ -252,6 +335,7 @@ impl PinnedBuffer {
         match &self.mode {
             Mode::Pooled { inner, .. } => &inner[self.start..(self.start + self.len)],
             Mode::BoxedSlice { inner } => &inner[self.start..(self.start + self.len)],
+            Mode::Static { inner } => &inner[self.start..(self.start + self.len)],
             Mode::Ptr { inner, .. } => unsafe {
                 slice::from_raw_parts(inner.add(self.start), self.len)
             },
@@ -280,17 +364,48 @@ impl PinnedBuffer {
         mem::forget(self);
 
         match mode {
-            Mode::Pooled { .. } | Mode::Ptr { .. } => {
+            Mode::Pooled { .. } | Mode::Ptr { .. } | Mode::Static { .. } => {
                 unreachable!("we already asserted that this is a boxed slice")
             }
             Mode::BoxedSlice { inner } => Pin::into_inner(inner),
         }
     }
+
+    /// Consumes the buffer and returns the inner static slice that was used to create the object.
+    /// Note that the inner slice will be returned in its full extent, ignoring active region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer was not created from a caller-provided static slice.
+    pub fn into_inner_static_slice(self) -> &'static mut [u8] {
+        assert!(matches!(self.mode, Mode::Static { .. }));
+
+        // We are destroying the buffer without going through the usual drop logic.
+        // SAFETY: We are forgetting self, so nobody should mind that we stole its contents.
+        let mode = unsafe { ptr::read(&self.mode) };
+        mem::forget(self);
+
+        match mode {
+            Mode::Pooled { .. } | Mode::Ptr { .. } | Mode::BoxedSlice { .. } => {
+                unreachable!("we already asserted that this is a static slice")
+            }
+            Mode::Static { inner } => Pin::into_inner(inner),
+        }
+    }
 }
 
 impl Drop for PinnedBuffer {
     fn drop(&mut self) {
-        if let Mode::Pooled { index_in_pool, .. } = self.mode {
+        if let Mode::Pooled {
+            index_in_pool,
+            counted,
+            ..
+        } = self.mode
+        {
+            if counted {
+                POOLED_BYTES_IN_USE.sub(POOL_BUFFER_CAPACITY_BYTES);
+            }
+
             POOL.with(|pool| {
                 let mut pool = pool.borrow_mut();
                 pool.remove(index_in_pool);
@@ -305,7 +420,7 @@ impl !Send for PinnedBuffer {}
 #[negative_impl]
 impl !Sync for PinnedBuffer {}
 
-const POOL_BUFFER_CAPACITY_BYTES: usize = 64 * 1024;
+pub(crate) const POOL_BUFFER_CAPACITY_BYTES: usize = 64 * 1024;
 
 thread_local! {
     static POOL: RefCell<PinnedSlabChain<UnsafeCell<[u8; POOL_BUFFER_CAPACITY_BYTES]>>> = RefCell::new(PinnedSlabChain::new());
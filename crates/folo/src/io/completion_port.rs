@@ -4,13 +4,20 @@ use crate::{
     util::{OwnedHandle, ThreadSafe},
 };
 use negative_impl::negative_impl;
-use std::sync::Arc;
-use windows::Win32::{
-    Foundation::{HANDLE, INVALID_HANDLE_VALUE},
-    Storage::FileSystem::SetFileCompletionNotificationModes,
-    System::{
-        WindowsProgramming::{FILE_SKIP_COMPLETION_PORT_ON_SUCCESS, FILE_SKIP_SET_EVENT_ON_HANDLE},
-        IO::CreateIoCompletionPort,
+use std::{mem, num::NonZeroU32, sync::Arc};
+use windows::{
+    Wdk::Storage::FileSystem::{
+        FileReplaceCompletionInformation, NtSetInformationFile, FILE_COMPLETION_INFORMATION,
+    },
+    Win32::{
+        Foundation::{HANDLE, INVALID_HANDLE_VALUE},
+        Storage::FileSystem::SetFileCompletionNotificationModes,
+        System::{
+            WindowsProgramming::{
+                FILE_SKIP_COMPLETION_PORT_ON_SUCCESS, FILE_SKIP_SET_EVENT_ON_HANDLE,
+            },
+            IO::{CreateIoCompletionPort, IO_STATUS_BLOCK},
+        },
     },
 };
 
@@ -20,12 +27,21 @@ use windows::Win32::{
 // threads.
 pub(crate) type CompletionPortHandle = Arc<ThreadSafe<OwnedHandle<HANDLE>>>;
 
+/// The completion key used for I/O primitives bound without an explicit key. Notifications with
+/// this key are completions of operations started via `Driver::new_operation()`.
+pub(crate) const DEFAULT_COMPLETION_KEY: usize = 0;
+
+/// The concurrency value (1) of the completion ports owned by async workers. In the thread-per-core
+/// model, each completion port is read from by exactly one thread - the worker that owns it.
+pub(crate) const THREAD_PER_CORE_CONCURRENCY: NonZeroU32 = NonZeroU32::MIN;
+
 /// The I/O completion port is used to notify the I/O driver that an I/O operation has completed.
 /// It must be associated with each file/socket/handle that is capable of asynchronous I/O. We do
 /// not expose this in the public API, just use it internally to implement I/O primitives.
 ///
 /// Each async worker thread has a single I/O completion port used for all I/O operations. This type
-/// is single-threaded to prevent accidental sharing between threads.
+/// is single-threaded to prevent accidental sharing between threads. A port meant to be shared by
+/// a group of threads must be created with a matching concurrency value via `with_concurrency()`.
 #[derive(Debug)]
 pub(crate) struct CompletionPort {
     // This is a shared handle, which means it is plausible that even after the CompletionPort
@@ -37,7 +53,20 @@ pub(crate) struct CompletionPort {
 }
 
 impl CompletionPort {
+    /// Creates a completion port for the thread-per-core model, read from only by the current
+    /// thread. See `THREAD_PER_CORE_CONCURRENCY`.
     pub(crate) fn new() -> Self {
+        Self::with_concurrency(THREAD_PER_CORE_CONCURRENCY)
+    }
+
+    /// Creates a completion port that allows up to `concurrency` of the threads reading from it to
+    /// run at the same time. The operating system releases completion notifications to waiting
+    /// threads only while fewer than this many of them are running, so a value above 1 is only
+    /// meaningful if the port is shared by a group of threads (e.g. to oversubscribe a processor
+    /// or to share a port between workers instead of using one per core).
+    ///
+    /// The concurrency value is fixed for the life of the port.
+    pub(crate) fn with_concurrency(concurrency: NonZeroU32) -> Self {
         // SAFETY: We wrap it in OwnedHandle, ensuring it is released when dropped. I/O completion
         // ports are safe to close from any thread, as required by the OwnedHandle API contract.
         let handle = unsafe {
@@ -45,7 +74,7 @@ impl CompletionPort {
                 INVALID_HANDLE_VALUE,
                 HANDLE::default(),
                 0, // Ignored as we are not binding a handle to the port.
-                1, // The port is only to be read from by one thread (the current thread).
+                concurrency.get(),
             ).expect("creating an I/O completion port should never fail unless the OS is critically out of resources"))
         };
 
@@ -57,14 +86,31 @@ impl CompletionPort {
 
     /// Binds an I/O primitive to the completion port when provided a handle to the I/O primitive.
     /// This causes notifications from that I/O primitive to arrive at the completion port.
+    ///
+    /// Notifications are tagged with the default completion key, which routes them to the
+    /// operation store of the I/O driver.
     pub(crate) fn bind(&self, handle: &(impl Into<IoPrimitive> + Copy)) -> io::Result<()> {
+        self.bind_with_key(handle, DEFAULT_COMPLETION_KEY)
+    }
+
+    /// Binds an I/O primitive to the completion port, tagging all notifications from that I/O
+    /// primitive with the given completion key. The I/O driver uses the key to decide which
+    /// subsystem receives the notification.
+    pub(crate) fn bind_with_key(
+        &self,
+        handle: &(impl Into<IoPrimitive> + Copy),
+        completion_key: usize,
+    ) -> io::Result<()> {
         let handle = HANDLE::from((*handle).into());
 
         // SAFETY: Our own handle cannot be invalid because we are keeping it alive via Arc.
         // We have to assume the user provided a valid handle (but if not, it will just be an
         // error result). We ignore the return value because it is our own handle on success.
+        //
+        // The concurrency value is ignored when binding to an existing port - it was fixed when
+        // the port was created.
         unsafe {
-            CreateIoCompletionPort(handle, ***self.handle, 0, 1)?;
+            CreateIoCompletionPort(handle, ***self.handle, completion_key, 0)?;
         }
 
         // Why FILE_SKIP_SET_EVENT_ON_HANDLE: https://devblogs.microsoft.com/oldnewthing/20200221-00/?p=103466/
@@ -89,6 +135,37 @@ impl CompletionPort {
         Ok(())
     }
 
+    /// Removes the association between an I/O primitive and whatever completion port it is bound
+    /// to, allowing it to be bound to a different completion port (possibly in a different runtime).
+    ///
+    /// The caller must ensure that there are no pending I/O operations on the primitive, as their
+    /// completion notifications would be lost.
+    pub(crate) fn unbind(handle: &(impl Into<IoPrimitive> + Copy)) -> io::Result<()> {
+        let handle = HANDLE::from((*handle).into());
+
+        // A null port means "remove the association" (supported since Windows 8.1).
+        let completion_info = FILE_COMPLETION_INFORMATION::default();
+        let mut status_block = IO_STATUS_BLOCK::default();
+
+        // SAFETY: The pointer and length describe a valid FILE_COMPLETION_INFORMATION, which is
+        // what this information class expects. We have to assume the caller provided a valid
+        // I/O primitive handle (but if not, it will just be an error result).
+        unsafe {
+            NtSetInformationFile(
+                handle,
+                &mut status_block,
+                &completion_info as *const _ as *const _,
+                mem::size_of::<FILE_COMPLETION_INFORMATION>() as u32,
+                FileReplaceCompletionInformation,
+            )
+            .ok()?;
+        }
+
+        PRIMITIVES_UNBOUND.with(Event::observe_unit);
+
+        Ok(())
+    }
+
     /// Obtains a thread-safe handle to the completion port. The primary use case is to give this
     /// to an IoWaker so that it can be used to wake up the thread that owns this completion port.
     pub(crate) fn handle(&self) -> CompletionPortHandle {
@@ -112,3 +189,77 @@ thread_local! {
         .build()
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{hint, ptr, sync::mpsc, thread};
+    use windows::Win32::System::IO::{
+        GetQueuedCompletionStatus, PostQueuedCompletionStatus, OVERLAPPED,
+    };
+
+    #[test]
+    fn concurrency_limits_threads_released_by_port() {
+        assert!(!second_thread_is_released(CompletionPort::new()));
+        assert!(second_thread_is_released(CompletionPort::with_concurrency(
+            NonZeroU32::new(2).unwrap()
+        )));
+    }
+
+    /// Whether a second thread can take a notification from the port while the thread that took
+    /// the first one is still running.
+    fn second_thread_is_released(port: CompletionPort) -> bool {
+        let handle = port.handle();
+
+        for _ in 0..2 {
+            // SAFETY: The port is kept alive by the handle and nobody uses the OVERLAPPED pointer.
+            unsafe {
+                PostQueuedCompletionStatus(***handle, 0, 0, None).unwrap();
+            }
+        }
+
+        let (taken_tx, taken_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+
+        let first = thread::spawn({
+            let handle = Arc::clone(&handle);
+
+            move || {
+                assert!(take_notification(***handle, 1000));
+                taken_tx.send(()).unwrap();
+
+                // A thread blocked on anything does not count against the concurrency value, so
+                // we keep running until the test is done.
+                while done_rx.try_recv().is_err() {
+                    hint::spin_loop();
+                }
+            }
+        });
+
+        taken_rx.recv().unwrap();
+        let released = take_notification(***handle, 500);
+
+        done_tx.send(()).unwrap();
+        first.join().unwrap();
+
+        released
+    }
+
+    fn take_notification(port: HANDLE, timeout_millis: u32) -> bool {
+        let mut bytes_transferred = 0;
+        let mut completion_key = 0;
+        let mut overlapped: *mut OVERLAPPED = ptr::null_mut();
+
+        // SAFETY: The caller keeps the port alive and the out pointers are all valid.
+        unsafe {
+            GetQueuedCompletionStatus(
+                port,
+                &mut bytes_transferred,
+                &mut completion_key,
+                &mut overlapped,
+                timeout_millis,
+            )
+        }
+        .is_ok()
+    }
+}
@@ -1,13 +1,30 @@
 use crate::constants::GENERAL_MILLISECONDS_BUCKETS;
-use crate::io::operation::{Operation, OperationStore};
-use crate::io::{self, CompletionPort, IoPrimitive, IoWaker, PinnedBuffer, WAKE_UP_COMPLETION_KEY};
+use crate::io::operation::{CompletionCounters, Operation, OperationStore};
+use crate::io::{
+    self, CompletionPort, CompletionPortHandle, IoPrimitive, IoWaker, PinnedBuffer,
+    DEFAULT_COMPLETION_KEY, THREAD_PER_CORE_CONCURRENCY, WAKE_UP_COMPLETION_KEY,
+};
 use crate::metrics::{Event, EventBuilder, Magnitude};
+use crate::trace::{event, Level};
+use std::collections::HashMap;
+use std::fmt;
 use std::mem::{self, MaybeUninit};
+use std::num::NonZeroU32;
+use std::sync::Arc;
 use windows::Win32::{
     Foundation::WAIT_TIMEOUT,
     System::IO::{GetQueuedCompletionStatusEx, OVERLAPPED_ENTRY},
 };
 use windows_result::HRESULT;
+#[cfg(test)]
+use {
+    crate::io::OperationResultFuture,
+    std::ptr,
+    windows::Win32::{
+        Foundation::ERROR_IO_PENDING,
+        System::IO::{PostQueuedCompletionStatus, OVERLAPPED},
+    },
+};
 
 /// Max number of I/O operations to dequeue in one go. Presumably getting more data from the OS with
 /// a single call is desirable but the exact impact of different values on performance is not known.
@@ -24,7 +41,6 @@ pub const IO_DEQUEUE_BATCH_SIZE: usize = 1024;
 /// The driver must not be dropped while any I/O operation is in progress. To shut down safely, the
 /// I/O driver must be polled until it signals that all I/O operations have completed (`is_inert()`
 /// returns true).
-#[derive(Debug)]
 pub(crate) struct Driver {
     completion_port: CompletionPort,
 
@@ -35,25 +51,58 @@ pub(crate) struct Driver {
     //
     // This does not store the read/write buffers, only the operation metadata.
     operation_store: OperationStore,
+
+    // Receivers of completion notifications tagged with a nonzero completion key, for subsystems
+    // that bind their own I/O primitives or post their own completion packets. Notifications with
+    // the default key go to the operation store instead.
+    completion_handlers: HashMap<usize, CompletionHandler>,
 }
 
+/// Receives the completion notifications tagged with a specific completion key. The OVERLAPPED
+/// pointer in the entry is whatever the poster of the notification put there (possibly null).
+pub(crate) type CompletionHandler = Box<dyn FnMut(&OVERLAPPED_ENTRY)>;
+
 impl Driver {
     /// # Safety
     ///
     /// See safety requirements on the type.
     pub(crate) unsafe fn new() -> Self {
+        Self::with_completion_port_concurrency(THREAD_PER_CORE_CONCURRENCY)
+    }
+
+    /// Creates a driver whose completion port has the given concurrency value. See
+    /// `CompletionPort::with_concurrency()`.
+    ///
+    /// # Safety
+    ///
+    /// See safety requirements on the type.
+    pub(crate) unsafe fn with_completion_port_concurrency(concurrency: NonZeroU32) -> Self {
         Self {
-            completion_port: CompletionPort::new(),
+            completion_port: CompletionPort::with_concurrency(concurrency),
             operation_store: OperationStore::new(),
+            completion_handlers: HashMap::new(),
         }
     }
 
+    /// Reports the completions of the I/O operations of this driver to the given counters, which
+    /// are typically shared by all the drivers of a runtime.
+    pub(crate) fn set_completion_counters(&mut self, counters: Arc<CompletionCounters>) {
+        self.operation_store.set_completion_counters(counters);
+    }
+
     /// Whether the driver has entered a state where it is safe to drop it. This requires that all
     /// ongoing I/O operations be completed and the completion notification received.
     pub fn is_inert(&self) -> bool {
         self.operation_store.is_empty()
     }
 
+    /// Requests cancellation of all I/O operations in flight whose I/O primitive is known, returning
+    /// how many cancellations were requested. The canceled operations still need to be completed
+    /// via `process_completions()` before the driver becomes inert.
+    pub(crate) fn cancel_all_operations(&self) -> usize {
+        self.operation_store.cancel_all()
+    }
+
     /// Binds an I/O primitive to the completion port of this driver, provided a handle to the I/O
     /// primitive in question (file handle, socket, ...). This must be called once for every I/O
     /// primitive used with this I/O driver.
@@ -64,6 +113,47 @@ impl Driver {
         self.completion_port.bind(handle)
     }
 
+    /// Binds an I/O primitive to the completion port of this driver, routing its completion
+    /// notifications to the handler registered for `completion_key` instead of the operation store.
+    pub(crate) fn bind_io_primitive_with_key(
+        &self,
+        handle: &(impl Into<IoPrimitive> + Copy),
+        completion_key: usize,
+    ) -> io::Result<()> {
+        self.completion_port.bind_with_key(handle, completion_key)
+    }
+
+    /// Registers a handler for completion notifications tagged with the given completion key.
+    ///
+    /// The default key and the wakeup key are reserved by the driver and cannot be registered, nor
+    /// can a key that already has a handler.
+    pub(crate) fn register_completion_handler(
+        &mut self,
+        completion_key: usize,
+        handler: CompletionHandler,
+    ) -> io::Result<()> {
+        if completion_key == DEFAULT_COMPLETION_KEY || completion_key == WAKE_UP_COMPLETION_KEY {
+            return Err(io::Error::LogicError(format!(
+                "completion key {completion_key:#x} is reserved by the I/O driver"
+            )));
+        }
+
+        if self.completion_handlers.contains_key(&completion_key) {
+            return Err(io::Error::LogicError(format!(
+                "completion key {completion_key:#x} already has a handler"
+            )));
+        }
+
+        self.completion_handlers.insert(completion_key, handler);
+        Ok(())
+    }
+
+    /// Removes the handler for the given completion key. Notifications that arrive for the key
+    /// afterwards are discarded.
+    pub(crate) fn unregister_completion_handler(&mut self, completion_key: usize) {
+        self.completion_handlers.remove(&completion_key);
+    }
+
     /// Starts preparing for a new I/O operation on some primitive bound to this driver. The caller
     /// must provide the buffer to pick up the data from or to deliver the data to.
     ///
@@ -89,6 +179,12 @@ impl Driver {
         IoWaker::new(self.completion_port.handle())
     }
 
+    /// Obtains a handle to the completion port of this driver, for posting completion packets to it
+    /// from other threads.
+    pub(crate) fn completion_port_handle(&self) -> CompletionPortHandle {
+        self.completion_port.handle()
+    }
+
     /// Process any I/O completion notifications and return their results to the callers. If there
     /// is no queued I/O, we wait up to `max_wait_time_ms` milliseconds for new I/O activity, after
     /// which we simply return.
@@ -149,12 +245,93 @@ impl Driver {
                     continue;
                 }
 
-                self.operation_store.complete_operation(overlapped_entry);
+                if overlapped_entry.lpCompletionKey == DEFAULT_COMPLETION_KEY {
+                    self.operation_store.complete_operation(overlapped_entry);
+                    continue;
+                }
+
+                match self
+                    .completion_handlers
+                    .get_mut(&overlapped_entry.lpCompletionKey)
+                {
+                    Some(handler) => handler(&overlapped_entry),
+                    None => {
+                        event!(
+                            Level::WARN,
+                            message = "discarding completion with unknown key",
+                            key = overlapped_entry.lpCompletionKey
+                        );
+
+                        UNROUTED_COMPLETIONS.with(Event::observe_unit);
+                    }
+                }
             }
         }
     }
 }
 
+#[cfg(test)]
+impl Driver {
+    /// Starts an operation that has no native I/O behind it, for tests that drive completions
+    /// themselves via `post_synthetic_completion()`. Returns the pending result of the operation
+    /// and the OVERLAPPED pointer that identifies it.
+    pub(crate) fn begin_synthetic_operation(
+        &mut self,
+        buffer: PinnedBuffer,
+    ) -> (OperationResultFuture, *mut OVERLAPPED) {
+        let mut overlapped_ptr: *mut OVERLAPPED = ptr::null_mut();
+
+        // SAFETY: We do not start any native operation, we just report that completion is
+        // pending. The test is responsible for posting a completion for the operation.
+        let future = unsafe {
+            self.new_operation(buffer).begin(|_, overlapped, _| {
+                overlapped_ptr = overlapped;
+                Err(io::Error::Windows(ERROR_IO_PENDING.into()))
+            })
+        };
+
+        (future, overlapped_ptr)
+    }
+
+    /// Posts a synthetic completion notification to the completion port of this driver, to be
+    /// processed by the next `process_completions()` call as if it came from the operating system.
+    ///
+    /// The OVERLAPPED pointer is only meaningful to whoever receives the completion key - for the
+    /// default key, it must come from `begin_synthetic_operation()` and be posted only once.
+    pub(crate) fn post_synthetic_completion(
+        &self,
+        completion_key: usize,
+        overlapped: *mut OVERLAPPED,
+        bytes_transferred: u32,
+    ) -> io::Result<()> {
+        // SAFETY: The completion port is kept alive by self. What the OVERLAPPED pointer refers to
+        // is the responsibility of the caller (see above).
+        unsafe {
+            PostQueuedCompletionStatus(
+                ***self.completion_port.handle(),
+                bytes_transferred,
+                completion_key,
+                Some(overlapped),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Driver")
+            .field("completion_port", &self.completion_port)
+            .field("operation_store", &self.operation_store)
+            .field(
+                "completion_handlers",
+                &self.completion_handlers.keys().collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
 impl Drop for Driver {
     fn drop(&mut self) {
         // We must ensure that all I/O operations are completed before we drop the driver. This is
@@ -187,9 +364,140 @@ thread_local! {
         .build()
         .unwrap();
 
+    static UNROUTED_COMPLETIONS: Event = EventBuilder::new()
+        .name("io_async_completions_unrouted")
+        .build()
+        .unwrap();
+
     static GET_COMPLETED_DURATION: Event = EventBuilder::new()
         .name("io_async_completions_get_duration_millis")
         .buckets(GENERAL_MILLISECONDS_BUCKETS)
         .build()
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use std::{cell::RefCell, rc::Rc, thread};
+
+    fn buffer() -> PinnedBuffer {
+        PinnedBuffer::from_boxed_slice(Box::new([0; 16]))
+    }
+
+    #[test]
+    fn out_of_order_completions_reach_their_originators() {
+        // SAFETY: We complete all operations before the driver is dropped.
+        let mut driver = unsafe { Driver::new() };
+
+        let (first, first_overlapped) = driver.begin_synthetic_operation(buffer());
+        let (second, second_overlapped) = driver.begin_synthetic_operation(buffer());
+
+        driver
+            .post_synthetic_completion(DEFAULT_COMPLETION_KEY, second_overlapped, 2)
+            .unwrap();
+        driver.process_completions(0);
+
+        let mut first = Box::pin(first);
+        assert!(first.as_mut().now_or_never().is_none());
+        assert_eq!(second.now_or_never().unwrap().unwrap().len(), 2);
+        assert!(!driver.is_inert());
+
+        driver
+            .post_synthetic_completion(DEFAULT_COMPLETION_KEY, first_overlapped, 1)
+            .unwrap();
+        driver.process_completions(0);
+
+        assert_eq!(first.now_or_never().unwrap().unwrap().len(), 1);
+        assert!(driver.is_inert());
+    }
+
+    #[test]
+    fn completion_of_abandoned_operation_is_ignored() {
+        // SAFETY: We complete all operations before the driver is dropped.
+        let mut driver = unsafe { Driver::new() };
+
+        let (future, overlapped) = driver.begin_synthetic_operation(buffer());
+
+        // Nobody is waiting for the result any more but the operation is still owned by the OS.
+        drop(future);
+        assert!(!driver.is_inert());
+
+        driver
+            .post_synthetic_completion(DEFAULT_COMPLETION_KEY, overlapped, 5)
+            .unwrap();
+        driver.process_completions(0);
+
+        assert!(driver.is_inert());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn polling_operation_on_another_thread_panics() {
+        // Operations are !Send, so only a bug (e.g. in unsafe code) can move one to another thread.
+        struct SendAnyway<T>(T);
+
+        // SAFETY: Deliberately wrong, to simulate such a bug. The future is only polled once.
+        unsafe impl<T> Send for SendAnyway<T> {}
+
+        // SAFETY: We complete all operations before the driver is dropped.
+        let mut driver = unsafe { Driver::new() };
+
+        let (future, overlapped) = driver.begin_synthetic_operation(buffer());
+        let future = SendAnyway(future);
+
+        let result = thread::spawn(move || {
+            let future = future;
+            future.0.now_or_never().is_some()
+        })
+        .join();
+        assert!(result.is_err());
+
+        driver
+            .post_synthetic_completion(DEFAULT_COMPLETION_KEY, overlapped, 0)
+            .unwrap();
+        driver.process_completions(0);
+        assert!(driver.is_inert());
+    }
+
+    #[test]
+    fn completions_are_routed_by_key() {
+        const KEY: usize = 0x1234;
+
+        // SAFETY: We never start any operations.
+        let mut driver = unsafe { Driver::new() };
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+
+        driver
+            .register_completion_handler(KEY, {
+                let received = Rc::clone(&received);
+                Box::new(move |entry| received.borrow_mut().push(entry.dwNumberOfBytesTransferred))
+            })
+            .unwrap();
+
+        driver
+            .post_synthetic_completion(KEY, ptr::null_mut(), 3)
+            .unwrap();
+        driver
+            .post_synthetic_completion(WAKE_UP_COMPLETION_KEY, ptr::null_mut(), 0)
+            .unwrap();
+        driver
+            .post_synthetic_completion(KEY + 1, ptr::null_mut(), 4)
+            .unwrap();
+        driver.process_completions(0);
+
+        // The wakeup and the completion with an unknown key are both discarded.
+        assert_eq!(*received.borrow(), vec![3]);
+
+        driver.unregister_completion_handler(KEY);
+
+        driver
+            .post_synthetic_completion(KEY, ptr::null_mut(), 5)
+            .unwrap();
+        driver.process_completions(0);
+
+        assert_eq!(*received.borrow(), vec![3]);
+    }
+}
@@ -29,6 +29,19 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// The error reported by operations that did not complete before their deadline.
+    pub fn timed_out() -> Self {
+        Error::StdIo(std::io::ErrorKind::TimedOut.into())
+    }
+
+    /// Whether this is the error reported by operations that did not complete before their
+    /// deadline.
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self, Error::StdIo(e) if e.kind() == std::io::ErrorKind::TimedOut)
+    }
+}
+
 impl From<Error> for std::io::Error {
     fn from(value: Error) -> Self {
         match value {
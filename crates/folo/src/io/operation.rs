@@ -1,20 +1,34 @@
-use super::{OperationResult, PinnedBuffer};
+use super::{IoPrimitive, OperationResult, PinnedBuffer};
+use crate::trace::{event, Level};
 use crate::{
     constants::{GENERAL_BYTES_BUCKETS, GENERAL_MILLISECONDS_BUCKETS},
     io,
     metrics::{Event, EventBuilder, Magnitude},
+    time::{Clock, Delay},
     util::{LowPrecisionInstant, PinnedSlabChain},
 };
 use negative_impl::negative_impl;
 use pin_project::pin_project;
 use std::{
-    cell::{RefCell, UnsafeCell}, fmt, future::Future, mem::{self, ManuallyDrop}, ptr, task::Poll
+    cell::{Cell, RefCell, UnsafeCell},
+    fmt,
+    future::Future,
+    iter,
+    mem::{self, ManuallyDrop},
+    pin::Pin,
+    ptr,
+    rc::Rc,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
 };
-use tracing::{event, Level};
 use windows::Win32::{
-    Foundation::{ERROR_IO_PENDING, NTSTATUS, STATUS_SUCCESS},
+    Foundation::{ERROR_IO_PENDING, HANDLE, NTSTATUS, STATUS_SUCCESS},
     Networking::WinSock::{SOCKET_ERROR, WSA_IO_PENDING},
-    System::IO::{OVERLAPPED, OVERLAPPED_ENTRY},
+    System::IO::{CancelIoEx, OVERLAPPED, OVERLAPPED_ENTRY},
 };
 
 /// Maintains the backing storage for the metadata structures of I/O operations submitted to the
@@ -40,15 +54,25 @@ pub(super) struct OperationStore {
     // reference from the slab chain and giving it to the operating system to mutate, which would
     // be invalid Rust without Unsafecell.
     items: RefCell<PinnedSlabChain<UnsafeCell<OperationCore>>>,
+
+    // Shared by all the operation stores of a runtime, to report how operations completed.
+    completion_counters: Arc<CompletionCounters>,
 }
 
 impl OperationStore {
     pub fn new() -> Self {
         Self {
             items: RefCell::new(PinnedSlabChain::new()),
+            completion_counters: Arc::new(CompletionCounters::default()),
         }
     }
 
+    /// Reports completions to the given counters from now on, instead of to the ones created
+    /// together with the store.
+    pub fn set_completion_counters(&mut self, counters: Arc<CompletionCounters>) {
+        self.completion_counters = counters;
+    }
+
     /// Whether the operation store is empty and it is safe to drop the instance.
     pub fn is_empty(&self) -> bool {
         self.items.borrow().is_empty()
@@ -95,20 +119,21 @@ impl OperationStore {
         let status = NTSTATUS(overlapped_entry.Internal as i32);
 
         OPERATIONS_COMPLETED_ASYNC.with(Event::observe_unit);
+        self.completion_counters
+            .deferred
+            .fetch_add(1, atomic::Ordering::Relaxed);
         OPERATION_COMPLETED_BYTES.with(|x| x.observe(bytes_transferred as Magnitude));
 
         // SAFETY: The core is only referenced by either Operation or the operating system at any
         // given time, so there is no possibility of multiple exclusive references being created.
         let core = &mut *(overlapped_entry.lpOverlapped as *mut OperationCore);
 
+        #[cfg(debug_assertions)]
+        core.assert_owning_thread();
+
         // The buffer is returned to the originator, carrying any data affected by the operation.
         // This also enables them to reuse the buffer if they wish to do so.
-        let mut buffer = core
-            .buffer
-            .take()
-            .expect("buffer must exist because we only remove it after completion");
-
-        buffer.set_len(bytes_transferred);
+        let (buffer, attached_buffers) = core.take_buffers(bytes_transferred);
 
         let duration = LowPrecisionInstant::now().duration_since(
             core.started
@@ -125,14 +150,19 @@ impl OperationStore {
 
         // The operation may not have been successful, so we need to investigate the status.
         // We ignore the tx return value because the receiver may have dropped already.
-        if status != STATUS_SUCCESS {
-            _ = result_tx.send(Err(io::OperationError::new(
+        let result = if status != STATUS_SUCCESS {
+            Err(io::OperationError::new(
                 io::Error::Windows(status.into()),
                 buffer,
-            )));
+            ))
         } else {
-            _ = result_tx.send(Ok(buffer));
-        }
+            Ok(buffer)
+        };
+
+        _ = result_tx.send(CompletedOperation {
+            result,
+            attached_buffers,
+        });
 
         // All done!
         self.release(core.key);
@@ -158,31 +188,73 @@ impl OperationStore {
         // given time, so there is no possibility of multiple exclusive references being created.
         let core = &mut *(overlapped as *mut OperationCore);
 
-        // The buffer is returned to the originator, carrying any data affected by the operation.
-        // This also enables them to reuse the buffer if they wish to do so.
-        let mut buffer = core
-            .buffer
-            .take()
-            .expect("buffer must exist because we only remove it after completion");
+        #[cfg(debug_assertions)]
+        core.assert_owning_thread();
 
         let bytes_transferred = core.immediate_bytes_transferred as usize;
-        assert!(bytes_transferred <= buffer.len());
+        assert!(bytes_transferred <= core.buffers_len());
 
         OPERATIONS_COMPLETED_SYNC.with(Event::observe_unit);
+        self.completion_counters
+            .immediate
+            .fetch_add(1, atomic::Ordering::Relaxed);
         OPERATION_COMPLETED_BYTES.with(|x| x.observe(bytes_transferred as Magnitude));
 
-        buffer.set_len(bytes_transferred);
+        // The buffer is returned to the originator, carrying any data affected by the operation.
+        // This also enables them to reuse the buffer if they wish to do so.
+        let (buffer, attached_buffers) = core.take_buffers(bytes_transferred);
 
         _ = core
             .result_tx
             .take()
             .expect("result tx must exist because we have not yet sent the result")
-            .send(Ok(buffer));
+            .send(CompletedOperation {
+                result: Ok(buffer),
+                attached_buffers,
+            });
 
         // All done!
         self.release(core.key);
     }
 
+    /// Requests cancellation of every operation in flight that knows the I/O primitive it was
+    /// started on (see `Operation::set_primitive()`), returning how many cancellations were
+    /// requested. Used on shutdown, so we do not depend on the I/O primitives being closed to get
+    /// all operations to complete.
+    ///
+    /// A cancellation is just a request - the canceled operations still complete via the usual
+    /// completion notification, which must be processed before the store can be dropped. Operations
+    /// that complete in the meantime are not affected.
+    pub fn cancel_all(&self) -> usize {
+        let items = self.items.borrow();
+        let mut canceled = 0;
+
+        for item in items.iter() {
+            let core = item.get();
+
+            // SAFETY: We only read fields that the operating system does not touch and only take a
+            // pointer to the OVERLAPPED structure, never a reference, so we do not race with it.
+            let (primitive, started) = unsafe { ((*core).primitive, (*core).started.is_some()) };
+
+            // Operations that have not been started yet are still owned by their originator and
+            // will never see a completion notification - there is nothing to cancel.
+            let (Some(primitive), true) = (primitive, started) else {
+                continue;
+            };
+
+            // SAFETY: The OVERLAPPED pointer identifies an operation that has been started and has
+            // not yet been completed (or it would have been removed from the store). This fails if
+            // the operation has completed in the meantime or the primitive has already been closed
+            // (which cancels the operation by itself), both of which are fine.
+            if unsafe { CancelIoEx(primitive, Some(ptr::addr_of_mut!((*core).overlapped))) }.is_ok()
+            {
+                canceled += 1;
+            }
+        }
+
+        canceled
+    }
+
     fn release(&self, key: OperationKey) {
         assert!(key != OperationKey::MAX);
 
@@ -201,6 +273,15 @@ impl OperationStore {
 
 type OperationKey = usize;
 
+/// Counts the I/O operations that completed immediately (without a completion notification, thanks
+/// to `FILE_SKIP_COMPLETION_PORT_ON_SUCCESS`) versus those whose completion was delivered via the
+/// I/O completion port. Operations that fail to start are not counted.
+#[derive(Debug, Default)]
+pub(crate) struct CompletionCounters {
+    pub(crate) immediate: AtomicU64,
+    pub(crate) deferred: AtomicU64,
+}
+
 /// Constrained API surface that allows an operation to command the store that owns it. This creates
 /// a circular reference between an operation and the OperationStore, so we always use
 /// OperationStore via interior mutability to prevent accidents here.
@@ -251,6 +332,10 @@ struct OperationCore {
     /// the buffer to the caller and set this to None.
     buffer: Option<PinnedBuffer>,
 
+    /// The additional buffers of a vectored operation, which the operation uses after `buffer`, in
+    /// order. Empty for other operations. Returned to the caller together with `buffer`.
+    attached_buffers: Vec<PinnedBuffer>,
+
     /// Used to operate the control node, which requires us to know our own key.
     key: OperationKey,
 
@@ -261,12 +346,24 @@ struct OperationCore {
 
     /// This is where the I/O completion handler will deliver the result of the operation.
     /// Value is cleared when consumed, to make it obvious if any accidental reuse occurs.
-    result_tx: Option<oneshot::Sender<io::OperationResult>>,
-    result_rx: Option<oneshot::Receiver<io::OperationResult>>,
+    result_tx: Option<oneshot::Sender<CompletedOperation>>,
+    result_rx: Option<oneshot::Receiver<CompletedOperation>>,
 
     /// Timestamp of when the operation is started. Used to report I/O operation durations.
     started: Option<LowPrecisionInstant>,
 
+    /// The I/O primitive the operation is started on, if known. Allows the operation to be canceled
+    /// via `OperationStore::cancel_all()`.
+    primitive: Option<HANDLE>,
+
+    /// If set, this flag is cleared when the operation core is released, i.e. once the operating
+    /// system no longer uses the operation. See `Operation::clear_when_released()`.
+    in_flight: Option<Rc<Cell<bool>>>,
+
+    /// The thread whose I/O driver owns the operation. Only this thread may complete it.
+    #[cfg(debug_assertions)]
+    owning_thread: std::thread::ThreadId,
+
     // Once pinned, this type cannot be unpinned.
     _phantom_pin: std::marker::PhantomPinned,
 }
@@ -283,20 +380,67 @@ impl OperationCore {
         Self {
             overlapped: OVERLAPPED::default(),
             buffer: Some(buffer),
+            attached_buffers: Vec::new(),
             key,
             immediate_bytes_transferred: 0,
             result_tx: Some(result_tx),
             result_rx: Some(result_rx),
             started: None,
+            primitive: None,
+            in_flight: None,
+            #[cfg(debug_assertions)]
+            owning_thread: std::thread::current().id(),
             _phantom_pin: std::marker::PhantomPinned,
         }
     }
+
+    /// The combined length of the active regions of all the buffers of the operation.
+    fn buffers_len(&self) -> usize {
+        self.buffer.as_ref().map_or(0, PinnedBuffer::len)
+            + self.attached_buffers.iter().map(PinnedBuffer::len).sum::<usize>()
+    }
+
+    /// Takes the buffers out of a completed operation, setting their active regions to the data
+    /// transferred. The buffers of a vectored operation are filled in order, each up to the length
+    /// of its active region when the operation started.
+    fn take_buffers(&mut self, bytes_transferred: usize) -> (PinnedBuffer, Vec<PinnedBuffer>) {
+        let mut buffer = self
+            .buffer
+            .take()
+            .expect("buffer must exist because we only remove it after completion");
+        let mut attached_buffers = mem::take(&mut self.attached_buffers);
+
+        if attached_buffers.is_empty() {
+            buffer.set_len(bytes_transferred);
+            return (buffer, attached_buffers);
+        }
+
+        let mut remaining = bytes_transferred;
+
+        for buffer in iter::once(&mut buffer).chain(attached_buffers.iter_mut()) {
+            let filled = remaining.min(buffer.len());
+            buffer.set_len(filled);
+            remaining -= filled;
+        }
+
+        (buffer, attached_buffers)
+    }
+
+    #[cfg(debug_assertions)]
+    fn assert_owning_thread(&self) {
+        assert_eq!(
+            self.owning_thread,
+            std::thread::current().id(),
+            "I/O operation completed on a thread other than the one that owns it"
+        );
+    }
 }
 
 impl fmt::Debug for OperationCore {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("OperationCore")
             .field("buffer", &self.buffer)
+            .field("attached_buffers", &self.attached_buffers)
             .field("key", &self.key)
             .field(
                 "immediate_bytes_transferred",
@@ -305,10 +449,27 @@ impl fmt::Debug for OperationCore {
             .field("result_tx", &self.result_tx)
             .field("result_rx", &self.result_rx)
             .field("started", &self.started)
+            .field("primitive", &self.primitive)
+            .field("in_flight", &self.in_flight)
             .finish()
     }
 }
 
+impl Drop for OperationCore {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.in_flight.take() {
+            // If the flag was already clear, another operation must have shared it - exactly what
+            // the flag is supposed to prevent.
+            debug_assert!(
+                in_flight.get(),
+                "in-flight flag was cleared while its operation was still in flight"
+            );
+
+            in_flight.set(false);
+        }
+    }
+}
+
 // We need to to avoid accidents. All our I/O operations need to stay on the same thread when they
 // are in the Rust universe. The OS can do what it wants when it holds ownership but for us they
 // are single-threaded.
@@ -337,6 +498,29 @@ impl Operation {
         self.core.overlapped.Anonymous.Anonymous.OffsetHigh = (offset >> 32) as u32;
     }
 
+    /// Records the I/O primitive that the operation is going to be started on, which allows the
+    /// I/O driver to cancel the operation if it is still in flight when the worker shuts down.
+    /// Operations without a known primitive can only complete on their own.
+    pub fn set_primitive(&mut self, primitive: impl Into<IoPrimitive>) {
+        self.core.primitive = Some(HANDLE::from(primitive.into()));
+    }
+
+    /// Clears the flag once the operation is released - after it has completed and its result has
+    /// been delivered, or if it fails to start or is abandoned before it is started. Dropping the
+    /// future of the operation does not clear the flag, as the operating system may still be using
+    /// the operation. This allows I/O primitives to track which of their operations are in flight.
+    ///
+    /// The caller must have set the flag before creating the operation, with nothing else in flight
+    /// holding on to the same flag.
+    pub fn clear_when_released(&mut self, in_flight: Rc<Cell<bool>>) {
+        debug_assert!(
+            in_flight.get(),
+            "in-flight flag must be set before it is given to an operation"
+        );
+
+        self.core.in_flight = Some(in_flight);
+    }
+
     /// Executes an I/O operation, using the specified callback to pass the operation buffer and
     /// OVERLAPPED metadata structure to native OS functions.
     ///
@@ -407,12 +591,19 @@ impl Operation {
                 let buffer = (*core).buffer.take().expect(
                     "buffer must exist because we only remove it after completion or failure and right now we are doing the latter",
                 );
+                let attached_buffers = mem::take(&mut (*core).attached_buffers);
 
                 control_node.release((*core).key);
 
                 return OperationResultFuture {
                     receiver: result_rx,
                     error: Some(io::OperationError::new(e, buffer)),
+                    attached_buffers,
+                    bytes_transferred_counter: None,
+                    deadline_expired: None,
+                    timeout: None,
+                    #[cfg(debug_assertions)]
+                    owning_thread: std::thread::current().id(),
                 }
             }
         }
@@ -420,7 +611,103 @@ impl Operation {
         OperationResultFuture {
             receiver: result_rx,
             error: None,
+            attached_buffers: Vec::new(),
+            bytes_transferred_counter: None,
+            deadline_expired: None,
+            timeout: None,
+            #[cfg(debug_assertions)]
+            owning_thread: std::thread::current().id(),
+        }
+    }
+
+    /// Executes an I/O operation like `begin()` but cancels it if it has not completed within the
+    /// given timeout, in which case the operation fails with `io::Error::timed_out()`. As with any
+    /// other failure, the buffer is returned via the `io::OperationError`.
+    ///
+    /// The I/O primitive must be the one the operation is started on, as that is what we need to
+    /// cancel the operation. Only this operation is canceled - other operations on the same I/O
+    /// primitive are unaffected.
+    ///
+    /// The timeout is measured on the current async worker thread, so it may be exceeded if the
+    /// thread is busy. An operation that completes successfully in the meantime is not failed, even
+    /// if the timeout has already elapsed.
+    ///
+    /// # Safety
+    ///
+    /// Same as `begin()`. In addition, the I/O primitive must remain valid until the returned
+    /// future completes or is dropped.
+    pub unsafe fn begin_with_timeout<F>(
+        self,
+        primitive: impl Into<IoPrimitive>,
+        timeout: Duration,
+        f: F,
+    ) -> OperationResultFuture
+    where
+        F: FnOnce(&'static mut [u8], *mut OVERLAPPED, &mut u32) -> io::Result<()>,
+    {
+        let handle = HANDLE::from(primitive.into());
+        self.core.primitive = Some(handle);
+
+        let mut overlapped_ptr: *mut OVERLAPPED = ptr::null_mut();
+
+        let mut future = self.begin(|buffer, overlapped, immediate_bytes_transferred| {
+            overlapped_ptr = overlapped;
+            f(buffer, overlapped, immediate_bytes_transferred)
+        });
+
+        // If the operation failed to start, there is nothing to time out.
+        if future.error.is_none() {
+            future.timeout = Some(OperationTimeout {
+                delay: Delay::with_clock(&Clock::new(), timeout),
+                handle,
+                overlapped: overlapped_ptr,
+                canceled: false,
+            });
         }
+
+        future
+    }
+
+    /// Executes a vectored I/O operation, which uses the operation buffer followed by each of the
+    /// additional buffers, in order. Otherwise the same as `begin()`, except that the callback
+    /// receives all the buffers (starting with the operation buffer) instead of only one.
+    ///
+    /// Convert the returned future via `OperationResultFuture::into_vectored()` to get all the
+    /// buffers back once the operation is complete. The active region of each buffer is set to the
+    /// data transferred to/from it - the buffers are filled in order, each up to the length of its
+    /// active region when the operation started.
+    ///
+    /// # Safety
+    ///
+    /// Same as `begin()`.
+    pub unsafe fn begin_vectored<F>(
+        self,
+        attached_buffers: Vec<PinnedBuffer>,
+        f: F,
+    ) -> OperationResultFuture
+    where
+        F: FnOnce(Vec<&'static mut [u8]>, *mut OVERLAPPED, &mut u32) -> io::Result<()>,
+    {
+        self.core.attached_buffers = attached_buffers;
+
+        // The buffers are owned by the operation core, which stays put until the operation is
+        // complete, and the buffers themselves are pinned, so the slices remain valid until then.
+        let attached_slices: Vec<&'static mut [u8]> = self
+            .core
+            .attached_buffers
+            .iter_mut()
+            // SAFETY: Same as in `into_callback_arguments()` - the lifetime is a lie but the
+            // callback is only allowed to use the slices for the duration of the call.
+            .map(|buffer| unsafe { mem::transmute::<&mut [u8], &mut [u8]>(buffer.as_mut_slice()) })
+            .collect();
+
+        self.begin(|buffer, overlapped, immediate_bytes_transferred| {
+            let mut buffers = Vec::with_capacity(attached_slices.len() + 1);
+            buffers.push(buffer);
+            buffers.extend(attached_slices);
+
+            f(buffers, overlapped, immediate_bytes_transferred)
+        })
     }
 
     fn into_callback_arguments(self) -> (&'static mut [u8], *mut OVERLAPPED, &'static mut u32) {
@@ -460,8 +747,111 @@ impl Operation {
 #[derive(Debug)]
 pub struct OperationResultFuture {
     #[pin]
-    receiver: oneshot::Receiver<io::OperationResult>,
-    error: Option<io::OperationError>
+    receiver: oneshot::Receiver<CompletedOperation>,
+    error: Option<io::OperationError>,
+
+    // The additional buffers of a vectored operation, once the operation has completed. Taken by
+    // `VectoredOperationResultFuture` after the result has been delivered.
+    attached_buffers: Vec<PinnedBuffer>,
+
+    // If set, the number of bytes transferred by a successful operation is added to this counter.
+    bytes_transferred_counter: Option<Arc<AtomicU64>>,
+
+    // If set and true when the operation fails, the failure is reported as a timeout. Operations
+    // are canceled when their deadline expires, so the original error just says "canceled".
+    deadline_expired: Option<Arc<AtomicBool>>,
+
+    // If set, the operation is canceled if it has not completed by the time this elapses.
+    timeout: Option<OperationTimeout>,
+
+    // The result is delivered by the I/O driver of this thread, so polling from any other thread
+    // means the operation has escaped the thread that owns it.
+    #[cfg(debug_assertions)]
+    owning_thread: std::thread::ThreadId,
+}
+
+/// Cancels a single pending operation once its timeout elapses. See `Operation::begin_with_timeout()`.
+#[derive(Debug)]
+struct OperationTimeout {
+    delay: Delay,
+
+    // The I/O primitive the operation was started on, together with the OVERLAPPED structure that
+    // identifies the operation to the operating system.
+    handle: HANDLE,
+    overlapped: *mut OVERLAPPED,
+
+    // Set once we have asked the operating system to cancel the operation.
+    canceled: bool,
+}
+
+impl OperationTimeout {
+    /// Cancels the operation if the timeout has elapsed.
+    ///
+    /// # Safety
+    ///
+    /// The operation must not have completed yet. As completions are processed on the current
+    /// thread, this means its result must not have been delivered yet.
+    unsafe fn poll_cancel(&mut self, cx: &mut std::task::Context<'_>) {
+        if self.canceled || Pin::new(&mut self.delay).poll(cx).is_pending() {
+            return;
+        }
+
+        self.canceled = true;
+
+        OPERATIONS_TIMED_OUT.with(Event::observe_unit);
+
+        // SAFETY: The operation has not completed, so the OVERLAPPED structure is still owned by
+        // the operating system and identifies our operation. The caller of begin_with_timeout()
+        // promised that the handle is still valid.
+        unsafe {
+            // This fails if the operation completed in the meantime, which is fine - we will get
+            // its result soon.
+            _ = CancelIoEx(self.handle, Some(self.overlapped));
+        }
+    }
+}
+
+impl OperationResultFuture {
+    /// Creates a future that fails with the given error, for operations that fail before they are
+    /// started.
+    pub(crate) fn from_error(error: io::OperationError) -> Self {
+        // The result is never received, as the error is returned first.
+        let (_, receiver) = oneshot::channel();
+
+        Self {
+            receiver,
+            error: Some(error),
+            attached_buffers: Vec::new(),
+            bytes_transferred_counter: None,
+            deadline_expired: None,
+            timeout: None,
+            #[cfg(debug_assertions)]
+            owning_thread: std::thread::current().id(),
+        }
+    }
+
+    /// Adds the number of bytes transferred by the operation to the given counter once the
+    /// operation completes successfully.
+    pub(crate) fn count_bytes_into(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.bytes_transferred_counter = Some(counter);
+        self
+    }
+
+    /// Converts the future of an operation started via `Operation::begin_vectored()` into one that
+    /// returns all the buffers of the operation.
+    pub(crate) fn into_vectored(self) -> VectoredOperationResultFuture {
+        VectoredOperationResultFuture {
+            inner: Some(self),
+            error: None,
+        }
+    }
+
+    /// Reports a failure of the operation as a timeout if the flag is set by the time the
+    /// operation completes, indicating that the operation was canceled due to an expired deadline.
+    pub(crate) fn time_out_if_expired(mut self, deadline_expired: Arc<AtomicBool>) -> Self {
+        self.deadline_expired = Some(deadline_expired);
+        self
+    }
 }
 
 impl Future for OperationResultFuture {
@@ -470,17 +860,123 @@ impl Future for OperationResultFuture {
     fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            *this.owning_thread,
+            std::thread::current().id(),
+            "I/O operation polled on a thread other than the one that owns it"
+        );
+
         if let Some(err) = this.error.take() {
             return Poll::Ready(Err(err));
         }
 
         match this.receiver.poll(cx) {
-            Poll::Ready(v) => Poll::Ready(v.expect("")),
-            Poll::Pending => Poll::Pending,
+            Poll::Ready(v) => {
+                let CompletedOperation {
+                    mut result,
+                    attached_buffers,
+                } = v.expect("");
+
+                if let (Err(e), Some(expired)) = (&mut result, this.deadline_expired) {
+                    if expired.load(atomic::Ordering::Acquire) {
+                        e.inner = io::Error::timed_out();
+                    }
+                }
+
+                if let (Err(e), Some(timeout)) = (&mut result, this.timeout) {
+                    if timeout.canceled {
+                        e.inner = io::Error::timed_out();
+                    }
+                }
+
+                if let (Ok(buffer), Some(counter)) = (&result, this.bytes_transferred_counter) {
+                    let bytes_transferred = buffer.len()
+                        + attached_buffers.iter().map(PinnedBuffer::len).sum::<usize>();
+
+                    counter.fetch_add(bytes_transferred as u64, atomic::Ordering::Relaxed);
+                }
+
+                *this.attached_buffers = attached_buffers;
+
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                if let Some(timeout) = this.timeout {
+                    // SAFETY: The result has not been delivered, so the operation is still pending.
+                    unsafe { timeout.poll_cancel(cx) };
+                }
+
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The result of an operation as delivered by the I/O driver to the originator of the operation.
+#[derive(Debug)]
+struct CompletedOperation {
+    result: io::OperationResult,
+
+    // The additional buffers of a vectored operation. Empty for other operations.
+    attached_buffers: Vec<PinnedBuffer>,
+}
+
+/// The future of a vectored I/O operation, which completes with all the buffers of the operation.
+#[pin_project]
+#[derive(Debug)]
+pub struct VectoredOperationResultFuture {
+    // None if the operation was never started.
+    #[pin]
+    inner: Option<OperationResultFuture>,
+
+    // If set, the operation was never started and this is returned as the result.
+    error: Option<io::VectoredOperationError>,
+}
+
+impl VectoredOperationResultFuture {
+    /// Creates a future that fails with the given error, for operations that fail before they are
+    /// started.
+    pub(crate) fn from_error(error: io::VectoredOperationError) -> Self {
+        Self {
+            inner: None,
+            error: Some(error),
         }
     }
 }
 
+impl Future for VectoredOperationResultFuture {
+    type Output = io::VectoredOperationResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Some(error) = this.error.take() {
+            return Poll::Ready(Err(error));
+        }
+
+        let mut inner = this
+            .inner
+            .as_pin_mut()
+            .expect("operation is always started if there is no error");
+
+        let Poll::Ready(result) = inner.as_mut().poll(cx) else {
+            return Poll::Pending;
+        };
+
+        let attached_buffers = mem::take(inner.project().attached_buffers);
+
+        Poll::Ready(match result {
+            Ok(buffer) => Ok(io::VectoredResult::new(
+                iter::once(buffer).chain(attached_buffers).collect(),
+            )),
+            Err(io::OperationError { inner, buffer }) => Err(io::VectoredOperationError::new(
+                inner,
+                iter::once(buffer).chain(attached_buffers).collect(),
+            )),
+        })
+    }
+}
 
 impl Drop for Operation {
     fn drop(&mut self) {
@@ -504,6 +1000,11 @@ thread_local! {
         .build()
         .unwrap();
 
+    static OPERATIONS_TIMED_OUT: Event = EventBuilder::new()
+        .name("io_ops_timed_out")
+        .build()
+        .unwrap();
+
     static OPERATION_COMPLETED_BYTES: Event = EventBuilder::new()
         .name("io_completed_bytes")
         .buckets(GENERAL_BYTES_BUCKETS)
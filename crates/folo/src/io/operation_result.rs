@@ -40,3 +40,66 @@ impl OperationResultExt for OperationResult {
         }
     }
 }
+
+/// The outcome of a successful vectored I/O operation (e.g. `TcpConnection::receive_vectored()`).
+#[derive(Debug)]
+pub struct VectoredResult {
+    /// The buffers given to the operation, in the same order. The active region of each is set to
+    /// the data transferred to/from it. The buffers are filled in order, so all buffers before the
+    /// last one holding data are completely filled.
+    pub buffers: Vec<PinnedBuffer>,
+
+    /// The total number of bytes transferred, across all buffers.
+    pub bytes_transferred: usize,
+}
+
+impl VectoredResult {
+    pub(crate) fn new(buffers: Vec<PinnedBuffer>) -> Self {
+        let bytes_transferred = buffers.iter().map(PinnedBuffer::len).sum();
+
+        Self {
+            buffers,
+            bytes_transferred,
+        }
+    }
+
+    /// The buffers that hold data, in order. Any buffers after these were not used.
+    pub fn filled(&self) -> &[PinnedBuffer] {
+        let used = self
+            .buffers
+            .iter()
+            .rposition(|buffer| !buffer.is_empty())
+            .map_or(0, |index| index + 1);
+
+        &self.buffers[..used]
+    }
+
+    pub fn into_buffers(self) -> Vec<PinnedBuffer> {
+        self.buffers
+    }
+}
+
+/// An error for a vectored I/O operation. Contains not only the error information but also the data
+/// buffers that were used, enabling them to be inspected or reused.
+#[derive(Debug, Error)]
+#[error("vectored I/O operation failed: {inner}")]
+pub struct VectoredOperationError {
+    pub inner: crate::io::Error,
+    pub buffers: Vec<PinnedBuffer>,
+}
+
+impl VectoredOperationError {
+    pub fn new(inner: crate::io::Error, buffers: Vec<PinnedBuffer>) -> Self {
+        Self { inner, buffers }
+    }
+
+    pub fn into_inner(self) -> crate::io::Error {
+        self.inner
+    }
+
+    pub fn into_inner_and_buffers(self) -> (crate::io::Error, Vec<PinnedBuffer>) {
+        (self.inner, self.buffers)
+    }
+}
+
+pub type VectoredOperationResult = std::result::Result<VectoredResult, VectoredOperationError>;
@@ -0,0 +1,105 @@
+use crate::{
+    io::{self, CompletionPortHandle},
+    rt::current_async_agent,
+};
+use negative_impl::negative_impl;
+use windows::Win32::System::IO::{PostQueuedCompletionStatus, OVERLAPPED};
+
+/// Registers a handler on the current async worker thread for user-defined completion packets
+/// posted via `post_wakeup()`, for integrating event sources that are not natively supported by
+/// Folo (e.g. a third party library that reports events from its own threads).
+///
+/// Completion packets are routed to handlers by their completion key, so the key must be unique
+/// among the handlers of the worker thread. Folo itself posts completion packets with completion
+/// key 0 (for all of its own I/O operations) and with a private key used to wake up the worker,
+/// so these cannot be registered. Registering a reserved key or a key that already has a handler
+/// fails with `LogicError`.
+///
+/// The handler is called on the current async worker thread with the data given to
+/// `post_wakeup()`, for as long as the returned registration is alive. It is called from within
+/// the I/O driver, so it must be fast and must not start I/O operations - to do more work, it
+/// can hand the data to a task (e.g. via a channel).
+///
+/// # Panics
+///
+/// Panics if the current thread is not an async worker thread owned by a Folo runtime.
+pub fn register_wakeup_handler<F>(
+    completion_key: usize,
+    mut handler: F,
+) -> io::Result<WakeupRegistration>
+where
+    F: FnMut(usize) + 'static,
+{
+    let completion_port = current_async_agent::with_io(|io| {
+        io.register_completion_handler(
+            completion_key,
+            Box::new(move |entry| handler(entry.lpOverlapped as usize)),
+        )?;
+
+        Ok::<_, io::Error>(io.completion_port_handle())
+    })?;
+
+    Ok(WakeupRegistration {
+        target: WakeupTarget {
+            completion_port,
+            completion_key,
+        },
+    })
+}
+
+/// Posts a completion packet carrying `data` to the async worker thread that registered the
+/// target, where it is delivered to the handler given to `register_wakeup_handler()`. This is a
+/// non-blocking operation that may be called from any thread.
+///
+/// The data is not interpreted by Folo in any way. Packets that arrive after the registration has
+/// been dropped (or after the worker thread has stopped) are discarded.
+pub fn post_wakeup(target: &WakeupTarget, data: usize) -> io::Result<()> {
+    // SAFETY: We keep the completion port alive via Arc, so the handle must be valid. The
+    // OVERLAPPED pointer is only used to carry the data - nobody dereferences it, as packets with
+    // this completion key go to the registered handler, not the I/O operation store.
+    unsafe {
+        PostQueuedCompletionStatus(
+            ***target.completion_port,
+            0,
+            target.completion_key,
+            Some(data as *mut OVERLAPPED),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Keeps the handler given to `register_wakeup_handler()` registered until dropped.
+#[derive(Debug)]
+pub struct WakeupRegistration {
+    target: WakeupTarget,
+}
+
+impl WakeupRegistration {
+    /// Returns the target to give to `post_wakeup()`, which may be cloned and moved to any thread.
+    pub fn target(&self) -> WakeupTarget {
+        self.target.clone()
+    }
+}
+
+impl Drop for WakeupRegistration {
+    fn drop(&mut self) {
+        // If the worker is already shutting down, the handler goes away together with the driver.
+        current_async_agent::try_with_io(|io| {
+            io.unregister_completion_handler(self.target.completion_key)
+        });
+    }
+}
+
+#[negative_impl]
+impl !Send for WakeupRegistration {}
+#[negative_impl]
+impl !Sync for WakeupRegistration {}
+
+/// Identifies the wakeup handler of a specific async worker thread, for posting completion packets
+/// to it via `post_wakeup()`.
+#[derive(Clone, Debug)]
+pub struct WakeupTarget {
+    completion_port: CompletionPortHandle,
+    completion_key: usize,
+}
@@ -0,0 +1,139 @@
+use crate::{
+    io::{self, OperationResultExt, PinnedBuffer},
+    net::TcpConnection,
+};
+use negative_impl::negative_impl;
+
+/// Accumulates data received from a connection across multiple `receive()` calls, for protocol
+/// parsers that need to see a complete frame (e.g. a length-prefixed message or a line) before they
+/// can make progress.
+///
+/// Each `receive_into()` receives into a pooled `PinnedBuffer` and appends the received bytes to the
+/// accumulated data. The parser inspects the accumulated data via `filled()` and calls `consume()`
+/// once it has parsed a prefix of it. The storage of consumed bytes is reused, so the memory usage
+/// is bounded by the largest amount of unparsed data ever accumulated.
+#[derive(Debug, Default)]
+pub struct ReadBuffer {
+    storage: Vec<u8>,
+
+    // Bytes before this offset have been consumed and are waiting to be compacted away.
+    consumed: usize,
+}
+
+impl ReadBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receives the next chunk of data from the connection and appends it to the accumulated data.
+    ///
+    /// Returns the number of bytes received, with 0 meaning the connection was closed.
+    pub async fn receive_into(&mut self, connection: &mut TcpConnection) -> io::Result<usize> {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()?;
+
+        self.append(buffer.as_slice());
+        Ok(buffer.len())
+    }
+
+    /// The accumulated data that has not yet been consumed.
+    pub fn filled(&self) -> &[u8] {
+        &self.storage[self.consumed..]
+    }
+
+    /// The number of accumulated bytes that have not yet been consumed.
+    pub fn len(&self) -> usize {
+        self.storage.len() - self.consumed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes the first `count` bytes from the accumulated data, typically after they have been
+    /// successfully parsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is greater than the number of accumulated bytes.
+    pub fn consume(&mut self, count: usize) {
+        assert!(
+            count <= self.len(),
+            "cannot consume {} bytes from a read buffer with only {} bytes",
+            count,
+            self.len()
+        );
+
+        self.consumed += count;
+
+        if self.consumed == self.storage.len() {
+            // Everything has been consumed - we can start from the beginning for free.
+            self.storage.clear();
+            self.consumed = 0;
+        }
+    }
+
+    pub(crate) fn append(&mut self, data: &[u8]) {
+        // We compact when appending (instead of when consuming) because a parser may consume in
+        // many small steps, whereas appending is where the storage would need to grow.
+        if self.consumed > 0 && self.storage.len() + data.len() > self.storage.capacity() {
+            self.storage.drain(..self.consumed);
+            self.consumed = 0;
+        }
+
+        self.storage.extend_from_slice(data);
+    }
+}
+
+#[negative_impl]
+impl !Send for ReadBuffer {}
+#[negative_impl]
+impl !Sync for ReadBuffer {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_and_consumes() {
+        let mut buffer = ReadBuffer::new();
+        assert!(buffer.is_empty());
+
+        buffer.append(b"hello ");
+        buffer.append(b"world");
+        assert_eq!(buffer.filled(), b"hello world");
+
+        buffer.consume(6);
+        assert_eq!(buffer.filled(), b"world");
+        assert_eq!(buffer.len(), 5);
+
+        buffer.consume(5);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn compacts_instead_of_growing() {
+        let mut buffer = ReadBuffer::new();
+
+        buffer.append(&[1; 100]);
+        let capacity = buffer.storage.capacity();
+
+        for _ in 0..100 {
+            buffer.consume(50);
+            buffer.append(&[2; 50]);
+        }
+
+        assert_eq!(buffer.len(), 100);
+        assert_eq!(buffer.storage.capacity(), capacity);
+    }
+
+    #[test]
+    #[should_panic]
+    fn consume_too_much_panics() {
+        let mut buffer = ReadBuffer::new();
+        buffer.append(b"abc");
+        buffer.consume(4);
+    }
+}
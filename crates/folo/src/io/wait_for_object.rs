@@ -0,0 +1,106 @@
+use crate::io::{self, wakeup_signal, WakeupSender};
+use std::ffi::c_void;
+use windows::Win32::{
+    Foundation::{BOOLEAN, HANDLE, INVALID_HANDLE_VALUE},
+    System::Threading::{
+        RegisterWaitForSingleObject, UnregisterWaitEx, INFINITE, WT_EXECUTEONLYONCE,
+    },
+};
+
+/// Waits for a Windows synchronization object (e.g. an event, a process or a thread) to become
+/// signaled, without blocking the current async worker thread.
+///
+/// The wait itself is performed by the Windows thread pool, which forwards the result to the
+/// current async worker thread via its I/O completion port. Dropping the future before it completes
+/// cancels the wait.
+///
+/// # Safety
+///
+/// The handle must remain valid until the returned future completes or is dropped.
+///
+/// # Panics
+///
+/// Panics if polled on a thread that is not an async worker thread owned by a Folo runtime.
+pub async unsafe fn wait_for_object(handle: HANDLE) -> io::Result<()> {
+    let (sender, future) = wakeup_signal();
+
+    // SAFETY: Forwarding the caller's promise that the handle remains valid while we wait.
+    let registration = unsafe { WaitRegistration::new(handle, sender)? };
+
+    let result = future.await;
+
+    // The registration must be released even after the wait has been satisfied.
+    drop(registration);
+
+    result
+}
+
+// Owns a thread pool wait registration and the wakeup sender that the wait callback signals.
+// Dropping it cancels the wait if it has not yet been satisfied.
+struct WaitRegistration {
+    wait_handle: HANDLE,
+
+    // Boxed Option<WakeupSender> that we share with the wait callback. The callback takes the
+    // sender from here and signals it. We reclaim the box once the wait has been unregistered.
+    context: *mut Option<WakeupSender>,
+}
+
+impl WaitRegistration {
+    /// # Safety
+    ///
+    /// The handle must remain valid until the registration is dropped.
+    unsafe fn new(handle: HANDLE, sender: WakeupSender) -> io::Result<Self> {
+        let context = Box::into_raw(Box::new(Some(sender)));
+        let mut wait_handle = HANDLE::default();
+
+        // SAFETY: The context remains valid until we unregister the wait, which waits for any
+        // in-progress callback to complete before returning.
+        let result = unsafe {
+            RegisterWaitForSingleObject(
+                &mut wait_handle,
+                handle,
+                Some(wait_callback),
+                Some(context as *const c_void),
+                INFINITE,
+                WT_EXECUTEONLYONCE,
+            )
+        };
+
+        if let Err(e) = result {
+            // SAFETY: The callback was never registered, so we are the only owner of the context.
+            // Dropping the sender completes the wakeup future, so it does not linger.
+            drop(unsafe { Box::from_raw(context) });
+            return Err(e.into());
+        }
+
+        Ok(Self {
+            wait_handle,
+            context,
+        })
+    }
+}
+
+impl Drop for WaitRegistration {
+    fn drop(&mut self) {
+        // SAFETY: INVALID_HANDLE_VALUE makes this block until any in-progress callback has
+        // completed, after which the callback can no longer access the context.
+        unsafe {
+            // There is nothing meaningful we can do if this fails, so we ignore the result.
+            _ = UnregisterWaitEx(self.wait_handle, INVALID_HANDLE_VALUE);
+        }
+
+        // SAFETY: The wait is unregistered, so we are the only owner of the context. If the wait
+        // was never satisfied, dropping the sender completes the wakeup future with an error.
+        drop(unsafe { Box::from_raw(self.context) });
+    }
+}
+
+unsafe extern "system" fn wait_callback(context: *mut c_void, _timed_out: BOOLEAN) {
+    // SAFETY: The registration keeps the context alive until this callback has returned. We only
+    // register with WT_EXECUTEONLYONCE, so there are no concurrent callbacks accessing it.
+    let sender = unsafe { &mut *(context as *mut Option<WakeupSender>) };
+
+    if let Some(sender) = sender.take() {
+        sender.wake();
+    }
+}
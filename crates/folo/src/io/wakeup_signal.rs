@@ -0,0 +1,151 @@
+use crate::{
+    io::{
+        self, CompletionPortHandle, OperationResultExt, OperationResultFuture, PinnedBuffer,
+        DEFAULT_COMPLETION_KEY,
+    },
+    rt::current_async_agent,
+    util::ThreadSafe,
+};
+use negative_impl::negative_impl;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    task::{self, Poll},
+};
+use windows::Win32::{
+    Foundation::ERROR_IO_PENDING,
+    System::IO::{PostQueuedCompletionStatus, OVERLAPPED},
+};
+
+// The completion packet reports this many bytes transferred when the sender signals the wakeup.
+const SIGNALED_BYTES: u32 = 1;
+// The completion packet reports this many bytes transferred when the sender is dropped unused.
+const ABANDONED_BYTES: u32 = 0;
+
+/// Creates a wakeup signal for the current async worker thread, for integrating event sources that
+/// are not natively supported by Folo (e.g. callbacks from a third party library's own threads).
+///
+/// The sender can be moved to any thread. Signaling it posts a completion packet to the I/O
+/// completion port of the current async worker thread, which completes the future returned here.
+/// The future is completed with an error if the sender is dropped without signaling. To deliver any
+/// number of wakeups carrying data to a long-lived handler instead, see `register_wakeup_handler()`.
+///
+/// The completion packet is delivered just like the completion of any I/O operation started by
+/// Folo, so it cannot be confused with the completions of other operations - there is no need to
+/// pick a unique completion key (operations use the default key of the I/O driver).
+///
+/// Like any pending I/O operation, an unsignaled sender prevents the runtime from completing its
+/// shutdown - the worker thread waits for the completion packet before it terminates.
+///
+/// # Panics
+///
+/// Panics if the current thread is not an async worker thread owned by a Folo runtime.
+pub fn wakeup_signal() -> (WakeupSender, WakeupFuture) {
+    let (operation, completion_port) = current_async_agent::with_io(|io| {
+        (
+            io.new_operation(PinnedBuffer::from_boxed_slice(Box::new([0]))),
+            io.completion_port_handle(),
+        )
+    });
+
+    let mut overlapped_ptr: *mut OVERLAPPED = ptr::null_mut();
+
+    // SAFETY: We are required to pass the OVERLAPPED pointer to the OS. We hand it over to the
+    // sender, which always posts it to the completion port exactly once (when signaled or dropped).
+    let inner = unsafe {
+        operation.begin(|_, overlapped, _| {
+            overlapped_ptr = overlapped;
+
+            // There is no native operation to start - we just report that completion is pending.
+            Err(io::Error::Windows(ERROR_IO_PENDING.into()))
+        })
+    };
+
+    (
+        WakeupSender {
+            completion_port,
+            // SAFETY: The pointer is only used to identify the operation to the completion port,
+            // we never dereference it on the sender side.
+            overlapped: Some(unsafe { ThreadSafe::new(overlapped_ptr) }),
+        },
+        WakeupFuture { inner },
+    )
+}
+
+/// The thread-safe half of a wakeup signal created by `wakeup_signal()`.
+#[derive(Debug)]
+pub struct WakeupSender {
+    completion_port: CompletionPortHandle,
+
+    // Consumed when the completion packet is posted.
+    overlapped: Option<ThreadSafe<*mut OVERLAPPED>>,
+}
+
+impl WakeupSender {
+    /// Completes the wakeup future on the async worker thread that created the signal. This is a
+    /// non-blocking operation.
+    pub fn wake(mut self) {
+        self.post(SIGNALED_BYTES);
+    }
+
+    fn post(&mut self, bytes_transferred: u32) {
+        let Some(overlapped) = self.overlapped.take() else {
+            return;
+        };
+
+        // SAFETY: We keep the completion port alive via Arc, so the handle must be valid. The
+        // OVERLAPPED pointer belongs to an operation that is waiting for exactly this packet.
+        unsafe {
+            // We ignore the result from this because it does not really matter - if anything goes
+            // wrong, probably the entire app is going away anyway.
+            _ = PostQueuedCompletionStatus(
+                ***self.completion_port,
+                bytes_transferred,
+                DEFAULT_COMPLETION_KEY,
+                Some(overlapped.into_inner()),
+            );
+        }
+    }
+}
+
+impl Drop for WakeupSender {
+    fn drop(&mut self) {
+        // The operation must always be completed, otherwise its resources would never be released.
+        self.post(ABANDONED_BYTES);
+    }
+}
+
+/// The single-threaded half of a wakeup signal created by `wakeup_signal()`. Completes when the
+/// sender is signaled, or with an error if the sender is dropped without signaling.
+#[pin_project]
+#[derive(Debug)]
+pub struct WakeupFuture {
+    #[pin]
+    inner: OperationResultFuture,
+}
+
+impl Future for WakeupFuture {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        match this.inner.poll(cx) {
+            Poll::Ready(result) => Poll::Ready(match result.into_inner() {
+                Ok(buffer) if buffer.len() == SIGNALED_BYTES as usize => Ok(()),
+                Ok(_) => Err(io::Error::LogicError(
+                    "wakeup sender was dropped without signaling".to_string(),
+                )),
+                Err(e) => Err(e),
+            }),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[negative_impl]
+impl !Send for WakeupFuture {}
+#[negative_impl]
+impl !Sync for WakeupFuture {}
@@ -13,6 +13,7 @@ pub mod rt;
 pub mod sync;
 pub mod util;
 pub mod time;
+mod trace;
 
 #[cfg(feature = "hyper")]
 pub mod hyper;
@@ -1,6 +1,47 @@
+mod accept_backoff;
+mod accept_loop;
+mod accept_socket_pool;
+mod accept_tuner;
+mod backpressure;
+mod buffered_writer;
+mod codec;
+mod conditional_accept;
+mod connection_deadline;
+mod connection_id;
+mod connection_multiplexer;
+mod connection_registry;
+mod framed_connection;
+mod interfaces;
+mod message_server;
+mod prefix_routing;
+mod qos;
+mod server_events;
+mod server_registry;
 mod tcp_connection;
+mod tcp_connection_split;
+mod tcp_info;
 mod tcp_server;
+mod tcp_server_stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub(crate) mod winsock;
 
+pub(crate) use accept_socket_pool::*;
+pub use backpressure::*;
+pub use buffered_writer::*;
+pub use codec::*;
+pub(crate) use connection_deadline::*;
+pub use connection_id::*;
+pub use framed_connection::*;
+pub use message_server::*;
+pub use prefix_routing::{PrefixRoute, MAX_ROUTE_PREFIX_LENGTH};
+pub(crate) use qos::DscpFlow;
+pub use qos::MAX_DSCP;
+pub use server_events::*;
+pub(crate) use server_registry::ServerRegistry;
+pub use server_registry::{ServerInfo, ServerState};
 pub use tcp_connection::*;
+pub use tcp_connection_split::*;
+pub use tcp_info::*;
 pub use tcp_server::*;
+pub use tcp_server_stats::*;
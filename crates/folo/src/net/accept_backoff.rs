@@ -0,0 +1,184 @@
+use crate::trace::{event, Level};
+use crate::{
+    io,
+    net::winsock,
+    time::{Clock, Delay},
+};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+pub(super) const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+pub(super) const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Tracks consecutive failures to create sockets for accepting connections due to resource
+/// exhaustion, pausing the start of new accept operations for exponentially increasing periods so
+/// that a resource shortage does not turn into a busy loop of failing accept attempts.
+///
+/// Shared between the TCP dispatcher and all of its accept operations, on the dispatcher thread.
+#[derive(Debug)]
+pub(super) struct AcceptBackoff {
+    initial: Duration,
+    max: Duration,
+
+    consecutive_failures: Cell<u32>,
+    paused_until: Cell<Option<Instant>>,
+}
+
+impl AcceptBackoff {
+    pub(super) fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            consecutive_failures: Cell::new(0),
+            paused_until: Cell::new(None),
+        }
+    }
+
+    /// Records a resource exhaustion failure and pauses new accept operations, returning the
+    /// length of the pause.
+    pub(super) fn on_resource_exhaustion(&self) -> Duration {
+        let failures = self.consecutive_failures.get().saturating_add(1);
+        self.consecutive_failures.set(failures);
+
+        let delay = self.delay_after(failures);
+        self.paused_until.set(Instant::now().checked_add(delay));
+
+        delay
+    }
+
+    /// Records that a socket was successfully created, resetting the backoff.
+    pub(super) fn on_success(&self) {
+        self.consecutive_failures.set(0);
+    }
+
+    /// Records the outcome of an attempt to create a socket, pausing new accept operations if it
+    /// failed due to resource exhaustion. Other failures do not affect the backoff.
+    pub(super) fn on_socket_created<T>(&self, result: &io::Result<T>) {
+        match result {
+            Ok(_) => self.on_success(),
+            Err(e) if winsock::is_resource_exhaustion(e) => {
+                let pause = self.on_resource_exhaustion();
+
+                event!(
+                    Level::ERROR,
+                    message = "out of resources to create socket - pausing accepting connections",
+                    pause_millis = pause.as_millis() as u64
+                );
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Waits until new accept operations are allowed to start. Completes immediately if there is
+    /// no pause in effect.
+    pub(super) async fn wait(&self) {
+        let Some(paused_until) = self.paused_until.get() else {
+            return;
+        };
+
+        let remaining = paused_until.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return;
+        }
+
+        Delay::with_clock(&Clock::new(), remaining).await;
+    }
+
+    fn delay_after(&self, consecutive_failures: u32) -> Duration {
+        // The first failure waits for the initial delay, every subsequent one doubles it.
+        let exponent = consecutive_failures.saturating_sub(1).min(31);
+
+        self.initial.saturating_mul(1 << exponent).min(self.max)
+    }
+}
+
+impl Default for AcceptBackoff {
+    fn default() -> Self {
+        Self::new(DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::RuntimeBuilder;
+    use std::sync::mpsc;
+    use windows::Win32::Networking::WinSock::{WSAECONNRESET, WSAEMFILE, WSA_ERROR};
+
+    fn winsock_error(detail: WSA_ERROR) -> io::Error {
+        io::Error::Winsock {
+            code: detail.0,
+            detail,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_millis(50));
+
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(10));
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(20));
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(40));
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(50));
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn success_resets_delay() {
+        let backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_secs(1));
+
+        backoff.on_resource_exhaustion();
+        backoff.on_resource_exhaustion();
+        backoff.on_success();
+
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn many_failures_do_not_overflow() {
+        let backoff = AcceptBackoff::new(Duration::from_millis(10), Duration::from_secs(1));
+
+        for _ in 0..100 {
+            backoff.on_resource_exhaustion();
+        }
+
+        assert_eq!(backoff.on_resource_exhaustion(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn injected_socket_exhaustion_pauses_accepting() {
+        let runtime = RuntimeBuilder::new().max_processors(1).build().unwrap();
+        let runtime_clone = runtime.clone();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        runtime.spawn_on_any(move || async move {
+            let backoff = AcceptBackoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+            // Failures unrelated to resources do not pause accepting.
+            backoff.on_socket_created::<()>(&Err(winsock_error(WSAECONNRESET)));
+            let started = Instant::now();
+            backoff.wait().await;
+            let unrelated_pause = started.elapsed();
+
+            backoff.on_socket_created::<()>(&Err(winsock_error(WSAEMFILE)));
+            let started = Instant::now();
+            backoff.wait().await;
+            let exhaustion_pause = started.elapsed();
+
+            result_tx.send((unrelated_pause, exhaustion_pause)).unwrap();
+
+            runtime_clone.stop();
+        });
+
+        let (unrelated_pause, exhaustion_pause) = result_rx.recv().unwrap();
+        runtime.wait();
+
+        assert!(unrelated_pause < Duration::from_millis(100));
+
+        // Timers are not perfectly precise, so we allow for some slack.
+        assert!(exhaustion_pause >= Duration::from_millis(90));
+    }
+}
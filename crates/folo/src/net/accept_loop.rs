@@ -0,0 +1,221 @@
+use futures::{
+    future::{self, Either},
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+/// Keeps a fixed number of accept-like operations in flight for each of a set of sources (e.g.
+/// listen sockets), starting a new operation for a source whenever one of its operations
+/// completes. This is the core of any server that accepts work from the operating system by
+/// keeping operations queued in advance, so that a burst of incoming work does not have to wait
+/// for us to start operations one by one.
+///
+/// The loop does not decide what to do with completed operations or when to stop - the owner
+/// polls it via `next_or()`, racing the operations against its own orders (e.g. a shutdown
+/// command), and dispatches the results. Dropping the loop abandons the operations in flight;
+/// `drain()` can be used to instead wait for them to complete, for example after canceling them.
+#[derive(Debug)]
+pub(crate) struct AcceptLoop<F: Future> {
+    operations: Pin<Box<FuturesUnordered<SourceOperation<F>>>>,
+
+    // The number of operations in flight for each source, indexed by source.
+    in_flight: Vec<usize>,
+
+    per_source: usize,
+}
+
+impl<F: Future> AcceptLoop<F> {
+    /// Creates a loop that keeps `per_source` operations in flight for each of `source_count`
+    /// sources, once filled via `refill()`.
+    pub(crate) fn new(source_count: usize, per_source: usize) -> Self {
+        Self {
+            operations: Box::pin(FuturesUnordered::new()),
+            in_flight: vec![0; source_count],
+            per_source,
+        }
+    }
+
+    /// The number of operations in flight, across all sources.
+    pub(crate) fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Changes the number of operations to keep in flight for each source. Growing takes effect on
+    /// the next `refill()`. Shrinking does not cancel operations already in flight - completed
+    /// operations are just not replaced until each source is back under the new limit.
+    pub(crate) fn set_per_source(&mut self, per_source: usize) {
+        self.per_source = per_source;
+    }
+
+    /// Starts new operations via `start` until every source has the desired number of operations
+    /// in flight. The factory receives the index of the source to start the operation for.
+    pub(crate) fn refill(&mut self, mut start: impl FnMut(usize) -> F) {
+        for (source, in_flight) in self.in_flight.iter_mut().enumerate() {
+            while *in_flight < self.per_source {
+                self.operations.push(SourceOperation {
+                    source,
+                    inner: start(source),
+                });
+
+                *in_flight += 1;
+            }
+        }
+    }
+
+    /// Waits for either the next operation to complete or for `orders` to complete, whichever
+    /// comes first. A completed operation is returned together with the index of its source, and
+    /// is no longer counted as in flight, so the next `refill()` replaces it.
+    ///
+    /// If no operations are in flight (e.g. because the owner stopped refilling the loop), this
+    /// waits only for the orders.
+    pub(crate) async fn next_or<O>(&mut self, orders: O) -> Either<(usize, F::Output), O::Output>
+    where
+        O: Future,
+    {
+        let next_operation = if self.operations.is_empty() {
+            Either::Left(future::pending())
+        } else {
+            Either::Right(self.operations.next())
+        };
+
+        match future::select(next_operation, std::pin::pin!(orders)).await {
+            Either::Left((Some((source, output)), _)) => {
+                self.in_flight[source] -= 1;
+                Either::Left((source, output))
+            }
+            Either::Left((None, _)) => {
+                unreachable!("we only poll the operations if there are some in flight")
+            }
+            Either::Right((orders_output, _)) => Either::Right(orders_output),
+        }
+    }
+
+    /// Waits for all the operations in flight to complete, handing each output to `sink`. No new
+    /// operations are started, though the loop may be refilled afterwards. Typically used after
+    /// canceling the operations, to receive the results of any that completed before the
+    /// cancellation took effect.
+    pub(crate) async fn drain(&mut self, mut sink: impl FnMut(F::Output)) {
+        while let Some((source, output)) = self.operations.next().await {
+            self.in_flight[source] -= 1;
+            sink(output);
+        }
+    }
+}
+
+/// An operation tagged with the index of the source it was started for.
+#[pin_project]
+struct SourceOperation<F> {
+    source: usize,
+
+    #[pin]
+    inner: F,
+}
+
+impl<F: Future> Future for SourceOperation<F> {
+    type Output = (usize, F::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        this.inner.poll(cx).map(|output| (*this.source, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn refill_keeps_operations_in_flight_per_source() {
+        let mut accept_loop = AcceptLoop::new(2, 3);
+        let mut started = Vec::new();
+
+        accept_loop.refill(|source| {
+            started.push(source);
+            future::ready(source * 10)
+        });
+
+        assert_eq!(started, [0, 0, 0, 1, 1, 1]);
+        assert_eq!(accept_loop.len(), 6);
+
+        // Everything is already in flight, so nothing new is started.
+        accept_loop.refill(|_| unreachable!());
+
+        let Either::Left((source, output)) = block_on(accept_loop.next_or(future::pending::<()>()))
+        else {
+            panic!("orders can never complete");
+        };
+
+        assert_eq!(output, source * 10);
+        assert_eq!(accept_loop.len(), 5);
+
+        // Only the completed operation is replaced.
+        let mut restarted = Vec::new();
+        accept_loop.refill(|source| {
+            restarted.push(source);
+            future::ready(source * 10)
+        });
+
+        assert_eq!(restarted, [source]);
+        assert_eq!(accept_loop.len(), 6);
+    }
+
+    #[test]
+    fn shrinking_replaces_completed_operations_only_below_limit() {
+        let mut accept_loop = AcceptLoop::new(1, 3);
+        accept_loop.refill(future::ready);
+
+        accept_loop.set_per_source(1);
+
+        // Two operations are still in flight, which is above the new limit.
+        block_on(accept_loop.next_or(future::pending::<()>()));
+        accept_loop.refill(|_| unreachable!());
+        assert_eq!(accept_loop.len(), 2);
+
+        // Once one is in flight, we are at the limit.
+        block_on(accept_loop.next_or(future::pending::<()>()));
+        accept_loop.refill(|_| unreachable!());
+        assert_eq!(accept_loop.len(), 1);
+
+        accept_loop.set_per_source(2);
+        accept_loop.refill(future::ready);
+        assert_eq!(accept_loop.len(), 2);
+    }
+
+    #[test]
+    fn empty_loop_waits_only_for_orders() {
+        let mut accept_loop = AcceptLoop::<future::Ready<()>>::new(1, 1);
+
+        let result = block_on(accept_loop.next_or(future::ready("stop")));
+
+        assert!(matches!(result, Either::Right("stop")));
+    }
+
+    #[test]
+    fn drain_completes_all_operations() {
+        let mut accept_loop = AcceptLoop::new(3, 2);
+        accept_loop.refill(future::ready);
+
+        let mut drained = Vec::new();
+        block_on(accept_loop.drain(|output| drained.push(output)));
+
+        drained.sort_unstable();
+        assert_eq!(drained, [0, 0, 1, 1, 2, 2]);
+        assert_eq!(accept_loop.len(), 0);
+
+        // A drained loop starts over from scratch.
+        let mut started = 0;
+        accept_loop.refill(|source| {
+            started += 1;
+            future::ready(source)
+        });
+        assert_eq!(started, 6);
+    }
+}
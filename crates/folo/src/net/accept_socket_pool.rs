@@ -0,0 +1,84 @@
+use crate::trace::{event, Level};
+use crate::{
+    io::CompletionPort,
+    net::winsock,
+    rt::{current_runtime, SynchronousTaskType},
+    util::OwnedHandle,
+};
+use std::sync::{Arc, Mutex};
+use windows::Win32::Networking::WinSock::SOCKET;
+
+// Beyond this many idle sockets, closed connections are no longer recycled. This limits how many
+// sockets stay open after a burst of connections has passed.
+const MAX_POOLED_SOCKETS: usize = 4096;
+
+/// Sockets of closed connections that have been disconnected for reuse, ready to be used for
+/// accepting new connections without the cost of creating a new socket.
+///
+/// Connections are closed on any async worker thread, so the pool is thread-safe.
+#[derive(Debug, Default)]
+pub(crate) struct AcceptSocketPool {
+    sockets: Mutex<Vec<OwnedHandle<SOCKET>>>,
+}
+
+impl AcceptSocketPool {
+    /// Takes a socket from the pool, if there is one available.
+    pub(crate) fn take(&self) -> Option<OwnedHandle<SOCKET>> {
+        self.sockets
+            .lock()
+            .expect("pool lock is never poisoned because we never panic while holding it")
+            .pop()
+    }
+
+    /// Disconnects the socket of a closed connection and returns it to the pool. If the socket
+    /// cannot be reused, it is simply closed.
+    ///
+    /// The disconnect is a blocking operation, so it happens on a synchronous worker thread.
+    pub(crate) fn recycle(self: Arc<Self>, socket: Arc<OwnedHandle<SOCKET>>) {
+        _ = current_runtime::with(|runtime| {
+            runtime.spawn_sync(SynchronousTaskType::Syscall, move || {
+                // If someone else still holds the socket (e.g. a shutdown in progress), we cannot
+                // reuse it and it will be closed by whoever drops it last.
+                let Some(socket) = Arc::into_inner(socket) else {
+                    return;
+                };
+
+                if let Err(e) = winsock::disconnect_for_reuse(*socket) {
+                    event!(
+                        Level::DEBUG,
+                        message = "connection socket cannot be reused - closing",
+                        error = e.to_string()
+                    );
+                    return;
+                }
+
+                self.put_disconnected(socket);
+            })
+        });
+    }
+
+    /// Returns a socket that has already been disconnected for reuse (e.g. by a send that
+    /// disconnected the socket when done) to the pool. If the socket cannot be reused, it is
+    /// simply closed.
+    pub(crate) fn put_disconnected(&self, socket: OwnedHandle<SOCKET>) {
+        // The socket is bound to the completion port of the worker that handled the connection.
+        // The next connection may be handled by a different worker, so we need to unbind it.
+        if let Err(e) = CompletionPort::unbind(&*socket) {
+            event!(
+                Level::DEBUG,
+                message = "connection socket cannot be reused - closing",
+                error = e.to_string()
+            );
+            return;
+        }
+
+        let mut sockets = self
+            .sockets
+            .lock()
+            .expect("pool lock is never poisoned because we never panic while holding it");
+
+        if sockets.len() < MAX_POOLED_SOCKETS {
+            sockets.push(socket);
+        }
+    }
+}
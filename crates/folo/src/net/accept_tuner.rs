@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+/// How often the tuner reconsiders the number of accept operations to keep in flight.
+const TUNING_WINDOW: Duration = Duration::from_millis(100);
+
+/// How many accept operations are added after a window in which the server was saturated.
+const ADDITIVE_INCREASE: usize = 16;
+
+/// A window in which fewer than `1 / IDLE_FRACTION` of the target number of accept operations
+/// completed is considered idle.
+const IDLE_FRACTION: usize = 4;
+
+/// Decides how many accept operations a TCP server keeps in flight, growing and shrinking the
+/// number with the rate at which connections arrive. See `TcpServerBuilder::adaptive_accepts()`.
+///
+/// The control algorithm is additive-increase, multiplicative-decrease (AIMD), evaluated once per
+/// tuning window of 100 milliseconds based on the accept operations that completed in the window:
+///
+/// * If at least as many accept operations completed as the target (i.e. every accept operation
+///   was used up within one window) or any connection was reported for backlog pressure, the
+///   server is saturated and the target grows by a fixed step.
+/// * If fewer than a quarter of the target completed, the server is idle and the target is halved
+///   for every window that elapsed since the previous evaluation.
+/// * Otherwise, the target stays as it is.
+///
+/// The target always stays within the configured bounds and starts at the minimum.
+///
+/// The tuner is only informed when accept operations complete, so it cannot act while no
+/// connections arrive. An idle period is instead accounted for all at once when the next
+/// connection arrives. This is also when shrinking would take effect anyway, as the dispatcher
+/// shrinks by not replacing completed accept operations, not by canceling ones in flight.
+#[derive(Debug)]
+pub(crate) struct AcceptTuner {
+    min: usize,
+    max: usize,
+    target: usize,
+
+    window_start: Instant,
+    completions_in_window: usize,
+
+    // The total reported via `ServerStats::backlog_pressure` when the window started.
+    backlog_pressure_at_window_start: u64,
+}
+
+impl AcceptTuner {
+    pub(crate) fn new(min: usize, max: usize, now: Instant, backlog_pressure: u64) -> Self {
+        debug_assert!(min <= max);
+
+        Self {
+            min,
+            max,
+            target: min,
+            window_start: now,
+            completions_in_window: 0,
+            backlog_pressure_at_window_start: backlog_pressure,
+        }
+    }
+
+    /// The number of accept operations to keep in flight, across all listen sockets.
+    pub(crate) fn target(&self) -> usize {
+        self.target
+    }
+
+    /// Records the completion of an accept operation, given the current total of connections
+    /// reported for backlog pressure. Returns the new target if this ended a tuning window and
+    /// the target changed as a result.
+    pub(crate) fn record_completion(
+        &mut self,
+        now: Instant,
+        backlog_pressure: u64,
+    ) -> Option<usize> {
+        self.completions_in_window += 1;
+
+        let elapsed = now.saturating_duration_since(self.window_start);
+
+        if elapsed < TUNING_WINDOW {
+            return None;
+        }
+
+        let previous_target = self.target;
+
+        let saturated = self.completions_in_window >= self.target
+            || backlog_pressure > self.backlog_pressure_at_window_start;
+        let idle = self.completions_in_window < self.target / IDLE_FRACTION;
+
+        if saturated {
+            self.target = (self.target + ADDITIVE_INCREASE).min(self.max);
+        } else if idle {
+            let elapsed_windows = elapsed.as_nanos() / TUNING_WINDOW.as_nanos();
+            let halvings = elapsed_windows.min(usize::BITS as u128 - 1) as u32;
+
+            self.target = (self.target >> halvings).max(self.min);
+        }
+
+        self.window_start = now;
+        self.completions_in_window = 0;
+        self.backlog_pressure_at_window_start = backlog_pressure;
+
+        (self.target != previous_target).then_some(self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_additively_while_saturated() {
+        let start = Instant::now();
+        let mut tuner = AcceptTuner::new(8, 50, start, 0);
+
+        assert_eq!(tuner.target(), 8);
+
+        // Completing the whole target within a window is saturation.
+        for _ in 0..7 {
+            assert_eq!(tuner.record_completion(start, 0), None);
+        }
+
+        assert_eq!(
+            tuner.record_completion(start + TUNING_WINDOW, 0),
+            Some(8 + ADDITIVE_INCREASE)
+        );
+
+        // Backlog pressure is saturation regardless of the number of completions.
+        assert_eq!(
+            tuner.record_completion(start + TUNING_WINDOW * 2, 1),
+            Some(8 + ADDITIVE_INCREASE * 2)
+        );
+
+        // Growth stops at the maximum.
+        assert_eq!(
+            tuner.record_completion(start + TUNING_WINDOW * 3, 2),
+            Some(50)
+        );
+        assert_eq!(tuner.record_completion(start + TUNING_WINDOW * 4, 3), None);
+        assert_eq!(tuner.target(), 50);
+    }
+
+    #[test]
+    fn shrinks_multiplicatively_when_idle() {
+        let start = Instant::now();
+        let mut tuner = AcceptTuner::new(4, 64, start, 0);
+
+        // Get to the maximum via backlog pressure.
+        for window in 1..=4 {
+            tuner.record_completion(start + TUNING_WINDOW * window, window.into());
+        }
+
+        assert_eq!(tuner.target(), 64);
+
+        // One completion in one window is idle, so the target is halved.
+        assert_eq!(
+            tuner.record_completion(start + TUNING_WINDOW * 5, 4),
+            Some(32)
+        );
+
+        // Two idle windows halve it twice.
+        assert_eq!(
+            tuner.record_completion(start + TUNING_WINDOW * 7, 4),
+            Some(8)
+        );
+
+        // A long idle period shrinks it to the minimum.
+        assert_eq!(
+            tuner.record_completion(start + Duration::from_secs(3600), 4),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn moderate_load_keeps_target() {
+        let start = Instant::now();
+        let mut tuner = AcceptTuner::new(16, 64, start, 0);
+
+        // Half the target completing in a window is neither saturated nor idle.
+        for _ in 0..7 {
+            tuner.record_completion(start, 0);
+        }
+
+        assert_eq!(tuner.record_completion(start + TUNING_WINDOW, 0), None);
+        assert_eq!(tuner.target(), 16);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::trace::{event, Level};
+use crate::util::panic_message;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+};
+
+/// Reported to the `on_backpressure` callback of a TCP server when the number of connections being
+/// handled by `on_accept` crosses one of the configured watermarks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// The number of active connections has reached the high watermark - handlers are not keeping
+    /// up with the rate of incoming connections.
+    Engaged { active_connections: u64 },
+
+    /// The number of active connections has dropped back to the low watermark after having
+    /// reached the high watermark.
+    Relieved { active_connections: u64 },
+}
+
+pub(crate) type BackpressureCallback = Arc<dyn Fn(Backpressure) + Send + Sync>;
+
+/// Watches the number of active connections of a TCP server and invokes the backpressure callback
+/// whenever it crosses one of the watermarks. Shared between the TCP dispatcher (which adds active
+/// connections) and the workers handling the connections (which remove them).
+pub(crate) struct BackpressureMonitor {
+    high_watermark: u64,
+    low_watermark: u64,
+
+    // Whether we have reported `Engaged` without a matching `Relieved` yet.
+    engaged: AtomicBool,
+
+    callback: BackpressureCallback,
+}
+
+impl BackpressureMonitor {
+    pub(crate) fn new(
+        high_watermark: u64,
+        low_watermark: u64,
+        callback: BackpressureCallback,
+    ) -> Self {
+        Self {
+            high_watermark,
+            low_watermark,
+            engaged: AtomicBool::new(false),
+            callback,
+        }
+    }
+
+    /// Called after the number of active connections has increased to `active_connections`.
+    pub(crate) fn on_increased(&self, active_connections: u64) {
+        if active_connections >= self.high_watermark
+            && !self.engaged.swap(true, atomic::Ordering::Relaxed)
+        {
+            self.report(Backpressure::Engaged { active_connections });
+        }
+    }
+
+    /// Called after the number of active connections has decreased to `active_connections`.
+    pub(crate) fn on_decreased(&self, active_connections: u64) {
+        if active_connections <= self.low_watermark
+            && self.engaged.swap(false, atomic::Ordering::Relaxed)
+        {
+            self.report(Backpressure::Relieved { active_connections });
+        }
+    }
+
+    fn report(&self, report: Backpressure) {
+        // A panic would otherwise unwind into the dispatcher or into the connection cleanup that
+        // triggered the report.
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| (self.callback)(report))) {
+            event!(
+                Level::ERROR,
+                message = "backpressure callback panicked",
+                panic = panic_message(payload.as_ref())
+            );
+        }
+    }
+}
+
+impl std::fmt::Debug for BackpressureMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackpressureMonitor")
+            .field("high_watermark", &self.high_watermark)
+            .field("low_watermark", &self.low_watermark)
+            .field("engaged", &self.engaged)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn monitor(high: u64, low: u64) -> (BackpressureMonitor, Arc<Mutex<Vec<Backpressure>>>) {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = Arc::clone(&reports);
+
+        let monitor = BackpressureMonitor::new(
+            high,
+            low,
+            Arc::new(move |report| reports_clone.lock().unwrap().push(report)),
+        );
+
+        (monitor, reports)
+    }
+
+    #[test]
+    fn reports_with_hysteresis() {
+        let (monitor, reports) = monitor(10, 5);
+
+        monitor.on_increased(9);
+        assert!(reports.lock().unwrap().is_empty());
+
+        monitor.on_increased(10);
+        monitor.on_increased(11);
+
+        // Dropping below the high watermark is not enough - we need to reach the low watermark.
+        monitor.on_decreased(9);
+        monitor.on_decreased(6);
+        monitor.on_increased(10);
+        monitor.on_decreased(5);
+        monitor.on_decreased(4);
+
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![
+                Backpressure::Engaged {
+                    active_connections: 10
+                },
+                Backpressure::Relieved {
+                    active_connections: 5
+                },
+            ]
+        );
+    }
+}
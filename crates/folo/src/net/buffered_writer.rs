@@ -0,0 +1,110 @@
+use crate::trace::{event, Level};
+use crate::{
+    io::{self, OperationResultExt, PinnedBuffer},
+    net::TcpConnection,
+};
+use negative_impl::negative_impl;
+
+/// Coalesces many small writes to a connection into fewer, larger sends. Obtain one via
+/// `TcpConnection::buffered_writer()`.
+///
+/// Data is collected into a pooled buffer and only sent when the buffer fills up or when `flush()`
+/// is called. This saves a send operation (and the completion that comes with it) for every small
+/// piece of data, which adds up for protocols that build messages from many small parts (e.g. HTTP
+/// response headers).
+///
+/// You must call `flush()` once you are done writing - the writer cannot send data when dropped, as
+/// that requires waiting for the send to complete. Data that was not flushed is lost, which is
+/// reported as an error by `TcpConnection::shutdown()`.
+#[derive(Debug)]
+pub struct BufferedWriter<'a> {
+    connection: &'a mut TcpConnection,
+
+    // The active region of the buffer is the data waiting to be sent. Only None while a send is in
+    // progress (and after a send has failed, until the next write).
+    buffer: Option<PinnedBuffer>,
+}
+
+impl<'a> BufferedWriter<'a> {
+    pub(super) fn new(connection: &'a mut TcpConnection) -> Self {
+        Self {
+            connection,
+            buffer: None,
+        }
+    }
+
+    /// Appends data to the buffer, sending the buffered data to the peer whenever the buffer fills
+    /// up. Completes once all the data is either in the buffer or has been sent.
+    pub async fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| empty_buffer(self.connection));
+
+            let buffered = buffer.len();
+            let count = data.len().min(buffer.capacity() - buffered);
+
+            buffer.set_len(buffered + count);
+            buffer.as_mut_slice()[buffered..].copy_from_slice(&data[..count]);
+            data = &data[count..];
+
+            if buffer.len() == buffer.capacity() {
+                self.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends all the buffered data to the peer.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        let Some(buffer) = self.buffer.take() else {
+            return Ok(());
+        };
+
+        if buffer.is_empty() {
+            self.buffer = Some(buffer);
+            return Ok(());
+        }
+
+        let mut buffer = self.connection.send(buffer).await.into_inner()?;
+
+        // We keep the buffer for the next round of writes.
+        buffer.set_len(0);
+        self.buffer = Some(buffer);
+
+        Ok(())
+    }
+
+    /// The number of bytes waiting to be sent.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.as_ref().map_or(0, PinnedBuffer::len)
+    }
+}
+
+impl Drop for BufferedWriter<'_> {
+    fn drop(&mut self) {
+        let unflushed = self.buffered_len();
+
+        if unflushed != 0 {
+            self.connection.mark_unflushed_data_lost();
+
+            event!(
+                Level::ERROR,
+                message = "buffered writer dropped without flushing - buffered data was lost",
+                unflushed
+            );
+        }
+    }
+}
+
+#[negative_impl]
+impl !Send for BufferedWriter<'_> {}
+#[negative_impl]
+impl !Sync for BufferedWriter<'_> {}
+
+fn empty_buffer(connection: &TcpConnection) -> PinnedBuffer {
+    let mut buffer = connection.new_buffer();
+    buffer.set_len(0);
+    buffer
+}
@@ -0,0 +1,282 @@
+use crate::io::{self, ReadBuffer};
+
+/// Translates between the bytes of a connection and the frames (messages) of a protocol.
+///
+/// A codec instance is used with a single connection, so it may keep state between calls (e.g. how
+/// far it has already scanned for a frame delimiter).
+pub trait Codec {
+    /// The frame type produced by decoding and consumed by encoding.
+    type Frame;
+
+    /// Decodes the next frame from the start of the received data, consuming the bytes that make
+    /// up the frame. Returns `None` if the buffer does not yet contain a complete frame, in which
+    /// case more data needs to be received before trying again.
+    ///
+    /// Returns an error if the data is not valid for the protocol. The connection cannot be used
+    /// for this protocol after that.
+    fn decode(&mut self, buffer: &mut ReadBuffer) -> io::Result<Option<Self::Frame>>;
+
+    /// Encodes a frame, appending its bytes to the output.
+    fn encode(&mut self, frame: Self::Frame, output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// The default maximum frame length of `LengthDelimitedCodec`.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Frames data by prefixing each frame with its length, as a 32-bit big-endian integer.
+///
+/// Frames longer than the maximum frame length are rejected when decoding, to protect against
+/// peers that would otherwise make us buffer arbitrary amounts of data.
+#[derive(Clone, Debug)]
+pub struct LengthDelimitedCodec {
+    max_frame_length: usize,
+}
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+impl LengthDelimitedCodec {
+    pub fn new() -> Self {
+        Self {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Sets the maximum length of a frame (excluding the length prefix). Defaults to
+    /// `DEFAULT_MAX_FRAME_LENGTH`.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Codec for LengthDelimitedCodec {
+    type Frame = Vec<u8>;
+
+    fn decode(&mut self, buffer: &mut ReadBuffer) -> io::Result<Option<Vec<u8>>> {
+        let Some(prefix) = buffer.filled().get(..LENGTH_PREFIX_SIZE) else {
+            return Ok(None);
+        };
+
+        let length = u32::from_be_bytes(prefix.try_into().expect("slice has the prefix size"));
+        let length = length as usize;
+
+        if length > self.max_frame_length {
+            return Err(invalid_data(format!(
+                "frame of {length} bytes exceeds the maximum frame length of {} bytes",
+                self.max_frame_length
+            )));
+        }
+
+        let Some(frame) = buffer
+            .filled()
+            .get(LENGTH_PREFIX_SIZE..LENGTH_PREFIX_SIZE + length)
+        else {
+            return Ok(None);
+        };
+
+        let frame = frame.to_vec();
+        buffer.consume(LENGTH_PREFIX_SIZE + length);
+
+        Ok(Some(frame))
+    }
+
+    fn encode(&mut self, frame: Vec<u8>, output: &mut Vec<u8>) -> io::Result<()> {
+        let length = u32::try_from(frame.len())
+            .ok()
+            .filter(|length| *length as usize <= self.max_frame_length)
+            .ok_or_else(|| {
+                invalid_data(format!(
+                    "frame of {} bytes exceeds the maximum frame length of {} bytes",
+                    frame.len(),
+                    self.max_frame_length
+                ))
+            })?;
+
+        output.extend_from_slice(&length.to_be_bytes());
+        output.extend_from_slice(&frame);
+
+        Ok(())
+    }
+}
+
+/// The default maximum line length of `LinesCodec`.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Frames text as lines terminated by `\n`. A `\r` before the `\n` is also removed when decoding,
+/// so lines terminated by `\r\n` are understood. Encoding terminates lines with `\n` only.
+///
+/// Lines longer than the maximum line length are rejected when decoding, to protect against peers
+/// that would otherwise make us buffer arbitrary amounts of data.
+#[derive(Clone, Debug)]
+pub struct LinesCodec {
+    max_line_length: usize,
+
+    // How far into the received data we have already searched for the end of the line, so we do
+    // not search the same bytes again after receiving more data.
+    searched: usize,
+}
+
+impl LinesCodec {
+    pub fn new() -> Self {
+        Self {
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            searched: 0,
+        }
+    }
+
+    /// Sets the maximum length of a line (excluding the terminator). Defaults to
+    /// `DEFAULT_MAX_LINE_LENGTH`.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Codec for LinesCodec {
+    type Frame = String;
+
+    fn decode(&mut self, buffer: &mut ReadBuffer) -> io::Result<Option<String>> {
+        let filled = buffer.filled();
+
+        let Some(offset) = filled[self.searched..].iter().position(|b| *b == b'\n') else {
+            self.searched = filled.len();
+
+            if filled.len() > self.max_line_length + 1 {
+                return Err(invalid_data(format!(
+                    "line exceeds the maximum line length of {} bytes",
+                    self.max_line_length
+                )));
+            }
+
+            return Ok(None);
+        };
+
+        let terminator = self.searched + offset;
+        self.searched = 0;
+
+        let line = &filled[..terminator];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line.len() > self.max_line_length {
+            return Err(invalid_data(format!(
+                "line of {} bytes exceeds the maximum line length of {} bytes",
+                line.len(),
+                self.max_line_length
+            )));
+        }
+
+        let line = String::from_utf8(line.to_vec())
+            .map_err(|e| invalid_data(format!("line is not valid UTF-8: {e}")))?;
+
+        buffer.consume(terminator + 1);
+
+        Ok(Some(line))
+    }
+
+    fn encode(&mut self, frame: String, output: &mut Vec<u8>) -> io::Result<()> {
+        if frame.contains('\n') {
+            return Err(invalid_data(
+                "line to encode must not contain a line terminator".to_string(),
+            ));
+        }
+
+        output.extend_from_slice(frame.as_bytes());
+        output.push(b'\n');
+
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::StdIo(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_delimited_round_trip() {
+        let mut codec = LengthDelimitedCodec::new();
+
+        let mut encoded = Vec::new();
+        codec.encode(b"hello".to_vec(), &mut encoded).unwrap();
+        codec.encode(Vec::new(), &mut encoded).unwrap();
+
+        let mut buffer = ReadBuffer::new();
+
+        // Not enough data for even the length prefix.
+        buffer.append(&encoded[..2]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        // Length prefix but only part of the frame.
+        buffer.append(&encoded[2..6]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.append(&encoded[6..]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Vec::new()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let mut codec = LinesCodec::new();
+
+        let mut buffer = ReadBuffer::new();
+        buffer.append(b"first\r\nsec");
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some("first".to_string())
+        );
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.append(b"ond\n");
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap(),
+            Some("second".to_string())
+        );
+        assert!(buffer.is_empty());
+
+        let mut encoded = Vec::new();
+        codec.encode("third".to_string(), &mut encoded).unwrap();
+        assert_eq!(encoded, b"third\n");
+        assert!(codec.encode("a\nb".to_string(), &mut encoded).is_err());
+    }
+
+    #[test]
+    fn lines_rejects_long_lines() {
+        let mut codec = LinesCodec::new().max_line_length(3);
+
+        let mut buffer = ReadBuffer::new();
+        buffer.append(b"abcdef");
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn length_delimited_rejects_oversized_frames() {
+        let mut codec = LengthDelimitedCodec::new().max_frame_length(3);
+
+        assert!(codec.encode(b"hello".to_vec(), &mut Vec::new()).is_err());
+
+        let mut buffer = ReadBuffer::new();
+        buffer.append(&5u32.to_be_bytes());
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+}
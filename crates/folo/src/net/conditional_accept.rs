@@ -0,0 +1,212 @@
+use crate::io::{self, wait_for_object};
+use crate::net::winsock;
+use crate::trace::{event, Level};
+use crate::util::OwnedHandle;
+use std::{
+    mem,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+use windows::Win32::{
+    Foundation::HANDLE,
+    Networking::WinSock::{
+        ioctlsocket, ntohs, setsockopt, WSAAccept, WSAEventSelect, WSAGetLastError, AF_INET,
+        CF_ACCEPT, CF_REJECT, FD_ACCEPT, FIONBIO, QOS, SOCKADDR, SOCKADDR_IN, SOCKET, SOCKET_ERROR,
+        SOL_SOCKET, SO_CONDITIONAL_ACCEPT, WSABUF, WSAECONNREFUSED, WSAEVENT, WSAEWOULDBLOCK,
+    },
+    System::Threading::{CreateEventW, ResetEvent},
+};
+
+/// Decides whether to accept a connection from the given peer address.
+pub(crate) type AcceptFilter = Arc<dyn Fn(SocketAddr) -> bool + Send + Sync>;
+
+/// Makes the application decide whether to accept incoming connections on a listen socket, instead
+/// of the protocol stack accepting them automatically. Must be called before `listen()`.
+pub(crate) fn enable(listen_socket: SOCKET) -> io::Result<()> {
+    let enabled: u32 = 1;
+
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    winsock::to_io_result(unsafe {
+        setsockopt(
+            listen_socket,
+            SOL_SOCKET,
+            SO_CONDITIONAL_ACCEPT,
+            Some(&enabled.to_ne_bytes()),
+        )
+    })
+}
+
+/// Whether an error returned by `ConditionalAcceptor::accept()` means that the accept filter
+/// rejected the connection.
+pub(crate) fn is_rejected(error: &io::Error) -> bool {
+    matches!(error, io::Error::Winsock { detail, .. } if *detail == WSAECONNREFUSED)
+}
+
+/// Accepts connections from a listen socket with conditional accept enabled, consulting an accept
+/// filter before each connection is established.
+///
+/// Conditional accept is not available via `AcceptEx()`, so this uses `WSAAccept()` in non-blocking
+/// mode instead, waiting for connection requests via an event that the socket signals.
+pub(crate) struct ConditionalAcceptor {
+    listen_socket: Arc<OwnedHandle<SOCKET>>,
+    filter: AcceptFilter,
+
+    // Signaled by the listen socket when a connection request is waiting.
+    event: OwnedHandle<HANDLE>,
+}
+
+impl ConditionalAcceptor {
+    pub(crate) fn new(
+        listen_socket: Arc<OwnedHandle<SOCKET>>,
+        filter: AcceptFilter,
+    ) -> io::Result<Self> {
+        // SAFETY: We wrap it in OwnedHandle, ensuring it is released when dropped. Events are safe
+        // to close from any thread.
+        let event = unsafe { OwnedHandle::new(CreateEventW(None, true, false, None)?) };
+
+        // This also switches the listen socket to non-blocking mode.
+        // SAFETY: Both handles are valid. We detach the event when dropped.
+        winsock::to_io_result(unsafe {
+            WSAEventSelect(
+                **listen_socket,
+                WSAEVENT(event.0 as isize),
+                FD_ACCEPT as i32,
+            )
+        })?;
+
+        Ok(Self {
+            listen_socket,
+            filter,
+            event,
+        })
+    }
+
+    /// Waits for the next connection request that passes the filter. Requests rejected by the filter
+    /// are reported as an error for which `is_rejected()` returns true.
+    ///
+    /// The accepted socket inherits the non-blocking mode of the listen socket, which must be undone
+    /// via `restore_defaults()` before the socket is used.
+    pub(crate) async fn accept(&self) -> io::Result<(OwnedHandle<SOCKET>, SocketAddrV4)> {
+        loop {
+            // We reset the event before checking for connection requests, so a request that arrives
+            // right after the check still wakes us up.
+            // SAFETY: The event is kept alive by self.
+            unsafe { ResetEvent(*self.event)? };
+
+            let mut peer_addr = SOCKADDR_IN::default();
+            let mut peer_addr_len = mem::size_of::<SOCKADDR_IN>() as i32;
+
+            // SAFETY: The pointer and length describe a valid SOCKADDR_IN. The callback data points
+            // to our filter, which outlives the call. We take ownership of the returned socket.
+            let result = unsafe {
+                WSAAccept(
+                    **self.listen_socket,
+                    Some(&mut peer_addr as *mut _ as *mut SOCKADDR),
+                    Some(&mut peer_addr_len as *mut _),
+                    Some(accept_condition),
+                    &self.filter as *const AcceptFilter as usize,
+                )
+            };
+
+            match result {
+                Ok(socket) => {
+                    // SAFETY: The socket was just created for us and nobody else owns it.
+                    let socket = unsafe { OwnedHandle::new(socket) };
+
+                    return Ok((socket, to_socket_addr_v4(&peer_addr)));
+                }
+                Err(_) => {
+                    // SAFETY: Nothing unsafe here, just an FFI call.
+                    let detail = unsafe { WSAGetLastError() };
+
+                    if detail != WSAEWOULDBLOCK {
+                        return Err(io::Error::Winsock {
+                            code: SOCKET_ERROR,
+                            detail,
+                        });
+                    }
+                }
+            }
+
+            // No connection request waiting - wait until one arrives.
+            // SAFETY: The event is kept alive by self, which outlives the future.
+            unsafe { wait_for_object(*self.event).await? };
+        }
+    }
+}
+
+impl Drop for ConditionalAcceptor {
+    fn drop(&mut self) {
+        // SAFETY: The listen socket is still valid, as we hold a reference to it. This detaches the
+        // event, so the socket no longer refers to it after the event is closed.
+        unsafe {
+            // If this fails, the socket is broken anyway.
+            _ = WSAEventSelect(**self.listen_socket, WSAEVENT::default(), 0);
+        }
+    }
+}
+
+/// Returns a socket accepted by `ConditionalAcceptor` to blocking mode without event notifications,
+/// which it inherited from the listen socket.
+pub(crate) fn restore_defaults(socket: SOCKET) -> io::Result<()> {
+    let mut non_blocking: u32 = 0;
+
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    unsafe {
+        winsock::to_io_result(WSAEventSelect(socket, WSAEVENT::default(), 0))?;
+        winsock::to_io_result(ioctlsocket(socket, FIONBIO, &mut non_blocking))
+    }
+}
+
+// Called by WSAAccept() with the address of the peer, before the connection is established.
+unsafe extern "system" fn accept_condition(
+    caller_id: *mut WSABUF,
+    _caller_data: *mut WSABUF,
+    _sqos: *mut QOS,
+    _gqos: *mut QOS,
+    _callee_id: *mut WSABUF,
+    _callee_data: *mut WSABUF,
+    _group: *mut u32,
+    callback_data: usize,
+) -> i32 {
+    // SAFETY: The callback data is the filter given by ConditionalAcceptor::accept(), which is
+    // alive for the duration of the WSAAccept() call that invokes us.
+    let filter = unsafe { &*(callback_data as *const AcceptFilter) };
+
+    // SAFETY: The caller ID is provided by the operating system and holds the peer address.
+    let Some(peer_addr) = (unsafe { caller_id.as_ref() })
+        .filter(|caller_id| caller_id.len as usize >= mem::size_of::<SOCKADDR_IN>())
+        // SAFETY: We checked that the buffer is big enough for a SOCKADDR_IN.
+        .map(|caller_id| unsafe { &*(caller_id.buf.0 as *const SOCKADDR_IN) })
+        .filter(|peer_addr| peer_addr.sin_family == AF_INET)
+    else {
+        // We only listen on IPv4, so this is not expected to happen.
+        return CF_REJECT as i32;
+    };
+
+    // We must not unwind into the operating system, so a panicking filter rejects the connection.
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        filter(to_socket_addr_v4(peer_addr).into())
+    })) {
+        Ok(true) => CF_ACCEPT as i32,
+        Ok(false) => CF_REJECT as i32,
+        Err(_) => {
+            event!(
+                Level::ERROR,
+                "accept filter panicked - rejecting connection"
+            );
+            CF_REJECT as i32
+        }
+    }
+}
+
+fn to_socket_addr_v4(addr: &SOCKADDR_IN) -> SocketAddrV4 {
+    // SAFETY: Reading the union is fine - all variants are the same address as plain bytes.
+    let ip = unsafe { addr.sin_addr.S_un.S_addr };
+
+    // SAFETY: Nothing unsafe here, just an FFI call.
+    let port = unsafe { ntohs(addr.sin_port) };
+
+    SocketAddrV4::new(Ipv4Addr::from(u32::from_be(ip)), port)
+}
@@ -0,0 +1,108 @@
+use crate::io::{self, IoPrimitive};
+use std::{
+    ffi::c_void,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    time::Duration,
+};
+use windows::Win32::{
+    Foundation::{BOOLEAN, HANDLE, INVALID_HANDLE_VALUE},
+    Networking::WinSock::SOCKET,
+    System::{
+        Threading::{CreateTimerQueueTimer, DeleteTimerQueueTimer, WT_EXECUTEONLYONCE},
+        IO::CancelIoEx,
+    },
+};
+
+/// Cancels all I/O operations on a socket once a deadline passes, flagging them as timed out.
+///
+/// The timer is serviced by the Windows thread pool, so the deadline is enforced even if the owning
+/// async worker thread is busy. Dropping the timer disarms it.
+#[derive(Debug)]
+pub(crate) struct DeadlineTimer {
+    timer: HANDLE,
+
+    // Boxed context that we share with the timer callback. We reclaim the box once the timer has
+    // been deleted.
+    context: *mut DeadlineContext,
+}
+
+#[derive(Debug)]
+struct DeadlineContext {
+    socket: SOCKET,
+    expired: Arc<AtomicBool>,
+}
+
+impl DeadlineTimer {
+    /// Arms a timer that sets the `expired` flag and cancels all I/O operations on the socket after
+    /// the given duration has passed.
+    ///
+    /// # Safety
+    ///
+    /// The socket must remain valid until the timer is dropped.
+    pub(crate) unsafe fn new(
+        socket: SOCKET,
+        due_in: Duration,
+        expired: Arc<AtomicBool>,
+    ) -> io::Result<Self> {
+        let context = Box::into_raw(Box::new(DeadlineContext { socket, expired }));
+        let mut timer = HANDLE::default();
+
+        // Round up, so we never fire before the deadline. The maximum value would mean "never".
+        let due_millis = u32::try_from(due_in.as_micros().div_ceil(1000))
+            .unwrap_or(u32::MAX)
+            .min(u32::MAX - 1);
+
+        // SAFETY: The context remains valid until we delete the timer, which waits for any
+        // in-progress callback to complete before returning.
+        let result = unsafe {
+            CreateTimerQueueTimer(
+                &mut timer,
+                None,
+                Some(deadline_callback),
+                Some(context as *const c_void),
+                due_millis,
+                0,
+                WT_EXECUTEONLYONCE,
+            )
+        };
+
+        if let Err(e) = result {
+            // SAFETY: The timer was never created, so we are the only owner of the context.
+            drop(unsafe { Box::from_raw(context) });
+            return Err(e.into());
+        }
+
+        Ok(Self { timer, context })
+    }
+}
+
+impl Drop for DeadlineTimer {
+    fn drop(&mut self) {
+        // SAFETY: INVALID_HANDLE_VALUE makes this block until any in-progress callback has
+        // completed, after which the callback can no longer access the context.
+        unsafe {
+            // There is nothing meaningful we can do if this fails, so we ignore the result.
+            _ = DeleteTimerQueueTimer(None, self.timer, INVALID_HANDLE_VALUE);
+        }
+
+        // SAFETY: The timer is deleted, so we are the only owner of the context.
+        drop(unsafe { Box::from_raw(self.context) });
+    }
+}
+
+unsafe extern "system" fn deadline_callback(context: *mut c_void, _timer_fired: BOOLEAN) {
+    // SAFETY: The timer keeps the context alive until this callback has returned.
+    let context = unsafe { &*(context as *const DeadlineContext) };
+
+    // The flag must be set before canceling, so the canceled operations see it when they complete.
+    context.expired.store(true, atomic::Ordering::Release);
+
+    // SAFETY: The owner of the timer guarantees the socket remains valid while the timer exists.
+    unsafe {
+        // This fails if there was nothing to cancel, which is fine.
+        _ = CancelIoEx(HANDLE::from(IoPrimitive::from(context.socket)), None);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{self, AtomicU64},
+};
+
+/// Uniquely identifies a connection within the process, for the entire lifetime of the connection.
+/// Suitable for correlating log entries and traces that relate to the same connection.
+///
+/// IDs are generated on the worker thread that takes ownership of the connection, from a counter
+/// owned by that thread, so generating them involves no cross-thread synchronization.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(u64);
+
+// The upper bits identify the thread that generated the ID, the lower bits are a sequence number
+// incremented for every ID generated on that thread.
+const SEQUENCE_BITS: u32 = 48;
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+
+impl ConnectionId {
+    /// Generates a new ID, unique within the process.
+    pub(crate) fn next() -> Self {
+        let worker = WORKER.with(|x| *x);
+        let sequence = NEXT_SEQUENCE.get();
+
+        // 2^48 connections on a single thread is not something we expect to ever see.
+        assert!(
+            sequence <= SEQUENCE_MASK,
+            "connection ID sequence exhausted on the current thread"
+        );
+
+        NEXT_SEQUENCE.set(sequence + 1);
+
+        Self((worker << SEQUENCE_BITS) | sequence)
+    }
+
+    /// The raw numeric value of the ID.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    fn worker(&self) -> u64 {
+        self.0 >> SEQUENCE_BITS
+    }
+
+    fn sequence(&self) -> u64 {
+        self.0 & SEQUENCE_MASK
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.worker(), self.sequence())
+    }
+}
+
+impl fmt::Debug for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConnectionId({})", self)
+    }
+}
+
+// Every thread that generates IDs is assigned a distinct value for the upper bits on first use.
+static NEXT_WORKER: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static WORKER: u64 = NEXT_WORKER.fetch_add(1, atomic::Ordering::Relaxed);
+    static NEXT_SEQUENCE: Cell<u64> = const { Cell::new(0) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, thread};
+
+    #[test]
+    fn unique_across_threads() {
+        let ids = (0..4)
+            .map(|_| thread::spawn(|| (0..100).map(|_| ConnectionId::next()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|x| x.join().unwrap())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(ids.len(), 400);
+    }
+
+    #[test]
+    fn monotonic_per_thread() {
+        let first = ConnectionId::next();
+        let second = ConnectionId::next();
+
+        assert_eq!(first.worker(), second.worker());
+        assert_eq!(first.sequence() + 1, second.sequence());
+        assert!(first < second);
+    }
+
+    #[test]
+    fn display() {
+        let id = ConnectionId((3 << SEQUENCE_BITS) | 1027);
+
+        assert_eq!(id.to_string(), "3-1027");
+        assert_eq!(format!("{:?}", id), "ConnectionId(3-1027)");
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{rt::current_runtime, util::PinnedSlabChain};
+use futures::{
+    channel::mpsc,
+    future::{self, LocalBoxFuture},
+    task::AtomicWaker,
+    FutureExt, StreamExt,
+};
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    task::{self, Poll, Wake, Waker},
+};
+
+/// Creates the handler of a connection on the worker that drives it.
+pub(crate) type ConnectionHandlerFn = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()> + Send>;
+
+/// Hands over connection handlers to the multiplexer of one async worker.
+pub(crate) type MultiplexerSender = mpsc::UnboundedSender<ConnectionHandlerFn>;
+
+/// Starts a connection multiplexer on every async worker of the current runtime. Each multiplexer
+/// drives the handlers it receives until they complete and stops once its sender has been dropped
+/// and all its handlers have completed.
+pub(crate) fn start_on_all_workers() -> Vec<MultiplexerSender> {
+    current_runtime::with(|runtime| {
+        (0..runtime.async_worker_count())
+            .map(|worker_index| {
+                let (handlers_tx, handlers_rx) = mpsc::unbounded();
+
+                // The multiplexer stops on its own, so there is nothing to wait for.
+                _ = runtime.spawn_on_worker(worker_index, move || async move {
+                    let mut multiplexer = ConnectionMultiplexer::new(handlers_rx);
+                    future::poll_fn(|cx| multiplexer.poll(cx)).await
+                });
+
+                handlers_tx
+            })
+            .collect()
+    })
+}
+
+/// Drives the handlers of many connections from a single task, instead of spawning a task for
+/// each connection. The handlers are kept in a slab and only polled when they have been woken up,
+/// so mostly-idle connections cost little more than the memory of their handler.
+struct ConnectionMultiplexer {
+    new_handlers: mpsc::UnboundedReceiver<ConnectionHandlerFn>,
+
+    handlers: PinnedSlabChain<HandlerSlot>,
+
+    // The generation of the handler in each slot of the slab, or zero if the slot is vacant. Wakers
+    // of completed handlers may outlive them, so we use this to ignore wake-ups meant for a previous
+    // occupant of a slot.
+    generations: Vec<u64>,
+    next_generation: u64,
+
+    ready: Arc<ReadyQueue>,
+}
+
+struct HandlerSlot {
+    handler: LocalBoxFuture<'static, ()>,
+    waker: Waker,
+}
+
+impl ConnectionMultiplexer {
+    fn new(new_handlers: mpsc::UnboundedReceiver<ConnectionHandlerFn>) -> Self {
+        Self {
+            new_handlers,
+            handlers: PinnedSlabChain::new(),
+            generations: Vec::new(),
+            next_generation: 1,
+            ready: Arc::new(ReadyQueue::default()),
+        }
+    }
+
+    fn poll(&mut self, cx: &mut task::Context<'_>) -> Poll<()> {
+        self.ready.driver_waker.register(cx.waker());
+
+        let mut accepting = true;
+
+        loop {
+            match self.new_handlers.poll_next_unpin(cx) {
+                Poll::Ready(Some(handler_fn)) => self.insert(handler_fn()),
+                Poll::Ready(None) => {
+                    accepting = false;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // Handlers woken up while we are polling are left for the next round, so a handler that
+        // keeps waking itself up cannot starve the other tasks of the worker.
+        let ready = mem::take(&mut *self.ready.handlers.lock().expect("poisoned lock"));
+
+        for (index, generation) in ready {
+            if self.generations[index] != generation {
+                // Completed already - this is a leftover wake-up.
+                continue;
+            }
+
+            let mut slot = self.handlers.get_mut(index);
+            let slot = &mut *slot;
+            let mut handler_cx = task::Context::from_waker(&slot.waker);
+
+            if slot.handler.poll_unpin(&mut handler_cx).is_ready() {
+                self.handlers.remove(index);
+                self.generations[index] = 0;
+            }
+        }
+
+        if !accepting && self.handlers.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn insert(&mut self, handler: LocalBoxFuture<'static, ()>) {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        let inserter = self.handlers.begin_insert();
+        let index = inserter.index();
+
+        let waker = Waker::from(Arc::new(HandlerWaker {
+            index,
+            generation,
+            ready: Arc::clone(&self.ready),
+        }));
+
+        // Every handler is polled once to get it started.
+        waker.wake_by_ref();
+
+        inserter.insert(HandlerSlot { handler, waker });
+
+        if self.generations.len() <= index {
+            self.generations.resize(index + 1, 0);
+        }
+
+        self.generations[index] = generation;
+    }
+}
+
+/// The handlers that have been woken up and are waiting to be polled by the multiplexer.
+#[derive(Default)]
+struct ReadyQueue {
+    // Slab index and generation of each handler.
+    handlers: Mutex<Vec<(usize, u64)>>,
+
+    driver_waker: AtomicWaker,
+}
+
+/// Wakes up one handler of a multiplexer. Wakers may be used from any thread, even though the
+/// handlers themselves never leave the worker of the multiplexer.
+struct HandlerWaker {
+    index: usize,
+    generation: u64,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Wake for HandlerWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready
+            .handlers
+            .lock()
+            .expect("poisoned lock")
+            .push((self.index, self.generation));
+
+        self.ready.driver_waker.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn drives_handlers_until_completed() {
+        let (handlers_tx, handlers_rx) = mpsc::unbounded();
+        let mut multiplexer = ConnectionMultiplexer::new(handlers_rx);
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+
+        handlers_tx
+            .unbounded_send(Box::new(|| {
+                async {
+                    _ = release_rx.await;
+                }
+                .boxed_local()
+            }))
+            .unwrap();
+        handlers_tx
+            .unbounded_send(Box::new(|| async {}.boxed_local()))
+            .unwrap();
+
+        assert!(multiplexer.poll(&mut cx).is_pending());
+
+        // The second handler completed on its first poll, freeing its slot.
+        assert_eq!(multiplexer.handlers.len(), 1);
+
+        release_tx.send(()).unwrap();
+        drop(handlers_tx);
+
+        assert!(multiplexer.poll(&mut cx).is_ready());
+        assert!(multiplexer.handlers.is_empty());
+    }
+
+    #[test]
+    fn stale_wake_does_not_poll_new_occupant() {
+        let (handlers_tx, handlers_rx) = mpsc::unbounded();
+        let mut multiplexer = ConnectionMultiplexer::new(handlers_rx);
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+
+        let stale_waker = Rc::new(Cell::new(None));
+        let stale_waker_clone = Rc::clone(&stale_waker);
+
+        let first: LocalBoxFuture<'static, ()> = future::poll_fn(move |cx| {
+            stale_waker_clone.set(Some(cx.waker().clone()));
+            Poll::Ready(())
+        })
+        .boxed_local();
+        multiplexer.insert(first);
+        assert!(multiplexer.poll(&mut cx).is_pending());
+
+        let polls = Rc::new(Cell::new(0));
+        let polls_clone = Rc::clone(&polls);
+
+        // Takes over the slot of the first handler.
+        let second: LocalBoxFuture<'static, ()> = future::poll_fn(move |_| {
+            polls_clone.set(polls_clone.get() + 1);
+            Poll::<()>::Pending
+        })
+        .boxed_local();
+        multiplexer.insert(second);
+        assert!(multiplexer.poll(&mut cx).is_pending());
+        assert_eq!(polls.get(), 1);
+
+        stale_waker.take().unwrap().wake();
+        assert!(multiplexer.poll(&mut cx).is_pending());
+        assert_eq!(polls.get(), 1);
+
+        drop(handlers_tx);
+    }
+}
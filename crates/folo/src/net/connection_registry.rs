@@ -0,0 +1,109 @@
+use crate::net::ConnectionId;
+use std::{
+    collections::HashMap,
+    future::{self, Future},
+    sync::{Arc, Mutex},
+};
+
+/// The connections of a server whose `on_accept` handler is still running, so that they can be
+/// closed from outside their handlers via `TcpServerHandle::close_connection()`.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionRegistry {
+    // Sending on the channel tells the connection task to abandon its handler.
+    connections: Mutex<HashMap<ConnectionId, oneshot::Sender<()>>>,
+}
+
+impl ConnectionRegistry {
+    /// Registers a connection until the returned guard is dropped. The returned future completes
+    /// when someone asks for the connection to be closed - it never completes otherwise.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        id: ConnectionId,
+    ) -> (RegisteredConnection, impl Future<Output = ()>) {
+        let (close_tx, close_rx) = oneshot::channel();
+
+        self.connections
+            .lock()
+            .expect("poisoned lock")
+            .insert(id, close_tx);
+
+        let close_requested = async move {
+            // The sender is dropped without sending when the connection is unregistered, which is
+            // not a request to close it.
+            if close_rx.await.is_err() {
+                future::pending::<()>().await;
+            }
+        };
+
+        (
+            RegisteredConnection {
+                registry: Arc::clone(self),
+                id,
+            },
+            close_requested,
+        )
+    }
+
+    /// Asks for a connection to be closed. Returns `false` if there is no such connection.
+    pub(crate) fn close(&self, id: ConnectionId) -> bool {
+        let close_tx = self.connections.lock().expect("poisoned lock").remove(&id);
+
+        // If the send fails, the connection task has already gone away on its own.
+        close_tx.is_some_and(|close_tx| close_tx.send(()).is_ok())
+    }
+}
+
+/// Removes a connection from the registry when dropped.
+#[derive(Debug)]
+pub(crate) struct RegisteredConnection {
+    registry: Arc<ConnectionRegistry>,
+    id: ConnectionId,
+}
+
+impl Drop for RegisteredConnection {
+    fn drop(&mut self) {
+        self.registry
+            .connections
+            .lock()
+            .expect("poisoned lock")
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn close_signals_registered_connection() {
+        let registry = Arc::new(ConnectionRegistry::default());
+        let id = ConnectionId::next();
+
+        let (registered, close_requested) = registry.register(id);
+        let mut close_requested = Box::pin(close_requested);
+        assert!(close_requested.as_mut().now_or_never().is_none());
+
+        assert!(registry.close(id));
+        assert!(close_requested.now_or_never().is_some());
+
+        // It is no longer registered, so it cannot be closed again.
+        assert!(!registry.close(id));
+        drop(registered);
+    }
+
+    #[test]
+    fn unregistered_connection_is_not_closed() {
+        let registry = Arc::new(ConnectionRegistry::default());
+        let id = ConnectionId::next();
+
+        assert!(!registry.close(id));
+
+        let (registered, close_requested) = registry.register(id);
+        drop(registered);
+
+        // Unregistering is not a request to close.
+        assert!(!registry.close(id));
+        assert!(Box::pin(close_requested).now_or_never().is_none());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    io::{self, OperationResultExt, OperationResultFuture, ReadBuffer},
+    net::{Codec, TcpConnection},
+};
+use futures::{Sink, Stream};
+use negative_impl::negative_impl;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+/// A connection that sends and receives frames instead of bytes, using a codec to translate
+/// between the two. Obtain one via `TcpConnection::framed()`.
+///
+/// Received frames are obtained via the `Stream` implementation, which ends when the peer closes
+/// the connection. If the peer closes the connection in the middle of a frame, the stream yields an
+/// `UnexpectedEof` error instead. Frames are sent via the `Sink` implementation - encoded frames are
+/// collected and only sent when a buffer's worth has accumulated or when the sink is flushed.
+///
+/// Closing the sink only flushes it. To gracefully shut down the connection, flush the sink, take
+/// the connection via `into_inner()` and call `TcpConnection::shutdown()`.
+#[derive(Debug)]
+pub struct FramedConnection<C: Codec> {
+    connection: TcpConnection,
+    codec: C,
+
+    // Data received but not yet decoded into frames.
+    received: ReadBuffer,
+    receive_in_progress: Option<OperationResultFuture>,
+
+    // Encoded frames waiting to be sent. Bytes before `sent` have already been sent.
+    unsent: Vec<u8>,
+    sent: usize,
+    send_in_progress: Option<OperationResultFuture>,
+}
+
+impl<C: Codec> FramedConnection<C> {
+    pub(super) fn new(connection: TcpConnection, codec: C) -> Self {
+        Self {
+            connection,
+            codec,
+            received: ReadBuffer::new(),
+            receive_in_progress: None,
+            unsent: Vec::new(),
+            sent: 0,
+            send_in_progress: None,
+        }
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Returns the connection, for example to shut it down gracefully.
+    ///
+    /// Any received data that has not yet been decoded into frames is lost, as are any frames that
+    /// have not yet been flushed. This must not be called while a receive or send is in progress
+    /// (i.e. after a `Pending` result from the stream or a flush), as the data of that operation
+    /// would also be lost.
+    pub fn into_inner(self) -> TcpConnection {
+        self.connection
+    }
+}
+
+impl<C: Codec + Unpin> Stream for FramedConnection<C> {
+    type Item = io::Result<C::Frame>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            match this.codec.decode(&mut this.received) {
+                Ok(Some(frame)) => return Poll::Ready(Some(Ok(frame))),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+
+            let receive = this.receive_in_progress.get_or_insert_with(|| {
+                let buffer = this.connection.new_buffer();
+                this.connection.receive(buffer)
+            });
+
+            let result = match Pin::new(receive).poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            this.receive_in_progress = None;
+
+            let buffer = match result.into_inner() {
+                Ok(buffer) => buffer,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            };
+
+            if buffer.is_empty() {
+                // The peer closed the connection.
+                if this.received.is_empty() {
+                    return Poll::Ready(None);
+                }
+
+                return Poll::Ready(Some(Err(io::Error::StdIo(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed by peer in the middle of a frame",
+                )))));
+            }
+
+            this.received.append(buffer.as_slice());
+        }
+    }
+}
+
+impl<C: Codec + Unpin> Sink<C::Frame> for FramedConnection<C> {
+    type Error = io::Error;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        // We accept more frames until we have at least a buffer's worth of data to send.
+        if self.unsent.len() - self.sent < io::POOL_BUFFER_CAPACITY_BYTES {
+            return Poll::Ready(Ok(()));
+        }
+
+        self.as_mut().poll_flush(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: C::Frame) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        this.codec.encode(frame, &mut this.unsent)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+
+        loop {
+            if let Some(send) = this.send_in_progress.as_mut() {
+                let result = match Pin::new(send).poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => return Poll::Pending,
+                };
+
+                this.send_in_progress = None;
+
+                // The returned buffer has its length set to the number of bytes actually sent. If
+                // that is less than we asked for, the rest is sent by the next operation.
+                match result.into_inner() {
+                    Ok(buffer) if buffer.is_empty() => {
+                        return Poll::Ready(Err(io::Error::StdIo(
+                            std::io::ErrorKind::WriteZero.into(),
+                        )))
+                    }
+                    Ok(buffer) => this.sent += buffer.len(),
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+
+            if this.sent == this.unsent.len() {
+                this.unsent.clear();
+                this.sent = 0;
+                return Poll::Ready(Ok(()));
+            }
+
+            let remaining = &this.unsent[this.sent..];
+
+            let mut buffer = this.connection.new_buffer();
+            let count = remaining.len().min(buffer.capacity());
+            buffer
+                .as_mut_slice_with_len(count)
+                .copy_from_slice(&remaining[..count]);
+
+            this.send_in_progress = Some(this.connection.send(buffer));
+        }
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[negative_impl]
+impl<C: Codec> !Send for FramedConnection<C> {}
+#[negative_impl]
+impl<C: Codec> !Sync for FramedConnection<C> {}
@@ -0,0 +1,94 @@
+use crate::io;
+use std::net::Ipv4Addr;
+use windows::Win32::{
+    Foundation::{ERROR_BUFFER_OVERFLOW, ERROR_SUCCESS, WIN32_ERROR},
+    NetworkManagement::IpHelper::{
+        GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+        GAA_FLAG_SKIP_MULTICAST, IP_ADAPTER_ADDRESSES_LH,
+    },
+    Networking::WinSock::{AF_INET, SOCKADDR_IN},
+};
+
+// The size Microsoft recommends starting with, which is enough for most systems.
+const INITIAL_BUFFER_SIZE: usize = 15 * 1024;
+
+/// Looks up the IPv4 addresses of the network interface with the given name, which is matched
+/// against the friendly name of the interface (e.g. "Ethernet", case-insensitive) and against the
+/// adapter name (a GUID). Returns `None` if there is no such interface.
+///
+/// This is a blocking call, so it should be made on a synchronous worker thread.
+pub(crate) fn ipv4_addresses_of_interface(name: &str) -> io::Result<Option<Vec<Ipv4Addr>>> {
+    // We use u64 elements to satisfy the alignment requirements of the adapter structures.
+    let mut buffer = vec![0_u64; INITIAL_BUFFER_SIZE / 8];
+
+    loop {
+        let mut size = (buffer.len() * 8) as u32;
+
+        // SAFETY: The buffer is suitably aligned and the size tells the function how big it is.
+        let result = WIN32_ERROR(unsafe {
+            GetAdaptersAddresses(
+                AF_INET.0 as u32,
+                GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER,
+                None,
+                Some(buffer.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH),
+                &mut size,
+            )
+        });
+
+        match result {
+            ERROR_SUCCESS => break,
+            // The set of interfaces may change between calls, so we keep trying until it fits.
+            ERROR_BUFFER_OVERFLOW => buffer.resize((size as usize).div_ceil(8), 0),
+            e => return Err(windows::core::Error::from(e).into()),
+        }
+    }
+
+    let name = name.to_lowercase();
+    let mut adapter = buffer.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+
+    // SAFETY: The operating system filled the buffer with a valid linked list of adapters, which
+    // lives as long as the buffer does.
+    while let Some(current) = unsafe { adapter.as_ref() } {
+        adapter = current.Next;
+
+        // SAFETY: The names are valid null-terminated strings, as set up by the operating system.
+        let friendly_name = unsafe { current.FriendlyName.to_string() }.unwrap_or_default();
+        // SAFETY: As above.
+        let adapter_name = unsafe { current.AdapterName.to_string() }.unwrap_or_default();
+
+        if friendly_name.to_lowercase() != name && adapter_name.to_lowercase() != name {
+            continue;
+        }
+
+        let mut addresses = Vec::new();
+        let mut unicast = current.FirstUnicastAddress;
+
+        // SAFETY: Same linked list guarantees as for the adapters.
+        while let Some(current) = unsafe { unicast.as_ref() } {
+            unicast = current.Next;
+
+            let address = current.Address;
+
+            if address.lpSockaddr.is_null()
+                || (address.iSockaddrLength as usize) < size_of::<SOCKADDR_IN>()
+            {
+                continue;
+            }
+
+            // SAFETY: We only asked for IPv4 addresses and checked that the size matches.
+            let address = unsafe { &*(address.lpSockaddr as *const SOCKADDR_IN) };
+
+            if address.sin_family != AF_INET {
+                continue;
+            }
+
+            // SAFETY: Reading the union is fine - all variants are the same address as plain bytes.
+            let ip = unsafe { address.sin_addr.S_un.S_addr };
+            addresses.push(Ipv4Addr::from(u32::from_be(ip)));
+        }
+
+        return Ok(Some(addresses));
+    }
+
+    Ok(None)
+}
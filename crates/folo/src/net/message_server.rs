@@ -0,0 +1,177 @@
+use crate::{
+    io::{self, ReadBuffer},
+    net::{Codec, LengthDelimitedCodec, TcpConnection, TcpServerBuilder, TcpServerHandle},
+};
+use negative_impl::negative_impl;
+use std::{
+    future::Future,
+    num::{NonZeroU16, NonZeroUsize},
+};
+
+/// Builds a TCP server that handles request/response protocols, calling a function for every
+/// request message received and sending back the response message it returns.
+///
+/// The messages are framed by a codec - `LengthDelimitedCodec` by default. The server takes care
+/// of receiving until a complete request has arrived and of sending the complete response, so the
+/// message handler only ever deals with whole messages.
+///
+/// Requests on the same connection are handled one at a time, in the order they were received.
+pub struct MessageServerBuilder<C, M, MF>
+where
+    C: Codec<Frame = Vec<u8>> + Clone + Send + 'static,
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+    codec: C,
+    on_message: Option<M>,
+    port: Option<NonZeroU16>,
+    ephemeral_port: bool,
+    max_connections: Option<NonZeroUsize>,
+}
+
+impl<M, MF> MessageServerBuilder<LengthDelimitedCodec, M, MF>
+where
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+    /// Creates a builder for a server that uses length-delimited framing.
+    pub fn new() -> Self {
+        Self::with_codec(LengthDelimitedCodec::new())
+    }
+}
+
+impl<M, MF> Default for MessageServerBuilder<LengthDelimitedCodec, M, MF>
+where
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, M, MF> MessageServerBuilder<C, M, MF>
+where
+    C: Codec<Frame = Vec<u8>> + Clone + Send + 'static,
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+    /// Creates a builder for a server that uses a custom codec for framing. Every connection gets
+    /// its own clone of the codec.
+    pub fn with_codec(codec: C) -> Self {
+        Self {
+            codec,
+            on_message: None,
+            port: None,
+            ephemeral_port: false,
+            max_connections: None,
+        }
+    }
+
+    pub fn port(mut self, port: NonZeroU16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Lets the operating system choose a free port for the server to listen on. Use
+    /// `TcpServerHandle::local_port()` to find out which port was chosen.
+    pub fn ephemeral_port(mut self) -> Self {
+        self.ephemeral_port = true;
+        self
+    }
+
+    /// Sets the function to call for every request message. The returned future resolves to the
+    /// response message. The function may be called from any async task worker thread and any
+    /// number of times concurrently (for different connections).
+    pub fn on_message(mut self, callback: M) -> Self {
+        self.on_message = Some(callback);
+        self
+    }
+
+    /// Sets the maximum number of connections that may be open at the same time. See
+    /// `TcpServerBuilder::max_connections()`.
+    pub fn max_connections(mut self, max_connections: NonZeroUsize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    pub async fn build(self) -> io::Result<TcpServerHandle> {
+        let on_message = self.on_message.ok_or_else(|| {
+            io::Error::InvalidOptions("on_message callback must be set".to_string())
+        })?;
+        let codec = self.codec;
+
+        let mut builder = TcpServerBuilder::new().on_accept(move |connection| {
+            serve_messages(connection, codec.clone(), on_message.clone())
+        });
+
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+
+        if self.ephemeral_port {
+            builder = builder.ephemeral_port();
+        }
+
+        if let Some(max_connections) = self.max_connections {
+            builder = builder.max_connections(max_connections);
+        }
+
+        builder.build().await
+    }
+}
+
+#[negative_impl]
+impl<C, M, MF> !Send for MessageServerBuilder<C, M, MF>
+where
+    C: Codec<Frame = Vec<u8>> + Clone + Send + 'static,
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+}
+#[negative_impl]
+impl<C, M, MF> !Sync for MessageServerBuilder<C, M, MF>
+where
+    C: Codec<Frame = Vec<u8>> + Clone + Send + 'static,
+    M: Fn(Vec<u8>) -> MF + Clone + Send + 'static,
+    MF: Future<Output = Vec<u8>> + 'static,
+{
+}
+
+/// Runs the receive-decode-handle-encode-send loop for one connection, until the peer closes it.
+async fn serve_messages<C, M, MF>(
+    mut connection: TcpConnection,
+    mut codec: C,
+    on_message: M,
+) -> io::Result<()>
+where
+    C: Codec<Frame = Vec<u8>>,
+    M: Fn(Vec<u8>) -> MF,
+    MF: Future<Output = Vec<u8>>,
+{
+    let mut received = ReadBuffer::new();
+    let mut response_bytes = Vec::new();
+
+    loop {
+        // A single receive may have delivered multiple requests, so we handle all complete ones
+        // before receiving more.
+        while let Some(request) = codec.decode(&mut received)? {
+            let response = (on_message)(request).await;
+
+            response_bytes.clear();
+            codec.encode(response, &mut response_bytes)?;
+            connection.send_large(&response_bytes).await?;
+        }
+
+        if received.receive_into(&mut connection).await? == 0 {
+            if !received.is_empty() {
+                return Err(io::Error::StdIo(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed by peer in the middle of a message",
+                )));
+            }
+
+            return Ok(());
+        }
+    }
+}
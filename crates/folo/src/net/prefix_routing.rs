@@ -0,0 +1,162 @@
+use crate::{
+    io::{self, PinnedBuffer},
+    net::TcpConnection,
+    time::{Clock, Delay},
+};
+use futures::{future::LocalBoxFuture, FutureExt};
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The longest prefix a `PrefixRoute` may match on. Protocols can be told apart by their first
+/// few bytes, so routing never needs to look further than this.
+pub const MAX_ROUTE_PREFIX_LENGTH: usize = 256;
+
+pub(super) const DEFAULT_PREFIX_SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Peeking completes as soon as any data is available, so if the client has only sent part of a
+// prefix, we wait this long before peeking again instead of peeking in a busy loop.
+const PEEK_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+type RouteHandler =
+    Arc<dyn Fn(TcpConnection) -> LocalBoxFuture<'static, io::Result<()>> + Send + Sync>;
+
+/// Routes the connections that start with a specific sequence of bytes to a dedicated handler,
+/// allowing one server to serve multiple protocols on the same port. See
+/// `TcpServerBuilder::route_by_prefix()`.
+#[derive(Clone)]
+pub struct PrefixRoute {
+    prefix: Vec<u8>,
+    handler: RouteHandler,
+}
+
+impl PrefixRoute {
+    /// Creates a route that hands the connections whose first bytes are `prefix` to `handler`.
+    /// The prefix must not be empty or longer than `MAX_ROUTE_PREFIX_LENGTH`.
+    ///
+    /// The handler receives the connection with the prefix still unread, so it sees the entire
+    /// stream, just like `on_accept` would.
+    pub fn new<F, FF>(prefix: impl Into<Vec<u8>>, handler: F) -> Self
+    where
+        F: Fn(TcpConnection) -> FF + Send + Sync + 'static,
+        FF: Future<Output = io::Result<()>> + 'static,
+    {
+        Self {
+            prefix: prefix.into(),
+            handler: Arc::new(move |connection| (handler)(connection).boxed_local()),
+        }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+}
+
+impl fmt::Debug for PrefixRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixRoute")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+/// The outcome of peeking at the first bytes of a connection.
+#[derive(Debug, PartialEq, Eq)]
+enum Match {
+    Route(usize),
+    NeedMoreData,
+    NoRoute,
+}
+
+/// Matches the data received so far against the routes, in order. A route is only skipped once
+/// the data rules it out, so a route listed earlier wins even if its prefix is longer.
+fn match_routes(routes: &[PrefixRoute], data: &[u8]) -> Match {
+    for (index, route) in routes.iter().enumerate() {
+        if data.starts_with(&route.prefix) {
+            return Match::Route(index);
+        }
+
+        if route.prefix.starts_with(data) {
+            return Match::NeedMoreData;
+        }
+    }
+
+    Match::NoRoute
+}
+
+/// Peeks at the first bytes of the connection until they either match one of the routes or rule
+/// out all of them, returning the handler of the matching route. Returns `None` if no route
+/// matches, including if the client does not send enough data before the timeout or closes the
+/// connection before sending a full prefix.
+///
+/// Nothing is consumed from the connection, so whichever handler receives it sees all the data.
+pub(super) async fn select_route(
+    connection: &mut TcpConnection,
+    routes: &[PrefixRoute],
+    timeout: Duration,
+) -> io::Result<Option<RouteHandler>> {
+    let Some(longest_prefix) = routes.iter().map(|x| x.prefix.len()).max() else {
+        return Ok(None);
+    };
+
+    let deadline = Instant::now() + timeout;
+    let clock = Clock::new();
+    let mut buffer = PinnedBuffer::from_pool();
+
+    loop {
+        let now = Instant::now();
+
+        if now >= deadline {
+            return Ok(None);
+        }
+
+        buffer.set_len(longest_prefix);
+
+        buffer = match connection.peek_with_timeout(buffer, deadline - now).await {
+            Ok(buffer) => buffer,
+            Err(e) if e.inner.is_timed_out() => return Ok(None),
+            Err(e) => return Err(e.inner),
+        };
+
+        // The connection was closed before the client sent enough data to be routed.
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+
+        match match_routes(routes, buffer.as_slice()) {
+            Match::Route(index) => return Ok(Some(Arc::clone(&routes[index].handler))),
+            Match::NoRoute => return Ok(None),
+            Match::NeedMoreData => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                Delay::with_clock(&clock, PEEK_RETRY_INTERVAL.min(remaining)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes(prefixes: &[&[u8]]) -> Vec<PrefixRoute> {
+        prefixes
+            .iter()
+            .map(|prefix| PrefixRoute::new(*prefix, |_| async { Ok(()) }))
+            .collect()
+    }
+
+    #[test]
+    fn matches_first_route_in_order() {
+        let routes = routes(&[b"GET ", b"G", b"SSH-"]);
+
+        assert_eq!(match_routes(&routes, b"GET /"), Match::Route(0));
+        assert_eq!(match_routes(&routes, b"GE"), Match::NeedMoreData);
+        assert_eq!(match_routes(&routes, b"GX"), Match::Route(1));
+        assert_eq!(match_routes(&routes, b"SSH-2.0"), Match::Route(2));
+        assert_eq!(match_routes(&routes, b"SS"), Match::NeedMoreData);
+        assert_eq!(match_routes(&routes, b"\x16\x03"), Match::NoRoute);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::io;
+use std::mem;
+use windows::Win32::{
+    Foundation::{ERROR_ACCESS_DENIED, ERROR_NOT_SUPPORTED, HANDLE},
+    NetworkManagement::QoS::{
+        QOSAddSocketToFlow, QOSCloseHandle, QOSCreateHandle, QOSSetFlow, QOSSetOutgoingDSCPValue,
+        QOSTrafficTypeBestEffort, QOS_NON_ADAPTIVE_FLOW, QOS_VERSION,
+    },
+    Networking::WinSock::SOCKET,
+};
+
+/// The largest valid DSCP value - DSCP is a 6-bit field.
+pub const MAX_DSCP: u8 = 63;
+
+/// A Windows QoS flow that marks the outgoing packets of a connected socket with a DSCP value.
+/// Dropping the flow removes the marking.
+#[derive(Debug)]
+pub(crate) struct DscpFlow {
+    qos_handle: HANDLE,
+}
+
+impl DscpFlow {
+    /// Starts marking the outgoing packets of the socket with the given DSCP value.
+    ///
+    /// Returns `None` if Windows does not allow this process to set the DSCP value (setting it
+    /// requires administrative privileges or a matching QoS policy) or does not support it.
+    ///
+    /// # Safety
+    ///
+    /// The socket must be connected and must remain valid until the flow is dropped.
+    pub(crate) unsafe fn new(socket: SOCKET, dscp: u8) -> io::Result<Option<Self>> {
+        if dscp > MAX_DSCP {
+            return Err(io::Error::InvalidOptions(format!(
+                "DSCP value {dscp} is out of range - the maximum is {MAX_DSCP}"
+            )));
+        }
+
+        let version = QOS_VERSION {
+            MajorVersion: 1,
+            MinorVersion: 0,
+        };
+        let mut qos_handle = HANDLE::default();
+
+        // SAFETY: We pass valid pointers, nothing else to worry about.
+        unsafe { QOSCreateHandle(&version, &mut qos_handle) }.ok()?;
+
+        // From here on, dropping the flow closes the QoS handle, which also removes the socket
+        // from any flows created via the handle.
+        let flow = Self { qos_handle };
+
+        let mut flow_id = 0;
+        let dscp_value = dscp as u32;
+
+        // SAFETY: The caller guarantees the socket is valid and connected. Without a destination
+        // address, the flow applies to the peer the socket is connected to.
+        let result = unsafe {
+            QOSAddSocketToFlow(
+                flow.qos_handle,
+                socket,
+                None,
+                QOSTrafficTypeBestEffort,
+                QOS_NON_ADAPTIVE_FLOW,
+                &mut flow_id,
+            )
+            .ok()
+            .and_then(|_| {
+                QOSSetFlow(
+                    flow.qos_handle,
+                    flow_id,
+                    QOSSetOutgoingDSCPValue,
+                    mem::size_of_val(&dscp_value) as u32,
+                    &dscp_value as *const u32 as *const _,
+                    0,
+                    None,
+                )
+                .ok()
+            })
+        };
+
+        match result {
+            Ok(()) => Ok(Some(flow)),
+            Err(e)
+                if [ERROR_ACCESS_DENIED.into(), ERROR_NOT_SUPPORTED.into()].contains(&e.code()) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for DscpFlow {
+    fn drop(&mut self) {
+        // SAFETY: We own the handle. There is nothing meaningful we can do if this fails.
+        _ = unsafe { QOSCloseHandle(self.qos_handle) };
+    }
+}
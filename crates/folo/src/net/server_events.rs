@@ -0,0 +1,293 @@
+use crate::{io, net::ConnectionId};
+use futures::Stream;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc, Mutex,
+    },
+    task::{self, Poll, Waker},
+};
+
+/// A lifecycle event of a TCP server, delivered to subscribers obtained via
+/// `TcpServerHandle::events()`.
+#[derive(Clone, Debug)]
+pub enum ServerEvent {
+    /// A connection was accepted and handed over to the `on_accept` callback.
+    Accepted { id: ConnectionId, peer: SocketAddr },
+
+    /// The `on_accept` callback of a connection has completed (successfully or not).
+    Closed { id: ConnectionId },
+
+    /// The `on_accept` callback of a connection returned an error. Followed by `Closed`.
+    HandlerError {
+        id: ConnectionId,
+        error: Arc<io::Error>,
+    },
+
+    /// The `on_accept` callback of a connection panicked. The connection was closed and the worker
+    /// that ran the callback keeps serving its other connections. Followed by `Closed`.
+    ///
+    /// Only reported if panics unwind. With `panic = "abort"`, the process aborts instead.
+    HandlerPanicked { id: ConnectionId, message: String },
+
+    /// Accepting a connection failed. Connections reset by the peer before they could be accepted
+    /// are not reported, as they are a normal occurrence with impatient clients.
+    AcceptError { error: Arc<io::Error> },
+}
+
+/// How many events each subscriber can fall behind before the oldest ones are discarded.
+pub const SERVER_EVENTS_CAPACITY: usize = 1024;
+
+/// A stream of the lifecycle events of a TCP server, obtained via `TcpServerHandle::events()`.
+///
+/// Every subscriber receives every event that happens after it subscribed. The stream is lossy:
+/// the server never waits for subscribers, so if a subscriber falls more than
+/// `SERVER_EVENTS_CAPACITY` events behind, the oldest undelivered events are discarded (see
+/// `missed()`). This keeps a slow subscriber from slowing down the server.
+///
+/// The stream ends once the server has stopped and all its connections have been closed.
+#[derive(Debug)]
+pub struct ServerEvents {
+    channel: Arc<EventChannel>,
+    queue: Arc<Mutex<EventQueue>>,
+}
+
+impl ServerEvents {
+    /// The number of events this subscriber has missed because it fell too far behind.
+    pub fn missed(&self) -> u64 {
+        self.queue.lock().expect("poisoned lock").missed
+    }
+}
+
+impl Stream for ServerEvents {
+    type Item = ServerEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.lock().expect("poisoned lock");
+
+        if let Some(event) = queue.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for ServerEvents {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().expect("poisoned lock");
+
+        state
+            .queues
+            .retain(|queue| !Arc::ptr_eq(queue, &self.queue));
+
+        self.channel
+            .subscriber_count
+            .store(state.queues.len(), atomic::Ordering::Relaxed);
+    }
+}
+
+/// The sending side of the server events, shared by the dispatcher and all its connections. The
+/// subscribers' streams end when this is dropped.
+#[derive(Debug)]
+pub(crate) struct ServerEventSender {
+    channel: Arc<EventChannel>,
+}
+
+impl ServerEventSender {
+    pub(crate) fn new() -> Self {
+        Self {
+            channel: Arc::new(EventChannel::default()),
+        }
+    }
+
+    /// Publishes an event to all current subscribers. The event is only created if there is
+    /// someone to receive it, so there is no cost when nobody is subscribed.
+    pub(crate) fn send(&self, event: impl FnOnce() -> ServerEvent) {
+        if self
+            .channel
+            .subscriber_count
+            .load(atomic::Ordering::Relaxed)
+            == 0
+        {
+            return;
+        }
+
+        let event = event();
+
+        // Waking a subscriber may run arbitrary code, so we only do it once the locks are released.
+        let wakers: Vec<_> = {
+            let state = self.channel.state.lock().expect("poisoned lock");
+
+            state
+                .queues
+                .iter()
+                .filter_map(|queue| {
+                    let mut queue = queue.lock().expect("poisoned lock");
+
+                    if queue.events.len() == SERVER_EVENTS_CAPACITY {
+                        queue.events.pop_front();
+                        queue.missed += 1;
+                    }
+
+                    queue.events.push_back(event.clone());
+                    queue.waker.take()
+                })
+                .collect()
+        };
+
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Creates a handle that can be used to subscribe to the events after the sender has been
+    /// given away.
+    pub(crate) fn subscriptions(&self) -> ServerEventSubscriptions {
+        ServerEventSubscriptions {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl Drop for ServerEventSender {
+    fn drop(&mut self) {
+        let wakers: Vec<_> = {
+            let mut state = self.channel.state.lock().expect("poisoned lock");
+            state.closed = true;
+
+            state
+                .queues
+                .iter()
+                .filter_map(|queue| {
+                    let mut queue = queue.lock().expect("poisoned lock");
+                    queue.closed = true;
+                    queue.waker.take()
+                })
+                .collect()
+        };
+
+        // Same as when sending, we wake the subscribers only after releasing the locks.
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Creates new subscribers for the events of a server.
+#[derive(Debug)]
+pub(crate) struct ServerEventSubscriptions {
+    channel: Arc<EventChannel>,
+}
+
+impl ServerEventSubscriptions {
+    pub(crate) fn subscribe(&self) -> ServerEvents {
+        let mut state = self.channel.state.lock().expect("poisoned lock");
+
+        let queue = Arc::new(Mutex::new(EventQueue {
+            // If the server is already gone, there will never be any events.
+            closed: state.closed,
+            ..Default::default()
+        }));
+
+        state.queues.push(Arc::clone(&queue));
+
+        self.channel
+            .subscriber_count
+            .store(state.queues.len(), atomic::Ordering::Relaxed);
+
+        ServerEvents {
+            channel: Arc::clone(&self.channel),
+            queue,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct EventChannel {
+    state: Mutex<ChannelState>,
+
+    // Mirrors the number of queues, so senders can skip creating events when nobody is listening
+    // without taking the lock.
+    subscriber_count: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    queues: Vec<Arc<Mutex<EventQueue>>>,
+
+    // Set when the sender is dropped.
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct EventQueue {
+    events: VecDeque<ServerEvent>,
+    missed: u64,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, StreamExt};
+
+    fn accept_error() -> ServerEvent {
+        ServerEvent::AcceptError {
+            error: Arc::new(io::Error::LogicError("test".to_string())),
+        }
+    }
+
+    #[test]
+    fn every_subscriber_receives_events_until_closed() {
+        let sender = ServerEventSender::new();
+        let subscriptions = sender.subscriptions();
+
+        let first = subscriptions.subscribe();
+        let second = subscriptions.subscribe();
+
+        sender.send(accept_error);
+        drop(sender);
+
+        for events in [first, second] {
+            let events = block_on(events.collect::<Vec<_>>());
+            assert_eq!(events.len(), 1);
+            assert!(matches!(events[0], ServerEvent::AcceptError { .. }));
+        }
+
+        // Subscribing after the sender is gone yields an empty stream.
+        assert!(block_on(subscriptions.subscribe().next()).is_none());
+    }
+
+    #[test]
+    fn slow_subscriber_misses_oldest_events() {
+        let sender = ServerEventSender::new();
+        let events = sender.subscriptions().subscribe();
+
+        for _ in 0..SERVER_EVENTS_CAPACITY + 5 {
+            sender.send(accept_error);
+        }
+
+        assert_eq!(events.missed(), 5);
+
+        drop(sender);
+        assert_eq!(block_on(events.count()), SERVER_EVENTS_CAPACITY);
+    }
+
+    #[test]
+    fn events_are_not_created_without_subscribers() {
+        let sender = ServerEventSender::new();
+
+        sender.send(|| panic!("event created without subscribers"));
+
+        let events = sender.subscriptions().subscribe();
+        drop(events);
+
+        sender.send(|| panic!("event created without subscribers"));
+    }
+}
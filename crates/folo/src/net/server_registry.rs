@@ -0,0 +1,139 @@
+use crate::net::ServerCounters;
+use std::sync::{
+    atomic::{self, AtomicBool},
+    Arc, Mutex,
+};
+
+/// Describes a TCP server that was registered with its runtime via
+/// `TcpServerBuilder::register_with_runtime()`, as listed by `RuntimeClient::servers()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// The port the server is listening on.
+    pub local_port: u16,
+
+    pub state: ServerState,
+
+    /// The number of connections whose handler is running.
+    pub active_connections: u64,
+}
+
+/// Whether a registered TCP server is accepting new connections. Servers are removed from the
+/// registry once they stop, so a stopped server is never listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerState {
+    Accepting,
+
+    /// Paused via `TcpServerHandle::pause()`.
+    Paused,
+}
+
+/// The TCP servers of a runtime that opted into registration, in the order they started.
+#[derive(Debug, Default)]
+pub(crate) struct ServerRegistry {
+    servers: Mutex<Vec<Arc<RegisteredServer>>>,
+}
+
+#[derive(Debug)]
+struct RegisteredServer {
+    local_port: u16,
+    counters: Arc<ServerCounters>,
+    paused: AtomicBool,
+}
+
+impl ServerRegistry {
+    /// Registers a server until the returned registration is dropped.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        local_port: u16,
+        counters: Arc<ServerCounters>,
+    ) -> ServerRegistration {
+        let server = Arc::new(RegisteredServer {
+            local_port,
+            counters,
+            paused: AtomicBool::new(false),
+        });
+
+        self.servers
+            .lock()
+            .expect("poisoned lock")
+            .push(Arc::clone(&server));
+
+        ServerRegistration {
+            registry: Arc::clone(self),
+            server,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<ServerInfo> {
+        self.servers
+            .lock()
+            .expect("poisoned lock")
+            .iter()
+            .map(|server| ServerInfo {
+                local_port: server.local_port,
+                state: if server.paused.load(atomic::Ordering::Relaxed) {
+                    ServerState::Paused
+                } else {
+                    ServerState::Accepting
+                },
+                active_connections: server
+                    .counters
+                    .connections_active
+                    .load(atomic::Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// Keeps a server listed in the registry of its runtime, removing it when dropped.
+#[derive(Debug)]
+pub(crate) struct ServerRegistration {
+    registry: Arc<ServerRegistry>,
+    server: Arc<RegisteredServer>,
+}
+
+impl ServerRegistration {
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.server.paused.store(paused, atomic::Ordering::Relaxed);
+    }
+}
+
+impl Drop for ServerRegistration {
+    fn drop(&mut self) {
+        self.registry
+            .servers
+            .lock()
+            .expect("poisoned lock")
+            .retain(|server| !Arc::ptr_eq(server, &self.server));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_registered_servers_until_dropped() {
+        let registry = Arc::new(ServerRegistry::default());
+
+        let first = registry.register(1000, Arc::new(ServerCounters::default()));
+        let second = registry.register(2000, Arc::new(ServerCounters::default()));
+        second.set_paused(true);
+
+        let servers = registry.snapshot();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].local_port, 1000);
+        assert_eq!(servers[0].state, ServerState::Accepting);
+        assert_eq!(servers[1].local_port, 2000);
+        assert_eq!(servers[1].state, ServerState::Paused);
+
+        drop(first);
+
+        let servers = registry.snapshot();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].local_port, 2000);
+
+        drop(second);
+        assert!(registry.snapshot().is_empty());
+    }
+}
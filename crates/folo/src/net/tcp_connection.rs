@@ -1,94 +1,1051 @@
-use std::sync::Arc;
+use std::{
+    any::{Any, TypeId},
+    cell::Cell,
+    collections::HashMap,
+    future::Future,
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
-    io::{self, OperationResultExt, OperationResultFuture, PinnedBuffer},
-    net::winsock,
-    rt::{current_async_agent, current_runtime, SynchronousTaskType},
+    io::{
+        self, CompletionPort, OperationResultExt, OperationResultFuture, PinnedBuffer,
+        VectoredOperationResultFuture,
+    },
+    net::{
+        winsock, AcceptSocketPool, BufferedWriter, Codec, ConnectionId, DeadlineTimer, DscpFlow,
+        FramedConnection, ReadHalf, ServerCounters, TcpInfo, WriteHalf,
+    },
+    rt::{current_async_agent, current_runtime, spawn_on_worker, SynchronousTaskType},
+    trace::{event, Level},
     util::OwnedHandle,
 };
 use negative_impl::negative_impl;
 use windows::{
     core::PSTR,
-    Win32::Networking::WinSock::{WSARecv, WSASend, WSASendDisconnect, SOCKET, WSABUF},
+    Win32::Networking::WinSock::{
+        WSARecv, WSASend, WSASendDisconnect, MSG_PEEK, SOCKET, TF_DISCONNECT, TF_REUSE_SOCKET,
+        TP_ELEMENT_MEMORY, TRANSMIT_PACKETS_ELEMENT, TRANSMIT_PACKETS_ELEMENT_0, WSABUF,
+    },
 };
 
+/// The result of `TcpConnection::receive_with_deadline()`.
+#[derive(Debug)]
+pub struct DeadlineReceive {
+    /// The received data, with the active region set to the bytes received. If the connection was
+    /// closed or no data arrived before the deadline, the length is 0.
+    pub buffer: PinnedBuffer,
+
+    /// Whether the deadline passed before the receive completed. Any data in the buffer arrived
+    /// before the deadline.
+    pub timed_out: bool,
+}
+
+/// How a connection sizes the buffers it allocates for its own reads and writes, such as those of
+/// `send_large()`, the buffered writer and framed connections. See
+/// `TcpConnection::set_transfer_mode()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Buffers are taken from the thread-local buffer pool. Pooled buffers are cheap to obtain and
+    /// their memory is shared by all connections on the thread but their size is fixed, so large
+    /// payloads take many I/O operations.
+    #[default]
+    Pooled,
+
+    /// Buffers of `buffer_size` bytes are allocated for each operation, so large payloads take
+    /// fewer I/O operations.
+    ///
+    /// The memory is not shared with other connections: every in-flight operation on the
+    /// connection holds its own `buffer_size` bytes until it completes, and a buffered writer or
+    /// framed connection keeps a buffer of that size for as long as it exists. With many
+    /// concurrent connections in bulk mode, memory usage grows as `connections * buffer_size`, so
+    /// only switch to bulk mode for payloads that are known to be large.
+    Bulk { buffer_size: NonZeroUsize },
+}
+
 #[derive(Debug)]
 pub struct TcpConnection {
     // This is an Arc because some operations (e.g. shutdown) involve synchronous logic and
-    // therefore we must share the socket between multiple threads.
-    pub(super) socket: Arc<OwnedHandle<SOCKET>>,
+    // therefore we must share the socket between multiple threads. Only None during drop.
+    socket: Option<Arc<OwnedHandle<SOCKET>>>,
+
+    id: ConnectionId,
+
+    // Activity counters of the server that accepted the connection, if any.
+    counters: Option<Arc<ServerCounters>>,
+
+    // If set, the socket is returned to this pool for reuse when the connection is dropped.
+    socket_pool: Option<Arc<AcceptSocketPool>>,
+
+    // Armed while a deadline is set and has not yet passed. Must be dropped before the socket.
+    deadline: Option<DeadlineTimer>,
+
+    // Set once the current deadline has passed. Every deadline gets a fresh flag, so operations
+    // that timed out under a previous deadline keep reporting a timeout.
+    deadline_expired: Arc<AtomicBool>,
+
+    // Set while the deadline is the handshake deadline applied by the server, until the handler
+    // calls `mark_established()`. If the deadline passes while this is set, the connection is reset.
+    handshake_pending: bool,
+
+    // Set while a receive (or peek) operation is in flight, until the operating system is done
+    // with it - even if the future of the operation has been dropped. See `receive()`.
+    receive_in_flight: Rc<Cell<bool>>,
+
+    // Set while a send operation is in flight, in the same way as `receive_in_flight`. See `send()`.
+    send_in_flight: Rc<Cell<bool>>,
+
+    // Set if a buffered writer was dropped with data that was never sent. Reported by `shutdown()`.
+    unflushed_data_lost: bool,
+
+    // Present while outgoing packets are marked with a DSCP value. Must be dropped before the socket.
+    dscp_flow: Option<DscpFlow>,
+
+    transfer_mode: TransferMode,
+
+    // Values attached via `set_extension()`, keyed by their type. An empty map does not allocate,
+    // so connections that do not use extensions do not pay for it.
+    extensions: HashMap<TypeId, Box<dyn Any>>,
 }
 
 impl TcpConnection {
+    /// Wraps a freshly accepted connection socket, binding it to the I/O driver of the current
+    /// async worker thread. From this point on, the connection can only be used on this thread.
+    ///
+    /// If a socket pool is provided, the socket is recycled into the pool when the connection is
+    /// dropped, instead of being closed.
+    pub(super) fn from_accepted_socket(
+        socket: OwnedHandle<SOCKET>,
+        counters: Arc<ServerCounters>,
+        socket_pool: Option<Arc<AcceptSocketPool>>,
+    ) -> Self {
+        let mut connection = Self::from_socket(socket, Some(counters));
+        connection.socket_pool = socket_pool;
+        connection
+    }
+
+    /// Wraps a socket that was connected to a peer by the current process, binding it to the I/O
+    /// driver of the current async worker thread.
+    pub(super) fn from_connected_socket(socket: OwnedHandle<SOCKET>) -> Self {
+        Self::from_socket(socket, None)
+    }
+
+    fn from_socket(socket: OwnedHandle<SOCKET>, counters: Option<Arc<ServerCounters>>) -> Self {
+        current_async_agent::with_io(|io| io.bind_io_primitive(&*socket).unwrap());
+
+        Self {
+            socket: Some(Arc::new(socket)),
+            id: ConnectionId::next(),
+            counters,
+            socket_pool: None,
+            deadline: None,
+            deadline_expired: Arc::new(AtomicBool::new(false)),
+            handshake_pending: false,
+            receive_in_flight: Rc::new(Cell::new(false)),
+            send_in_flight: Rc::new(Cell::new(false)),
+            unflushed_data_lost: false,
+            dscp_flow: None,
+            transfer_mode: TransferMode::Pooled,
+            extensions: HashMap::new(),
+        }
+    }
+
+    fn socket(&self) -> &Arc<OwnedHandle<SOCKET>> {
+        self.socket
+            .as_ref()
+            .expect("socket is only removed when the connection is dropped")
+    }
+
+    /// The process-unique ID of the connection, stable for the lifetime of the connection.
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Sets how the connection sizes the buffers it allocates for subsequent reads and writes.
+    /// Operations already in progress keep the buffers they started with.
+    ///
+    /// Switch to `TransferMode::Bulk` before a known-large payload (e.g. an upload or a file that
+    /// cannot be sent via `send_file()`) and back to `TransferMode::Pooled` afterwards, to keep
+    /// small control messages on the cheap pooled buffers. See `TransferMode` for the memory
+    /// tradeoff.
+    pub fn set_transfer_mode(&mut self, mode: TransferMode) {
+        self.transfer_mode = mode;
+    }
+
+    pub fn transfer_mode(&self) -> TransferMode {
+        self.transfer_mode
+    }
+
+    /// Returns a new buffer sized according to the transfer mode of the connection,
+    /// for passing to `receive()` or `send()`. The active region covers the entire capacity.
+    pub fn new_buffer(&self) -> PinnedBuffer {
+        match self.transfer_mode {
+            TransferMode::Pooled => PinnedBuffer::from_pool(),
+            TransferMode::Bulk { buffer_size } => {
+                PinnedBuffer::from_boxed_slice(vec![0; buffer_size.get()].into_boxed_slice())
+            }
+        }
+    }
+
+    /// Attaches a value to the connection, replacing and returning any value of the same type that
+    /// was attached before. This allows layered code (e.g. authentication, rate limiting, tracing)
+    /// to keep per-connection state on the connection itself, without wrapping it.
+    ///
+    /// There is one slot per type, so use a dedicated type (e.g. a newtype) for each kind of value
+    /// to avoid clashing with other layers. The values do not need to be `Send` because the
+    /// connection never leaves its worker thread, except via `migrate_to_worker()` - extensions
+    /// are dropped when the connection is migrated and must be attached again on the target worker.
+    ///
+    /// The values are kept in a hash map keyed by type, so each lookup costs a hash of the
+    /// `TypeId` and a downcast, without any allocation. The map only allocates once the first value
+    /// is attached.
+    pub fn set_extension<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .map(|previous| {
+                *previous
+                    .downcast()
+                    .expect("extensions are keyed by their type, so the type always matches")
+            })
+    }
+
+    /// Returns the value of type `T` attached via `set_extension()`, if any.
+    pub fn extension<T: 'static>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the value of type `T` attached via `set_extension()`, if
+    /// any.
+    pub fn extension_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.extensions
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Detaches and returns the value of type `T` attached via `set_extension()`, if any.
+    pub fn remove_extension<T: 'static>(&mut self) -> Option<T> {
+        self.extensions.remove(&TypeId::of::<T>()).map(|value| {
+            *value
+                .downcast()
+                .expect("extensions are keyed by their type, so the type always matches")
+        })
+    }
+
+    /// Sets a deadline for all I/O operations on the connection, or removes it if `None`.
+    ///
+    /// Once the deadline passes, any operations in progress are canceled and they, as well as any
+    /// operations started afterwards, fail with a timeout error (see `io::Error::is_timed_out()`).
+    /// This is useful for enforcing a time limit on an entire exchange (e.g. a request and its
+    /// response) without applying a timeout to each operation separately.
+    ///
+    /// Setting a new deadline replaces the previous one. Removing or extending the deadline does not
+    /// affect operations that have already timed out - they remain failed. This includes the
+    /// handshake deadline set via `TcpServerBuilder::initial_read_deadline()`, which is lifted by
+    /// setting a deadline just as it is by `mark_established()`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) -> io::Result<()> {
+        self.handshake_pending = false;
+
+        // The old timer is disarmed before we create a new flag, so it cannot expire the new one.
+        self.deadline = None;
+        self.deadline_expired = Arc::new(AtomicBool::new(false));
+
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+
+        // SAFETY: The timer is dropped before the socket, as guaranteed by our Drop impl.
+        self.deadline = Some(unsafe {
+            DeadlineTimer::new(
+                ***self.socket(),
+                deadline.saturating_duration_since(Instant::now()),
+                Arc::clone(&self.deadline_expired),
+            )?
+        });
+
+        Ok(())
+    }
+
+    /// Applies the handshake deadline of `TcpServerBuilder::initial_read_deadline()`.
+    pub(super) fn set_handshake_deadline(&mut self, timeout: Duration) -> io::Result<()> {
+        self.set_deadline(Some(Instant::now() + timeout))?;
+        self.handshake_pending = true;
+
+        Ok(())
+    }
+
+    /// Signals that the connection has moved past its handshake phase, lifting the handshake
+    /// deadline set via `TcpServerBuilder::initial_read_deadline()`. Call this once the client has
+    /// proven itself (e.g. once the TLS handshake completes or the first request has been
+    /// received), after which the connection is only subject to the deadlines the handler sets.
+    ///
+    /// Has no effect if the connection has no handshake deadline. If the handshake deadline has
+    /// already passed, it is too late - the connection remains failed and is reset when dropped.
+    pub fn mark_established(&mut self) {
+        if !self.handshake_pending || self.deadline_expired() {
+            return;
+        }
+
+        // Removing the deadline cannot fail.
+        _ = self.set_deadline(None);
+    }
+
+    /// Requests that a receive only completes once at least `bytes` bytes are available
+    /// (`SO_RCVLOWAT`), which saves wake-ups for protocols with a known minimum frame size.
+    ///
+    /// Windows does not currently support this option for TCP sockets - the call then fails with
+    /// `io::Error::Winsock` carrying `WSAENOPROTOOPT` and the connection is unaffected. Handle that
+    /// error by falling back to reassembling frames from smaller receives (e.g. via `framed()`).
+    pub fn set_recv_lowat(&mut self, bytes: u32) -> io::Result<()> {
+        winsock::set_receive_low_water_mark(***self.socket(), bytes)
+    }
+
+    /// Requests that a send only proceeds once there is room for at least `bytes` bytes in the send
+    /// buffer (`SO_SNDLOWAT`).
+    ///
+    /// Windows does not currently support this option for TCP sockets - the call then fails with
+    /// `io::Error::Winsock` carrying `WSAENOPROTOOPT` and the connection is unaffected.
+    pub fn set_send_lowat(&mut self, bytes: u32) -> io::Result<()> {
+        winsock::set_send_low_water_mark(***self.socket(), bytes)
+    }
+
+    /// Marks outgoing packets of the connection with the given DSCP (Differentiated Services Code
+    /// Point) value, which network equipment may use to prioritize the traffic. Valid values are
+    /// `0..=MAX_DSCP`. Replaces any previously set value.
+    ///
+    /// Windows only allows a process to set the DSCP value if it is running with administrative
+    /// privileges or if a QoS policy configured on the machine permits it. If the DSCP value cannot
+    /// be set for this reason, a warning is logged and the call has no effect. Even when set, the
+    /// marking may be overwritten by QoS policies or by network equipment along the way.
+    pub fn set_dscp(&mut self, value: u8) -> io::Result<()> {
+        self.dscp_flow = None;
+
+        // SAFETY: The flow is dropped before the socket, as guaranteed by our Drop impl.
+        self.dscp_flow = unsafe { DscpFlow::new(***self.socket(), value)? };
+
+        if self.dscp_flow.is_none() {
+            event!(
+                Level::WARN,
+                message = "not permitted to set DSCP value on connection - ignoring",
+                value
+            );
+        }
+
+        Ok(())
+    }
+
+    fn deadline_expired(&self) -> bool {
+        self.deadline_expired.load(atomic::Ordering::Acquire)
+    }
+
+    /// Marks an operation of the given kind as being in flight, failing if another one already is.
+    /// The mark is cleared by the operation once it is released (see
+    /// `Operation::clear_when_released()`).
+    fn claim(&self, in_flight: &Rc<Cell<bool>>, kind: &'static str) -> io::Result<()> {
+        if in_flight.replace(true) {
+            event!(
+                Level::ERROR,
+                message = "rejected concurrent operation on connection",
+                kind,
+                id = self.id.to_string()
+            );
+
+            return Err(io::Error::LogicError(format!(
+                "another {kind} is already in progress on the connection"
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Receives the next buffer of data.
     ///
     /// The buffer will be returned in the result with the active region set to the bytes read, with
     /// a length of 0 if the connection was closed.
     ///
-    /// You should not call this multiple times concurrently because there is no guarantee that the
-    /// continuations will be called in a particular order.
+    /// Only one receive may be in progress at a time, as concurrent receives would compete for the
+    /// incoming data with no guarantee of which one gets which part of it. This applies to every
+    /// operation that receives or peeks data on the connection (including via its read half) and
+    /// lasts until the operating system has completed the operation, even if its future is dropped
+    /// earlier. Starting another receive while one is in progress fails immediately with
+    /// `io::Error::LogicError`, returning the buffer unused.
     pub fn receive(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.receive_shared(buffer)
+    }
+
+    // The `*_shared` variants of the operations exist for the split halves of the connection, which
+    // only have shared access to it. Exclusivity is instead required by the halves themselves.
+
+    /// Receives the next buffer of data like `receive()` but gives up if no data arrives within
+    /// the given timeout, failing with `io::Error::timed_out()`. The buffer is returned via the
+    /// `io::OperationError` so it can be reused.
+    ///
+    /// Unlike `set_deadline()`, this only affects this one operation.
+    pub fn receive_with_timeout(
+        &mut self,
+        buffer: PinnedBuffer,
+        timeout: Duration,
+    ) -> OperationResultFuture {
+        let future = self.receive_core(buffer, 0, Some(timeout));
+
+        match &self.counters {
+            Some(counters) => future.count_bytes_into(Arc::clone(&counters.bytes_received)),
+            None => future,
+        }
+    }
+
+    /// Receives the next buffer of data like `receive()` but stops waiting once the deadline
+    /// passes. Unlike `receive_with_timeout()`, a timeout is not reported as an error - instead,
+    /// whatever data arrived before the deadline is returned, with `timed_out` set to tell the
+    /// caller that the receive was cut short. This suits streaming protocols, for which a partial
+    /// read is still useful progress.
+    ///
+    /// Timeouts caused by the connection deadline (see `set_deadline()`) are reported the same way.
+    /// Other failures are reported as errors, as usual.
+    ///
+    /// # Partial data on cancellation
+    ///
+    /// The timeout is enforced by canceling the receive operation. Windows reports the outcome of a
+    /// canceled operation via the same completion notification as any other outcome, including the
+    /// number of bytes transferred before the cancellation took effect, and the returned buffer's
+    /// `len()` is set to that number. For TCP, a receive completes as soon as any data is
+    /// available, so the cancellation and the arrival of data race each other:
+    ///
+    /// * If data arrives first, the operation completes successfully despite the cancellation and
+    ///   `timed_out` is `false`, even if the deadline has already passed.
+    /// * If the cancellation wins, the operation completes with `STATUS_CANCELLED` and typically
+    ///   zero bytes - any data that arrives afterwards stays queued in the socket for the next
+    ///   receive, so no data is lost either way.
+    pub async fn receive_with_deadline(
+        &mut self,
+        mut buffer: PinnedBuffer,
+        deadline: Instant,
+    ) -> Result<DeadlineReceive, io::OperationError> {
+        let now = Instant::now();
+
+        // An expired deadline would fail the operation before it even starts, leaving the buffer
+        // length as the caller set it, so we report the timeout ourselves.
+        if deadline <= now || self.deadline_expired() {
+            buffer.set_len(0);
+
+            return Ok(DeadlineReceive {
+                buffer,
+                timed_out: true,
+            });
+        }
+
+        match self.receive_with_timeout(buffer, deadline - now).await {
+            Ok(buffer) => Ok(DeadlineReceive {
+                buffer,
+                timed_out: false,
+            }),
+            Err(e) if e.inner.is_timed_out() => Ok(DeadlineReceive {
+                buffer: e.buffer,
+                timed_out: true,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Receives at most `max` bytes of data, like `receive()` but without requesting more than
+    /// that from the operating system. Use this to read a bounded amount (e.g. a fixed-size header)
+    /// without also consuming the data that follows it, which then remains available to the next
+    /// receive.
+    ///
+    /// The limit is additionally capped by the capacity of the buffer after its start. As with
+    /// `receive()`, fewer bytes may be returned if fewer are available.
+    ///
+    /// Fails with `io::Error::LogicError` if `max` is 0, as an empty result would be
+    /// indistinguishable from the connection having been closed by the peer.
+    pub fn receive_up_to(&mut self, mut buffer: PinnedBuffer, max: usize) -> OperationResultFuture {
+        if max == 0 {
+            return OperationResultFuture::from_error(io::OperationError::new(
+                io::Error::LogicError("cannot receive up to 0 bytes".to_string()),
+                buffer,
+            ));
+        }
+
+        buffer.set_len(max.min(buffer.capacity() - buffer.start()));
+        self.receive_shared(buffer)
+    }
+
+    /// Discards up to `max` bytes of received data that has not yet been read, returning the number
+    /// of bytes discarded. Use this to resynchronize a protocol after an error (e.g. to skip to the
+    /// next frame boundary) or to clean up a connection before reusing it.
+    ///
+    /// Only data that is already buffered by the operating system is discarded - this does not
+    /// wait for more data to arrive, so data that arrives later is available to the next receive
+    /// as usual. Returns 0 if no data is buffered or the connection was closed by the peer.
+    pub async fn discard_input(&mut self, max: usize) -> io::Result<usize> {
+        let mut discarded = 0;
+        let mut scratch = PinnedBuffer::from_pool();
+
+        while discarded < max {
+            let available = winsock::bytes_available(***self.socket())?;
+
+            if available == 0 {
+                break;
+            }
+
+            // The data is already buffered, so this completes without waiting.
+            let received = self
+                .receive_up_to(scratch, available.min(max - discarded))
+                .await
+                .into_inner()?;
+
+            if received.is_empty() {
+                break;
+            }
+
+            discarded += received.len();
+            scratch = received.use_all();
+        }
+
+        Ok(discarded)
+    }
+
+    pub(super) fn receive_shared(&self, buffer: PinnedBuffer) -> OperationResultFuture {
+        let future = self.receive_core(buffer, 0, None);
+
+        match &self.counters {
+            Some(counters) => future.count_bytes_into(Arc::clone(&counters.bytes_received)),
+            None => future,
+        }
+    }
+
+    /// Receives the next data into a set of buffers, filling them in order, each up to the length
+    /// of its active region. This allows large amounts of data to be received without a single
+    /// contiguous buffer of that size (e.g. into a set of pooled buffers).
+    ///
+    /// Like `receive()`, this completes as soon as any data is available, so the buffers are not
+    /// necessarily filled - see `VectoredResult::filled()` for the buffers that received data and
+    /// `VectoredResult::bytes_transferred` for the total. If the connection was closed, no data is
+    /// received.
+    ///
+    /// The buffers are given to the operation by value, not by reference, because the operating
+    /// system writes into all of them for as long as the operation is in progress - they must
+    /// stay pinned in place and alive until then, even if the returned future is dropped early.
+    /// Ownership is returned with the result (or with the error) once the operation is complete.
+    ///
+    /// Fails with `io::Error::InvalidOptions` if no buffers are given.
+    pub fn receive_vectored(
+        &mut self,
+        buffers: Vec<PinnedBuffer>,
+    ) -> VectoredOperationResultFuture {
+        let mut buffers = buffers.into_iter();
+
+        let Some(first_buffer) = buffers.next() else {
+            return VectoredOperationResultFuture::from_error(io::VectoredOperationError::new(
+                io::Error::InvalidOptions(
+                    "vectored receive requires at least one buffer".to_string(),
+                ),
+                Vec::new(),
+            ));
+        };
+
+        if let Err(e) = self.claim(&self.receive_in_flight, "receive") {
+            return VectoredOperationResultFuture::from_error(io::VectoredOperationError::new(
+                e,
+                std::iter::once(first_buffer).chain(buffers).collect(),
+            ));
+        }
+
+        let mut operation = current_async_agent::with_io(|io| io.new_operation(first_buffer));
+        operation.set_primitive(***self.socket());
+        operation.clear_when_released(Rc::clone(&self.receive_in_flight));
+
+        let start = |buffers: Vec<&mut [u8]>, overlapped, immediate_bytes_transferred: &mut u32| {
+            if self.deadline_expired() {
+                return Err(io::Error::timed_out());
+            }
+
+            // The array only needs to live for the duration of the call - the operating system
+            // takes what it needs from it before returning.
+            let wsabufs: Vec<WSABUF> = buffers
+                .into_iter()
+                .map(|buffer| WSABUF {
+                    len: buffer.len() as u32,
+                    buf: PSTR::from_raw(buffer.as_mut_ptr()),
+                })
+                .collect();
+
+            let mut flags: u32 = 0;
+
+            // SAFETY: The buffers and OVERLAPPED pointer are valid for the duration of the call,
+            // as guaranteed by the operation.
+            winsock::to_io_result(unsafe {
+                WSARecv(
+                    ***self.socket(),
+                    &wsabufs,
+                    Some(immediate_bytes_transferred as *mut u32),
+                    &mut flags as *mut u32,
+                    Some(overlapped),
+                    None,
+                )
+            })
+        };
+
         // SAFETY: We are required to pass the OVERLAPPED pointer to the completion routine. We do.
-        unsafe {
-            current_async_agent::with_io(|io| io.new_operation(buffer)).begin(
-                |buffer, overlapped, immediate_bytes_transferred| {
-                    let wsabuf = WSABUF {
-                        len: buffer.len() as u32,
-                        buf: PSTR::from_raw(buffer.as_mut_ptr()),
-                    };
-
-                    let wsabufs = [wsabuf];
-                    let mut flags: u32 = 0;
-
-                    winsock::to_io_result(WSARecv(
-                        **self.socket,
-                        &wsabufs,
-                        Some(immediate_bytes_transferred as *mut u32),
-                        &mut flags as *mut u32,
-                        Some(overlapped),
-                        None,
-                    ))
-                },
-            )
+        let future = unsafe { operation.begin_vectored(buffers.collect(), start) };
+
+        let future = future.time_out_if_expired(Arc::clone(&self.deadline_expired));
+
+        match &self.counters {
+            Some(counters) => future
+                .count_bytes_into(Arc::clone(&counters.bytes_received))
+                .into_vectored(),
+            None => future.into_vectored(),
         }
     }
 
+    /// Receives the next buffer of data without removing it from the socket, so the same data will
+    /// be returned again by the next `receive()`. This is useful for inspecting the first bytes of
+    /// a connection (e.g. to detect the protocol in use) before deciding how to handle it.
+    ///
+    /// The buffer will be returned in the result with the active region set to the bytes peeked,
+    /// with a length of 0 if the connection was closed.
+    ///
+    /// Peeking only sees the data that the operating system has already received - it completes as
+    /// soon as any data is available, even if this is fewer bytes than the buffer could hold. If
+    /// you need more data than was returned, you need to peek again after more data has arrived.
+    pub fn peek(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.peek_shared(buffer)
+    }
+
+    pub(super) fn peek_shared(&self, buffer: PinnedBuffer) -> OperationResultFuture {
+        // Peeked data is not counted as received, as it will be counted when actually received.
+        self.receive_core(buffer, MSG_PEEK.0 as u32, None)
+    }
+
+    /// Peeks like `peek()` but gives up if no data arrives within the given timeout, failing with
+    /// `io::Error::timed_out()`.
+    pub(super) fn peek_with_timeout(
+        &mut self,
+        buffer: PinnedBuffer,
+        timeout: Duration,
+    ) -> OperationResultFuture {
+        self.receive_core(buffer, MSG_PEEK.0 as u32, Some(timeout))
+    }
+
+    fn receive_core(
+        &self,
+        buffer: PinnedBuffer,
+        flags: u32,
+        timeout: Option<Duration>,
+    ) -> OperationResultFuture {
+        if let Err(e) = self.claim(&self.receive_in_flight, "receive") {
+            return OperationResultFuture::from_error(io::OperationError::new(e, buffer));
+        }
+
+        let mut operation = current_async_agent::with_io(|io| io.new_operation(buffer));
+        operation.set_primitive(***self.socket());
+        operation.clear_when_released(Rc::clone(&self.receive_in_flight));
+
+        let start = |buffer: &mut [u8], overlapped, immediate_bytes_transferred: &mut u32| {
+            if self.deadline_expired() {
+                return Err(io::Error::timed_out());
+            }
+
+            let wsabuf = WSABUF {
+                len: buffer.len() as u32,
+                buf: PSTR::from_raw(buffer.as_mut_ptr()),
+            };
+
+            let wsabufs = [wsabuf];
+            let mut flags = flags;
+
+            // SAFETY: The buffer and OVERLAPPED pointer are valid for the duration of the call,
+            // as guaranteed by the operation.
+            winsock::to_io_result(unsafe {
+                WSARecv(
+                    ***self.socket(),
+                    &wsabufs,
+                    Some(immediate_bytes_transferred as *mut u32),
+                    &mut flags as *mut u32,
+                    Some(overlapped),
+                    None,
+                )
+            })
+        };
+
+        // SAFETY: We are required to pass the OVERLAPPED pointer to the completion routine. We do.
+        // When timing out, the socket remains valid for as long as the operation is pending, as
+        // closing the socket completes all pending operations on it.
+        let future = unsafe {
+            match timeout {
+                Some(timeout) => operation.begin_with_timeout(***self.socket(), timeout, start),
+                None => operation.begin(start),
+            }
+        };
+
+        future.time_out_if_expired(Arc::clone(&self.deadline_expired))
+    }
+
+    /// Sends all of the data to the peer, regardless of its size. The data is copied into buffers
+    /// sized according to the transfer mode of the connection and sent in as many send operations
+    /// as needed, completing once all of it has been sent.
+    ///
+    /// Ordering is preserved because the send operations are issued one after another, each only
+    /// after the previous one has completed. If a send operation transfers only part of its buffer,
+    /// the remainder is sent by the next operation.
+    pub async fn send_large(&mut self, data: &[u8]) -> io::Result<()> {
+        self.send_large_shared(data).await
+    }
+
+    pub(super) async fn send_large_shared(&self, data: &[u8]) -> io::Result<()> {
+        let mut buffer = self.new_buffer();
+        let mut remaining = data;
+
+        while !remaining.is_empty() {
+            let count = remaining.len().min(buffer.capacity());
+            buffer
+                .as_mut_slice_with_len(count)
+                .copy_from_slice(&remaining[..count]);
+
+            // The returned buffer has its length set to the number of bytes actually sent.
+            let sent = self.send_shared(buffer).await.into_inner()?;
+
+            if sent.is_empty() {
+                return Err(io::Error::StdIo(std::io::ErrorKind::WriteZero.into()));
+            }
+
+            remaining = &remaining[sent.len()..];
+            buffer = sent.use_all();
+        }
+
+        Ok(())
+    }
+
+    /// Returns a writer that coalesces many small writes into fewer sends. The writer must be
+    /// flushed via `BufferedWriter::flush()` before it is dropped, or the buffered data is lost.
+    pub fn buffered_writer(&mut self) -> BufferedWriter<'_> {
+        BufferedWriter::new(self)
+    }
+
     /// Sends a buffer of data to the peer.
     ///
     /// The buffer will be returned in the result to allow reuse.
     ///
-    /// You may call this multiple times concurrently. The buffers will be sent in the order they
-    /// are submitted.
+    /// Only one send may be in progress at a time, in the same way as only one receive may be (see
+    /// `receive()`). This applies to every operation that sends data on the connection (including
+    /// via its write half). Starting another send while one is in progress fails immediately with
+    /// `io::Error::LogicError`, returning the buffer unused.
     pub fn send(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.send_shared(buffer)
+    }
+
+    pub(super) fn send_shared(&self, buffer: PinnedBuffer) -> OperationResultFuture {
+        if let Err(e) = self.claim(&self.send_in_flight, "send") {
+            return OperationResultFuture::from_error(io::OperationError::new(e, buffer));
+        }
+
+        let mut operation = current_async_agent::with_io(|io| io.new_operation(buffer));
+        operation.set_primitive(***self.socket());
+        operation.clear_when_released(Rc::clone(&self.send_in_flight));
+
+        // SAFETY: We are required to pass the OVERLAPPED pointer to the completion routine. We do.
+        let future = unsafe {
+            operation.begin(|buffer, overlapped, immediate_bytes_transferred| {
+                if self.deadline_expired() {
+                    return Err(io::Error::timed_out());
+                }
+
+                let wsabuf = WSABUF {
+                    len: buffer.len() as u32,
+                    buf: PSTR::from_raw(buffer.as_mut_ptr()),
+                };
+
+                let wsabufs = [wsabuf];
+
+                winsock::to_io_result(WSASend(
+                    ***self.socket(),
+                    &wsabufs,
+                    Some(immediate_bytes_transferred as *mut u32),
+                    0,
+                    Some(overlapped),
+                    None,
+                ))
+            })
+        }
+        .time_out_if_expired(Arc::clone(&self.deadline_expired));
+
+        match &self.counters {
+            Some(counters) => future.count_bytes_into(Arc::clone(&counters.bytes_sent)),
+            None => future,
+        }
+    }
+
+    /// Sends a final buffer of data to the peer and gracefully closes the connection, as a single
+    /// overlapped operation. This saves the separate close of a typical request/response exchange
+    /// that ends with the server closing the connection (e.g. HTTP/1.0 or `Connection: close`).
+    ///
+    /// All bytes of the buffer are sent before the connection is closed - the peer receives the data
+    /// followed by the end of the stream (FIN). Unlike `shutdown()`, this does not wait for the peer
+    /// to close its side of the connection. Like `send()`, this fails if another send is still in
+    /// progress.
+    ///
+    /// If the connection was accepted by a server that reuses accept sockets, the socket goes
+    /// straight back into the pool once the operation completes, without a separate disconnect.
+    ///
+    /// The connection is closed even if an error is returned, in which case it is not known how
+    /// much of the data was received by the peer.
+    pub async fn send_and_disconnect(mut self, buffer: PinnedBuffer) -> io::Result<()> {
+        let reuse_socket = self.socket_pool.is_some();
+
+        let flags = if reuse_socket {
+            TF_DISCONNECT | TF_REUSE_SOCKET
+        } else {
+            TF_DISCONNECT
+        };
+
+        let transmit_packets = winsock::transmit_packets_fn(***self.socket())?;
+
+        self.claim(&self.send_in_flight, "send")?;
+
+        let mut operation = current_async_agent::with_io(|io| io.new_operation(buffer));
+        operation.set_primitive(***self.socket());
+        operation.clear_when_released(Rc::clone(&self.send_in_flight));
+
         // SAFETY: We are required to pass the OVERLAPPED pointer to the completion routine. We do.
-        unsafe {
-            current_async_agent::with_io(|io| io.new_operation(buffer)).begin(
-                |buffer, overlapped, immediate_bytes_transferred| {
-                    let wsabuf = WSABUF {
-                        len: buffer.len() as u32,
-                        buf: PSTR::from_raw(buffer.as_mut_ptr()),
-                    };
-
-                    let wsabufs = [wsabuf];
-
-                    winsock::to_io_result(WSASend(
-                        **self.socket,
-                        &wsabufs,
-                        Some(immediate_bytes_transferred as *mut u32),
-                        0,
-                        Some(overlapped),
-                        None,
-                    ))
-                },
+        let future = unsafe {
+            operation.begin(|buffer, overlapped, immediate_bytes_transferred| {
+                if self.deadline_expired() {
+                    return Err(io::Error::timed_out());
+                }
+
+                // The element only needs to be valid for the duration of the call, as Winsock
+                // captures it. The data it points to lives in the operation buffer, which is
+                // kept alive until the operation completes.
+                let element = TRANSMIT_PACKETS_ELEMENT {
+                    dwElFlags: TP_ELEMENT_MEMORY,
+                    cLength: buffer.len() as u32,
+                    Anonymous: TRANSMIT_PACKETS_ELEMENT_0 {
+                        pBuffer: buffer.as_mut_ptr().cast(),
+                    },
+                };
+
+                // TransmitPackets does not report the bytes transferred when it completes
+                // immediately but it either sends everything or fails.
+                *immediate_bytes_transferred = buffer.len() as u32;
+
+                winsock::bool_to_io_result(transmit_packets(
+                    ***self.socket(),
+                    &element,
+                    1,
+                    0,
+                    overlapped,
+                    flags,
+                ))
+            })
+        }
+        .time_out_if_expired(Arc::clone(&self.deadline_expired));
+
+        let future = match &self.counters {
+            Some(counters) => future.count_bytes_into(Arc::clone(&counters.bytes_sent)),
+            None => future,
+        };
+
+        let result = future.await.into_inner();
+
+        // We have already disconnected the socket, so the pool can take it as-is.
+        if let Some(socket_pool) = self.socket_pool.take() {
+            if result.is_ok() {
+                self.deadline = None;
+                self.dscp_flow = None;
+
+                let socket = self
+                    .socket
+                    .take()
+                    .expect("socket is only removed when the connection is dropped");
+
+                if let Some(socket) = Arc::into_inner(socket) {
+                    socket_pool.put_disconnected(socket);
+                }
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Wraps the connection into one that sends and receives frames of a protocol, using the given
+    /// codec to translate between frames and bytes.
+    pub fn framed<C: Codec>(self, codec: C) -> FramedConnection<C> {
+        FramedConnection::new(self, codec)
+    }
+
+    /// Splits the connection into a read half and a write half, which can be used concurrently
+    /// (e.g. by separate tasks) to receive and send data at the same time. The connection is closed
+    /// once both halves have been dropped.
+    ///
+    /// Windows allows overlapped receive and send operations to be in progress on the same socket
+    /// at the same time, so the two halves do not need to coordinate with each other. Each half
+    /// allows only one operation at a time, as described on `receive()` and `send()`.
+    ///
+    /// The halves can be put back together via `ReadHalf::reunite()`.
+    pub fn split(self) -> (ReadHalf, WriteHalf) {
+        let connection = Rc::new(self);
+
+        (
+            ReadHalf::new(Rc::clone(&connection)),
+            WriteHalf::new(connection),
+        )
+    }
+
+    /// Consumes the connection and returns the underlying socket, for handing the connection off to
+    /// code that does not use Folo (e.g. a library performing blocking I/O).
+    ///
+    /// The socket is unbound from the I/O completion port of the current async worker thread, so
+    /// completions of I/O operations on it are no longer delivered to Folo. The socket may then be
+    /// used for blocking I/O or bound to a different I/O completion port. Any deadline set on the
+    /// connection no longer applies and the socket is not returned to the accept socket pool.
+    ///
+    /// There must not be any I/O operations on the connection in progress, as their completions
+    /// would be lost. An error is returned if the socket is still in use elsewhere (e.g. by a
+    /// shutdown that has not finished) or if it cannot be unbound from the completion port. In
+    /// either case, the connection is closed.
+    pub fn into_raw_socket(mut self) -> io::Result<OwnedHandle<SOCKET>> {
+        // The deadline timer and DSCP flow refer to the socket, so they must go first.
+        self.deadline = None;
+        self.dscp_flow = None;
+        self.socket_pool = None;
+
+        let socket = self
+            .socket
+            .take()
+            .expect("socket is only removed when the connection is dropped");
+
+        let socket = Arc::into_inner(socket).ok_or_else(|| {
+            io::Error::LogicError(
+                "cannot release the socket of a connection while it is still in use".to_string(),
             )
+        })?;
+
+        CompletionPort::unbind(&*socket)?;
+
+        Ok(socket)
+    }
+
+    /// Closes the connection abortively, sending a reset (RST) to the peer instead of the graceful
+    /// close (FIN) that happens when the connection is dropped. Use this to immediately get rid of
+    /// a peer that violates the protocol or otherwise misbehaves, without spending any more
+    /// resources on it.
+    ///
+    /// Any data that has been sent but not yet transmitted is discarded, as is any data received
+    /// from the peer that has not been read. The socket is not returned to the accept socket pool.
+    ///
+    /// The connection is closed even if an error is returned, though it may then be closed
+    /// gracefully instead of being reset.
+    pub fn abort(mut self) -> io::Result<()> {
+        // A reset socket cannot be recycled for new connections.
+        self.socket_pool = None;
+
+        winsock::enable_abortive_close(***self.socket())
+    }
+
+    /// Declines to serve the connection, closing it gracefully. Use this when a handler decides
+    /// not to proceed with a connection after accepting it - typically after some async work such
+    /// as consulting a rate limiter or an authorization service. Unlike returning an error from
+    /// the handler, this is not counted as a failure but as a declined connection (see
+    /// `ServerStats::connections_declined`).
+    ///
+    /// Always succeeds, returning `Ok(())` so that a handler can end with
+    /// `return connection.decline();`.
+    pub fn decline(self) -> io::Result<()> {
+        if let Some(counters) = &self.counters {
+            counters
+                .connections_declined
+                .fetch_add(1, atomic::Ordering::Relaxed);
+        }
+
+        event!(
+            Level::DEBUG,
+            message = "connection declined by handler",
+            id = self.id.to_string()
+        );
+
+        // The connection is closed when dropped.
+        Ok(())
+    }
+
+    /// Moves the connection to the async worker thread with the given index and continues handling
+    /// it there, by calling `continuation` with the migrated connection on the target worker. This
+    /// allows a handler to process a connection on the worker that holds related state (e.g. the
+    /// state of the client, once the client has been identified).
+    ///
+    /// A socket can only deliver completions to one I/O completion port, so the socket is unbound
+    /// from the completion port of the current worker and bound to that of the target worker. The
+    /// migration does not wait for in-flight operations - there must not be any I/O operations on
+    /// the connection in progress, as their completions would be lost. Await them all first.
+    ///
+    /// The connection keeps its ID and continues to count towards the statistics of its server.
+    /// Any deadline, DSCP value or extension set on the connection does not carry over and must be
+    /// set again on the target worker if needed.
+    ///
+    /// Completes with the result of the continuation once it has completed. An error is returned
+    /// if the worker index is out of bounds or if the socket cannot be released from the current
+    /// worker (see `into_raw_socket()`), in which case the connection is closed.
+    pub async fn migrate_to_worker<FN, F, R>(
+        mut self,
+        worker_index: usize,
+        continuation: FN,
+    ) -> io::Result<R>
+    where
+        FN: FnOnce(TcpConnection) -> F + Send + 'static,
+        F: Future<Output = R> + 'static,
+        R: Send + 'static,
+    {
+        let worker_count = current_runtime::with(|runtime| runtime.async_worker_count());
+
+        if worker_index >= worker_count {
+            return Err(io::Error::LogicError(format!(
+                "cannot migrate connection to worker {worker_index} - there are only {worker_count} async workers"
+            )));
         }
+
+        let id = self.id;
+        let counters = self.counters.take();
+        let socket_pool = self.socket_pool.take();
+
+        let socket = self.into_raw_socket()?;
+
+        Ok(spawn_on_worker(worker_index, move || async move {
+            let mut connection = TcpConnection::from_socket(socket, counters);
+            connection.id = id;
+            connection.socket_pool = socket_pool;
+
+            continuation(connection).await
+        })
+        .await)
+    }
+
+    /// Queries the statistics the operating system keeps about the connection, such as the
+    /// round-trip time, the congestion window and the number of retransmissions. Useful for
+    /// diagnosing why a connection is slow. See `TcpInfo` for the supported Windows versions.
+    ///
+    /// The query is a blocking system call, so it is executed on a synchronous worker thread.
+    pub async fn tcp_info(&self) -> io::Result<TcpInfo> {
+        let socket_clone = Arc::clone(self.socket());
+
+        current_runtime::with(|runtime| {
+            runtime.spawn_sync(SynchronousTaskType::Syscall, move || {
+                winsock::tcp_info(**socket_clone)
+            })
+        })
+        .await
     }
 
     /// Performs a graceful shutdown of the connection, allowing time for all pending data transfers
     /// to complete. After this, you may drop the object and be assured that no data was lost in
     /// transit - this guarantee does not exist without calling the shutdown method.
     ///
-    /// An error result indicates that a graceful shutdown was not possible.
+    /// An error result indicates that a graceful shutdown was not possible. This includes the case
+    /// where a `BufferedWriter` was dropped without flushing, as its data never reached the peer.
     pub async fn shutdown(&mut self) -> io::Result<()> {
         // How this works is that we:
         // 1) Tell the OS that we will not send any more data. This sets the wheels in motion.
@@ -99,22 +1056,79 @@ impl TcpConnection {
         // 3) Done! Once we get the EOF, we can be sure that the peer has received all of our data
         //    and our FIN has been acknowledged, so no more activity can occur on the wire
 
-        let socket_clone = Arc::clone(&self.socket);
+        if self.unflushed_data_lost {
+            return Err(io::Error::LogicError(
+                "a buffered writer was dropped without flushing - the peer did not receive all \
+                 the data written to the connection"
+                    .to_string(),
+            ));
+        }
+
+        self.shutdown_send_shared().await?;
+
+        let received_data = self.receive(PinnedBuffer::from_pool()).await.into_inner()?;
+
+        if !received_data.is_empty() {
+            return Err(io::Error::LogicError("socket received data when shutting down - this may be an error depending on the communication protocol in use".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+impl TcpConnection {
+    /// Tells the peer that we will not send any more data, without waiting for anything in return.
+    pub(super) async fn shutdown_send_shared(&self) -> io::Result<()> {
+        let socket_clone = Arc::clone(self.socket());
+
         current_runtime::with(|runtime| {
             runtime.spawn_sync(SynchronousTaskType::Syscall, move || {
                 // SAFETY: Socket liveness is ensured by our shared ownership of the socket handle.
                 winsock::to_io_result(unsafe { WSASendDisconnect(**socket_clone, None) })
             })
         })
-        .await?;
+        .await
+    }
 
-        let received_data = self.receive(PinnedBuffer::from_pool()).await.into_inner()?;
+    /// Records that data written to the connection was lost, to be reported by `shutdown()`.
+    pub(super) fn mark_unflushed_data_lost(&mut self) {
+        self.unflushed_data_lost = true;
+    }
+}
 
-        if !received_data.is_empty() {
-            return Err(io::Error::LogicError("socket received data when shutting down - this may be an error depending on the communication protocol in use".to_string()));
+impl Drop for TcpConnection {
+    fn drop(&mut self) {
+        // The deadline timer and DSCP flow refer to the socket, so they must go first.
+        self.deadline = None;
+        self.dscp_flow = None;
+
+        // A client that did not get through the handshake in time is not worth a graceful close.
+        if let Some(socket) = &self.socket {
+            if self.handshake_pending && self.deadline_expired() {
+                // A reset socket cannot be recycled for new connections.
+                self.socket_pool = None;
+
+                if let Err(e) = winsock::enable_abortive_close(***socket) {
+                    event!(
+                        Level::WARN,
+                        message = "failed to reset connection after handshake deadline",
+                        id = self.id.to_string(),
+                        error = e.to_string()
+                    );
+                }
+            }
         }
 
-        Ok(())
+        let Some(socket_pool) = self.socket_pool.take() else {
+            return;
+        };
+
+        let socket = self
+            .socket
+            .take()
+            .expect("socket is only removed when the connection is dropped");
+
+        socket_pool.recycle(socket);
     }
 }
 
@@ -0,0 +1,95 @@
+use crate::{
+    io::{self, OperationResultFuture, PinnedBuffer},
+    net::{ConnectionId, TcpConnection},
+};
+use negative_impl::negative_impl;
+use std::rc::Rc;
+
+/// The receiving half of a connection split via `TcpConnection::split()`.
+#[derive(Debug)]
+pub struct ReadHalf {
+    connection: Rc<TcpConnection>,
+}
+
+impl ReadHalf {
+    pub(super) fn new(connection: Rc<TcpConnection>) -> Self {
+        Self { connection }
+    }
+
+    /// The process-unique ID of the connection this half belongs to.
+    pub fn id(&self) -> ConnectionId {
+        self.connection.id()
+    }
+
+    /// Receives the next buffer of data. See `TcpConnection::receive()`.
+    pub fn receive(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.connection.receive_shared(buffer)
+    }
+
+    /// Receives the next buffer of data without removing it from the socket. See
+    /// `TcpConnection::peek()`.
+    pub fn peek(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.connection.peek_shared(buffer)
+    }
+
+    /// Puts the two halves of a connection back together, undoing `TcpConnection::split()`. If the
+    /// halves belong to different connections, they are returned unchanged as the error.
+    pub fn reunite(self, write: WriteHalf) -> Result<TcpConnection, (ReadHalf, WriteHalf)> {
+        if !Rc::ptr_eq(&self.connection, &write.connection) {
+            return Err((self, write));
+        }
+
+        drop(write);
+
+        Ok(Rc::into_inner(self.connection)
+            .expect("the two halves are the only references to the connection"))
+    }
+}
+
+#[negative_impl]
+impl !Send for ReadHalf {}
+#[negative_impl]
+impl !Sync for ReadHalf {}
+
+/// The sending half of a connection split via `TcpConnection::split()`.
+#[derive(Debug)]
+pub struct WriteHalf {
+    connection: Rc<TcpConnection>,
+}
+
+impl WriteHalf {
+    pub(super) fn new(connection: Rc<TcpConnection>) -> Self {
+        Self { connection }
+    }
+
+    /// The process-unique ID of the connection this half belongs to.
+    pub fn id(&self) -> ConnectionId {
+        self.connection.id()
+    }
+
+    /// Sends a buffer of data to the peer. See `TcpConnection::send()`.
+    pub fn send(&mut self, buffer: PinnedBuffer) -> OperationResultFuture {
+        self.connection.send_shared(buffer)
+    }
+
+    /// Sends all of the data to the peer, regardless of its size. See
+    /// `TcpConnection::send_large()`.
+    pub async fn send_large(&mut self, data: &[u8]) -> io::Result<()> {
+        self.connection.send_large_shared(data).await
+    }
+
+    /// Tells the peer that no more data will be sent. Any sends in progress must be completed
+    /// first. The read half keeps receiving until the peer closes its end of the connection.
+    ///
+    /// Unlike `TcpConnection::shutdown()`, this does not wait for the peer to close its end, as that
+    /// would mean taking over the job of the read half. For a graceful shutdown, keep receiving on
+    /// the read half until the end of the stream before dropping the halves.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.connection.shutdown_send_shared().await
+    }
+}
+
+#[negative_impl]
+impl !Send for WriteHalf {}
+#[negative_impl]
+impl !Sync for WriteHalf {}
@@ -0,0 +1,255 @@
+use std::time::Duration;
+use windows::Win32::Networking::WinSock::{
+    TCP_INFO_v0, TCP_INFO_v1, TCPSTATE, TCPSTATE_CLOSED, TCPSTATE_CLOSE_WAIT, TCPSTATE_CLOSING,
+    TCPSTATE_ESTABLISHED, TCPSTATE_FIN_WAIT_1, TCPSTATE_FIN_WAIT_2, TCPSTATE_LAST_ACK,
+    TCPSTATE_LISTEN, TCPSTATE_SYN_RCVD, TCPSTATE_SYN_SENT, TCPSTATE_TIME_WAIT,
+};
+
+/// A snapshot of the statistics the operating system keeps about a TCP connection, as returned by
+/// `TcpConnection::tcp_info()`.
+///
+/// Requires Windows 10 version 1703 or Windows Server 2019 or newer. The `send_limits` field
+/// requires Windows 10 build 20348 or Windows Server 2022 or newer and is `None` on older versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpInfo {
+    pub state: TcpState,
+
+    /// The maximum segment size.
+    pub mss: u32,
+
+    /// How long the connection has existed.
+    pub connection_time: Duration,
+
+    /// Whether TCP timestamps are used on the connection.
+    pub timestamps_enabled: bool,
+
+    /// The smoothed round-trip time estimate.
+    pub rtt: Duration,
+
+    /// The lowest round-trip time observed on the connection.
+    pub min_rtt: Duration,
+
+    /// The number of bytes sent but not yet acknowledged by the peer.
+    pub bytes_in_flight: u32,
+
+    /// The congestion window, in bytes.
+    pub congestion_window: u32,
+
+    /// The send window advertised by the peer, in bytes.
+    pub send_window: u32,
+
+    /// The receive window advertised to the peer, in bytes.
+    pub receive_window: u32,
+
+    /// The size of the receive buffer, in bytes.
+    pub receive_buffer: u32,
+
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+
+    /// The number of bytes that arrived out of order.
+    pub bytes_reordered: u32,
+
+    pub bytes_retransmitted: u32,
+    pub fast_retransmits: u32,
+    pub duplicate_acks_received: u32,
+
+    /// The number of times the retransmission timer expired. Each of these stalls the connection
+    /// for at least one retransmission timeout, so they are a common cause of slow connections.
+    pub timeout_episodes: u32,
+
+    /// The number of times the SYN was retransmitted while establishing the connection.
+    pub syn_retransmits: u8,
+
+    /// What has been limiting the rate of sending, or `None` if not supported by the operating
+    /// system.
+    pub send_limits: Option<TcpSendLimits>,
+}
+
+impl From<TCP_INFO_v0> for TcpInfo {
+    fn from(value: TCP_INFO_v0) -> Self {
+        Self {
+            state: value.State.into(),
+            mss: value.Mss,
+            connection_time: Duration::from_millis(value.ConnectionTimeMs),
+            timestamps_enabled: value.TimestampsEnabled.as_bool(),
+            rtt: Duration::from_micros(value.RttUs.into()),
+            min_rtt: Duration::from_micros(value.MinRttUs.into()),
+            bytes_in_flight: value.BytesInFlight,
+            congestion_window: value.Cwnd,
+            send_window: value.SndWnd,
+            receive_window: value.RcvWnd,
+            receive_buffer: value.RcvBuf,
+            bytes_sent: value.BytesOut,
+            bytes_received: value.BytesIn,
+            bytes_reordered: value.BytesReordered,
+            bytes_retransmitted: value.BytesRetrans,
+            fast_retransmits: value.FastRetrans,
+            duplicate_acks_received: value.DupAcksIn,
+            timeout_episodes: value.TimeoutEpisodes,
+            syn_retransmits: value.SynRetrans,
+            send_limits: None,
+        }
+    }
+}
+
+impl From<TCP_INFO_v1> for TcpInfo {
+    fn from(value: TCP_INFO_v1) -> Self {
+        Self {
+            state: value.State.into(),
+            mss: value.Mss,
+            connection_time: Duration::from_millis(value.ConnectionTimeMs),
+            timestamps_enabled: value.TimestampsEnabled.as_bool(),
+            rtt: Duration::from_micros(value.RttUs.into()),
+            min_rtt: Duration::from_micros(value.MinRttUs.into()),
+            bytes_in_flight: value.BytesInFlight,
+            congestion_window: value.Cwnd,
+            send_window: value.SndWnd,
+            receive_window: value.RcvWnd,
+            receive_buffer: value.RcvBuf,
+            bytes_sent: value.BytesOut,
+            bytes_received: value.BytesIn,
+            bytes_reordered: value.BytesReordered,
+            bytes_retransmitted: value.BytesRetrans,
+            fast_retransmits: value.FastRetrans,
+            duplicate_acks_received: value.DupAcksIn,
+            timeout_episodes: value.TimeoutEpisodes,
+            syn_retransmits: value.SynRetrans,
+            send_limits: Some(TcpSendLimits {
+                receive_window: SendLimit::new(
+                    value.SndLimTransRwin,
+                    value.SndLimTimeRwin,
+                    value.SndLimBytesRwin,
+                ),
+                congestion_window: SendLimit::new(
+                    value.SndLimTransCwnd,
+                    value.SndLimTimeCwnd,
+                    value.SndLimBytesCwnd,
+                ),
+                sender: SendLimit::new(
+                    value.SndLimTransSnd,
+                    value.SndLimTimeSnd,
+                    value.SndLimBytesSnd,
+                ),
+            }),
+        }
+    }
+}
+
+/// The state of a TCP connection, as defined by RFC 793.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+
+    /// A state not known to Folo, with the raw value reported by the operating system.
+    Other(i32),
+}
+
+impl From<TCPSTATE> for TcpState {
+    fn from(value: TCPSTATE) -> Self {
+        match value {
+            TCPSTATE_CLOSED => Self::Closed,
+            TCPSTATE_LISTEN => Self::Listen,
+            TCPSTATE_SYN_SENT => Self::SynSent,
+            TCPSTATE_SYN_RCVD => Self::SynReceived,
+            TCPSTATE_ESTABLISHED => Self::Established,
+            TCPSTATE_FIN_WAIT_1 => Self::FinWait1,
+            TCPSTATE_FIN_WAIT_2 => Self::FinWait2,
+            TCPSTATE_CLOSE_WAIT => Self::CloseWait,
+            TCPSTATE_CLOSING => Self::Closing,
+            TCPSTATE_LAST_ACK => Self::LastAck,
+            TCPSTATE_TIME_WAIT => Self::TimeWait,
+            other => Self::Other(other.0),
+        }
+    }
+}
+
+/// What has been limiting the rate of sending on a TCP connection. Sending is always limited by
+/// one of these factors, so comparing the time spent under each one tells whether a slow
+/// connection is held back by the peer, by the network or by the application itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpSendLimits {
+    /// Sending was limited by the receive window advertised by the peer.
+    pub receive_window: SendLimit,
+
+    /// Sending was limited by the congestion window (i.e. by the network).
+    pub congestion_window: SendLimit,
+
+    /// Sending was limited by the sender itself not providing data fast enough.
+    pub sender: SendLimit,
+}
+
+/// How much sending on a TCP connection has been limited by one factor. See `TcpSendLimits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendLimit {
+    /// The number of times sending became limited by this factor.
+    pub transitions: u32,
+
+    /// The total time sending was limited by this factor.
+    pub time: Duration,
+
+    /// The number of bytes sent while limited by this factor.
+    pub bytes: u64,
+}
+
+impl SendLimit {
+    fn new(transitions: u32, time_millis: u32, bytes: u64) -> Self {
+        Self {
+            transitions,
+            time: Duration::from_millis(time_millis.into()),
+            bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Foundation::BOOLEAN;
+
+    #[test]
+    fn v0_has_no_send_limits() {
+        let info = TcpInfo::from(TCP_INFO_v0 {
+            State: TCPSTATE_ESTABLISHED,
+            RttUs: 1500,
+            TimestampsEnabled: BOOLEAN(1),
+            ..Default::default()
+        });
+
+        assert_eq!(info.state, TcpState::Established);
+        assert_eq!(info.rtt, Duration::from_micros(1500));
+        assert!(info.timestamps_enabled);
+        assert_eq!(info.send_limits, None);
+    }
+
+    #[test]
+    fn v1_reports_send_limits() {
+        let info = TcpInfo::from(TCP_INFO_v1 {
+            State: TCPSTATE(42),
+            SndLimTransCwnd: 3,
+            SndLimTimeCwnd: 250,
+            SndLimBytesCwnd: 9000,
+            ..Default::default()
+        });
+
+        assert_eq!(info.state, TcpState::Other(42));
+        assert_eq!(
+            info.send_limits.unwrap().congestion_window,
+            SendLimit {
+                transitions: 3,
+                time: Duration::from_millis(250),
+                bytes: 9000,
+            }
+        );
+    }
+}
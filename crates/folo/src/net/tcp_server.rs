@@ -1,36 +1,101 @@
+use crate::trace::{event, Level};
 use crate::{
-    io::{self, OperationResultExt},
-    net::{winsock, TcpConnection},
+    io::{self, pooled_buffer_bytes, CompletionPort, IoPrimitive, OperationResultExt},
+    metrics::{Event, EventBuilder, Magnitude},
+    net::{
+        accept_backoff::{AcceptBackoff, DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_BACKOFF},
+        accept_loop::AcceptLoop,
+        accept_tuner::AcceptTuner,
+        conditional_accept::{self, AcceptFilter, ConditionalAcceptor},
+        connection_multiplexer::{self, MultiplexerSender},
+        connection_registry::ConnectionRegistry,
+        interfaces,
+        prefix_routing::{self, PrefixRoute, DEFAULT_PREFIX_SNIFF_TIMEOUT},
+        server_registry::ServerRegistration,
+        winsock::{self, AcceptErrorKind},
+        AcceptSocketPool, Backpressure, BackpressureCallback, BackpressureMonitor, ConnectionId,
+        ServerCounters, ServerEvent, ServerEventSender, ServerEventSubscriptions, ServerEvents,
+        ServerStats, TcpConnection, MAX_DSCP, MAX_ROUTE_PREFIX_LENGTH,
+    },
     rt::{
-        current_async_agent, current_runtime, spawn_on_any, RemoteJoinHandle, SynchronousTaskType,
+        current_async_agent, current_runtime, spawn_on_any, NumaNodeId, RemoteJoinHandle,
+        SynchronousTaskType, WorkerId,
     },
-    util::OwnedHandle,
+    time::{Clock, Delay},
+    util::{live_handles, panic_message, LowPrecisionInstant, OwnedHandle},
 };
 use core::slice;
 use futures::{
-    future::{select, Either},
-    stream::FuturesUnordered,
-    StreamExt,
+    channel::mpsc,
+    future::{self, select, Either, LocalBoxFuture, Shared},
+    FutureExt, StreamExt,
 };
 use negative_impl::negative_impl;
-use std::{future::Future, mem, num::NonZeroU16, sync::Arc};
-use tracing::{event, Level};
+use std::{
+    cell::Cell,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    mem,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    num::{NonZeroU16, NonZeroUsize},
+    panic::{self, AssertUnwindSafe},
+    pin::pin,
+    rc::Rc,
+    sync::{atomic, Arc},
+    time::{Duration, Instant},
+};
 use windows::Win32::Networking::WinSock::{
-    bind, htons, listen, setsockopt, AcceptEx, GetAcceptExSockaddrs, WSAIoctl, WSASocketA, AF_INET,
-    INADDR_ANY, IN_ADDR, IPPROTO_TCP, SIO_QUERY_RSS_PROCESSOR_INFO, SOCKADDR, SOCKADDR_IN, SOCKET,
-    SOCKET_PROCESSOR_AFFINITY, SOCK_STREAM, SOL_SOCKET, SO_UPDATE_ACCEPT_CONTEXT, WSAEACCES,
-    WSAEOPNOTSUPP, WSA_FLAG_OVERLAPPED,
+    bind, getsockname, htons, listen, ntohs, setsockopt, AcceptEx, GetAcceptExSockaddrs, WSAIoctl,
+    WSASocketA, AF_INET, IN_ADDR, IPPROTO_TCP, SIO_QUERY_RSS_PROCESSOR_INFO, SOCKADDR, SOCKADDR_IN,
+    SOCKET, SOCKET_PROCESSOR_AFFINITY, SOCK_STREAM, SOL_SOCKET, SO_UPDATE_ACCEPT_CONTEXT,
+    WSAEACCES, WSAEOPNOTSUPP, WSA_FLAG_OVERLAPPED,
 };
+use windows::Win32::{Foundation::HANDLE, System::IO::CancelIoEx};
 
 pub struct TcpServerBuilder<A, AF>
 where
     A: Fn(TcpConnection) -> AF + Clone + Send + 'static,
     AF: Future<Output = io::Result<()>> + 'static,
 {
-    port: Option<NonZeroU16>,
+    // Zero means the port is chosen by the operating system.
+    port: Option<u16>,
     on_accept: Option<A>,
+    max_connections: Option<NonZeroUsize>,
+    on_overload: Option<OverloadHandler>,
+    configure_socket: Option<SocketConfigurator>,
+    keepalive: bool,
+    affinity_by_peer: bool,
+    affinity_by_numa_node: bool,
+    multiplex_connections: bool,
+    listener: Option<OwnedHandle<SOCKET>>,
+    accept_backoff_initial: Duration,
+    accept_backoff_max: Duration,
+    reuse_accept_sockets: bool,
+    backpressure: Option<(NonZeroUsize, usize, BackpressureCallback)>,
+    bind_addresses: Vec<SocketAddr>,
+    interface: Option<String>,
+    dscp: Option<u8>,
+    register_with_runtime: bool,
+    query_rss_affinity: bool,
+    stop_on_handle_drop: bool,
+    accept_filter: Option<AcceptFilter>,
+    routes: Vec<PrefixRoute>,
+    prefix_sniff_timeout: Duration,
+    initial_read_deadline: Option<Duration>,
+    slow_handler_threshold: Option<Duration>,
+    backlog_pressure: Option<(Duration, BacklogPressureCallback)>,
+    adaptive_accepts: Option<(NonZeroUsize, NonZeroUsize)>,
 }
 
+// The overload handler is optional, so we type-erase it to avoid burdening every user of the
+// builder with an extra pair of type parameters they may never use.
+type OverloadHandler =
+    Arc<dyn Fn(TcpConnection) -> LocalBoxFuture<'static, io::Result<()>> + Send + Sync>;
+
+type SocketConfigurator = Arc<dyn Fn(SOCKET) -> io::Result<()> + Send + Sync>;
+
+type BacklogPressureCallback = Arc<dyn Fn(Duration) + Send + Sync>;
+
 impl<A, AF> TcpServerBuilder<A, AF>
 where
     A: Fn(TcpConnection) -> AF + Clone + Send + 'static,
@@ -40,11 +105,45 @@ where
         Self {
             port: None,
             on_accept: None,
+            max_connections: None,
+            on_overload: None,
+            configure_socket: None,
+            keepalive: false,
+            affinity_by_peer: false,
+            affinity_by_numa_node: false,
+            multiplex_connections: false,
+            listener: None,
+            accept_backoff_initial: DEFAULT_INITIAL_BACKOFF,
+            accept_backoff_max: DEFAULT_MAX_BACKOFF,
+            reuse_accept_sockets: false,
+            backpressure: None,
+            bind_addresses: Vec::new(),
+            interface: None,
+            dscp: None,
+            register_with_runtime: false,
+            query_rss_affinity: false,
+            stop_on_handle_drop: false,
+            accept_filter: None,
+            routes: Vec::new(),
+            prefix_sniff_timeout: DEFAULT_PREFIX_SNIFF_TIMEOUT,
+            initial_read_deadline: None,
+            slow_handler_threshold: None,
+            backlog_pressure: None,
+            adaptive_accepts: None,
         }
     }
 
     pub fn port(mut self, port: NonZeroU16) -> Self {
-        self.port = Some(port);
+        self.port = Some(port.get());
+        self
+    }
+
+    /// Lets the operating system choose a free port for the server to listen on. Use
+    /// `TcpServerHandle::local_port()` to find out which port was chosen.
+    ///
+    /// This is mostly useful in tests, to allow many servers to run side by side without conflicts.
+    pub fn ephemeral_port(mut self) -> Self {
+        self.port = Some(0);
         self
     }
 
@@ -52,11 +151,504 @@ where
     /// from any async task worker thread and any number of times concurrently.
     ///
     /// The connection will be closed when the provided TcpConnection is dropped.
+    ///
+    /// If the callback panics, the connection is closed and the panic is reported as a
+    /// `ServerEvent::HandlerPanicked` event, while the worker keeps serving other connections. This
+    /// requires panics to unwind - if the process is built with `panic = "abort"` (as the release
+    /// profile of this workspace is), a panicking callback aborts the process instead.
     pub fn on_accept(mut self, callback: A) -> Self {
         self.on_accept = Some(callback);
         self
     }
 
+    /// Sets the maximum number of connections that may be handled by `on_accept` at the same time.
+    /// A connection counts against the limit until the future returned by `on_accept` completes.
+    ///
+    /// Connections received while at the limit are closed immediately, unless an overload handler
+    /// is set via `on_overload()`, in which case they are routed to the overload handler instead.
+    pub fn max_connections(mut self, max_connections: NonZeroUsize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Sets the function to call instead of `on_accept` when a connection is received while the
+    /// server is at the `max_connections` limit. This allows the app to send a short rejection
+    /// (e.g. an HTTP 503 response) before closing the connection, instead of leaving the client
+    /// without any indication of what happened. Connections given to the overload handler do not
+    /// count against the limit.
+    ///
+    /// Note that the connection still needs to be accepted before it can be rejected, so this does
+    /// not reduce the cost of accepting connections under overload, only the cost of handling them.
+    ///
+    /// Requires `max_connections` to be set.
+    pub fn on_overload<O, OF>(mut self, callback: O) -> Self
+    where
+        O: Fn(TcpConnection) -> OF + Send + Sync + 'static,
+        OF: Future<Output = io::Result<()>> + 'static,
+    {
+        self.on_overload = Some(Arc::new(move |connection| {
+            (callback)(connection).boxed_local()
+        }));
+        self
+    }
+
+    /// Gives every accepted connection a deadline for getting through its handshake phase (e.g. a
+    /// TLS handshake layered on top by the handler), which can be much tighter than any timeout
+    /// that applies once the connection is established. This limits how long slow or stalled
+    /// clients can hold on to a connection before proving themselves.
+    ///
+    /// The deadline starts when the connection is accepted and is applied as the connection
+    /// deadline (see `TcpConnection::set_deadline()`), so once it passes, all I/O operations on the
+    /// connection fail with a timeout error and the connection is reset (not closed gracefully)
+    /// when the handler drops it.
+    ///
+    /// The handler lifts the deadline by calling `TcpConnection::mark_established()` once the
+    /// handshake is done. Setting a deadline of its own via `set_deadline()` also replaces it. A
+    /// handler that does neither is held to the deadline for the entire connection.
+    pub fn initial_read_deadline(mut self, timeout: Duration) -> Self {
+        self.initial_read_deadline = Some(timeout);
+        self
+    }
+
+    /// Reports connection handlers that take at least `threshold` to complete, by emitting a WARN
+    /// event with the connection ID and the elapsed time and by counting them in
+    /// `ServerStats::slow_handlers`. This is the connection handling counterpart of slow query
+    /// logging, for spotting pathological requests without tracing every connection.
+    ///
+    /// The time is measured from when the handler is started on its worker until it completes,
+    /// whether successfully or not, using a low-precision clock that is cheap to read. Handlers
+    /// abandoned via `TcpServerHandle::close_connection()` are measured until they are abandoned.
+    ///
+    /// Disabled by default.
+    pub fn slow_handler_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_handler_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets a function to call on the socket of every accepted connection before it is given to
+    /// `on_accept`. This can be used to set any socket options (e.g. via `setsockopt()`) that have
+    /// no dedicated method on the builder.
+    ///
+    /// The function is called on a synchronous worker thread and must be fast, as it is on the
+    /// critical path of accepting new connections. If it returns an error, the connection is
+    /// closed and treated as a failed accept.
+    pub fn configure_socket<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(SOCKET) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.configure_socket = Some(Arc::new(callback));
+        self
+    }
+
+    /// Enables TCP keepalive (`SO_KEEPALIVE`) on every accepted connection, so that connections to
+    /// peers that have silently disappeared are eventually detected and fail. The keepalive timing
+    /// is left at the system defaults, under which the first probe is only sent after two hours of
+    /// inactivity. Disabled by default.
+    pub fn enable_keepalive(mut self, enabled: bool) -> Self {
+        self.keepalive = enabled;
+        self
+    }
+
+    /// Marks outgoing packets of every accepted connection with the given DSCP value. Valid values
+    /// are `0..=MAX_DSCP`. See `TcpConnection::set_dscp()` for the caveats.
+    ///
+    /// If the DSCP value cannot be set on a connection, a warning is logged and the connection is
+    /// handled without it.
+    pub fn dscp(mut self, value: u8) -> Self {
+        self.dscp = Some(value);
+        self
+    }
+
+    /// Dispatches connections to async workers based on the IP address of the peer, so that
+    /// connections from the same client (including reconnects) are handled on the same worker.
+    /// This allows per-client state to be kept in thread-local storage of the worker.
+    ///
+    /// This is a hint, not a guarantee - many clients behind the same NAT gateway share an address
+    /// and will all land on the same worker, which may create hot spots. Placement by peer address
+    /// also ignores which processor the network adapter delivers the traffic of the connection to,
+    /// so it may cost some cross-processor traffic compared to the default placement.
+    pub fn affinity_by_peer(mut self) -> Self {
+        self.affinity_by_peer = true;
+        self
+    }
+
+    /// Dispatches each connection to an async worker on the NUMA node that the network adapter
+    /// delivers the traffic of the connection to, as reported by receive side scaling (RSS). This
+    /// keeps the handling of a connection near the memory that its data arrives in, without going
+    /// as far as placing it on the exact processor chosen by RSS.
+    ///
+    /// Connections without RSS information (e.g. because RSS is not enabled, the connection is over
+    /// loopback or it was accepted via `accept_filter()`) and connections from a NUMA node that the
+    /// runtime has no workers on are dispatched as usual. With a flat NUMA topology, this is
+    /// equivalent to the default placement. See `NumaNodeId`.
+    ///
+    /// Implies querying the RSS information of every connection, as with `query_rss_affinity()`.
+    /// Cannot be combined with `affinity_by_peer()`.
+    pub fn affinity_by_numa_node(mut self) -> Self {
+        self.affinity_by_numa_node = true;
+        self
+    }
+
+    /// Drives the `on_accept` handlers of connections from one long-lived task per async worker,
+    /// instead of spawning a new task for every connection. Each worker keeps the handlers of its
+    /// connections in a slab and only polls those that have been woken up, so a connection that is
+    /// waiting for data costs little more than the memory of its handler future. This suits servers
+    /// with very many mostly-idle connections, for which a task per connection is too heavy.
+    ///
+    /// Connections are distributed between the workers round-robin, or by peer address if
+    /// combined with `affinity_by_peer()`. A handler that blocks its worker delays every other
+    /// connection on the same worker, so the handlers must be well-behaved. Combine with
+    /// `max_connections()` to bound the number of handlers in memory.
+    pub fn multiplex_connections(mut self, enabled: bool) -> Self {
+        self.multiplex_connections = enabled;
+        self
+    }
+
+    /// Listens on each of the given local addresses instead of on a single port on all addresses.
+    /// This is useful on machines with multiple network interfaces, of which only some should be
+    /// used to serve traffic. Connections from all the addresses are handled the same way.
+    ///
+    /// If listening on any of the addresses fails, the server fails to start and does not listen on
+    /// any of them. Only IPv4 addresses are currently supported. `TcpServerHandle::local_port()`
+    /// reports the port of the first address.
+    ///
+    /// Cannot be combined with `port()`, `ephemeral_port()` or `from_listener()`.
+    pub fn bind_addresses(mut self, addresses: Vec<SocketAddr>) -> Self {
+        self.bind_addresses = addresses;
+        self
+    }
+
+    /// Listens on every IPv4 address of the named network interface, on the port given to
+    /// `port()` or `ephemeral_port()`. The name is either the friendly name of the interface as
+    /// shown by the operating system (e.g. "Ethernet", case-insensitive) or its adapter GUID.
+    ///
+    /// The addresses are looked up when the server is built, on a synchronous worker thread, so
+    /// changes to the interface made after that are not picked up. If the interface has multiple
+    /// addresses, the server listens on all of them; when using `ephemeral_port()`, each address
+    /// may get a different port, of which `TcpServerHandle::local_port()` reports the first.
+    ///
+    /// Building the server fails with `InvalidOptions` if there is no such interface or it has no
+    /// IPv4 address. Cannot be combined with `bind_addresses()` or `from_listener()`.
+    pub fn bind_interface(mut self, name: &str) -> Self {
+        self.interface = Some(name.to_string());
+        self
+    }
+
+    /// Adopts an existing listen socket instead of creating a new one, typically one released by
+    /// `TcpServerHandle::stop_and_release_listener()` of another server. This allows a server to
+    /// be replaced (e.g. with one that has a different configuration or runs in a different
+    /// runtime) without closing the listening endpoint, so no connections are refused during the
+    /// transition - they simply wait in the listen queue until the new server accepts them.
+    ///
+    /// The socket must already be bound and listening and must not be bound to any I/O completion
+    /// port. The server takes ownership of the socket and closes it when the server stops.
+    ///
+    /// Cannot be combined with `port()` or `ephemeral_port()`, as the socket is already bound.
+    pub fn from_listener(mut self, listener: OwnedHandle<SOCKET>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Sets how long the server pauses accepting new connections when the system runs out of the
+    /// resources needed to create sockets (e.g. due to a handle limit being reached). The first
+    /// failure pauses for `initial`, with each consecutive failure doubling the pause up to `max`.
+    /// A successfully created socket resets the pause back to `initial`.
+    ///
+    /// Connections that arrive during the pause wait in the listen queue. The defaults are 10 ms
+    /// and 1 second.
+    pub fn accept_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.accept_backoff_initial = initial;
+        self.accept_backoff_max = max;
+        self
+    }
+
+    /// Sets a function to call when connections wait too long to be accepted, which means the
+    /// dispatcher is not accepting them as fast as they arrive. Once the listen backlog is full, the
+    /// operating system silently drops new connection attempts and clients see timeouts, so this is
+    /// the early warning for that failure mode. Consider `reuse_accept_sockets()` or
+    /// `bind_addresses()` with multiple listen sockets if this happens regularly.
+    ///
+    /// The wait of each connection is measured via `SO_CONNECT_TIME` once the connection has been
+    /// accepted, so it covers the time from the client connecting until the server picked up the
+    /// connection. The measurement has a resolution of whole seconds and `threshold` must be at
+    /// least one second. The function is called with the measured wait of every connection that
+    /// waited at least `threshold` and each such connection is counted in
+    /// `ServerStats::backlog_pressure`.
+    ///
+    /// The function is called on the TCP dispatcher thread and must be fast, as all accepting waits
+    /// for it. Cannot be combined with `accept_filter()`, as connections accepted conditionally are
+    /// only established once accepted.
+    pub fn on_backlog_pressure<F>(mut self, threshold: Duration, callback: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.backlog_pressure = Some((threshold, Arc::new(callback)));
+        self
+    }
+
+    /// Adapts the number of accept operations kept in flight to the rate at which connections
+    /// arrive, within the given bounds, instead of always keeping the same large number in flight.
+    /// Every accept operation holds a socket and a buffer, so this keeps resource use proportional
+    /// to the load.
+    ///
+    /// The number starts at `min` and is reconsidered every 100 milliseconds, following an
+    /// additive-increase, multiplicative-decrease algorithm:
+    ///
+    /// * If the server was saturated - as many connections arrived as there were accept operations
+    ///   in flight, or a connection was reported for backlog pressure (if `on_backlog_pressure()`
+    ///   is set) - the number grows by a fixed step.
+    /// * If fewer than a quarter of the accept operations in flight were used, the number is
+    ///   halved for every 100 milliseconds that passed since it was last reconsidered.
+    ///
+    /// The number is only reconsidered when a connection arrives, and shrinking happens by not
+    /// replacing accept operations as they complete, so an idle server keeps its accept operations
+    /// until the next connection arrives. The current number is exposed via
+    /// `TcpServerHandle::accept_concurrency()`. With multiple listen sockets, it is split evenly
+    /// between them.
+    ///
+    /// Cannot be combined with `accept_filter()`, which always accepts one connection at a time.
+    pub fn adaptive_accepts(mut self, min: NonZeroUsize, max: NonZeroUsize) -> Self {
+        self.adaptive_accepts = Some((min, max));
+        self
+    }
+
+    /// Reuses the sockets of closed connections for accepting new connections, instead of creating
+    /// a new socket for every connection. This saves the cost of socket creation, which matters
+    /// when connections are short-lived and arrive at a high rate.
+    ///
+    /// When a connection is dropped, its socket is disconnected on a synchronous worker thread and
+    /// returned to a pool owned by the server. The disconnect waits for the connection to close
+    /// gracefully, so this is best combined with `TcpConnection::shutdown()`.
+    ///
+    /// A connection must not be dropped while it has I/O operations in progress, as the socket may
+    /// be given to a different worker before their completions have been processed.
+    pub fn reuse_accept_sockets(mut self, enabled: bool) -> Self {
+        self.reuse_accept_sockets = enabled;
+        self
+    }
+
+    /// Sets a function to call when the `on_accept` handlers are not keeping up with the rate of
+    /// incoming connections, as an early warning before the `max_connections` limit is reached.
+    ///
+    /// The function is called with `Backpressure::Engaged` when the number of connections being
+    /// handled reaches `high_watermark`, and with `Backpressure::Relieved` once it has dropped back
+    /// to `low_watermark`. The gap between the two watermarks is the hysteresis that prevents a
+    /// flood of reports when the number of connections hovers around a single threshold - after
+    /// `Engaged` is reported, no further reports are made until the count drops to `low_watermark`,
+    /// and vice versa.
+    ///
+    /// The function may be called from any thread and must be fast, as it is called on the critical
+    /// path of accepting and completing connections. `low_watermark` must be less than
+    /// `high_watermark`.
+    pub fn on_backpressure<F>(
+        mut self,
+        high_watermark: NonZeroUsize,
+        low_watermark: usize,
+        callback: F,
+    ) -> Self
+    where
+        F: Fn(Backpressure) + Send + Sync + 'static,
+    {
+        self.backpressure = Some((high_watermark, low_watermark, Arc::new(callback)));
+        self
+    }
+
+    /// Sets whether dropping the `TcpServerHandle` stops the server, as if `stop()` were called.
+    /// By default, the server keeps running after the handle is dropped, until the runtime stops.
+    ///
+    /// Dropping the handle only signals the server to stop - it cannot wait for ongoing connections
+    /// to finish processing, so any draining of existing connections happens in the background
+    /// after the handle is gone.
+    pub fn stop_on_handle_drop(mut self, enabled: bool) -> Self {
+        self.stop_on_handle_drop = enabled;
+        self
+    }
+
+    /// Queries the processor that receive side scaling (RSS) assigned to each accepted connection
+    /// (`SIO_QUERY_RSS_PROCESSOR_INFO`). The information is only used for trace logging and by
+    /// `affinity_by_numa_node()`, so by default the query is skipped to save a system call per
+    /// connection.
+    ///
+    /// Has no effect if RSS is not enabled on the system (see `TcpServerHandle::rss_enabled()`).
+    pub fn query_rss_affinity(mut self, enabled: bool) -> Self {
+        self.query_rss_affinity = enabled;
+        self
+    }
+
+    /// Lists the server in `RuntimeClient::servers()` (also available as `folo::rt::servers()`)
+    /// while it is running, with its port, whether it is accepting and how many connections it is
+    /// handling. Useful for debugging and for administration endpoints of services that run
+    /// multiple servers. The server is removed from the list when it stops.
+    pub fn register_with_runtime(mut self, enabled: bool) -> Self {
+        self.register_with_runtime = enabled;
+        self
+    }
+
+    /// Decides whether to accept each incoming connection based on the address of the peer, before
+    /// the connection is established. Rejected peers are refused without the server spending any
+    /// resources on them, making this a cheap way to turn away known-bad addresses.
+    ///
+    /// This uses conditional accept (`SO_CONDITIONAL_ACCEPT`), which disables the fast path that
+    /// the server normally uses to accept connections (overlapped `AcceptEx()` operations, many of
+    /// which are kept in flight). Instead, connections are accepted one at a time and the peer does
+    /// not get a response until the filter has been consulted, so accepting is slower and bursts of
+    /// new connections queue up in the listen queue. Only use this if the filtering is worth it.
+    ///
+    /// The filter is called on the TCP dispatcher thread and must be fast, as all accepting waits
+    /// for it. A panic in the filter rejects the connection.
+    ///
+    /// Cannot be combined with `from_listener()` or `reuse_accept_sockets()`. A server with an
+    /// accept filter cannot release its listen socket via
+    /// `TcpServerHandle::stop_and_release_listener()`.
+    pub fn accept_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Routes each connection to a handler based on the first bytes the client sends, allowing
+    /// one port to serve multiple protocols (e.g. HTTP and a custom binary protocol). Before a
+    /// connection is handed to a handler, the server peeks at its first bytes and gives the
+    /// connection to the first route in the list whose prefix they start with. Nothing is consumed
+    /// from the connection, so the chosen handler sees the entire stream, prefix included.
+    ///
+    /// Connections that match no route are given to `on_accept`, which can serve a default
+    /// protocol or simply return to close the connection. This includes connections on which the
+    /// client does not send a full prefix within the sniff timeout (see `prefix_sniff_timeout()`),
+    /// which makes `on_accept` the place for protocols in which the server speaks first.
+    ///
+    /// Prefixes may be at most `MAX_ROUTE_PREFIX_LENGTH` bytes long. Peeking happens on the async
+    /// worker that handles the connection, so a slow client does not hold up any other connection.
+    /// Connections count against `max_connections` while they are being routed.
+    pub fn route_by_prefix(mut self, routes: Vec<PrefixRoute>) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Sets how long the server waits for the client to send enough data to match a route set
+    /// via `route_by_prefix()`, before giving the connection to `on_accept`. Defaults to 5 seconds.
+    pub fn prefix_sniff_timeout(mut self, timeout: Duration) -> Self {
+        self.prefix_sniff_timeout = timeout;
+        self
+    }
+
+    /// Checks the configuration for problems, reporting all of them at once so they can be fixed
+    /// in one go instead of one at a time.
+    fn validate(&self) -> io::Result<()> {
+        let mut problems = Vec::new();
+
+        match (self.port, &self.listener, self.bind_addresses.is_empty()) {
+            (None, None, true) => problems.push("port must be set"),
+            (Some(_), Some(_), _) => {
+                problems.push("port cannot be set when adopting an existing listen socket")
+            }
+            (Some(_), _, false) => problems.push("port cannot be set when binding to addresses"),
+            (_, Some(_), false) => problems
+                .push("bind addresses cannot be set when adopting an existing listen socket"),
+            _ => {}
+        }
+
+        if self.interface.is_some() && self.listener.is_some() {
+            problems
+                .push("network interface cannot be set when adopting an existing listen socket");
+        }
+
+        if self.interface.is_some() && !self.bind_addresses.is_empty() {
+            problems.push("network interface cannot be combined with bind addresses");
+        }
+
+        if self.bind_addresses.iter().any(SocketAddr::is_ipv6) {
+            problems.push("only IPv4 bind addresses are supported");
+        }
+
+        if self.on_accept.is_none() {
+            problems.push("on_accept must be set");
+        }
+
+        if self.on_overload.is_some() && self.max_connections.is_none() {
+            problems.push("on_overload requires max_connections to be set");
+        }
+
+        if self.accept_backoff_initial.is_zero()
+            || self.accept_backoff_initial > self.accept_backoff_max
+        {
+            problems.push(
+                "accept backoff must be non-zero and the initial backoff must not exceed the maximum",
+            );
+        }
+
+        if let Some((high_watermark, low_watermark, _)) = &self.backpressure {
+            if *low_watermark >= high_watermark.get() {
+                problems.push("backpressure low watermark must be less than the high watermark");
+            }
+        }
+
+        if self.dscp.is_some_and(|dscp| dscp > MAX_DSCP) {
+            problems.push("DSCP value must not exceed MAX_DSCP");
+        }
+
+        if self.accept_filter.is_some() && self.listener.is_some() {
+            problems.push("accept filter cannot be used with an adopted listen socket");
+        }
+
+        if self.accept_filter.is_some() && self.reuse_accept_sockets {
+            problems.push("accept filter cannot be combined with socket reuse");
+        }
+
+        if self
+            .routes
+            .iter()
+            .any(|x| x.prefix().is_empty() || x.prefix().len() > MAX_ROUTE_PREFIX_LENGTH)
+        {
+            problems
+                .push("route prefixes must be non-empty and not exceed MAX_ROUTE_PREFIX_LENGTH");
+        }
+
+        if self.prefix_sniff_timeout.is_zero() {
+            problems.push("prefix sniff timeout must be non-zero");
+        }
+
+        if self.initial_read_deadline.is_some_and(|x| x.is_zero()) {
+            problems.push("initial read deadline must be non-zero");
+        }
+
+        if self.slow_handler_threshold.is_some_and(|x| x.is_zero()) {
+            problems.push("slow handler threshold must be non-zero");
+        }
+
+        if let Some((threshold, _)) = &self.backlog_pressure {
+            if *threshold < Duration::from_secs(1) {
+                problems.push("backlog pressure threshold must be at least one second");
+            }
+
+            if self.accept_filter.is_some() {
+                problems.push("backlog pressure cannot be observed with an accept filter");
+            }
+        }
+
+        if self.affinity_by_numa_node && self.affinity_by_peer {
+            problems.push("affinity by NUMA node cannot be combined with affinity by peer");
+        }
+
+        if let Some((min, max)) = &self.adaptive_accepts {
+            if min > max {
+                problems.push("adaptive accepts minimum must not exceed the maximum");
+            }
+
+            if self.accept_filter.is_some() {
+                problems.push("adaptive accepts cannot be combined with an accept filter");
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::InvalidOptions(problems.join("; ")))
+        }
+    }
+
     /// Builds the TCP server and starts accepting new connections.
     ///
     /// The startup process is gradual and connections may be received even before the result of
@@ -64,26 +656,118 @@ where
     /// returns an error (though an error response does imply that no further connections will be
     /// accepted and the server has shut down after a failed start).
     pub async fn build(self) -> io::Result<TcpServerHandle> {
-        let port = self
-            .port
-            .ok_or_else(|| io::Error::InvalidOptions("port must be set".to_string()))?;
-        let on_accept = self
-            .on_accept
-            .ok_or_else(|| io::Error::InvalidOptions("on_accept must be set".to_string()))?;
+        self.validate()?;
+
+        // The port is determined from the adopted listen socket or bind addresses if there are any.
+        let port = self.port.unwrap_or(0);
+
+        let bind_addresses = match self.interface {
+            Some(name) => interface_bind_addresses(name, port).await?,
+            None => self
+                .bind_addresses
+                .into_iter()
+                .filter_map(|address| match address {
+                    SocketAddr::V4(address) => Some(address),
+                    SocketAddr::V6(_) => None, // Rejected by validation.
+                })
+                .collect(),
+        };
+        let on_accept = self.on_accept.expect("validated above");
+
+        let backpressure = self
+            .backpressure
+            .map(|(high_watermark, low_watermark, callback)| {
+                Arc::new(BackpressureMonitor::new(
+                    high_watermark.get() as u64,
+                    low_watermark as u64,
+                    callback,
+                ))
+            });
 
         let (startup_completed_tx, startup_completed_rx) = oneshot::channel();
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (accept_control_tx, accept_control_rx) = mpsc::unbounded();
+        let (first_connection_tx, first_connection_rx) = oneshot::channel();
+
+        let counters = Arc::new(ServerCounters::default());
+        let dispatcher_counters = Arc::clone(&counters);
+
+        let connections = Arc::new(ConnectionRegistry::default());
+        let dispatcher_connections = Arc::clone(&connections);
+
+        let events = ServerEventSender::new();
+        let event_subscriptions = events.subscriptions();
+
+        let options = TcpServerOptions {
+            port,
+            on_accept,
+            max_connections: self.max_connections,
+            on_overload: self.on_overload,
+            configure_socket: self.configure_socket,
+            keepalive: self.keepalive,
+            affinity_by_peer: self.affinity_by_peer,
+            affinity_by_numa_node: self.affinity_by_numa_node,
+            multiplex_connections: self.multiplex_connections,
+            listener: self.listener,
+            accept_backoff_initial: self.accept_backoff_initial,
+            accept_backoff_max: self.accept_backoff_max,
+            reuse_accept_sockets: self.reuse_accept_sockets,
+            backpressure,
+            bind_addresses,
+            dscp: self.dscp,
+            accept_filter: self.accept_filter,
+            routes: self.routes.into(),
+            prefix_sniff_timeout: self.prefix_sniff_timeout,
+            initial_read_deadline: self.initial_read_deadline,
+            slow_handler_threshold: self.slow_handler_threshold,
+            backlog_pressure: self.backlog_pressure,
+            adaptive_accepts: self.adaptive_accepts,
+            register_with_runtime: self.register_with_runtime,
+            query_rss_affinity: self.query_rss_affinity,
+        };
 
         let join_handle = current_runtime::with(|x| {
             x.spawn_tcp_dispatcher(move || async move {
-                TcpDispatcher::new(port, on_accept, startup_completed_tx, shutdown_rx)
-                    .run()
-                    .await
+                TcpDispatcher::new(
+                    options,
+                    dispatcher_counters,
+                    dispatcher_connections,
+                    events,
+                    startup_completed_tx,
+                    shutdown_rx,
+                    accept_control_rx,
+                    first_connection_tx,
+                )
+                .run()
+                .await
             })
-        });
+        })?;
+
+        // The dispatcher normally reports its startup result promptly. If it does not, something
+        // is wrong with the runtime (e.g. the dispatcher worker is stuck) and we give up instead of
+        // waiting forever. Should the dispatcher start after all, it will find the stop command.
+        let startup_result = match select(
+            startup_completed_rx,
+            Delay::with_clock(&Clock::new(), DISPATCHER_STARTUP_TIMEOUT),
+        )
+        .await
+        {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => {
+                _ = shutdown_tx.send(ShutdownCommand::Stop);
+
+                event!(
+                    Level::ERROR,
+                    "TCP dispatcher did not report startup result in time - giving up"
+                );
+                return Err(io::Error::Internal(format!(
+                    "TCP dispatcher did not report startup result within {DISPATCHER_STARTUP_TIMEOUT:?}"
+                )));
+            }
+        };
 
-        match startup_completed_rx.await {
-            Ok(Ok(())) => {}
+        let startup_report = match startup_result {
+            Ok(Ok(x)) => x,
             Ok(Err(e)) => {
                 event!(
                     Level::ERROR,
@@ -97,16 +781,27 @@ where
                     "TCP dispatcher died before reporting startup result".to_string(),
                 ));
             }
-        }
+        };
 
         // We create the server handle even if startup failed because we use it to command the stop
         // in case of a failed startup.
-        let server_handle = TcpServerHandle::new(join_handle, shutdown_tx);
+        let server_handle = TcpServerHandle::new(
+            join_handle,
+            shutdown_tx,
+            accept_control_tx,
+            first_connection_rx,
+            startup_report,
+            counters,
+            connections,
+            event_subscriptions,
+            self.stop_on_handle_drop,
+        );
 
         event!(
             Level::DEBUG,
             message = "TCP server started",
-            port = port.get()
+            port = server_handle.local_port(),
+            rss_enabled = server_handle.rss_enabled()
         );
 
         Ok(server_handle)
@@ -140,25 +835,218 @@ where
 
 /// Control surface to operate the TCP server. The lifetime of this is not directly connected to the
 /// TCP server. Dropping this will not stop the server - you must explicitly call `stop()` to stop
-/// the server, and may call `wait()` to wait for the server to complete its shutdown process.
+/// the server, unless the server was built with `TcpServerBuilder::stop_on_handle_drop()`.
 pub struct TcpServerHandle {
     dispatcher_join_handle: RemoteJoinHandle<()>,
 
     // Consumed after signal is sent.
-    dispatcher_shutdown_tx: Option<oneshot::Sender<()>>,
+    dispatcher_shutdown_tx: Option<oneshot::Sender<ShutdownCommand>>,
+
+    // Tells the dispatcher to pause or resume accepting connections.
+    dispatcher_accept_control_tx: mpsc::UnboundedSender<AcceptControl>,
+
+    // Completes when the first connection is dispatched, or with an error if the dispatcher stops
+    // before that happens. Shared so that any number of callers can wait for it.
+    first_connection_rx: Shared<oneshot::Receiver<()>>,
+
+    local_port: u16,
+
+    // The worker thread that the TCP dispatcher reported it is running on.
+    dispatcher_worker: WorkerId,
+
+    rss_enabled: bool,
+
+    counters: Arc<ServerCounters>,
+
+    // Shared with the dispatcher, which registers every connection it hands to `on_accept`.
+    connections: Arc<ConnectionRegistry>,
+
+    event_subscriptions: ServerEventSubscriptions,
+
+    // If set, we call `stop()` when dropped.
+    stop_on_drop: bool,
 }
 
 impl TcpServerHandle {
+    #[allow(clippy::too_many_arguments)] // Private constructor, called from one place.
     fn new(
         dispatcher_join_handle: RemoteJoinHandle<()>,
-        dispatcher_shutdown_tx: oneshot::Sender<()>,
+        dispatcher_shutdown_tx: oneshot::Sender<ShutdownCommand>,
+        dispatcher_accept_control_tx: mpsc::UnboundedSender<AcceptControl>,
+        first_connection_rx: oneshot::Receiver<()>,
+        startup_report: StartupReport,
+        counters: Arc<ServerCounters>,
+        connections: Arc<ConnectionRegistry>,
+        event_subscriptions: ServerEventSubscriptions,
+        stop_on_drop: bool,
     ) -> Self {
         Self {
             dispatcher_join_handle,
             dispatcher_shutdown_tx: Some(dispatcher_shutdown_tx),
+            dispatcher_accept_control_tx,
+            first_connection_rx: first_connection_rx.shared(),
+            local_port: startup_report.local_port,
+            dispatcher_worker: startup_report.dispatcher_worker,
+            rss_enabled: startup_report.rss_enabled,
+            counters,
+            connections,
+            event_subscriptions,
+            stop_on_drop,
         }
     }
 
+    /// The port the server is listening on. This is the port given to the builder, unless the
+    /// operating system was asked to choose one via `ephemeral_port()`.
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// The worker that the TCP dispatcher is running on. All connections of the server are accepted
+    /// by this single worker, so its placement is relevant when reasoning about accept throughput.
+    ///
+    /// The dispatcher worker is not pinned to a processor, so the processor is the one the
+    /// dispatcher was observed on when it completed startup.
+    pub fn dispatcher_worker(&self) -> &WorkerId {
+        &self.dispatcher_worker
+    }
+
+    /// Whether receive side scaling (RSS) was found to be enabled on the system when the server
+    /// started. Without RSS, connections carry no processor affinity information, so the server
+    /// does not attempt to query it for each accepted connection.
+    pub fn rss_enabled(&self) -> bool {
+        self.rss_enabled
+    }
+
+    /// Returns a snapshot of the activity counters of the server. The counters keep being updated
+    /// after the server is stopped, as long as previously accepted connections remain in use.
+    pub fn stats(&self) -> ServerStats {
+        self.counters.snapshot()
+    }
+
+    /// Closes a connection accepted by this server by abandoning its `on_accept` handler: the
+    /// future returned by `on_accept` is dropped, along with everything it owns - normally
+    /// including the `TcpConnection`, which closes the connection. Any I/O operations the handler
+    /// was waiting for are abandoned. Returns `true` if the handler was told to stop.
+    ///
+    /// Returns `false` if there is no such connection. Connections are only known to the server
+    /// while their `on_accept` handler is running, so this is also what happens if the connection
+    /// has already closed on its own, or is racing to do so - a handler that completes just as the
+    /// close request arrives may complete normally instead of being abandoned, even if `true` was
+    /// returned. Either way, the connection is gone or about to be. Connections given to the
+    /// overload handler cannot be closed this way.
+    ///
+    /// The handler is abandoned on its own worker thread, so the connection may remain open for
+    /// a short time after this returns. If the handler has moved the `TcpConnection` elsewhere
+    /// (e.g. via `TcpConnection::migrate_to_worker()`), the connection is not closed.
+    pub fn close_connection(&self, id: ConnectionId) -> bool {
+        self.connections.close(id)
+    }
+
+    /// Subscribes to the lifecycle events of the server (connections accepted and closed, errors).
+    /// Events that happened before subscribing are not delivered. Any number of subscribers may
+    /// exist at the same time and the stream may be moved to any thread.
+    ///
+    /// The stream is lossy - a subscriber that falls behind misses events instead of slowing down
+    /// the server. See `ServerEvents` for details.
+    pub fn events(&self) -> ServerEvents {
+        self.event_subscriptions.subscribe()
+    }
+
+    /// Waits until the server has dispatched its first accepted connection to a handler, which
+    /// indicates that the server is actually serving. Resolves immediately if this already happened.
+    ///
+    /// Resolves to `false` if the server stopped before it dispatched any connection.
+    pub fn wait_for_first_connection(&self) -> impl Future<Output = bool> {
+        self.first_connection_rx
+            .clone()
+            .map(|result| result.is_ok())
+    }
+
+    /// The number of accept operations currently waiting for an incoming connection. The server
+    /// keeps a fixed number of accept operations going, starting a new one whenever one completes.
+    ///
+    /// If this stays near zero, the server cannot prepare new accept operations as fast as
+    /// connections arrive (e.g. because creating sockets is slow). If this stays at the maximum,
+    /// connections are not arriving faster than the server can accept them.
+    pub fn pending_accepts(&self) -> usize {
+        self.counters
+            .pending_accepts
+            .load(atomic::Ordering::Relaxed)
+    }
+
+    /// The number of accept operations the server aims to keep in flight, across all listen
+    /// sockets. This is fixed unless `TcpServerBuilder::adaptive_accepts()` is used, in which case
+    /// it follows the rate at which connections arrive.
+    ///
+    /// Compare with `pending_accepts()`, which is how many are actually in flight.
+    pub fn accept_concurrency(&self) -> usize {
+        self.counters
+            .accept_concurrency
+            .load(atomic::Ordering::Relaxed)
+    }
+
+    /// Pauses accepting new connections without closing the listen socket or affecting existing
+    /// connections. Connections that arrive while paused wait in the listen queue of the socket
+    /// until `resume()` is called (or are refused by the operating system if the queue is full).
+    ///
+    /// The method returns immediately. The accept operations in flight are canceled, though
+    /// connections that they accepted before the cancellation took effect are still dispatched.
+    /// With an accept filter (see `TcpServerBuilder::accept_filter()`), the accept operations in
+    /// flight cannot be canceled, so up to one connection per listen socket may still be accepted
+    /// and dispatched after the server is paused.
+    ///
+    /// Has no effect if the server is already paused or has stopped.
+    pub fn pause(&self) {
+        event!(Level::TRACE, "signaling TCP dispatcher to pause accepting");
+
+        // We ignore the result (maybe the remote side is already terminated).
+        _ = self
+            .dispatcher_accept_control_tx
+            .unbounded_send(AcceptControl::Pause);
+    }
+
+    /// Resumes accepting new connections after `pause()`. The method returns immediately.
+    ///
+    /// Has no effect if the server is not paused or has stopped.
+    pub fn resume(&self) {
+        event!(Level::TRACE, "signaling TCP dispatcher to resume accepting");
+
+        // We ignore the result (maybe the remote side is already terminated).
+        _ = self
+            .dispatcher_accept_control_tx
+            .unbounded_send(AcceptControl::Resume);
+    }
+
+    /// Puts the server into lame-duck mode: it stops accepting new connections and closes its
+    /// listen sockets, so load balancers probing the port take it out of rotation, but existing
+    /// connections are never touched - their handlers keep running until they complete on their
+    /// own. The method returns immediately.
+    ///
+    /// Before the listen sockets are closed, the pending accept operations are canceled and any
+    /// connections the operating system already accepted are still dispatched to `on_accept`, so no
+    /// connection that reached the server is dropped unserved. Connections still waiting in the
+    /// listen queue are refused when the socket closes.
+    ///
+    /// Unlike `pause()`, this cannot be undone - a new server must be started to accept
+    /// connections again. Unlike `stop()`, this is a drain rather than a shutdown. To drain and
+    /// then exit, wait until `stats().connections_active` drops to zero before stopping the
+    /// runtime.
+    ///
+    /// Has no effect if the server has already been stopped or put into lame-duck mode.
+    pub fn lame_duck(&mut self) {
+        let Some(dispatcher_shutdown_tx) = self.dispatcher_shutdown_tx.take() else {
+            // Shutdown signal already sent.
+            return;
+        };
+
+        // We ignore the result (maybe the remote side is already terminated).
+        event!(
+            Level::TRACE,
+            "signaling TCP dispatcher to enter lame-duck mode"
+        );
+        _ = dispatcher_shutdown_tx.send(ShutdownCommand::LameDuck);
+    }
+
     /// Stop the server. This will signal the server that it is to stop accepting new connections,
     /// and will start terminating existing connections. The method returns immediately. It may take
     /// some unspecified time for connection dispatch to actually stop and for ongoing connections
@@ -171,19 +1059,210 @@ impl TcpServerHandle {
 
         // We ignore the result (maybe the remote side is already terminated).
         event!(Level::TRACE, "signaling TCP dispatcher to stop");
-        let _ = dispatcher_shutdown_tx.send(());
+        let _ = dispatcher_shutdown_tx.send(ShutdownCommand::Stop);
     }
-}
-
-#[negative_impl]
-impl !Send for TcpServerHandle {}
-#[negative_impl]
-impl !Sync for TcpServerHandle {}
 
-const CONCURRENT_ACCEPT_OPERATIONS: usize = 1024;
+    /// Stops the server like `stop()` but instead of closing the listen socket, releases it to the
+    /// caller, who can give it to a new server via `TcpServerBuilder::from_listener()`. Connections
+    /// that arrive in the meantime wait in the listen queue of the socket.
+    ///
+    /// The socket is only released after all pending accept operations on it have been canceled and
+    /// the socket has been unbound from the I/O completion port of the current server. Connections
+    /// that were accepted in the meantime are still dispatched to the current server.
+    ///
+    /// Ownership of the socket transfers to the caller - the current server will no longer use or
+    /// close the socket, so it must be closed by whoever owns it last (which `OwnedHandle` does on
+    /// drop). Never close the raw `SOCKET` by other means while an `OwnedHandle` still owns it.
+    ///
+    /// Returns an error if the server has already been stopped or has stopped on its own, or if
+    /// the server listens on multiple sockets (see `TcpServerBuilder::bind_addresses()`), in which
+    /// case the server is stopped normally.
+    pub async fn stop_and_release_listener(&mut self) -> io::Result<OwnedHandle<SOCKET>> {
+        let Some(dispatcher_shutdown_tx) = self.dispatcher_shutdown_tx.take() else {
+            return Err(io::Error::LogicError(
+                "the TCP server has already been stopped".to_string(),
+            ));
+        };
+
+        let (listener_tx, listener_rx) = oneshot::channel();
+
+        event!(
+            Level::TRACE,
+            "signaling TCP dispatcher to stop and release the listen socket"
+        );
+
+        if dispatcher_shutdown_tx
+            .send(ShutdownCommand::ReleaseListener(listener_tx))
+            .is_err()
+        {
+            return Err(io::Error::LogicError(
+                "the TCP server has already stopped".to_string(),
+            ));
+        }
+
+        listener_rx.await.map_err(|_| {
+            io::Error::Internal(
+                "TCP dispatcher died before releasing the listen socket".to_string(),
+            )
+        })?
+    }
+}
+
+impl Drop for TcpServerHandle {
+    fn drop(&mut self) {
+        if self.stop_on_drop {
+            self.stop();
+        }
+    }
+}
+
+/// Resolves the addresses of a network interface into the addresses to listen on. The lookup is a
+/// blocking call, so it happens on a synchronous worker thread.
+async fn interface_bind_addresses(name: String, port: u16) -> io::Result<Vec<SocketAddrV4>> {
+    let (name, addresses) = current_runtime::with(|runtime| {
+        runtime.spawn_sync(SynchronousTaskType::Syscall, move || {
+            interfaces::ipv4_addresses_of_interface(&name).map(|addresses| (name, addresses))
+        })
+    })
+    .await?;
+
+    let Some(addresses) = addresses else {
+        return Err(io::Error::InvalidOptions(format!(
+            "network interface '{name}' does not exist"
+        )));
+    };
+
+    if addresses.is_empty() {
+        return Err(io::Error::InvalidOptions(format!(
+            "network interface '{name}' has no IPv4 address"
+        )));
+    }
+
+    Ok(addresses
+        .into_iter()
+        .map(|address| SocketAddrV4::new(address, port))
+        .collect())
+}
+
+/// What the TCP dispatcher reports back to the builder once it has started.
+struct StartupReport {
+    local_port: u16,
+    dispatcher_worker: WorkerId,
+    rss_enabled: bool,
+}
+
+/// What the TCP dispatcher is to do when shutting down.
+enum ShutdownCommand {
+    Stop,
+
+    // Every connection accepted by the operating system is to be dispatched before we stop.
+    LameDuck,
+
+    // The listen socket is to be released via this channel instead of closed.
+    ReleaseListener(oneshot::Sender<io::Result<OwnedHandle<SOCKET>>>),
+}
+
+/// Temporarily changes whether the TCP dispatcher accepts new connections.
+enum AcceptControl {
+    Pause,
+    Resume,
+}
+
+#[negative_impl]
+impl !Send for TcpServerHandle {}
+#[negative_impl]
+impl !Sync for TcpServerHandle {}
+
+/// The validated configuration of a TCP server, handed over from the builder to the dispatcher.
+struct TcpServerOptions<A, AF>
+where
+    A: Fn(TcpConnection) -> AF + Clone + Send + 'static,
+    AF: Future<Output = io::Result<()>> + 'static,
+{
+    // Zero means the port is chosen by the operating system.
+    port: u16,
+
+    // Whenever we receive a new connection, we spawn a new task with this callback to handle it.
+    // Once we schedule a task to call this, the dispatcher forgets about the connection - anything
+    // that happens afterward is the responsibility of the TcpConnection to organize.
+    on_accept: A,
+
+    max_connections: Option<NonZeroUsize>,
+
+    // Receives the connections that arrive while we are at `max_connections`.
+    // If not set, such connections are closed immediately.
+    on_overload: Option<OverloadHandler>,
+
+    // Applied to every accepted connection socket by the AcceptOne that accepted it.
+    configure_socket: Option<SocketConfigurator>,
+
+    // If set, SO_KEEPALIVE is enabled on every accepted connection socket.
+    keepalive: bool,
+
+    // If set, the worker for each connection is chosen based on the peer address.
+    affinity_by_peer: bool,
+
+    // If set, the worker for each connection is chosen from the NUMA node reported by RSS.
+    affinity_by_numa_node: bool,
+
+    // If set, connections are handed over to a multiplexer on each worker instead of each getting
+    // a task of its own.
+    multiplex_connections: bool,
+
+    // A listen socket adopted from elsewhere, used instead of creating our own. Consumed on startup.
+    listener: Option<OwnedHandle<SOCKET>>,
+
+    accept_backoff_initial: Duration,
+    accept_backoff_max: Duration,
+
+    // If set, the dispatcher recycles connection sockets via a pool.
+    reuse_accept_sockets: bool,
+
+    // Shared with every connection we dispatch, as they report when they are no longer active.
+    backpressure: Option<Arc<BackpressureMonitor>>,
+
+    // If not empty, we listen on each of these instead of on `port` on all addresses.
+    bind_addresses: Vec<SocketAddrV4>,
+
+    // Applied to every accepted connection on the worker that handles it.
+    dscp: Option<u8>,
+
+    // If set, connections are accepted conditionally instead of via AcceptEx.
+    accept_filter: Option<AcceptFilter>,
+
+    // If not empty, each connection is given to the handler of the first route its initial bytes
+    // match, falling back to `on_accept`. Shared with every connection task.
+    routes: Arc<[PrefixRoute]>,
+    prefix_sniff_timeout: Duration,
+
+    // Applied to every accepted connection on the worker that handles it, until the handler marks
+    // the connection as established.
+    initial_read_deadline: Option<Duration>,
+
+    // If set, handlers that take at least this long to complete are reported as slow.
+    slow_handler_threshold: Option<Duration>,
+
+    // If set, we measure how long each connection waited to be accepted and report the ones that
+    // waited at least the threshold.
+    backlog_pressure: Option<(Duration, BacklogPressureCallback)>,
+
+    // If set, the number of accept operations in flight is tuned between these bounds.
+    adaptive_accepts: Option<(NonZeroUsize, NonZeroUsize)>,
+
+    // If set, the server is listed in the server registry of the runtime while it is running.
+    register_with_runtime: bool,
+
+    // If set (and RSS is enabled), we query the processor affinity of every accepted connection.
+    query_rss_affinity: bool,
+}
+
+const CONCURRENT_ACCEPT_OPERATIONS: usize = 1024;
 // The default assigned by the OS seems to be around 128, which is not enough under high load.
 const PENDING_CONNECTION_LIMIT: i32 = 4096;
 
+// How long `TcpServerBuilder::build()` waits for the TCP dispatcher to report its startup result.
+const DISPATCHER_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// The TCP dispatcher manages the listen socket used to receive new connections. When a new
 /// connection is received, it is dispatched to be handled by the user-defined callback on a
 /// suitable worker, at which point the dispatcher is no longer involved.
@@ -192,20 +1271,51 @@ where
     A: Fn(TcpConnection) -> AF + Clone + Send + 'static,
     AF: Future<Output = io::Result<()>> + 'static,
 {
-    // We signal this once we are ready to receive connections (or when startup fails).
-    // If this is an error, you can expect that this (or a similar) error will also be included in
-    // the result of `TcpServerHandle::wait()`. Consumed on use.
-    startup_completed_tx: Option<oneshot::Sender<io::Result<()>>>,
+    // We signal this once we are ready to receive connections (or when startup fails), reporting
+    // the port we ended up listening on and the worker we are running on. If this is an error, you
+    // can expect that this (or a similar) error will also be included in the result of
+    // `TcpServerHandle::wait()`. Consumed on use.
+    startup_completed_tx: Option<oneshot::Sender<io::Result<StartupReport>>>,
 
     // If we receive a message from here, it means we need to shut down. Consumed on use.
-    shutdown_rx: Option<oneshot::Receiver<()>>,
+    shutdown_rx: Option<oneshot::Receiver<ShutdownCommand>>,
 
-    port: NonZeroU16,
+    // Tells us to pause or resume starting new accept operations. Consumed on startup.
+    accept_control_rx: Option<mpsc::UnboundedReceiver<AcceptControl>>,
 
-    // Whenever we receive a new connection, we spawn a new task with this callback to handle it.
-    // Once we schedule a task to call this, the dispatcher forgets about the connection - anything
-    // that happens afterward is the responsibility of the TcpConnection to organize.
-    on_accept: A,
+    // We signal this when we dispatch the first connection. Consumed on use. If we stop before
+    // that, dropping it tells the server handle that no connection was ever dispatched.
+    first_connection_tx: Cell<Option<oneshot::Sender<()>>>,
+
+    options: TcpServerOptions<A, AF>,
+
+    // Shared with the server handle and every connection we dispatch. The active connection count
+    // is incremented only by the dispatcher but decremented by whichever worker the connection was
+    // dispatched to.
+    counters: Arc<ServerCounters>,
+
+    // Every connection we dispatch to `on_accept` registers itself here while its handler runs, so
+    // the server handle can close it. Shared with the server handle.
+    connections: Arc<ConnectionRegistry>,
+
+    // Publishes lifecycle events to subscribers. Shared with every connection we dispatch to
+    // `on_accept`, so subscribers see the end of the stream only once those are all closed.
+    events: Arc<ServerEventSender>,
+
+    // Sockets of closed connections, ready to accept new connections. Only present if socket reuse
+    // is enabled. Shared with every connection we dispatch, which return their sockets here.
+    socket_pool: Option<Arc<AcceptSocketPool>>,
+
+    // One per async worker, if connections are multiplexed. Started on startup. Dropping these
+    // lets the multiplexers stop once they have finished handling their connections.
+    multiplexers: Option<Vec<MultiplexerSender>>,
+
+    // The multiplexer that receives the next connection, unless placed by peer address.
+    next_multiplexer: Cell<usize>,
+
+    // Lists the server in the server registry of the runtime, if registration is enabled. Created
+    // on startup and dropped when we stop.
+    registration: Option<ServerRegistration>,
     // TODO: on_connection_error (callback if connection fails, probably without affecting other connections or general health)
     // TODO: on_worker_error (callback if worker-level operation fails and we probably will not receive more traffic on this worker)
     // TODO: on_handler_error (callback if on_accept fails; do we need this or just let on_accept worry about it?)
@@ -216,24 +1326,64 @@ where
     A: Fn(TcpConnection) -> AF + Clone + Send + 'static,
     AF: Future<Output = io::Result<()>> + 'static,
 {
+    #[allow(clippy::too_many_arguments)] // Private constructor, called from one place.
     fn new(
-        port: NonZeroU16,
-        on_accept: A,
-        startup_completed_tx: oneshot::Sender<io::Result<()>>,
-        shutdown_rx: oneshot::Receiver<()>,
+        options: TcpServerOptions<A, AF>,
+        counters: Arc<ServerCounters>,
+        connections: Arc<ConnectionRegistry>,
+        events: ServerEventSender,
+        startup_completed_tx: oneshot::Sender<io::Result<StartupReport>>,
+        shutdown_rx: oneshot::Receiver<ShutdownCommand>,
+        accept_control_rx: mpsc::UnboundedReceiver<AcceptControl>,
+        first_connection_tx: oneshot::Sender<()>,
     ) -> Self {
+        let socket_pool = options
+            .reuse_accept_sockets
+            .then(|| Arc::new(AcceptSocketPool::default()));
+
         Self {
-            port,
-            on_accept,
+            options,
+            counters,
+            connections,
+            events: Arc::new(events),
+            socket_pool,
+            multiplexers: None,
+            next_multiplexer: Cell::new(0),
+            registration: None,
             startup_completed_tx: Some(startup_completed_tx),
             shutdown_rx: Some(shutdown_rx),
+            accept_control_rx: Some(accept_control_rx),
+            first_connection_tx: Cell::new(Some(first_connection_tx)),
         }
     }
 
     async fn run(&mut self) {
         let startup_result = match self.startup().await {
             Ok(x) => {
-                _ = self.startup_completed_tx.take().expect("we have completed startup so the tx must still be there because this is the only thing that uses it").send(Ok(()));
+                // We register before reporting success, so the server is listed by the time
+                // `TcpServerBuilder::build()` returns.
+                if self.options.register_with_runtime {
+                    self.registration = Some(current_runtime::with(|runtime| {
+                        runtime
+                            .server_registry()
+                            .register(x.local_port, Arc::clone(&self.counters))
+                    }));
+                }
+
+                let report = StartupReport {
+                    local_port: x.local_port,
+                    dispatcher_worker: WorkerId::current(),
+                    rss_enabled: x.rss_enabled,
+                };
+
+                _ = self
+                    .startup_completed_tx
+                    .take()
+                    .expect(
+                        "we have completed startup so the tx must still be there because this is \
+                         the only thing that uses it",
+                    )
+                    .send(Ok(report));
                 x
             }
             Err(e) => {
@@ -248,171 +1398,1029 @@ where
             }
         };
 
-        // Now we are up and running. Until we receive a shutdown command, we will keep accepting
-        // new connections and dispatching them to be handled by the user-defined callback.
-        self.run_accept_loop(startup_result).await;
-    }
+        if self.options.multiplex_connections {
+            self.multiplexers = Some(connection_multiplexer::start_on_all_workers());
+        }
+
+        // Now we are up and running. Until we receive a shutdown command, we will keep accepting
+        // new connections and dispatching them to be handled by the user-defined callback.
+        self.run_accept_loop(startup_result).await;
+
+        // We are no longer accepting connections, so we no longer list the server.
+        self.registration = None;
+    }
+
+    async fn startup(&mut self) -> io::Result<StartedTcpDispatcher> {
+        winsock::ensure_initialized();
+
+        let conditional = self.options.accept_filter.is_some();
+
+        // If anything fails, the sockets opened so far are closed when this is dropped.
+        let listen_sockets = match self.options.listener.take() {
+            // Someone else already bound the socket and started listening.
+            Some(listener) => vec![listener],
+            None if self.options.bind_addresses.is_empty() => {
+                vec![Self::open_listen_socket(
+                    SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.options.port),
+                    conditional,
+                )?]
+            }
+            None => self
+                .options
+                .bind_addresses
+                .iter()
+                .map(|address| Self::open_listen_socket(*address, conditional))
+                .collect::<io::Result<Vec<_>>>()?,
+        };
+
+        // If the operating system chose the port for us (or if the socket was adopted), this is how
+        // we find out which one it is.
+        let mut bound_addr = SOCKADDR_IN::default();
+        let mut bound_addr_len = mem::size_of::<SOCKADDR_IN>() as i32;
+
+        // SAFETY: The pointer and length describe a valid SOCKADDR_IN, which is what we bound to.
+        let local_port = unsafe {
+            winsock::to_io_result(getsockname(
+                *listen_sockets[0],
+                &mut bound_addr as *mut _ as *mut _,
+                &mut bound_addr_len as *mut _,
+            ))?;
+
+            ntohs(bound_addr.sin_port)
+        };
+
+        // Bind the sockets to the I/O completion port so we can process I/O completions.
+        for listen_socket in &listen_sockets {
+            current_async_agent::with_io(|io| io.bind_io_primitive(&**listen_socket))?;
+        }
+
+        event!(
+            Level::TRACE,
+            message = "opened TCP sockets for accepting connections",
+            count = listen_sockets.len()
+        );
+
+        // Without RSS, querying the processor affinity of each connection would only ever fail.
+        let rss_enabled = match winsock::is_rss_enabled(*listen_sockets[0]) {
+            Ok(enabled) => enabled,
+            Err(e) => {
+                event!(
+                    Level::DEBUG,
+                    message = "unable to determine whether RSS is enabled - assuming it is not",
+                    error = e.to_string()
+                );
+                false
+            }
+        };
+
+        let listen_sockets: Vec<_> = listen_sockets.into_iter().map(Arc::new).collect();
+
+        let conditional_acceptors = match &self.options.accept_filter {
+            Some(filter) => Some(
+                listen_sockets
+                    .iter()
+                    .map(|listen_socket| {
+                        ConditionalAcceptor::new(Arc::clone(listen_socket), Arc::clone(filter))
+                            .map(Rc::new)
+                    })
+                    .collect::<io::Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        Ok(StartedTcpDispatcher {
+            listen_sockets,
+            local_port,
+            rss_enabled,
+            conditional_acceptors,
+        })
+    }
+
+    fn open_listen_socket(
+        address: SocketAddrV4,
+        conditional: bool,
+    ) -> io::Result<OwnedHandle<SOCKET>> {
+        // SAFETY: We are required to close the handle once we are done with it,
+        // which we do via OwnedHandle that closes the handle on drop.
+        let listen_socket = unsafe {
+            OwnedHandle::new(WSASocketA(
+                AF_INET.0 as i32,
+                SOCK_STREAM.0,
+                IPPROTO_TCP.0,
+                None,
+                0,
+                WSA_FLAG_OVERLAPPED,
+            )?)
+        };
+
+        // TODO: Set send/receiver buffer sizes (will be inherited by spawned connections).
+
+        let mut addr = IN_ADDR::default();
+        addr.S_un.S_addr = u32::from(*address.ip()).to_be();
+
+        let socket_addr = SOCKADDR_IN {
+            sin_family: AF_INET,
+            // SAFETY: Nothing unsafe here, just an FFI call.
+            sin_port: unsafe { htons(address.port()) },
+            sin_addr: addr,
+            sin_zero: [0; 8],
+        };
+
+        // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+        unsafe {
+            winsock::to_io_result(bind(
+                *listen_socket,
+                &socket_addr as *const _ as *const _,
+                mem::size_of::<SOCKADDR_IN>() as i32,
+            ))?;
+        };
+
+        // This only takes effect if enabled before we start listening.
+        if conditional {
+            conditional_accept::enable(*listen_socket)?;
+        }
+
+        // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+        unsafe {
+            // A raw value for the queue length must be wrapped in the SOMAXCONN_HINT macro,
+            // which really is just negation - a negative value means "use the absolute value".
+            winsock::to_io_result(listen(*listen_socket, -PENDING_CONNECTION_LIMIT))?;
+        };
+
+        Ok(listen_socket)
+    }
+
+    async fn run_accept_loop(&mut self, startup_result: StartedTcpDispatcher) {
+        let listen_sockets = startup_result.listen_sockets;
+        let rss_enabled = startup_result.rss_enabled;
+        let conditional_acceptors = startup_result.conditional_acceptors;
+        let handle_limit = current_runtime::with(|runtime| runtime.handle_limit());
+        let max_buffer_memory = current_runtime::with(|runtime| runtime.max_buffer_memory());
+
+        // The accept operations are split evenly between the listen sockets. We track how many are
+        // in flight for each, so we know which socket to start new operations on. Conditional
+        // accepting handles one connection at a time, so there is no point in having more.
+        let mut accept_tuner = self.options.adaptive_accepts.map(|(min, max)| {
+            AcceptTuner::new(
+                min.get(),
+                max.get(),
+                Instant::now(),
+                self.counters
+                    .backlog_pressure
+                    .load(atomic::Ordering::Relaxed),
+            )
+        });
+
+        let accept_concurrency = match &accept_tuner {
+            Some(tuner) => tuner.target(),
+            None => CONCURRENT_ACCEPT_OPERATIONS,
+        };
+
+        let accepts_per_socket = if conditional_acceptors.is_some() {
+            1
+        } else {
+            (accept_concurrency / listen_sockets.len()).max(1)
+        };
+
+        self.counters.accept_concurrency.store(
+            accepts_per_socket * listen_sockets.len(),
+            atomic::Ordering::Relaxed,
+        );
+
+        // The act of accepting a connection is simply the first part of the lifecycle of a
+        // TcpConnection, so we can think of this as just a very long drawn-out constructor.
+        // On the other hand, accepting a connection does require use of resources owned by the
+        // dispatcher, so it is not an entirely isolated concept. We ensure proper resource
+        // management by always polling the accept future directly from within the dispatcher
+        // itself, so we do not need to create any complex resource sharing scheme for e.g. the
+        // socket because we stop polling if we release the resources. Any ongoing accept operations
+        // will be terminated when the socket is closed, after which the I/O driver will process a
+        // completion that will not be received by any awaiter any more and thus will be ignored.
+        // When we are shutting down, this operation will simply be abandoned - if the socket is
+        // still open at that point, the I/O driver of the dispatcher cancels the operation itself
+        // before it finishes shutting down.
+        //
+        // TODO: If a completion is ignored, won't that leave a dangling socket?
+        // Do we need some Operation::on_cancel() callback to clean up the socket in that case?
+        //
+        // Because we are doing two things concurrently (accepting connections + awaiting orders),
+        // we must use interior mutability or exclusive mutability only for one of these futures.
+        // We cannot give an exclusive reference to both futures.
+
+        // All the ongoing accept operations. We will keep this filled up to the limit of
+        // CONCURRENT_ACCEPT_OPERATIONS (or the target of the accept tuner), so whenever some get
+        // accepted, more accepts get queued.
+        let mut accept_loop = AcceptLoop::new(listen_sockets.len(), accepts_per_socket);
+
+        // If this completes, we shut down the dispatcher.
+        let mut shutdown_rx = self.shutdown_rx.take().expect("we only take this once");
+
+        // While paused, we do not start new accept operations. The ones in flight may complete.
+        let mut accept_control_rx = self
+            .accept_control_rx
+            .take()
+            .expect("we only take this once");
+        let mut paused = false;
+
+        // Set if the server handle was dropped without telling us to stop.
+        let mut handle_dropped = false;
+
+        // Set while we cancel the accept operations in flight (when pausing or releasing the listen
+        // socket), to prevent new ones from being started while we wait for them to complete.
+        let canceling_accepts = Rc::new(Cell::new(false));
+
+        // Shared by all accept operations, which pause if socket creation keeps failing.
+        let backoff = Rc::new(AcceptBackoff::new(
+            self.options.accept_backoff_initial,
+            self.options.accept_backoff_max,
+        ));
+
+        loop {
+            if !paused {
+                accept_loop.refill(|index| match &conditional_acceptors {
+                    Some(acceptors) => Either::Left(accept_conditionally(
+                        Rc::clone(&acceptors[index]),
+                        self.options.configure_socket.clone(),
+                        self.options.keepalive,
+                        Arc::clone(&self.counters),
+                    )),
+                    None => Either::Right(
+                        AcceptOne {
+                            listen_socket: Arc::clone(&listen_sockets[index]),
+                            configure_socket: self.options.configure_socket.clone(),
+                            keepalive: self.options.keepalive,
+                            canceling_accepts: Rc::clone(&canceling_accepts),
+                            backoff: Rc::clone(&backoff),
+                            socket_pool: self.socket_pool.clone(),
+                            counters: Arc::clone(&self.counters),
+                            query_affinity: rss_enabled
+                                && (self.options.query_rss_affinity
+                                    || self.options.affinity_by_numa_node),
+                            query_connect_time: self.options.backlog_pressure.is_some(),
+                            handle_limit,
+                            max_buffer_memory,
+                        }
+                        .execute(),
+                    ),
+                });
+            }
+
+            event!(
+                Level::TRACE,
+                message = "waiting for new connection or shutdown",
+                accept_futures_len = accept_loop.len(),
+            );
+
+            // Once the server handle is dropped without a stop command, nobody can give us orders
+            // anymore and we keep running until the runtime stops.
+            let orders = if handle_dropped {
+                Either::Left(future::pending())
+            } else {
+                Either::Right(select(&mut shutdown_rx, accept_control_rx.next()))
+            };
+
+            // If we are paused, we may run out of accept operations, in which case we just wait
+            // for orders.
+            let accept_result = match accept_loop.next_or(orders).await {
+                Either::Left((_, accept_result)) => {
+                    if let Some(tuner) = &mut accept_tuner {
+                        let backlog_pressure = self
+                            .counters
+                            .backlog_pressure
+                            .load(atomic::Ordering::Relaxed);
+
+                        if let Some(target) =
+                            tuner.record_completion(Instant::now(), backlog_pressure)
+                        {
+                            let per_socket = (target / listen_sockets.len()).max(1);
+                            accept_loop.set_per_source(per_socket);
+
+                            self.counters.accept_concurrency.store(
+                                per_socket * listen_sockets.len(),
+                                atomic::Ordering::Relaxed,
+                            );
+
+                            event!(
+                                Level::DEBUG,
+                                message = "adjusted number of accept operations in flight",
+                                target
+                            );
+                        }
+                    }
+
+                    accept_result
+                }
+                Either::Right(Either::Right((control, _))) => {
+                    match control {
+                        Some(AcceptControl::Pause) => {
+                            event!(Level::DEBUG, "TCP dispatcher pausing accepting");
+                            paused = true;
+
+                            // Conditional accepting happens on synchronous worker threads, where
+                            // the accept operations cannot be canceled, so those run to completion.
+                            if conditional_acceptors.is_none() {
+                                canceling_accepts.set(true);
+                                self.cancel_and_drain_accepts(&listen_sockets, &mut accept_loop)
+                                    .await;
+                                canceling_accepts.set(false);
+                            }
+
+                            if let Some(registration) = &self.registration {
+                                registration.set_paused(true);
+                            }
+                        }
+                        Some(AcceptControl::Resume) => {
+                            event!(Level::DEBUG, "TCP dispatcher resuming accepting");
+                            paused = false;
+
+                            if let Some(registration) = &self.registration {
+                                registration.set_paused(false);
+                            }
+                        }
+                        // The server handle is gone. It drops the shutdown sender first, which we
+                        // check first, so we normally notice it there instead.
+                        None => handle_dropped = true,
+                    }
+
+                    continue;
+                }
+                Either::Right(Either::Left((Err(_), _))) => {
+                    event!(
+                        Level::DEBUG,
+                        "TCP server handle dropped - dispatcher keeps running"
+                    );
+                    handle_dropped = true;
+                    continue;
+                }
+                Either::Right(Either::Left((Ok(command), _))) => {
+                    event!(Level::DEBUG, "TCP dispatcher shutting down",);
+
+                    if let ShutdownCommand::LameDuck = command {
+                        // Conditional accepting happens on synchronous worker threads, where the
+                        // accept operations cannot be canceled, so we leave them to be abandoned.
+                        if conditional_acceptors.is_none() {
+                            canceling_accepts.set(true);
+                            self.cancel_and_drain_accepts(&listen_sockets, &mut accept_loop)
+                                .await;
+                        }
+
+                        event!(
+                            Level::DEBUG,
+                            "TCP dispatcher in lame-duck mode - closing listen sockets"
+                        );
+
+                        // The listen sockets are closed when dropped, taking the server out of
+                        // rotation. Existing connections are not ours to touch.
+                        return;
+                    }
+
+                    if let ShutdownCommand::ReleaseListener(listener_tx) = command {
+                        let result = match <[_; 1]>::try_from(listen_sockets) {
+                            Ok(_) if conditional_acceptors.is_some() => Err(io::Error::LogicError(
+                                "cannot release the listen socket of a server with an accept \
+                                 filter"
+                                    .to_string(),
+                            )),
+                            Ok([listen_socket]) => {
+                                canceling_accepts.set(true);
+                                self.release_listener(listen_socket, accept_loop).await
+                            }
+                            Err(_) => Err(io::Error::LogicError(
+                                "cannot release the listen socket of a server with multiple \
+                                 listen sockets"
+                                    .to_string(),
+                            )),
+                        };
+
+                        // We ignore the result because it may be that nobody is listening anymore.
+                        _ = listener_tx.send(result);
+                        return;
+                    }
+
+                    // We will not accept any new connections. The existing "accept one" operations
+                    // will be dropped soon and any pending I/O will likewise be canceled as soon
+                    // as the OwnedHandle is dropped and the socket gets closed.
+                    return;
+                }
+            };
+
+            event!(
+                Level::TRACE,
+                message = "detected incoming TCP connection (or error)",
+                accept_futures_len = accept_loop.len(),
+                ?accept_result
+            );
+
+            match accept_result {
+                Ok(accepted_connection) => self.dispatch_accepted(accepted_connection),
+                Err(AcceptError {
+                    inner,
+                    kind: AcceptErrorKind::Transient,
+                }) if winsock::is_connection_reset(&inner) => {
+                    // The peer gave up before we finished accepting. Nothing wrong with the server.
+                    self.counters
+                        .connections_reset_during_accept
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+
+                    event!(
+                        Level::DEBUG,
+                        message = "connection reset by peer while accepting - ignoring",
+                        error = inner.to_string()
+                    );
+                }
+                Err(AcceptError {
+                    inner,
+                    kind: AcceptErrorKind::Transient,
+                }) => {
+                    self.counters
+                        .connections_failed
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+
+                    event!(
+                        Level::ERROR,
+                        message = "error accepting new connection - ignoring",
+                        error = inner.to_string()
+                    );
+
+                    self.events.send(|| ServerEvent::AcceptError {
+                        error: Arc::new(inner),
+                    });
+                    // TODO: Report error to callback if not successfully accepted..
+                }
+                Err(AcceptError {
+                    inner,
+                    kind: AcceptErrorKind::Fatal,
+                }) => {
+                    // The listen socket is broken, so every remaining and future accept operation
+                    // would fail the same way. Rather than spin on errors forever, we shut down.
+                    event!(
+                        Level::ERROR,
+                        message = "listen socket failed - TCP dispatcher shutting down",
+                        error = inner.to_string()
+                    );
+
+                    self.events.send(|| ServerEvent::AcceptError {
+                        error: Arc::new(inner),
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Cancels all pending accept operations on the listen sockets and waits for them to complete.
+    /// Connections accepted before the cancellation took effect are dispatched as usual.
+    async fn cancel_and_drain_accepts<F>(
+        &self,
+        listen_sockets: &[Arc<OwnedHandle<SOCKET>>],
+        accept_loop: &mut AcceptLoop<F>,
+    ) where
+        F: Future<Output = Result<AcceptedConnection, AcceptError>>,
+    {
+        for listen_socket in listen_sockets {
+            // SAFETY: The socket is kept alive by the Arc. Canceling I/O has no safety requirements
+            // beyond a valid handle - the operations are still completed via the completion port.
+            unsafe {
+                // This fails if there was nothing to cancel, which is fine.
+                _ = CancelIoEx(HANDLE::from(IoPrimitive::from(***listen_socket)), None);
+            }
+        }
+
+        accept_loop
+            .drain(|accept_result| match accept_result {
+                Ok(accepted_connection) => self.dispatch_accepted(accepted_connection),
+                Err(e) => {
+                    // These are expected - we just canceled the operations.
+                    event!(
+                        Level::TRACE,
+                        message = "accept operation ended after cancellation",
+                        error = e.inner.to_string()
+                    );
+                }
+            })
+            .await;
+    }
+
+    /// Cancels all pending accept operations on the listen socket and unbinds it from our I/O
+    /// completion port, so it can be adopted by a different server. Connections accepted before
+    /// the cancellation took effect are dispatched as usual.
+    async fn release_listener<F>(
+        &self,
+        listen_socket: Arc<OwnedHandle<SOCKET>>,
+        mut accept_loop: AcceptLoop<F>,
+    ) -> io::Result<OwnedHandle<SOCKET>>
+    where
+        F: Future<Output = Result<AcceptedConnection, AcceptError>>,
+    {
+        // We must wait for every accept operation to complete before unbinding from the completion
+        // port, as completion notifications of canceled operations are still delivered to the port.
+        self.cancel_and_drain_accepts(slice::from_ref(&listen_socket), &mut accept_loop)
+            .await;
+
+        // All the accept operations are gone, so we are the last owner of the socket.
+        let listen_socket = Arc::into_inner(listen_socket).ok_or_else(|| {
+            io::Error::Internal(
+                "listen socket still in use after all accept operations completed".to_string(),
+            )
+        })?;
+
+        CompletionPort::unbind(&*listen_socket)?;
+
+        event!(Level::DEBUG, "listen socket released");
+
+        Ok(listen_socket)
+    }
+
+    /// Hands over a newly accepted connection to `on_accept` (or the overload handler).
+    fn dispatch_accepted(&self, accepted_connection: AcceptedConnection) {
+        let AcceptedConnection {
+            socket: connection_socket,
+            peer_addr,
+            connect_time,
+            numa_node,
+        } = accepted_connection;
+
+        self.counters
+            .connections_accepted
+            .fetch_add(1, atomic::Ordering::Relaxed);
+
+        if let (Some((threshold, on_backlog_pressure)), Some(waited)) =
+            (&self.options.backlog_pressure, connect_time)
+        {
+            if waited >= *threshold {
+                self.counters
+                    .backlog_pressure
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+
+                event!(
+                    Level::WARN,
+                    message = "connection waited in listen backlog for too long",
+                    waited_secs = waited.as_secs()
+                );
+
+                // A panic must not take down the dispatcher, which would stop all accepting.
+                if let Err(payload) =
+                    panic::catch_unwind(AssertUnwindSafe(|| (on_backlog_pressure)(waited)))
+                {
+                    event!(
+                        Level::ERROR,
+                        message = "backlog pressure callback panicked",
+                        panic = panic_message(payload.as_ref())
+                    );
+                }
+            }
+        }
+
+        // Only the dispatcher increments the counter, so it cannot grow between this check
+        // and the increment below - it can only shrink, which is harmless.
+        let at_limit = self.options.max_connections.is_some_and(|max| {
+            self.counters
+                .connections_active
+                .load(atomic::Ordering::Relaxed)
+                >= max.get() as u64
+        });
+
+        if at_limit {
+            let Some(on_overload) = self.options.on_overload.clone() else {
+                event!(
+                    Level::DEBUG,
+                    "connection limit reached - closing new connection"
+                );
+                drop(connection_socket);
+                return;
+            };
+
+            event!(
+                Level::DEBUG,
+                "connection limit reached - dispatching new connection to overload handler"
+            );
+
+            let counters = Arc::clone(&self.counters);
+            let socket_pool = self.socket_pool.clone();
+            let dscp = self.options.dscp;
+
+            self.dispatch(peer_addr, numa_node, move || async move {
+                let mut tcp_connection =
+                    TcpConnection::from_accepted_socket(connection_socket, counters, socket_pool);
+                apply_dscp(&mut tcp_connection, dscp);
+
+                // As with `on_accept`, a panic must not take down the worker.
+                if let Err(payload) = AssertUnwindSafe((on_overload)(tcp_connection))
+                    .catch_unwind()
+                    .await
+                {
+                    event!(
+                        Level::ERROR,
+                        message = "overload handler panicked - closing connection",
+                        panic = panic_message(payload.as_ref())
+                    );
+                }
+            });
+
+            return;
+        }
 
-    async fn startup(&mut self) -> io::Result<StartedTcpDispatcher> {
-        winsock::ensure_initialized();
+        let active_connections = self
+            .counters
+            .connections_active
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            + 1;
 
-        // SAFETY: We are required to close the handle once we are done with it,
-        // which we do via OwnedHandle that closes the handle on drop.
-        let listen_socket = unsafe {
-            OwnedHandle::new(WSASocketA(
-                AF_INET.0 as i32,
-                SOCK_STREAM.0,
-                IPPROTO_TCP.0,
-                None,
-                0,
-                WSA_FLAG_OVERLAPPED,
-            )?)
+        ACTIVE_CONNECTIONS.with(|x| x.observe(active_connections as Magnitude));
+
+        if let Some(backpressure) = &self.options.backpressure {
+            backpressure.on_increased(active_connections);
+        }
+
+        let active_connection_guard = ActiveConnectionGuard {
+            counters: Arc::clone(&self.counters),
+            backpressure: self.options.backpressure.clone(),
         };
 
-        // TODO: Set send/receiver buffer sizes (will be inherited by spawned connections).
+        // New connection accepted! Spawn as task and detach.
+        let on_accept_clone = self.options.on_accept.clone();
+        let routes = Arc::clone(&self.options.routes);
+        let prefix_sniff_timeout = self.options.prefix_sniff_timeout;
+        let socket_pool = self.socket_pool.clone();
+        let dscp = self.options.dscp;
+        let initial_read_deadline = self.options.initial_read_deadline;
+        let slow_handler_threshold = self.options.slow_handler_threshold;
+        let events = Arc::clone(&self.events);
+        let connections = Arc::clone(&self.connections);
+
+        // TODO: Spawn on optimal processor, not a random one.
+        self.dispatch(peer_addr, numa_node, move || async move {
+            // Released when the handler completes (or the task is dropped).
+            let active_connection_guard = active_connection_guard;
+
+            let counters = Arc::clone(&active_connection_guard.counters);
+            let mut tcp_connection =
+                TcpConnection::from_accepted_socket(connection_socket, counters, socket_pool);
+            apply_dscp(&mut tcp_connection, dscp);
+            apply_initial_read_deadline(&mut tcp_connection, initial_read_deadline);
+
+            let id = tcp_connection.id();
+
+            // Unregistered when the handler completes (or the task is dropped). We register before
+            // announcing the connection, so it can be closed by whoever receives the event.
+            let (registered_connection, close_requested) = connections.register(id);
+
+            events.send(|| ServerEvent::Accepted {
+                id,
+                peer: peer_addr.into(),
+            });
 
-        let mut addr = IN_ADDR::default();
-        addr.S_un.S_addr = INADDR_ANY;
+            let started = LowPrecisionInstant::now();
+
+            // A panic in the handler must not take down the worker, which is also running the
+            // handlers of other connections. The connection is closed when the handler is dropped
+            // during unwinding. With `panic = "abort"` there is no unwinding to catch - the process
+            // aborts before `catch_unwind()` ever sees the panic.
+            let handler = pin!(AssertUnwindSafe(async move {
+                match prefix_routing::select_route(
+                    &mut tcp_connection,
+                    &routes,
+                    prefix_sniff_timeout,
+                )
+                .await?
+                {
+                    Some(route_handler) => (route_handler)(tcp_connection).await,
+                    None => (on_accept_clone)(tcp_connection).await,
+                }
+            })
+            .catch_unwind());
+            let result = match select(handler, pin!(close_requested)).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => {
+                    event!(
+                        Level::DEBUG,
+                        message = "abandoning connection handler on request of server handle",
+                        id = id.to_string()
+                    );
+                    Ok(Ok(()))
+                }
+            };
 
-        let socket_addr = SOCKADDR_IN {
-            sin_family: AF_INET,
-            // SAFETY: Nothing unsafe here, just an FFI call.
-            sin_port: unsafe { htons(self.port.get()) },
-            sin_addr: addr,
-            sin_zero: [0; 8],
-        };
+            if let Some(threshold) = slow_handler_threshold {
+                let elapsed = started.elapsed();
 
-        // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
-        unsafe {
-            winsock::to_io_result(bind(
-                *listen_socket,
-                &socket_addr as *const _ as *const _,
-                mem::size_of::<SOCKADDR_IN>() as i32,
-            ))?;
+                if elapsed >= threshold {
+                    active_connection_guard
+                        .counters
+                        .slow_handlers
+                        .fetch_add(1, atomic::Ordering::Relaxed);
 
-            // A raw value for the queue length must be wrapped in the SOMAXCONN_HINT macro,
-            // which really is just negation - a negative value means "use the absolute value".
-            winsock::to_io_result(listen(*listen_socket, -PENDING_CONNECTION_LIMIT))?;
-        };
+                    event!(
+                        Level::WARN,
+                        message = "connection handler was slow to complete",
+                        id = id.to_string(),
+                        elapsed_millis = elapsed.as_millis() as u64
+                    );
+                }
+            }
 
-        // Bind the socket to the I/O completion port so we can process I/O completions.
-        current_async_agent::with_io(|io| {
-            io.bind_io_primitive(&*listen_socket).unwrap();
-        });
+            drop(registered_connection);
 
-        event!(Level::TRACE, "opened TCP socket for accepting connections");
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    active_connection_guard
+                        .counters
+                        .connections_failed
+                        .fetch_add(1, atomic::Ordering::Relaxed);
 
-        Ok(StartedTcpDispatcher {
-            listen_socket: Arc::new(listen_socket),
-        })
+                    events.send(|| ServerEvent::HandlerError {
+                        id,
+                        error: Arc::new(e),
+                    });
+                }
+                Err(payload) => {
+                    active_connection_guard
+                        .counters
+                        .connections_failed
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+
+                    let message = panic_message(payload.as_ref());
+
+                    event!(
+                        Level::ERROR,
+                        message = "connection handler panicked - closing connection",
+                        id = id.to_string(),
+                        panic = message
+                    );
+
+                    events.send(|| ServerEvent::HandlerPanicked { id, message });
+                }
+            }
+
+            events.send(|| ServerEvent::Closed { id });
+
+            // TODO: If callback result is error, report this error.
+        });
     }
 
-    async fn run_accept_loop(&mut self, startup_result: StartedTcpDispatcher) {
-        let listen_socket = startup_result.listen_socket;
+    /// Spawns the task that takes ownership of a newly accepted connection, on the worker chosen
+    /// by the configured placement strategy.
+    fn dispatch<FN, F>(&self, peer_addr: SocketAddrV4, numa_node: Option<NumaNodeId>, future_fn: FN)
+    where
+        FN: FnOnce() -> F + Send + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        self.spawn_connection_task(peer_addr, numa_node, future_fn);
+
+        if let Some(first_connection_tx) = self.first_connection_tx.take() {
+            // We ignore the result (maybe nobody is waiting for the first connection).
+            _ = first_connection_tx.send(());
+        }
+    }
 
-        // The act of accepting a connection is simply the first part of the lifecycle of a
-        // TcpConnection, so we can think of this as just a very long drawn-out constructor.
-        // On the other hand, accepting a connection does require use of resources owned by the
-        // dispatcher, so it is not an entirely isolated concept. We ensure proper resource
-        // management by always polling the accept future directly from within the dispatcher
-        // itself, so we do not need to create any complex resource sharing scheme for e.g. the
-        // socket because we stop polling if we release the resources. Any ongoing accept operations
-        // will be terminated when the socket is closed, after which the I/O driver will process a
-        // completion that will not be received by any awaiter any more and thus will be ignored.
-        // When we are shutting down, this operation will simply be abandoned.
-        //
-        // TODO: If a completion is ignored, won't that leave a dangling socket?
-        // Do we need some Operation::on_cancel() callback to clean up the socket in that case?
-        //
-        // Because we are doing two things concurrently (accepting connections + awaiting orders),
-        // we must use interior mutability or exclusive mutability only for one of these futures.
-        // We cannot give an exclusive reference to both futures.
+    fn spawn_connection_task<FN, F>(
+        &self,
+        peer_addr: SocketAddrV4,
+        numa_node: Option<NumaNodeId>,
+        future_fn: FN,
+    ) where
+        FN: FnOnce() -> F + Send + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        let numa_node = numa_node.filter(|_| self.options.affinity_by_numa_node);
+
+        if let Some(multiplexers) = &self.multiplexers {
+            let index = if self.options.affinity_by_peer {
+                Self::worker_index_by_peer(peer_addr, multiplexers.len())
+            } else {
+                let index = self.next_multiplexer.get();
+                self.next_multiplexer.set((index + 1) % multiplexers.len());
+
+                // There is one multiplexer per async worker, in the same order as the workers.
+                numa_node
+                    .and_then(|node| Self::worker_index_on_numa_node(node, index))
+                    .unwrap_or(index)
+            };
 
-        // All the ongoing accept operations. We will keep this filled up to the limit of
-        // CONCURRENT_ACCEPT_OPERATIONS, so whenever some get accepted, more accepts get queued.
-        let mut accept_futures = Box::pin(FuturesUnordered::new());
+            // The multiplexers only stop after we drop their senders, so this cannot fail.
+            _ = multiplexers[index].unbounded_send(Box::new(move || future_fn().boxed_local()));
+            return;
+        }
 
-        // If this completes, we shut down the dispatcher.
-        let mut shutdown_received_future = self.shutdown_rx.take().expect("we only take this once");
+        if let Some(node) = numa_node {
+            // Falls back to any worker if the runtime has no workers on the node.
+            _ = current_runtime::with(|runtime| runtime.spawn_on_numa_node(node, future_fn));
+            return;
+        }
 
-        loop {
-            while accept_futures.len() < CONCURRENT_ACCEPT_OPERATIONS {
-                accept_futures.push(
-                    AcceptOne {
-                        listen_socket: Arc::clone(&listen_socket),
-                    }
-                    .execute(),
-                );
+        if !self.options.affinity_by_peer {
+            _ = spawn_on_any(future_fn);
+            return;
+        }
+
+        current_runtime::with(|runtime| {
+            let worker_index = Self::worker_index_by_peer(peer_addr, runtime.async_worker_count());
+
+            _ = runtime.spawn_on_worker(worker_index, future_fn);
+        });
+    }
+
+    /// Picks one of the async workers on the given NUMA node based on a round-robin counter, if the
+    /// runtime has any workers on the node.
+    fn worker_index_on_numa_node(node: NumaNodeId, counter: usize) -> Option<usize> {
+        current_runtime::with(|runtime| round_robin(runtime.numa_node_workers(node), counter))
+    }
+
+    fn worker_index_by_peer(peer_addr: SocketAddrV4, worker_count: usize) -> usize {
+        // We only hash the IP address - the port changes every time the client reconnects.
+        let mut hasher = DefaultHasher::new();
+        peer_addr.ip().hash(&mut hasher);
+        (hasher.finish() % worker_count as u64) as usize
+    }
+}
+
+/// Picks one of the workers based on a round-robin counter, if there are any.
+fn round_robin(workers: &[usize], counter: usize) -> Option<usize> {
+    (!workers.is_empty()).then(|| workers[counter % workers.len()])
+}
+
+/// Accepts the next connection that passes the accept filter. Connections rejected by the filter
+/// are counted and otherwise ignored.
+async fn accept_conditionally(
+    acceptor: Rc<ConditionalAcceptor>,
+    configure_socket: Option<SocketConfigurator>,
+    keepalive: bool,
+    counters: Arc<ServerCounters>,
+) -> Result<AcceptedConnection, AcceptError> {
+    // Released when we have a connection (or are dropped).
+    let pending_accept_guard = PendingAcceptGuard::new(Arc::clone(&counters));
+
+    let (connection_socket, peer_addr) = loop {
+        match acceptor.accept().await {
+            Err(e) if conditional_accept::is_rejected(&e) => {
+                counters
+                    .connections_rejected
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+
+                event!(Level::TRACE, "connection rejected by accept filter");
+            }
+            result => {
+                break result.map_err(|inner| AcceptError {
+                    kind: winsock::classify_accept_error(&inner),
+                    inner,
+                })?
             }
+        }
+    };
 
-            event!(
-                Level::TRACE,
-                message = "waiting for new connection or shutdown",
-                accept_futures_len = accept_futures.len(),
-            );
+    drop(pending_accept_guard);
 
-            let accept_result = match select(accept_futures.next(), shutdown_received_future).await
-            {
-                Either::Left((Some(accept_result), new_shutdown_received_future)) => {
-                    shutdown_received_future = new_shutdown_received_future;
-                    accept_result
-                }
-                Either::Left((None, _)) => {
-                    panic!("accept_futures stream ended unexpectedly - we are supposed to refill it before checking");
+    // Same as with AcceptEx, the socket configuration is synchronous work that we keep off the
+    // dispatcher thread.
+    let connection_socket = current_runtime::with(move |runtime| {
+        runtime.spawn_sync_on_any(
+            SynchronousTaskType::Syscall,
+            move || -> io::Result<OwnedHandle<SOCKET>> {
+                conditional_accept::restore_defaults(*connection_socket)?;
+
+                if keepalive {
+                    winsock::enable_keepalive(*connection_socket)?;
                 }
-                Either::Right(_) => {
-                    event!(Level::DEBUG, "TCP dispatcher shutting down",);
-                    // We will not accept any new connections. The existing "accept one" operations
-                    // will be dropped soon and any pending I/O will likewise be canceled as soon
-                    // as the OwnedHandle is dropped and the socket gets closed.
-                    return;
+
+                if let Some(configure_socket) = configure_socket {
+                    (configure_socket)(*connection_socket)?;
                 }
-            };
 
-            event!(
-                Level::TRACE,
-                message = "detected incoming TCP connection (or error)",
-                accept_futures_len = accept_futures.len(),
-                ?accept_result
-            );
+                Ok(connection_socket)
+            },
+        )
+    })
+    .await?;
+
+    Ok(AcceptedConnection {
+        socket: connection_socket,
+        peer_addr,
+        connect_time: None,
+        numa_node: None,
+    })
+}
 
-            let Ok(connection_socket) = accept_result else {
-                event!(
-                    Level::ERROR,
-                    message = "error accepting new connection - ignoring",
-                    error = accept_result.unwrap_err().to_string()
-                );
-                // TODO: Report error to callback if not successfully accepted..
-                continue;
-            };
+/// Applies the configured DSCP value to a newly accepted connection. Failure to do so does not
+/// prevent the connection from being handled, as the marking is only an optimization.
+fn apply_dscp(connection: &mut TcpConnection, dscp: Option<u8>) {
+    let Some(dscp) = dscp else {
+        return;
+    };
 
-            // New connection accepted! Spawn as task and detach.
-            let on_accept_clone = self.on_accept.clone();
+    if let Err(e) = connection.set_dscp(dscp) {
+        event!(
+            Level::WARN,
+            message = "failed to set DSCP value on accepted connection - ignoring",
+            error = e.to_string()
+        );
+    }
+}
 
-            // TODO: Spawn on optimal processor, not a random one.
-            _ = spawn_on_any(move || async move {
-                current_async_agent::with_io(|io| {
-                    io.bind_io_primitive(&*connection_socket).unwrap()
-                });
+fn apply_initial_read_deadline(connection: &mut TcpConnection, timeout: Option<Duration>) {
+    let Some(timeout) = timeout else {
+        return;
+    };
 
-                let tcp_connection = TcpConnection {
-                    socket: Arc::new(connection_socket),
-                };
+    if let Err(e) = connection.set_handshake_deadline(timeout) {
+        event!(
+            Level::WARN,
+            message = "failed to set initial read deadline on accepted connection - ignoring",
+            error = e.to_string()
+        );
+    }
+}
 
-                _ = (on_accept_clone)(tcp_connection).await;
+/// Decrements the active connection count of a TCP server when dropped.
+struct ActiveConnectionGuard {
+    counters: Arc<ServerCounters>,
+    backpressure: Option<Arc<BackpressureMonitor>>,
+}
 
-                // TODO: If callback result is error, report this error.
-            });
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        let active_connections = self
+            .counters
+            .connections_active
+            .fetch_sub(1, atomic::Ordering::Relaxed)
+            - 1;
+
+        if let Some(backpressure) = &self.backpressure {
+            backpressure.on_decreased(active_connections);
         }
     }
 }
 
+/// Counts an accept operation as pending in the server counters for as long as it exists.
+struct PendingAcceptGuard {
+    counters: Arc<ServerCounters>,
+}
+
+impl PendingAcceptGuard {
+    fn new(counters: Arc<ServerCounters>) -> Self {
+        counters
+            .pending_accepts
+            .fetch_add(1, atomic::Ordering::Relaxed);
+
+        Self { counters }
+    }
+}
+
+impl Drop for PendingAcceptGuard {
+    fn drop(&mut self) {
+        self.counters
+            .pending_accepts
+            .fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
 struct StartedTcpDispatcher {
     // This is an Arc because we need to share it between the worker itself and the "AcceptOne"
     // subtasks that it spawns. We use Arc to avoid the need for AcceptOne to take a reference to
     // the worker, which would at the very least conflict with the worker itself using an exclusive
     // reference to itself. We also share this with sync worker threads, so it needs to be Arc.
-    listen_socket: Arc<OwnedHandle<SOCKET>>,
+    listen_sockets: Vec<Arc<OwnedHandle<SOCKET>>>,
+
+    // The port of the first listen socket.
+    local_port: u16,
+
+    // Whether RSS is enabled on the system. Without it, there is no processor affinity to query.
+    rss_enabled: bool,
+
+    // If present, we accept via these (one per listen socket) instead of via AcceptEx.
+    conditional_acceptors: Option<Vec<Rc<ConditionalAcceptor>>>,
+}
+
+/// A connection socket accepted by AcceptOne, ready to be dispatched to a worker.
+#[derive(Debug)]
+struct AcceptedConnection {
+    socket: OwnedHandle<SOCKET>,
+    peer_addr: SocketAddrV4,
+
+    // How long the connection had been established when we accepted it, if we measured it.
+    connect_time: Option<Duration>,
+
+    // The NUMA node that RSS delivers the traffic of the connection to, if we queried it.
+    numa_node: Option<NumaNodeId>,
+}
+
+/// An error that occurred while accepting a connection, classified by whether the listen socket can
+/// still be used to accept more connections.
+#[derive(Debug)]
+struct AcceptError {
+    inner: io::Error,
+    kind: AcceptErrorKind,
+}
+
+impl From<io::Error> for AcceptError {
+    // Only a failure of the accept call itself can affect the listen socket, so anything that
+    // is converted implicitly is transient.
+    fn from(inner: io::Error) -> Self {
+        Self {
+            inner,
+            kind: AcceptErrorKind::Transient,
+        }
+    }
 }
 
 /// The state of a single "accept one connection" operation. We create this separate type to more
@@ -420,11 +2428,40 @@ struct StartedTcpDispatcher {
 /// management of the connection-accepting tasks.
 struct AcceptOne {
     listen_socket: Arc<OwnedHandle<SOCKET>>,
+    configure_socket: Option<SocketConfigurator>,
+    keepalive: bool,
+
+    // If set, the accept operations are being canceled and we must not start new ones.
+    canceling_accepts: Rc<Cell<bool>>,
+
+    backoff: Rc<AcceptBackoff>,
+
+    // If present, we take the connection socket from here instead of creating a new one.
+    socket_pool: Option<Arc<AcceptSocketPool>>,
+
+    // We count the accept operation as pending in here while it is waiting for a connection.
+    counters: Arc<ServerCounters>,
+
+    // If not set, we do not query the processor affinity of the accepted connection.
+    query_affinity: bool,
+
+    // If set, we query how long the accepted connection waited to be accepted.
+    query_connect_time: bool,
+
+    // If set, we do not create new sockets while this many handles are live in the process.
+    handle_limit: Option<usize>,
+
+    // If set, we do not accept new connections while this many bytes of pooled buffers are in use
+    // in the process.
+    max_buffer_memory: Option<usize>,
 }
 
 impl AcceptOne {
-    async fn execute(self) -> io::Result<OwnedHandle<SOCKET>> {
-        event!(Level::TRACE, "listening for an incoming connection");
+    /// Creates a fresh socket to accept the next connection into, recording the outcome for the
+    /// purposes of backing off on resource exhaustion.
+    async fn create_socket(&self) -> Result<OwnedHandle<SOCKET>, AcceptError> {
+        self.wait_for_capacity("handles", self.handle_limit, live_handles)
+            .await;
 
         // Creating the socket is an expensive synchronous operation, so do it on a synchronous
         // worker thread.
@@ -451,9 +2488,80 @@ impl AcceptOne {
                 },
             )
         })
-        .await?;
+        .await;
+
+        self.backoff.on_socket_created(&connection_socket);
+        let connection_socket = connection_socket?;
 
         event!(Level::TRACE, "socket created for next incoming connection");
+        Ok(connection_socket)
+    }
+
+    /// Waits until the process uses less than `limit` of a resource, if there is a limit. While at
+    /// the limit, we back off exactly like when the operating system runs out of resources, except
+    /// that nothing has failed yet - we are trying to avoid that.
+    async fn wait_for_capacity(
+        &self,
+        resource: &'static str,
+        limit: Option<usize>,
+        in_use: fn() -> Option<usize>,
+    ) {
+        let Some(limit) = limit else {
+            return;
+        };
+
+        while let Some(in_use) = in_use() {
+            // Waiting any longer would hold up the cancellation. We bail out before the accept
+            // operation is started.
+            if self.canceling_accepts.get() {
+                return;
+            }
+
+            if in_use < limit {
+                return;
+            }
+
+            let pause = self.backoff.on_resource_exhaustion();
+
+            event!(
+                Level::WARN,
+                message = "resource limit reached - pausing accepting connections",
+                resource,
+                in_use,
+                limit,
+                pause_millis = pause.as_millis() as u64
+            );
+
+            self.backoff.wait().await;
+        }
+    }
+
+    async fn execute(self) -> Result<AcceptedConnection, AcceptError> {
+        event!(Level::TRACE, "listening for an incoming connection");
+
+        // If we recently failed to create sockets due to lack of resources, give the system a
+        // moment to recover before trying again.
+        self.backoff.wait().await;
+
+        // Every accepted connection will take buffers from the pool, so while at the budget we let
+        // the connections in progress complete and release their buffers first.
+        self.wait_for_capacity("buffer memory", self.max_buffer_memory, pooled_buffer_bytes)
+            .await;
+
+        let connection_socket = match self.socket_pool.as_ref().and_then(|pool| pool.take()) {
+            Some(socket) => {
+                event!(
+                    Level::TRACE,
+                    "reusing pooled socket for next incoming connection"
+                );
+
+                self.counters
+                    .accept_sockets_reused
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+                socket
+            }
+            None => self.create_socket().await?,
+        };
 
         // NOTE: AcceptEx supports immediately pasting the first block of received data in here,
         // which may provide a performance boost when accepting the connection. This is optional
@@ -469,21 +2577,35 @@ impl AcceptOne {
         //      outBufLen - ((sizeof (sockaddr_in) + 16) * 2),
         //      sizeof (sockaddr_in) + 16, sizeof (sockaddr_in) + 16,
         //      &dwBytes, &olOverlap);
-        let buffer = io::PinnedBuffer::from_pool();
+        let mut buffer = io::PinnedBuffer::from_pool();
 
         // The data length in the buffer (if we were to want to use some) would be the buffer size
         // minus double of this (local + remote address).
         const ADDRESS_LENGTH: usize = mem::size_of::<SOCKADDR_IN>() + 16;
 
-        assert!(buffer.len() >= ADDRESS_LENGTH * 2);
+        // We do not receive any data, so we only need the address region of the buffer.
+        buffer.set_len(ADDRESS_LENGTH * 2);
+
+        // Creating the connection socket took a while, so the accept operations may have started
+        // being canceled in the meantime. An accept operation started now would never be canceled.
+        if self.canceling_accepts.get() {
+            return Err(AcceptError {
+                inner: io::Error::LogicError("accept operations are being canceled".to_string()),
+                kind: AcceptErrorKind::Fatal,
+            });
+        }
 
         // NOTE: This is an operation on the **listen socket**, not on the connection socekt, so it
         // is bound to the completion port of the listen socket. Note that we have not yet bound the
         // connection socket to any completion port.
-        let accept_operation = current_async_agent::with_io(|io| io.new_operation(buffer));
+        let mut accept_operation = current_async_agent::with_io(|io| io.new_operation(buffer));
+        accept_operation.set_primitive(**self.listen_socket);
 
         event!(Level::TRACE, "waiting for incoming connection to arrive");
 
+        // Released when the accept operation completes (or is dropped).
+        let pending_accept_guard = PendingAcceptGuard::new(Arc::clone(&self.counters));
+
         // SAFETY: We are required to pass the OVERLAPPED struct to the native I/O function to avoid
         // a resource leak. We do.
         let accept_result = unsafe {
@@ -510,7 +2632,19 @@ impl AcceptOne {
             })
         }
         .await
-        .into_inner()?;
+        .into_inner()
+        .map_err(|inner| AcceptError {
+            kind: winsock::classify_accept_error(&inner),
+            inner,
+        });
+
+        drop(pending_accept_guard);
+        let mut accept_result = accept_result?;
+
+        // The completed operation sets the length to the number of bytes received, which is zero.
+        // The addresses are written after the received data, so we extend the active region back
+        // over them before reading them.
+        accept_result.set_len(ADDRESS_LENGTH * 2);
 
         event!(
             Level::TRACE,
@@ -523,9 +2657,8 @@ impl AcceptOne {
         let mut remote_addr_len: i32 = 0;
 
         // This function will replace the pointer above to point to the actual data in question.
-        // Calling this is optional - we do not currently use this data, but we would in a real app.
         // SAFETY: As long as we pass in valid pointers that match the AcceptEx call, we are good.
-        unsafe {
+        let peer_addr = unsafe {
             GetAcceptExSockaddrs(
                 accept_result.as_slice().as_ptr() as *const _,
                 0,
@@ -535,24 +2668,52 @@ impl AcceptOne {
                 &mut local_addr_len as *mut _,
                 &mut remote_addr as *mut _,
                 &mut remote_addr_len as *mut _,
+            );
+
+            // We only listen on IPv4, so the remote address is always a SOCKADDR_IN. It points
+            // into the buffer, which is still alive here.
+            let remote_addr = &*(remote_addr as *const SOCKADDR_IN);
+
+            SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(remote_addr.sin_addr.S_un.S_addr)),
+                ntohs(remote_addr.sin_port),
             )
         };
 
         // This post-processing is synchronous work that is not free, so move it to a synchronous
         // worker thread.
         let listen_socket = Arc::clone(&self.listen_socket);
+        let configure_socket = self.configure_socket.clone();
+        let keepalive = self.keepalive;
+        let query_affinity = self.query_affinity;
+        let query_connect_time = self.query_connect_time;
+        let counters = Arc::clone(&self.counters);
 
         event!(
             Level::TRACE,
             "configuring socket for incoming connection (part 1)"
         );
 
-        let (connection_socket, _affinity_info) = current_runtime::with(move |runtime| {
+        let (connection_socket, numa_node, connect_time) = current_runtime::with(move |runtime| {
             runtime.spawn_sync_on_any(
                 SynchronousTaskType::Syscall,
-                move || -> io::Result<(OwnedHandle<SOCKET>, SOCKET_PROCESSOR_AFFINITY)> {
+                move || -> io::Result<_> {
                     event!(Level::TRACE, "configuring socket for incoming connection (part 2)");
 
+                    // A failure to measure is not worth failing the connection over.
+                    let connect_time = if query_connect_time {
+                        winsock::connect_time(*connection_socket).unwrap_or_else(|e| {
+                            event!(
+                                Level::DEBUG,
+                                message = "failed to query connect time of new connection",
+                                error = e.to_string()
+                            );
+                            None
+                        })
+                    } else {
+                        None
+                    };
+
                     // We need to refer to this via pointer, so let's copy it out to a place first.
                     let listen_socket = listen_socket.0;
 
@@ -577,14 +2738,30 @@ impl AcceptOne {
                         )
                     })?;
 
-                    // Inspect processor affinity configuration. We do not currently use this information but
-                    // one day we might. We execute this in synchronous mode because we cannot bind the socket
-                    // to our completion port - we are on the TCP dispatcher thread and the socket actually
-                    // needs to be bound to the completion port of the thread where we dispatch it to. Which we
-                    // do not know yet. So synchronous it is, on some synchronous worker thread.
+                    if keepalive {
+                        winsock::enable_keepalive(*connection_socket)?;
+                    }
+
+                    if let Some(configure_socket) = configure_socket {
+                        (configure_socket)(*connection_socket)?;
+                    }
+
+                    // Inspect processor affinity configuration, which tells us the NUMA node to
+                    // place the connection on if placement by NUMA node is enabled. We execute this
+                    // in synchronous mode because we cannot bind the socket to our completion
+                    // port - we are on the TCP dispatcher thread and the socket actually needs to
+                    // be bound to the completion port of the thread where we dispatch it to. Which
+                    // we do not know yet. So synchronous it is, on some synchronous worker thread.
                     let affinity_info: SOCKET_PROCESSOR_AFFINITY = SOCKET_PROCESSOR_AFFINITY::default();
                     let mut bytes_returned: u32 = 0;
 
+                    // If RSS is not enabled on the system, there is nothing to query. Even if it is,
+                    // the query is opt-in, as the result is only needed for placement by NUMA node.
+                    if !query_affinity {
+                        event!(Level::TRACE, "socket configured for incoming connection");
+                        return Ok((connection_socket, None, connect_time));
+                    }
+
                     // Prerequisite:
                     // 1) adapter must support RSS and have it enabled (e.g. not loopback)
                     // 2) adapter must be connected to peer
@@ -598,6 +2775,8 @@ impl AcceptOne {
                     // Processor number will be different for different connections.
                     // Not all processors will be used - typically only 16 processors are used for low level I/O.
                     // NB! This data may change during life of a connection - it is not fixed!
+                    counters.rss_queries.fetch_add(1, atomic::Ordering::Relaxed);
+
                     let affinity_result = unsafe {
                         winsock::to_io_result(WSAIoctl(
                             *connection_socket,
@@ -612,9 +2791,10 @@ impl AcceptOne {
                         ))
                     };
 
-                    match affinity_result {
+                    let numa_node = match affinity_result {
                         Ok(()) => {
                             event!(Level::TRACE, message = "RSS processor info for new connection", affinity_info = ?affinity_info);
+                            Some(NumaNodeId::new(affinity_info.NumaNodeId))
                         }
                         Err(io::Error::Winsock { detail, .. })
                             if detail == WSAEOPNOTSUPP || detail == WSAEACCES =>
@@ -624,26 +2804,37 @@ impl AcceptOne {
                                 message =
                                     "RSS not supported/enabled on network adapter used for new connection"
                             );
+                            None
                         }
                         Err(e) => {
+                            // The connection is still usable, just without a known NUMA node.
+                            counters
+                                .rss_queries_failed
+                                .fetch_add(1, atomic::Ordering::Relaxed);
+
                             event!(
                                 Level::ERROR,
                                 message = "error querying RSS processor info for new connection",
                                 error = e.to_string()
                             );
+                            None
                         }
-                    }
+                    };
 
                     event!(Level::TRACE, "socket configured for incoming connection");
 
-                    Ok((connection_socket, affinity_info))
+                    Ok((connection_socket, numa_node, connect_time))
                 },
             )
         }).await?;
 
         // The new socket is connected and ready! Finally!
-        // TODO: Attach RSS info so it can actually be used for smart dispatch decisions.
-        Ok(connection_socket)
+        Ok(AcceptedConnection {
+            socket: connection_socket,
+            peer_addr,
+            connect_time,
+            numa_node,
+        })
     }
 }
 
@@ -661,3 +2852,31 @@ where
     AF: Future<Output = io::Result<()>> + 'static,
 {
 }
+
+thread_local! {
+    static ACTIVE_CONNECTIONS: Event = EventBuilder::new()
+        .name("net_tcp_server_active_connections")
+        .buckets(ACTIVE_CONNECTIONS_BUCKETS)
+        .build()
+        .unwrap();
+}
+
+const ACTIVE_CONNECTIONS_BUCKETS: &[Magnitude] = &[0, 10, 100, 1000, 10000];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_cycles_through_workers_on_numa_node() {
+        let workers = [3, 5, 7];
+
+        let picked: Vec<_> = (0..6)
+            .map(|counter| round_robin(&workers, counter).unwrap())
+            .collect();
+        assert_eq!(picked, [3, 5, 7, 3, 5, 7]);
+
+        // Without workers on the node, the caller falls back to its own choice.
+        assert_eq!(round_robin(&[], 4), None);
+    }
+}
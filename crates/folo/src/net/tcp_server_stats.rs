@@ -0,0 +1,115 @@
+use std::sync::{
+    atomic::{self, AtomicU64, AtomicUsize},
+    Arc,
+};
+
+/// A point-in-time snapshot of the activity counters of a TCP server, obtained via
+/// `TcpServerHandle::stats()`.
+///
+/// The counters are updated independently by different worker threads, so the values in a
+/// snapshot are not guaranteed to be mutually consistent (e.g. a connection may already be counted
+/// as accepted but not yet as active).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Total number of connections accepted since the server was started.
+    pub connections_accepted: u64,
+
+    /// Number of connections currently being handled by the `on_accept` callback.
+    pub connections_active: u64,
+
+    /// Total number of connections that failed, either because accepting them failed or because
+    /// the `on_accept` callback returned an error.
+    pub connections_failed: u64,
+
+    /// Total number of connections that the `on_accept` callback declined via
+    /// `TcpConnection::decline()`. These are not counted as failed, as declining a connection is a
+    /// deliberate decision of the server (e.g. admission control).
+    pub connections_declined: u64,
+
+    /// Total number of connections that the peer reset or aborted before they could be accepted.
+    /// These are not counted as failed, as they are a normal occurrence with impatient clients.
+    pub connections_reset_during_accept: u64,
+
+    /// Total number of connections rejected by the accept filter (see
+    /// `TcpServerBuilder::accept_filter()`) before they were established.
+    pub connections_rejected: u64,
+
+    /// Total number of connections that waited to be accepted for at least the threshold set via
+    /// `TcpServerBuilder::on_backlog_pressure()`. Always zero if that is not set.
+    pub backlog_pressure: u64,
+
+    /// Total number of connections whose handler took at least the threshold set via
+    /// `TcpServerBuilder::slow_handler_threshold()` to complete. Always zero if that is not set.
+    pub slow_handlers: u64,
+
+    /// Total number of accept operations that used the socket of a closed connection instead of
+    /// creating a new socket (see `TcpServerBuilder::reuse_accept_sockets()`).
+    pub accept_sockets_reused: u64,
+
+    /// Total number of connections whose RSS processor affinity was queried (see
+    /// `TcpServerBuilder::query_rss_affinity()`). Always zero if the query is not enabled or if
+    /// RSS is not enabled on the system.
+    pub rss_queries: u64,
+
+    /// Total number of connections whose RSS processor affinity could not be queried (see
+    /// `TcpServerBuilder::query_rss_affinity()`) due to an unexpected error. Such connections are
+    /// still accepted but are handled as if RSS was not available.
+    pub rss_queries_failed: u64,
+
+    /// Total number of bytes received over all connections of the server.
+    pub bytes_received: u64,
+
+    /// Total number of bytes sent over all connections of the server.
+    pub bytes_sent: u64,
+}
+
+/// The live counters behind `ServerStats`, shared between the TCP dispatcher, the server handle and
+/// every connection accepted by the server.
+#[derive(Debug, Default)]
+pub(crate) struct ServerCounters {
+    pub(crate) connections_accepted: AtomicU64,
+    pub(crate) connections_active: AtomicU64,
+    pub(crate) connections_failed: AtomicU64,
+    pub(crate) connections_declined: AtomicU64,
+    pub(crate) connections_reset_during_accept: AtomicU64,
+    pub(crate) connections_rejected: AtomicU64,
+    pub(crate) backlog_pressure: AtomicU64,
+    pub(crate) slow_handlers: AtomicU64,
+    pub(crate) accept_sockets_reused: AtomicU64,
+    pub(crate) rss_queries: AtomicU64,
+    pub(crate) rss_queries_failed: AtomicU64,
+
+    // Accept operations submitted to the operating system and waiting for a connection. Exposed
+    // separately via `TcpServerHandle::pending_accepts()`, as it is a level, not an activity total.
+    pub(crate) pending_accepts: AtomicUsize,
+
+    // The number of accept operations the dispatcher aims to keep in flight. Exposed separately via
+    // `TcpServerHandle::accept_concurrency()`, as it is a level, not an activity total.
+    pub(crate) accept_concurrency: AtomicUsize,
+
+    // These are tallied directly by the I/O operations, which need their own reference to them.
+    pub(crate) bytes_received: Arc<AtomicU64>,
+    pub(crate) bytes_sent: Arc<AtomicU64>,
+}
+
+impl ServerCounters {
+    pub(crate) fn snapshot(&self) -> ServerStats {
+        ServerStats {
+            connections_accepted: self.connections_accepted.load(atomic::Ordering::Relaxed),
+            connections_active: self.connections_active.load(atomic::Ordering::Relaxed),
+            connections_failed: self.connections_failed.load(atomic::Ordering::Relaxed),
+            connections_declined: self.connections_declined.load(atomic::Ordering::Relaxed),
+            connections_reset_during_accept: self
+                .connections_reset_during_accept
+                .load(atomic::Ordering::Relaxed),
+            connections_rejected: self.connections_rejected.load(atomic::Ordering::Relaxed),
+            backlog_pressure: self.backlog_pressure.load(atomic::Ordering::Relaxed),
+            slow_handlers: self.slow_handlers.load(atomic::Ordering::Relaxed),
+            accept_sockets_reused: self.accept_sockets_reused.load(atomic::Ordering::Relaxed),
+            rss_queries: self.rss_queries.load(atomic::Ordering::Relaxed),
+            rss_queries_failed: self.rss_queries_failed.load(atomic::Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(atomic::Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(atomic::Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Helpers for testing TCP servers built on Folo, taking care of the boilerplate of starting a
+//! server, connecting to it and moving data back and forth.
+//!
+//! Available in the crate's own tests and, via the `testing` feature, in tests of downstream crates.
+
+use crate::{
+    io::{self, OperationResultExt, PinnedBuffer},
+    net::{winsock, TcpConnection, TcpServerBuilder, TcpServerHandle},
+    rt::{current_runtime, SynchronousTaskType},
+    util::OwnedHandle,
+};
+use std::{
+    mem,
+    net::{Ipv4Addr, SocketAddrV4},
+};
+use windows::Win32::Networking::WinSock::{
+    connect, htonl, htons, WSASocketA, AF_INET, IN_ADDR, IN_ADDR_0, IPPROTO_TCP, SOCKADDR_IN,
+    SOCKET, SOCK_STREAM, WSA_FLAG_OVERLAPPED,
+};
+
+/// Opens a connection to a TCP server listening on the given port on the loopback interface.
+///
+/// The returned connection is bound to the current async worker thread.
+pub async fn connect_loopback(port: u16) -> io::Result<TcpConnection> {
+    connect_to(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).await
+}
+
+/// Opens a connection to a TCP server listening on the given address, for servers that do not
+/// listen on `127.0.0.1` (e.g. when testing `TcpServerBuilder::bind_addresses()`).
+///
+/// The returned connection is bound to the current async worker thread.
+pub async fn connect_to(address: SocketAddrV4) -> io::Result<TcpConnection> {
+    winsock::ensure_initialized();
+
+    // Connecting is a blocking operation here, so we do it on a synchronous worker thread.
+    let socket = current_runtime::with(|runtime| {
+        runtime.spawn_sync(
+            SynchronousTaskType::Syscall,
+            move || -> io::Result<OwnedHandle<SOCKET>> {
+                // SAFETY: All we need to worry about here is cleanup, which we do via OwnedHandle.
+                let socket = unsafe {
+                    OwnedHandle::new(WSASocketA(
+                        AF_INET.0 as i32,
+                        SOCK_STREAM.0,
+                        IPPROTO_TCP.0,
+                        None,
+                        0,
+                        WSA_FLAG_OVERLAPPED,
+                    )?)
+                };
+
+                let socket_addr = SOCKADDR_IN {
+                    sin_family: AF_INET,
+                    // SAFETY: Nothing unsafe here, just an FFI call.
+                    sin_port: unsafe { htons(address.port()) },
+                    sin_addr: IN_ADDR {
+                        S_un: IN_ADDR_0 {
+                            // SAFETY: Nothing unsafe here, just an FFI call.
+                            S_addr: unsafe { htonl(address.ip().to_bits()) },
+                        },
+                    },
+                    sin_zero: [0; 8],
+                };
+
+                // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+                winsock::to_io_result(unsafe {
+                    connect(
+                        *socket,
+                        &socket_addr as *const _ as *const _,
+                        mem::size_of::<SOCKADDR_IN>() as i32,
+                    )
+                })?;
+
+                Ok(socket)
+            },
+        )
+    })
+    .await?;
+
+    Ok(TcpConnection::from_connected_socket(socket))
+}
+
+/// Starts a TCP server on a port chosen by the operating system, echoing back everything it
+/// receives on every connection. Use `TcpServerHandle::local_port()` to find out where to connect.
+pub async fn echo_server() -> io::Result<TcpServerHandle> {
+    TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .build()
+        .await
+}
+
+/// Echoes back everything received on the connection until the peer closes it. This is the
+/// connection handler used by `echo_server()`, for use with servers configured by the test itself.
+pub async fn echo(mut connection: TcpConnection) -> io::Result<()> {
+    loop {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()?;
+
+        if buffer.is_empty() {
+            // The peer closed the connection.
+            return Ok(());
+        }
+
+        connection.send(buffer).await.into_inner()?;
+    }
+}
@@ -1,6 +1,31 @@
-use crate::io;
-use std::sync::LazyLock;
-use windows::Win32::Networking::WinSock::{WSAGetLastError, WSAStartup, WSADATA};
+use crate::{io, net::TcpInfo};
+use std::{
+    mem, slice,
+    sync::{LazyLock, OnceLock},
+    time::Duration,
+};
+use windows::{
+    core::{GUID, HRESULT, PSTR},
+    Win32::{
+        Foundation::{
+            BOOL, ERROR_CONNECTION_ABORTED, ERROR_INVALID_HANDLE, ERROR_NETNAME_DELETED,
+            ERROR_NOT_ENOUGH_MEMORY, ERROR_NO_SYSTEM_RESOURCES, ERROR_OPERATION_ABORTED,
+            STATUS_CANCELLED, STATUS_CONNECTION_ABORTED, STATUS_CONNECTION_RESET,
+            STATUS_INVALID_HANDLE, STATUS_REMOTE_DISCONNECT,
+        },
+        Networking::WinSock::{
+            getsockopt, ioctlsocket, setsockopt, TCP_INFO_v0, TCP_INFO_v1, WSAGetLastError,
+            WSAIoctl, WSAStartup, FIONREAD, LINGER, LPFN_DISCONNECTEX, LPFN_TRANSMITPACKETS,
+            RSS_SCALABILITY_INFO, SIO_GET_EXTENSION_FUNCTION_POINTER,
+            SIO_QUERY_RSS_SCALABILITY_INFO, SIO_TCP_INFO, SOCKET, SOCKET_ERROR, SOL_SOCKET,
+            SO_CONNECT_TIME, SO_KEEPALIVE, SO_LINGER, SO_RCVLOWAT, SO_SNDLOWAT, TF_REUSE_SOCKET,
+            TRANSMIT_PACKETS_ELEMENT, WSADATA, WSAECONNABORTED, WSAECONNRESET, WSAEINVAL,
+            WSAEMFILE, WSAENETDOWN, WSAENETRESET, WSAENOBUFS, WSAENOTCONN, WSAENOTSOCK,
+            WSAEOPNOTSUPP, WSAID_DISCONNECTEX, WSAID_TRANSMITPACKETS, WSANOTINITIALISED,
+        },
+        System::IO::OVERLAPPED,
+    },
+};
 
 pub fn ensure_initialized() {
     *WINSOCK_STARTUP;
@@ -28,3 +53,412 @@ pub fn to_io_result(winsock_result: i32) -> io::Result<()> {
         })
     }
 }
+
+/// Closes the connection on a connected socket such that the socket can be reused to accept another
+/// connection. This is a blocking call that waits for the connection to be closed gracefully, so it
+/// must be called on a synchronous worker thread.
+pub fn disconnect_for_reuse(socket: SOCKET) -> io::Result<()> {
+    let disconnect_ex = disconnect_ex_fn(socket)?;
+
+    // SAFETY: The function pointer was provided by Winsock for exactly this purpose. Without an
+    // OVERLAPPED, the call is synchronous, so there are no lifetime concerns.
+    bool_to_io_result(unsafe { disconnect_ex(socket, std::ptr::null_mut(), TF_REUSE_SOCKET, 0) })
+}
+
+/// Converts the result of a Winsock extension function, which reports failure via a `FALSE` return
+/// value, into an IO result.
+pub fn bool_to_io_result(result: BOOL) -> io::Result<()> {
+    if result.as_bool() {
+        Ok(())
+    } else {
+        // SAFETY: Nothing unsafe here, just an FFI call.
+        let specific_error = unsafe { WSAGetLastError() };
+
+        Err(io::Error::Winsock {
+            code: SOCKET_ERROR,
+            detail: specific_error,
+        })
+    }
+}
+
+type DisconnectExFn = unsafe extern "system" fn(SOCKET, *mut OVERLAPPED, u32, u32) -> BOOL;
+
+pub type TransmitPacketsFn = unsafe extern "system" fn(
+    SOCKET,
+    *const TRANSMIT_PACKETS_ELEMENT,
+    u32,
+    u32,
+    *mut OVERLAPPED,
+    u32,
+) -> BOOL;
+
+// DisconnectEx is a Microsoft-specific extension that is not exported by name - it has to be looked
+// up via a socket. The function is the same for all TCP sockets, so we only look it up once.
+fn disconnect_ex_fn(socket: SOCKET) -> io::Result<DisconnectExFn> {
+    static DISCONNECT_EX: OnceLock<DisconnectExFn> = OnceLock::new();
+
+    if let Some(disconnect_ex) = DISCONNECT_EX.get() {
+        return Ok(*disconnect_ex);
+    }
+
+    let function: LPFN_DISCONNECTEX = extension_fn(socket, &WSAID_DISCONNECTEX)?;
+
+    let function = function.ok_or_else(|| {
+        io::Error::Internal("Winsock did not provide the DisconnectEx function".to_string())
+    })?;
+
+    Ok(*DISCONNECT_EX.get_or_init(|| function))
+}
+
+/// Looks up TransmitPackets, which sends data from memory and optionally disconnects the socket
+/// afterwards, all as a single overlapped operation. Like DisconnectEx, it is an extension that
+/// has to be looked up via a socket, so we only do that once.
+pub fn transmit_packets_fn(socket: SOCKET) -> io::Result<TransmitPacketsFn> {
+    static TRANSMIT_PACKETS: OnceLock<TransmitPacketsFn> = OnceLock::new();
+
+    if let Some(transmit_packets) = TRANSMIT_PACKETS.get() {
+        return Ok(*transmit_packets);
+    }
+
+    let function: LPFN_TRANSMITPACKETS = extension_fn(socket, &WSAID_TRANSMITPACKETS)?;
+
+    let function = function.ok_or_else(|| {
+        io::Error::Internal("Winsock did not provide the TransmitPackets function".to_string())
+    })?;
+
+    Ok(*TRANSMIT_PACKETS.get_or_init(|| function))
+}
+
+// Queries the pointer of the extension function with the given ID. `T` is the optional function
+// pointer type of the extension function, which is `None` if Winsock does not provide it.
+fn extension_fn<T: Default>(socket: SOCKET, id: &GUID) -> io::Result<T> {
+    let mut function = T::default();
+    let mut bytes_returned: u32 = 0;
+
+    // SAFETY: The input and output pointers and sizes describe valid values of the expected types.
+    to_io_result(unsafe {
+        WSAIoctl(
+            socket,
+            SIO_GET_EXTENSION_FUNCTION_POINTER,
+            Some(id as *const _ as *const _),
+            mem::size_of_val(id) as u32,
+            Some(&mut function as *mut _ as *mut _),
+            mem::size_of::<T>() as u32,
+            &mut bytes_returned,
+            None,
+            None,
+        )
+    })?;
+
+    Ok(function)
+}
+
+/// Enables TCP keepalive on a socket, using the system default keepalive timing.
+pub fn enable_keepalive(socket: SOCKET) -> io::Result<()> {
+    let enabled = BOOL::from(true);
+
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    to_io_result(unsafe {
+        setsockopt(
+            socket,
+            SOL_SOCKET,
+            SO_KEEPALIVE,
+            Some(&enabled.0.to_ne_bytes()),
+        )
+    })
+}
+
+/// Queries how long the socket has been connected (`SO_CONNECT_TIME`), in whole seconds. For a
+/// socket accepted via `AcceptEx()`, this includes the time the connection spent in the listen
+/// backlog. Returns `None` if the socket is not connected.
+pub fn connect_time(socket: SOCKET) -> io::Result<Option<Duration>> {
+    let mut seconds = [0_u8; mem::size_of::<u32>()];
+    let mut len = seconds.len() as i32;
+
+    // SAFETY: The output buffer and its length describe a valid value of the expected size.
+    to_io_result(unsafe {
+        getsockopt(
+            socket,
+            SOL_SOCKET,
+            SO_CONNECT_TIME,
+            PSTR::from_raw(seconds.as_mut_ptr()),
+            &mut len,
+        )
+    })?;
+
+    Ok(match u32::from_ne_bytes(seconds) {
+        u32::MAX => None,
+        seconds => Some(Duration::from_secs(seconds as u64)),
+    })
+}
+
+/// Queries how many bytes of received data are buffered on the socket and can be read immediately
+/// (`FIONREAD`).
+pub fn bytes_available(socket: SOCKET) -> io::Result<usize> {
+    let mut available: u32 = 0;
+
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    to_io_result(unsafe { ioctlsocket(socket, FIONREAD, &mut available) })?;
+
+    Ok(available as usize)
+}
+
+/// Makes closing the socket reset the connection (RST) instead of closing it gracefully (FIN), by
+/// enabling `SO_LINGER` with a zero timeout. Any data not yet sent is discarded on close.
+pub fn enable_abortive_close(socket: SOCKET) -> io::Result<()> {
+    let linger = LINGER {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+
+    // SAFETY: The option value points to a LINGER that lives for the duration of the call.
+    to_io_result(unsafe {
+        setsockopt(
+            socket,
+            SOL_SOCKET,
+            SO_LINGER,
+            Some(slice::from_raw_parts(
+                &linger as *const LINGER as *const u8,
+                mem::size_of::<LINGER>(),
+            )),
+        )
+    })
+}
+
+/// Sets the minimum number of bytes that must be available before a receive on the socket completes
+/// (`SO_RCVLOWAT`). Not supported for TCP on Windows, which fails with `WSAENOPROTOOPT`.
+pub fn set_receive_low_water_mark(socket: SOCKET, bytes: u32) -> io::Result<()> {
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    to_io_result(unsafe { setsockopt(socket, SOL_SOCKET, SO_RCVLOWAT, Some(&bytes.to_ne_bytes())) })
+}
+
+/// Sets the minimum number of bytes that must be sendable before a send on the socket proceeds
+/// (`SO_SNDLOWAT`). Not supported for TCP on Windows, which fails with `WSAENOPROTOOPT`.
+pub fn set_send_low_water_mark(socket: SOCKET, bytes: u32) -> io::Result<()> {
+    // SAFETY: All we need to be concerned about is passing in valid arguments, which we do.
+    to_io_result(unsafe { setsockopt(socket, SOL_SOCKET, SO_SNDLOWAT, Some(&bytes.to_ne_bytes())) })
+}
+
+/// Queries whether receive side scaling (RSS) is enabled on any network interface of the system.
+/// Without RSS, there is no processor affinity information to query for individual connections.
+pub fn is_rss_enabled(socket: SOCKET) -> io::Result<bool> {
+    let mut info = RSS_SCALABILITY_INFO::default();
+    let mut bytes_returned: u32 = 0;
+
+    // SAFETY: The output pointer and size describe a valid value of the expected type.
+    to_io_result(unsafe {
+        WSAIoctl(
+            socket,
+            SIO_QUERY_RSS_SCALABILITY_INFO,
+            None,
+            0,
+            Some(&mut info as *mut _ as *mut _),
+            mem::size_of::<RSS_SCALABILITY_INFO>() as u32,
+            &mut bytes_returned,
+            None,
+            None,
+        )
+    })?;
+
+    Ok(info.RssEnabled.as_bool())
+}
+
+/// Queries the TCP statistics of a connected socket (`SIO_TCP_INFO`). Uses the newest version of
+/// the structure supported by the operating system: version 1 requires Windows 10 build 20348 or
+/// Windows Server 2022, version 0 requires Windows 10 version 1703 or Windows Server 2019.
+pub fn tcp_info(socket: SOCKET) -> io::Result<TcpInfo> {
+    match query_tcp_info::<TCP_INFO_v1>(socket, 1) {
+        Ok(info) => Ok(info.into()),
+        // Older versions of Windows reject version 1 of the query, so we fall back to version 0.
+        Err(_) => Ok(query_tcp_info::<TCP_INFO_v0>(socket, 0)?.into()),
+    }
+}
+
+fn query_tcp_info<T: Default>(socket: SOCKET, version: u32) -> io::Result<T> {
+    let mut info = T::default();
+    let mut bytes_returned: u32 = 0;
+
+    // SAFETY: The input and output pointers and sizes describe valid values of the types expected
+    // for the requested version of the query.
+    to_io_result(unsafe {
+        WSAIoctl(
+            socket,
+            SIO_TCP_INFO,
+            Some(&version as *const _ as *const _),
+            mem::size_of::<u32>() as u32,
+            Some(&mut info as *mut _ as *mut _),
+            mem::size_of::<T>() as u32,
+            &mut bytes_returned,
+            None,
+            None,
+        )
+    })?;
+
+    Ok(info)
+}
+
+/// Whether an error from an accept operation affects only the connection being accepted or the
+/// listen socket itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptErrorKind {
+    /// Only the connection being accepted is affected (e.g. the peer reset the connection or the
+    /// system is temporarily out of resources). Accepting more connections may succeed.
+    Transient,
+
+    /// The listen socket is no longer usable (e.g. it was closed or the network subsystem went
+    /// down). No further connections can be accepted on it.
+    Fatal,
+}
+
+/// Classifies an error returned by an accept operation on a listen socket. Only errors from the
+/// accept call itself should be classified - other errors that occur while setting up a new
+/// connection never affect the listen socket.
+pub fn classify_accept_error(error: &io::Error) -> AcceptErrorKind {
+    let fatal = match error {
+        io::Error::Winsock { detail, .. } => [
+            WSAENOTSOCK,
+            WSAEINVAL,
+            WSAENETDOWN,
+            WSANOTINITIALISED,
+            WSAEOPNOTSUPP,
+        ]
+        .contains(detail),
+        // Immediate failures carry a Win32 error code, asynchronous failures an NTSTATUS.
+        io::Error::Windows(e) => [
+            ERROR_OPERATION_ABORTED.into(),
+            ERROR_INVALID_HANDLE.into(),
+            STATUS_CANCELLED.to_hresult(),
+            STATUS_INVALID_HANDLE.to_hresult(),
+        ]
+        .contains(&e.code()),
+        _ => false,
+    };
+
+    if fatal {
+        AcceptErrorKind::Fatal
+    } else {
+        AcceptErrorKind::Transient
+    }
+}
+
+/// Whether an error indicates that the peer reset or aborted the connection before we could finish
+/// accepting it. This is a normal event (e.g. a client that connects and immediately gives up) and
+/// is not a failure of the server.
+pub fn is_connection_reset(error: &io::Error) -> bool {
+    match error {
+        io::Error::Winsock { detail, .. } => {
+            [WSAECONNRESET, WSAECONNABORTED, WSAENETRESET, WSAENOTCONN].contains(detail)
+        }
+        // Immediate failures carry a Win32 error code, asynchronous failures an NTSTATUS.
+        io::Error::Windows(e) => [
+            HRESULT::from_win32(WSAECONNRESET.0 as u32),
+            HRESULT::from_win32(WSAECONNABORTED.0 as u32),
+            ERROR_NETNAME_DELETED.into(),
+            ERROR_CONNECTION_ABORTED.into(),
+            STATUS_CONNECTION_RESET.to_hresult(),
+            STATUS_CONNECTION_ABORTED.to_hresult(),
+            STATUS_REMOTE_DISCONNECT.to_hresult(),
+        ]
+        .contains(&e.code()),
+        _ => false,
+    }
+}
+
+/// Whether an error indicates that the system is (at least temporarily) out of the resources needed
+/// to create or use sockets, in which case retrying immediately is likely to fail the same way.
+pub fn is_resource_exhaustion(error: &io::Error) -> bool {
+    match error {
+        io::Error::Winsock { detail, .. } => [WSAEMFILE, WSAENOBUFS].contains(detail),
+        // Functions that report errors via GetLastError() carry the Winsock code as a Win32 code.
+        io::Error::Windows(e) => [
+            HRESULT::from_win32(WSAEMFILE.0 as u32),
+            HRESULT::from_win32(WSAENOBUFS.0 as u32),
+            ERROR_NOT_ENOUGH_MEMORY.into(),
+            ERROR_NO_SYSTEM_RESOURCES.into(),
+        ]
+        .contains(&e.code()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::{Foundation::WIN32_ERROR, Networking::WinSock::WSA_ERROR};
+
+    fn winsock_error(detail: WSA_ERROR) -> io::Error {
+        io::Error::Winsock {
+            code: SOCKET_ERROR,
+            detail,
+        }
+    }
+
+    fn win32_error(code: WIN32_ERROR) -> io::Error {
+        io::Error::Windows(windows_result::Error::from_hresult(code.into()))
+    }
+
+    #[test]
+    fn closed_listen_socket_is_fatal() {
+        assert_eq!(
+            classify_accept_error(&winsock_error(WSAENOTSOCK)),
+            AcceptErrorKind::Fatal
+        );
+        assert_eq!(
+            classify_accept_error(&win32_error(ERROR_OPERATION_ABORTED)),
+            AcceptErrorKind::Fatal
+        );
+        assert_eq!(
+            classify_accept_error(&io::Error::Windows(STATUS_CANCELLED.into())),
+            AcceptErrorKind::Fatal
+        );
+    }
+
+    #[test]
+    fn connection_failures_are_transient() {
+        assert_eq!(
+            classify_accept_error(&winsock_error(WSAECONNRESET)),
+            AcceptErrorKind::Transient
+        );
+        assert_eq!(
+            classify_accept_error(&winsock_error(WSAENOBUFS)),
+            AcceptErrorKind::Transient
+        );
+        assert_eq!(
+            classify_accept_error(&io::Error::Windows(STATUS_CONNECTION_RESET.into())),
+            AcceptErrorKind::Transient
+        );
+        assert_eq!(
+            classify_accept_error(&io::Error::LogicError("whatever".to_string())),
+            AcceptErrorKind::Transient
+        );
+    }
+
+    #[test]
+    fn resource_exhaustion_is_detected() {
+        assert!(is_resource_exhaustion(&winsock_error(WSAEMFILE)));
+        assert!(is_resource_exhaustion(&winsock_error(WSAENOBUFS)));
+        assert!(is_resource_exhaustion(&io::Error::Windows(
+            windows_result::Error::from_hresult(HRESULT::from_win32(WSAEMFILE.0 as u32))
+        )));
+        assert!(is_resource_exhaustion(&win32_error(
+            ERROR_NO_SYSTEM_RESOURCES
+        )));
+
+        assert!(!is_resource_exhaustion(&winsock_error(WSAECONNRESET)));
+        assert!(!is_resource_exhaustion(&win32_error(
+            ERROR_OPERATION_ABORTED
+        )));
+    }
+
+    #[test]
+    fn connection_resets_are_detected() {
+        assert!(is_connection_reset(&winsock_error(WSAECONNRESET)));
+        assert!(is_connection_reset(&win32_error(ERROR_NETNAME_DELETED)));
+        assert!(is_connection_reset(&io::Error::Windows(
+            STATUS_CONNECTION_RESET.into()
+        )));
+
+        assert!(!is_connection_reset(&winsock_error(WSAENOBUFS)));
+        assert!(!is_connection_reset(&win32_error(ERROR_OPERATION_ABORTED)));
+    }
+}
@@ -1,6 +1,7 @@
 mod async_agent;
 mod async_task_engine;
 mod builder;
+mod ctrl_c;
 pub(crate) mod current_async_agent;
 pub(crate) mod current_runtime;
 pub(crate) mod current_sync_agent;
@@ -8,19 +9,34 @@ mod erased_async_task;
 mod functions;
 mod local_join;
 mod local_task;
+mod numa;
 mod ready_after_poll;
 mod remote_join;
 mod remote_result_box;
 mod remote_task;
 mod remote_waker;
 mod runtime_client;
+mod runtime_metrics;
+mod spawn_options;
 mod sync_agent;
+#[cfg(feature = "fakes")]
+mod test_runtime;
+mod thread_priority;
 mod types;
 mod waker;
+mod worker_id;
 
 pub use builder::*;
+pub use ctrl_c::*;
 pub use functions::*;
 pub use local_join::*;
+pub use numa::*;
 pub use remote_join::*;
 pub use runtime_client::*;
+pub use runtime_metrics::*;
+pub use spawn_options::*;
+#[cfg(feature = "fakes")]
+pub use test_runtime::*;
+pub use thread_priority::*;
 pub(crate) use types::*;
+pub use worker_id::*;
@@ -1,10 +1,16 @@
 use super::erased_async_task::ErasedResultAsyncTask;
+use crate::trace::{event, Level};
 use crate::{
     io,
     metrics::{self, Event, EventBuilder, ReportPage},
     rt::{
-        async_task_engine::{AsyncTaskEngine, CycleResult}, current_runtime, local_task::LocalTask, LocalJoinHandle
-    }, time::advance_local_timers,
+        async_task_engine::{AsyncTaskEngine, CycleResult},
+        current_runtime,
+        local_task::LocalTask,
+        waker::TaskCounters,
+        LocalJoinHandle, SpawnOptions,
+    },
+    time::{advance_local_timers, current_instant},
 };
 use core_affinity::CoreId;
 use crossbeam::channel;
@@ -13,11 +19,15 @@ use std::{
     collections::VecDeque,
     fmt::{self, Debug, Formatter},
     future::Future,
-    pin::Pin, time::Instant,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::Arc,
 };
-use tracing::{event, Level};
 use windows::Win32::System::Threading::INFINITE;
 
+/// A task waiting to be handed over to the async task engine, with the options it was spawned with.
+type NewTask = (Pin<Box<dyn ErasedResultAsyncTask>>, SpawnOptions);
+
 /// Coordinates the operations of the Folo runtime on a single thread. There may be different
 /// types of agents assigned to different threads (e.g. async worker versus sync worker). This is
 /// the async agent.
@@ -47,7 +57,7 @@ pub struct AsyncAgent {
     // Tasks that have been enqueued but have not yet been handed over to the async task engine.
     // Includes both locally queued tasks and tasks enqueued from another thread, which are both
     // unified to the `ErasedResultAsyncTask` type.
-    new_tasks: RefCell<VecDeque<Pin<Box<dyn ErasedResultAsyncTask>>>>,
+    new_tasks: RefCell<VecDeque<NewTask>>,
 
     // If we are shutting down, we try ignore requests to schedule new tasks and do our best to
     // cleanup ASAP.
@@ -59,6 +69,7 @@ impl AsyncAgent {
         command_rx: channel::Receiver<AsyncAgentCommand>,
         metrics_tx: Option<channel::Sender<ReportPage>>,
         processor_id: CoreId,
+        completion_port_concurrency: NonZeroU32,
     ) -> Self {
         Self {
             command_rx,
@@ -69,7 +80,9 @@ impl AsyncAgent {
             engine: RefCell::new(unsafe { AsyncTaskEngine::new() }),
             // SAFETY: The I/O driver must not be dropped while there are pending I/O operations.
             // We ensure this by waiting for I/O to complete before returning from `run()`.
-            io: RefCell::new(unsafe { io::Driver::new() }),
+            io: RefCell::new(unsafe {
+                io::Driver::with_completion_port_concurrency(completion_port_concurrency)
+            }),
             new_tasks: RefCell::new(VecDeque::new()),
             shutting_down: Cell::new(false),
         }
@@ -83,6 +96,10 @@ impl AsyncAgent {
         &self.io
     }
 
+    pub(crate) fn set_task_counters(&self, counters: Arc<TaskCounters>) {
+        self.engine.borrow_mut().set_task_counters(counters);
+    }
+
     /// Spawns a task to execute a future on the current async worker thread.
     ///
     /// # Panics
@@ -90,6 +107,20 @@ impl AsyncAgent {
     /// Panics if the current thread is not an async worker thread. This is possible because there
     /// are more types of runtime threads than async worker threads - e.g. sync worker threads.
     pub fn spawn<F, R>(&self, future: F) -> LocalJoinHandle<R>
+    where
+        F: Future<Output = R> + 'static,
+        R: 'static,
+    {
+        self.spawn_with_options(SpawnOptions::default(), future)
+    }
+
+    /// Spawns a task to execute a future on the current async worker thread, with options that
+    /// control how the task is scheduled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread is not an async worker thread.
+    pub fn spawn_with_options<F, R>(&self, options: SpawnOptions, future: F) -> LocalJoinHandle<R>
     where
         F: Future<Output = R> + 'static,
         R: 'static,
@@ -124,7 +155,7 @@ impl AsyncAgent {
 
         // We queue up the tasks because we may be being called from within the async task engine
         // itself, so we cannot call back into it immediately.
-        self.new_tasks.borrow_mut().push_back(task);
+        self.new_tasks.borrow_mut().push_back((task, options));
         join_handle
     }
 
@@ -194,8 +225,10 @@ impl AsyncAgent {
                         // The tasks in this list may own resources that are already referenced by other
                         // tasks or external entities. We need to accept them into our regular process
                         // before dropping them - they are not safe to drop just because they are new.
-                        while let Some(erased_task) = self.new_tasks.borrow_mut().pop_front() {
-                            engine.enqueue_erased(erased_task);
+                        while let Some((erased_task, options)) =
+                            self.new_tasks.borrow_mut().pop_front()
+                        {
+                            engine.enqueue_erased(erased_task, options);
                         }
 
                         // Start cleaning up the async task engine. This may require some time if there
@@ -233,14 +266,14 @@ impl AsyncAgent {
             // - What are the perf implications of this call?
             // - Shall we pass the current instant to `execute_cycle` and get rid of low-resolution watch?
             // - Shall we introduce some cached sink for current time (both relative and absolute) that is updated with each cycle?
-            let now = Instant::now();
+            let now = current_instant();
             advance_local_timers(now);
 
             {
                 let mut new_tasks = self.new_tasks.borrow_mut();
 
-                while let Some(erased_task) = new_tasks.pop_front() {
-                    engine.enqueue_erased(erased_task);
+                while let Some((erased_task, options)) = new_tasks.pop_front() {
+                    engine.enqueue_erased(erased_task, options);
                 }
             }
 
@@ -280,9 +313,15 @@ impl AsyncAgent {
                     "there are no pending I/O operations - safe to shut down I/O driver"
                 );
             } else {
+                // Whatever is still in flight is owned by nobody anymore - the tasks are gone. We do
+                // not wait for the I/O primitives to be closed by whoever still holds them but
+                // cancel the operations, so their buffers are reclaimed in a deterministic manner.
+                let canceled = io.cancel_all_operations();
+
                 event!(
                     Level::TRACE,
-                    "waiting for I/O driver to complete pending operations"
+                    message = "waiting for I/O driver to complete pending operations",
+                    canceled
                 );
 
                 while !io.is_inert() {
@@ -321,7 +360,9 @@ impl AsyncAgent {
 
                     received_commands = true;
                     REMOTE_TASKS.with(Event::observe_unit);
-                    self.new_tasks.borrow_mut().push_back(erased_task);
+                    self.new_tasks
+                        .borrow_mut()
+                        .push_back((erased_task, SpawnOptions::default()));
                 }
                 Ok(AsyncAgentCommand::Terminate) => {
                     // We continue processing commands even after the terminate signal because
@@ -2,7 +2,11 @@ use crate::{
     constants::{GENERAL_MILLISECONDS_BUCKETS, POISONED_LOCK},
     io::IO_DEQUEUE_BATCH_SIZE,
     metrics::{Event, EventBuilder},
-    rt::{erased_async_task::ErasedResultAsyncTask, waker::WakeSignal},
+    rt::{
+        erased_async_task::ErasedResultAsyncTask,
+        waker::{TaskCounters, WakeSignal},
+        SpawnOptions, TaskPriority,
+    },
     util::{BuildPointerHasher, LowPrecisionInstant, PinnedSlabChain},
 };
 use negative_impl::negative_impl;
@@ -66,8 +70,8 @@ pub struct AsyncTaskEngine {
     // The active set contains all the tasks we want to poll. This is where all futures start.
     // The items are pinned pointers into the `tasks` collection.
     //
-    // This is a VedDeque because we do not require set characteristics and a deque is faster.
-    active: VecDeque<*mut Task>,
+    // High priority tasks are kept in a separate queue and polled first.
+    active: ActiveTasks,
 
     // The inactive set contains all the tasks that are sleeping. We will move them back to the
     // active set after a waker notifies us that a future needs to wake up. Note that the wakeup
@@ -96,6 +100,10 @@ pub struct AsyncTaskEngine {
     // flag to indicate that the awakened status of every inactive task should be directly probed.
     probe_embedded_wake_signals: Arc<AtomicBool>,
 
+    // Shared by the wake signals of all tasks, to report how many wake-ups were coalesced, and
+    // by the engine itself, to report how many tasks were spawned.
+    task_counters: Arc<TaskCounters>,
+
     // These tasks have completed and we are waiting for the references to them to be dropped (for
     // the tasks to become inert) so we can finish releasing resources.
     // The items are pinned pointers into the `tasks` collection.
@@ -124,21 +132,32 @@ impl AsyncTaskEngine {
     pub unsafe fn new() -> Self {
         Self {
             tasks: PinnedSlabChain::new(),
-            active: VecDeque::new(),
+            active: ActiveTasks::default(),
             inactive: HashSet::with_hasher(BuildPointerHasher::default()),
             #[allow(clippy::arc_with_non_send_sync)] // Clippy false positive? That's a big fat mutex!
             awakened: Arc::new(Mutex::new(VecDeque::with_capacity(AWAKENED_CAPACITY))),
             probe_embedded_wake_signals: Arc::new(AtomicBool::new(false)),
+            task_counters: Arc::new(TaskCounters::default()),
             completed: VecDeque::new(),
             shutting_down: false,
             last_cycle_ended: None,
         }
     }
 
+    /// Reports wake-ups of tasks enqueued from now on to the given counters, instead of to the
+    /// ones created together with the engine.
+    pub(crate) fn set_task_counters(&mut self, counters: Arc<TaskCounters>) {
+        self.task_counters = counters;
+    }
+
     /// Enqueues a future whose return type has been erased. It will be polled but no result
     /// will be made available by the async task engine - it is expected that some other mechanism
     /// is used to observe the result.
-    pub fn enqueue_erased(&mut self, erased_task: Pin<Box<dyn ErasedResultAsyncTask>>) {
+    pub fn enqueue_erased(
+        &mut self,
+        erased_task: Pin<Box<dyn ErasedResultAsyncTask>>,
+        options: SpawnOptions,
+    ) {
         // It is possible due to the eventually consistent nature between worker commands that a
         // worker will receive a new task after shutdown has already begun. We expect the worker
         // to perform the necessary filtering to prevent that from ever reaching the task engine.
@@ -147,6 +166,8 @@ impl AsyncTaskEngine {
             "cannot enqueue tasks after shutdown has begun"
         );
 
+        self.task_counters.spawned.fetch_add(1, Ordering::Relaxed);
+
         let inserter = self.tasks.begin_insert();
 
         // SAFETY: We are responsible for not dropping the task until it is inert. We accomplish
@@ -157,8 +178,10 @@ impl AsyncTaskEngine {
             Task::new(
                 inserter.index(),
                 erased_task,
+                options,
                 Arc::clone(&self.awakened),
                 Arc::clone(&self.probe_embedded_wake_signals),
+                Arc::clone(&self.task_counters),
             )
         };
 
@@ -330,7 +353,7 @@ impl AsyncTaskEngine {
         // We call .count() to force the iterator to be evaluated. We do not care about the count.
         _ = self
             .active
-            .drain(..)
+            .drain()
             .chain(self.inactive.drain())
             .map(|task_ptr| {
                 // SAFETY: This comes from a pinned slab and we are responsible for dropping tasks, which
@@ -366,6 +389,46 @@ impl !Send for AsyncTaskEngine {}
 #[negative_impl]
 impl !Sync for AsyncTaskEngine {}
 
+/// The active tasks of the engine, split into queues by task priority. The high priority queue is
+/// always drained first. Tasks are placed in the queue matching their priority whenever they become
+/// active.
+#[derive(Debug, Default)]
+struct ActiveTasks {
+    normal: VecDeque<*mut Task>,
+    high_priority: VecDeque<*mut Task>,
+}
+
+impl ActiveTasks {
+    fn push_back(&mut self, task_ptr: *mut Task) {
+        // SAFETY: This comes from a pinned slab and we are responsible for dropping tasks, which
+        // we never do until they progress through the lifecycle into the `completed` list.
+        let priority = unsafe { (*task_ptr).options.priority_value() };
+
+        match priority {
+            TaskPriority::Normal => self.normal.push_back(task_ptr),
+            TaskPriority::High => self.high_priority.push_back(task_ptr),
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<*mut Task> {
+        self.high_priority
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.normal.is_empty() && self.high_priority.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.normal.len() + self.high_priority.len()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = *mut Task> + '_ {
+        self.high_priority.drain(..).chain(self.normal.drain(..))
+    }
+}
+
 /// The result of executing one cycle of the async task engine.
 #[derive(Debug, PartialEq, Eq)]
 pub enum CycleResult {
@@ -388,6 +451,9 @@ pub(super) struct Task {
     // Used for dropping the task once we are done with it.
     index: usize,
 
+    // How the task was asked to be scheduled.
+    options: SpawnOptions,
+
     #[pin]
     wake_signal: WakeSignal,
 }
@@ -400,13 +466,20 @@ impl Task {
     unsafe fn new(
         index: usize,
         inner: Pin<Box<dyn ErasedResultAsyncTask>>,
+        options: SpawnOptions,
         awakened_queue: Arc<Mutex<VecDeque<*mut Task>>>,
         probe_embedded_wake_signals: Arc<AtomicBool>,
+        task_counters: Arc<TaskCounters>,
     ) -> Self {
         Self {
             inner: RefCell::new(inner),
             index,
-            wake_signal: WakeSignal::new(awakened_queue, probe_embedded_wake_signals),
+            options,
+            wake_signal: WakeSignal::new(
+                awakened_queue,
+                probe_embedded_wake_signals,
+                task_counters,
+            ),
         }
     }
 
@@ -425,6 +498,10 @@ impl Task {
 
         let mut context = task::Context::from_waker(waker);
 
+        // Any wake-up from now on may be reacting to something the poll does not see, so it must
+        // be delivered to ensure the task gets polled again.
+        self.wake_signal.begin_poll();
+
         // We are only accessing the erased task in poll() which is only called by the current
         // thread and never recursively, so we are not at risk of conflicting borrows.
         self.inner.borrow_mut().as_mut().poll(&mut context)
@@ -438,6 +515,8 @@ impl Task {
 impl Debug for Task {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Task")
+            .field("name", &self.options.name_value())
+            .field("priority", &self.options.priority_value())
             .field("wake_signal", &self.wake_signal)
             .finish()
     }
@@ -1,20 +1,28 @@
 use std::collections::HashMap;
 use std::fmt::{self, Debug, Formatter};
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::thread;
 
+use crate::trace::{event, Level};
 use crossbeam::channel;
 use crossbeam::queue::SegQueue;
-use tracing::{event, Level};
 
 use super::sync_agent::{SyncAgent, SyncAgentCommand};
 use super::{current_sync_agent, ErasedSyncTask};
-use crate::io::{self, IoWaker};
+use crate::io::{
+    self, enable_buffer_accounting, IoWaker, POOL_BUFFER_CAPACITY_BYTES,
+    THREAD_PER_CORE_CONCURRENCY,
+};
 use crate::metrics::ReportPage;
 use crate::rt::async_agent::{AsyncAgent, AsyncAgentCommand};
-use crate::rt::{current_async_agent, current_runtime, RuntimeClient};
+use crate::rt::{
+    current_async_agent, current_runtime, numa::numa_node_of, NumaNodeId, RuntimeClient,
+    ThreadPriority,
+};
+use crate::util::enable_handle_accounting;
 
 /// The thing with synchronous worker threads is that they often get blocked and spend time doing
 /// essentially nothing due to offloading blocking I/O onto these threads. Therefore, we spawn many
@@ -23,6 +31,8 @@ use crate::rt::{current_async_agent, current_runtime, RuntimeClient};
 /// fixed size might be acceptable.
 const SYNC_WORKERS_PER_PROCESSOR: usize = 2;
 
+const DEFAULT_THREAD_NAME_PREFIX: &str = "folo";
+
 struct ThreadStartResult<AgentReady, R> {
     join_handle: std::thread::JoinHandle<()>,
     start_tx: oneshot::Sender<AgentStartArguments>,
@@ -35,6 +45,14 @@ pub struct RuntimeBuilder {
     ad_hoc_entrypoint: bool,
     metrics_tx: Option<channel::Sender<ReportPage>>,
     max_processors: Option<usize>,
+    numa_node: Option<NumaNodeId>,
+    thread_name_prefix: String,
+    thread_priority: Option<ThreadPriority>,
+    worker_threads: Option<usize>,
+    allow_oversubscription: bool,
+    handle_limit: Option<NonZeroUsize>,
+    max_buffer_memory: Option<NonZeroUsize>,
+    completion_port_concurrency: NonZeroU32,
 }
 
 impl RuntimeBuilder {
@@ -44,6 +62,14 @@ impl RuntimeBuilder {
             ad_hoc_entrypoint: false,
             metrics_tx: None,
             max_processors: None,
+            numa_node: None,
+            thread_name_prefix: DEFAULT_THREAD_NAME_PREFIX.to_string(),
+            thread_priority: None,
+            worker_threads: None,
+            allow_oversubscription: false,
+            handle_limit: None,
+            max_buffer_memory: None,
+            completion_port_concurrency: THREAD_PER_CORE_CONCURRENCY,
         }
     }
 
@@ -86,6 +112,110 @@ impl RuntimeBuilder {
         self
     }
 
+    /// Restricts the runtime to the processors of one NUMA node, so that all its workers (and the
+    /// memory they allocate) stay on that node. This is useful for running one runtime per node,
+    /// e.g. with one server per network adapter, each on the node the adapter is attached to.
+    ///
+    /// Applied before `max_processors()`. Building the runtime fails with `InvalidOptions` if none
+    /// of the processors available to the process are on the node. See `NumaNodeId` for how the
+    /// topology is discovered.
+    pub fn numa_node(mut self, node: NumaNodeId) -> Self {
+        self.numa_node = Some(node);
+        self
+    }
+
+    /// Sets the prefix of the names of the worker threads, to make them easy to recognize in
+    /// debuggers and profilers. The threads are named `{prefix}-async-{index}`,
+    /// `{prefix}-sync-{processor}-{index}` and `{prefix}-tcp-dispatcher`.
+    ///
+    /// Defaults to "folo".
+    pub fn thread_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the scheduling priority of all the worker threads. If not set, the threads run at the
+    /// priority assigned to them by the operating system. If the priority cannot be applied (e.g.
+    /// due to insufficient permissions), `build()` fails.
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> Self {
+        self.thread_priority = Some(priority);
+        self
+    }
+
+    /// Sets the number of async worker threads. Each async worker is pinned to a processor, so the
+    /// recommended mapping is one async worker per processor, which is also the default.
+    ///
+    /// Requesting more async workers than there are processors available to the runtime is an
+    /// error unless `allow_oversubscription(true)` is also set.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    /// Permits more async workers than there are processors available to the runtime. The extra
+    /// workers share processors with the others (assigned round-robin), so they compete for the
+    /// same processor time and caches, and the locality that receive side scaling provides is
+    /// lost. This rarely improves throughput - consider it only for workloads that block their
+    /// async workers, which is better solved by moving the blocking work to sync workers.
+    ///
+    /// Defaults to false.
+    pub fn allow_oversubscription(mut self, allow: bool) -> Self {
+        self.allow_oversubscription = allow;
+        self
+    }
+
+    /// Sets the concurrency value of the I/O completion ports of the async workers, which is how
+    /// many of the threads reading from a port the operating system lets run at the same time.
+    ///
+    /// In the thread-per-core model, each async worker reads from its own completion port, so the
+    /// default of 1 is all that is ever used and a higher value does not change how the workers
+    /// are scheduled. This exists for experimenting with completion ports shared by a group of
+    /// threads, where the value limits how many of them run at once.
+    pub fn completion_port_concurrency(mut self, concurrency: NonZeroU32) -> Self {
+        self.completion_port_concurrency = concurrency;
+        self
+    }
+
+    /// Enables handle accounting, which counts the handles (files, sockets and such) owned by Folo
+    /// in the process, and sets a ceiling on them. Once the ceiling is reached, TCP servers stop
+    /// creating sockets for new connections until handles are released, backing off the same way
+    /// as when the operating system runs out of resources. This turns the abrupt failures at the
+    /// per-process handle limit into a gradual slowdown of accepting connections.
+    ///
+    /// Set the ceiling somewhat below the real limit, to leave room for handles created outside
+    /// of Folo and for the work in progress. The count is exposed as
+    /// `RuntimeMetrics::live_handles`.
+    ///
+    /// The accounting is process-wide and, once enabled by any runtime, stays enabled for the rest
+    /// of the life of the process. Handles created before it was enabled are not counted.
+    pub fn handle_limit(mut self, limit: NonZeroUsize) -> Self {
+        self.handle_limit = Some(limit);
+        self
+    }
+
+    /// Enables buffer memory accounting, which counts the bytes of pooled I/O buffers in use
+    /// across all worker threads, and sets a budget for them. Each worker thread has its own
+    /// buffer pool, so without a budget the total grows with the number of workers and the load.
+    ///
+    /// Taking a buffer from the pool never waits, so in-flight work can always make progress.
+    /// Instead, once the budget is reached, TCP servers stop accepting new connections until
+    /// buffers are released, backing off the same way as when the operating system runs out of
+    /// resources. The budget may therefore be exceeded by the work already in progress. The usage
+    /// is exposed as `RuntimeMetrics::pooled_buffer_bytes`.
+    ///
+    /// Every accept operation that a TCP server has in flight holds a pooled buffer of 64 KiB
+    /// while waiting for a connection, so with the default 1024 accept operations an idle server
+    /// holds up to 64 MiB of buffers. A smaller budget limits the number of accept operations in
+    /// flight before it limits anything else. The budget must be large enough for at least one
+    /// pooled buffer.
+    ///
+    /// The accounting is process-wide in the same way as with `handle_limit()`. Buffers taken
+    /// before it was enabled are not counted.
+    pub fn max_buffer_memory(mut self, bytes: NonZeroUsize) -> Self {
+        self.max_buffer_memory = Some(bytes);
+        self
+    }
+
     fn start_async_agent(
         &self,
         processor_id: core_affinity::CoreId,
@@ -94,16 +224,25 @@ impl RuntimeBuilder {
     {
         let worker_init = Arc::clone(&self.worker_init);
         let metrics_tx = self.metrics_tx.clone();
+        let completion_port_concurrency = self.completion_port_concurrency;
         let (start_tx, start_rx) = oneshot::channel::<AgentStartArguments>();
         let (ready_tx, ready_rx) = oneshot::channel::<AsyncAgentReady>();
         let (command_tx, command_rx) = channel::unbounded::<AsyncAgentCommand>();
 
         let join_handle = thread::Builder::new()
-            .name(format!("async-{}", worker_index))
+            .name(format!(
+                "{}-async-{}",
+                self.thread_name_prefix, worker_index
+            ))
             .spawn(move || {
                 worker_init();
 
-                let agent = Rc::new(AsyncAgent::new(command_rx, metrics_tx, processor_id));
+                let agent = Rc::new(AsyncAgent::new(
+                    command_rx,
+                    metrics_tx,
+                    processor_id,
+                    completion_port_concurrency,
+                ));
 
                 // Signal that we are ready to start.
                 ready_tx
@@ -119,12 +258,21 @@ impl RuntimeBuilder {
                     .expect("runtime startup process failed in infallible code");
 
                 core_affinity::set_for_current(processor_id);
+                agent.io().borrow_mut().set_completion_counters(Arc::clone(
+                    start.runtime_client.completion_counters(),
+                ));
+                agent.set_task_counters(Arc::clone(start.runtime_client.task_counters()));
+
                 current_async_agent::set(Rc::clone(&agent));
                 current_runtime::set(start.runtime_client);
 
                 agent.run();
             })?;
 
+        if let Some(priority) = self.thread_priority {
+            priority.apply_to(&join_handle)?;
+        }
+
         Ok(ThreadStartResult {
             join_handle,
             start_tx,
@@ -147,7 +295,10 @@ impl RuntimeBuilder {
         let (command_tx, command_rx) = channel::unbounded::<SyncAgentCommand>();
 
         let join_handle = thread::Builder::new()
-            .name(format!("sync-{}-{}", processor_id.id, worker_index))
+            .name(format!(
+                "{}-sync-{}-{}",
+                self.thread_name_prefix, processor_id.id, worker_index
+            ))
             .spawn(move || {
                 (worker_init)();
 
@@ -177,6 +328,10 @@ impl RuntimeBuilder {
                 agent.run();
             })?;
 
+        if let Some(priority) = self.thread_priority {
+            priority.apply_to(&join_handle)?;
+        }
+
         Ok(ThreadStartResult {
             join_handle,
             start_tx,
@@ -198,7 +353,7 @@ impl RuntimeBuilder {
         let metrics_tx = self.metrics_tx.clone();
 
         let join_handle = thread::Builder::new()
-            .name("tcp-dispatcher".to_string())
+            .name(format!("{}-tcp-dispatcher", self.thread_name_prefix))
             .spawn(move || {
                 (worker_init)();
 
@@ -206,6 +361,7 @@ impl RuntimeBuilder {
                     command_rx,
                     metrics_tx,
                     tcp_dispatcher_processor_id,
+                    THREAD_PER_CORE_CONCURRENCY,
                 ));
 
                 // Signal that we are ready to start.
@@ -225,12 +381,21 @@ impl RuntimeBuilder {
                 // pinned. As there can be only one, we do not want it starved of CPU time, so allow
                 // the OS to schedule it on any processor that it sees fit.
 
+                agent.io().borrow_mut().set_completion_counters(Arc::clone(
+                    start.runtime_client.completion_counters(),
+                ));
+                agent.set_task_counters(Arc::clone(start.runtime_client.task_counters()));
+
                 current_async_agent::set(Rc::clone(&agent));
                 current_runtime::set(start.runtime_client);
 
                 agent.run();
             })?;
 
+        if let Some(priority) = self.thread_priority {
+            priority.apply_to(&join_handle)?;
+        }
+
         Ok(ThreadStartResult {
             join_handle,
             start_tx,
@@ -250,6 +415,18 @@ impl RuntimeBuilder {
         let mut processor_ids =
             core_affinity::get_core_ids().expect("must always be able to identify processor IDs");
 
+        if let Some(numa_node) = self.numa_node {
+            processor_ids.retain(|processor_id| numa_node_of(*processor_id) == numa_node);
+
+            if processor_ids.is_empty() {
+                return Err(io::Error::InvalidOptions(format!(
+                    "the runtime has no processors to run on - no available processor is on NUMA \
+                     node {}",
+                    numa_node.get()
+                )));
+            }
+        }
+
         if let Some(max_processors) = self.max_processors {
             processor_ids.truncate(max_processors);
         }
@@ -259,10 +436,77 @@ impl RuntimeBuilder {
         // We will spawn one agent of each type (async + sync) for each processor.
         let processor_count = processor_ids.len();
 
-        let async_worker_count = processor_count;
+        let async_worker_count = self.worker_threads.unwrap_or(processor_count);
         let sync_worker_count = SYNC_WORKERS_PER_PROCESSOR * processor_count;
 
-        event!(Level::INFO, processor_count);
+        if processor_count == 0 {
+            return Err(io::Error::InvalidOptions(
+                "the runtime has no processors to run on - max_processors must be at least 1"
+                    .to_string(),
+            ));
+        }
+
+        if async_worker_count == 0 {
+            return Err(io::Error::InvalidOptions(
+                "worker_threads must be at least 1".to_string(),
+            ));
+        }
+
+        if async_worker_count > processor_count {
+            if !self.allow_oversubscription {
+                return Err(io::Error::InvalidOptions(format!(
+                    "worker_threads ({async_worker_count}) exceeds the number of available \
+                     processors ({processor_count}); use allow_oversubscription(true) to permit \
+                     this deliberately"
+                )));
+            }
+
+            event!(
+                Level::WARN,
+                message = "more async workers than processors - workers will share processors",
+                async_worker_count,
+                processor_count
+            );
+        }
+
+        // The workers are grouped by the NUMA node of the processor they are pinned to, so work can
+        // be placed on a specific node via `spawn_on_numa_node()`.
+        let mut workers_by_numa_node: HashMap<NumaNodeId, Vec<usize>> = HashMap::new();
+
+        for worker_index in 0..async_worker_count {
+            let processor_id = processor_ids[worker_index % processor_count];
+
+            workers_by_numa_node
+                .entry(numa_node_of(processor_id))
+                .or_default()
+                .push(worker_index);
+        }
+
+        let numa_node_count = workers_by_numa_node.len();
+
+        event!(
+            Level::INFO,
+            processor_count,
+            async_worker_count,
+            numa_node_count
+        );
+
+        if let Some(max_buffer_memory) = self.max_buffer_memory {
+            if max_buffer_memory.get() < POOL_BUFFER_CAPACITY_BYTES {
+                return Err(io::Error::InvalidOptions(format!(
+                    "max_buffer_memory ({max_buffer_memory}) must be at least the size of one \
+                     pooled buffer ({POOL_BUFFER_CAPACITY_BYTES} bytes)"
+                )));
+            }
+        }
+
+        if self.handle_limit.is_some() {
+            enable_handle_accounting();
+        }
+
+        if self.max_buffer_memory.is_some() {
+            enable_buffer_accounting();
+        }
 
         let mut join_handles = Vec::with_capacity(sync_worker_count + async_worker_count);
 
@@ -273,7 +517,8 @@ impl RuntimeBuilder {
         let mut async_ready_rxs = Vec::with_capacity(async_worker_count);
 
         for worker_index in 0..async_worker_count {
-            let processor_id = processor_ids[worker_index];
+            // With oversubscription, the extra workers are assigned to processors round-robin.
+            let processor_id = processor_ids[worker_index % processor_count];
             let ThreadStartResult {
                 join_handle,
                 start_tx,
@@ -383,8 +628,14 @@ impl RuntimeBuilder {
             sync_task_queues_by_processor,
             sync_priority_task_queues_by_processor,
             processor_ids.clone(),
+            workers_by_numa_node
+                .into_iter()
+                .map(|(k, v)| (k, v.into_boxed_slice()))
+                .collect(),
             join_handles.into_boxed_slice(),
             Arc::clone(&is_stopping),
+            self.handle_limit.map(NonZeroUsize::get),
+            self.max_buffer_memory.map(NonZeroUsize::get),
         );
 
         // In most cases, the entrypoint thread is merely parked. However, for interoperability
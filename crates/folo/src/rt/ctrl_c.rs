@@ -0,0 +1,208 @@
+use crate::io;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{self, Waker},
+};
+use windows::Win32::{
+    Foundation::{BOOL, FALSE, TRUE},
+    System::Console::{SetConsoleCtrlHandler, CTRL_C_EVENT},
+};
+
+/// Waits until the user presses Ctrl+C in the console of the process. This is the usual way to
+/// shut down a long-running server gracefully: start the server, await `ctrl_c()`, then stop the
+/// server and the runtime.
+///
+/// Any number of tasks (on any threads) may wait at the same time - each of them is woken up by
+/// the same Ctrl+C press. The task starts waiting when the returned future is first polled, so only
+/// presses after that complete it.
+///
+/// While at least one task is waiting, a console control handler is registered that consumes
+/// Ctrl+C, so the process is not terminated. The handler is unregistered once no task is waiting
+/// (because the futures have completed or been dropped), after which Ctrl+C reverts to its default
+/// behavior of terminating the process. Other console events (e.g. Ctrl+Break or closing the
+/// console window) are left to the default handling.
+///
+/// Fails if the console control handler cannot be registered.
+pub fn ctrl_c() -> impl Future<Output = io::Result<()>> {
+    CtrlC {
+        state: CtrlCState::Initial,
+    }
+}
+
+// Tracks the waiting tasks. Locked by the console control handler, which runs on a thread created
+// by the operating system for each console event.
+static WAITING: Mutex<Waiting> = Mutex::new(Waiting {
+    generation: 0,
+    next_id: 0,
+    wakers: Vec::new(),
+});
+
+// How many futures are waiting, which tells us when to register and unregister the handler. This
+// is separate from `WAITING` so we never hold a lock that the handler needs while calling into the
+// operating system to (un)register the handler.
+static REGISTRATIONS: Mutex<usize> = Mutex::new(0);
+
+struct Waiting {
+    // Incremented on every Ctrl+C, releasing everyone who started waiting before it.
+    generation: u64,
+
+    next_id: u64,
+
+    wakers: Vec<(u64, Waker)>,
+}
+
+enum CtrlCState {
+    Initial,
+    Waiting { id: u64, generation: u64 },
+    Done,
+}
+
+struct CtrlC {
+    state: CtrlCState,
+}
+
+impl CtrlC {
+    fn register() -> io::Result<()> {
+        let mut registrations = REGISTRATIONS.lock().expect("poisoned lock");
+
+        if *registrations == 0 {
+            // SAFETY: The handler is a function that lives for the lifetime of the process.
+            unsafe { SetConsoleCtrlHandler(Some(on_console_ctrl), true)? };
+        }
+
+        *registrations += 1;
+        Ok(())
+    }
+
+    fn unregister(id: u64) {
+        WAITING
+            .lock()
+            .expect("poisoned lock")
+            .wakers
+            .retain(|(x, _)| *x != id);
+
+        let mut registrations = REGISTRATIONS.lock().expect("poisoned lock");
+        *registrations -= 1;
+
+        if *registrations == 0 {
+            // SAFETY: Removing a handler we registered has no safety requirements. If this fails,
+            // there is nothing we can do - Ctrl+C will just remain consumed.
+            _ = unsafe { SetConsoleCtrlHandler(Some(on_console_ctrl), false) };
+        }
+    }
+}
+
+impl Future for CtrlC {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        match self.state {
+            CtrlCState::Initial => {
+                if let Err(e) = Self::register() {
+                    self.state = CtrlCState::Done;
+                    return task::Poll::Ready(Err(e));
+                }
+
+                let mut waiting = WAITING.lock().expect("poisoned lock");
+
+                let id = waiting.next_id;
+                waiting.next_id = waiting.next_id.wrapping_add(1);
+                waiting.wakers.push((id, cx.waker().clone()));
+
+                self.state = CtrlCState::Waiting {
+                    id,
+                    generation: waiting.generation,
+                };
+                task::Poll::Pending
+            }
+            CtrlCState::Waiting { id, generation } => {
+                let mut waiting = WAITING.lock().expect("poisoned lock");
+
+                if waiting.generation != generation {
+                    drop(waiting);
+
+                    Self::unregister(id);
+                    self.state = CtrlCState::Done;
+                    return task::Poll::Ready(Ok(()));
+                }
+
+                // The task may have been moved to a different waker since last time.
+                if let Some((_, waker)) = waiting.wakers.iter_mut().find(|(x, _)| *x == id) {
+                    waker.clone_from(cx.waker());
+                }
+
+                task::Poll::Pending
+            }
+            CtrlCState::Done => panic!("ctrl_c() future polled after completion"),
+        }
+    }
+}
+
+impl Drop for CtrlC {
+    fn drop(&mut self) {
+        if let CtrlCState::Waiting { id, .. } = self.state {
+            Self::unregister(id);
+        }
+    }
+}
+
+// Called by the operating system on a dedicated thread for every console event.
+unsafe extern "system" fn on_console_ctrl(ctrl_type: u32) -> BOOL {
+    if ctrl_type != CTRL_C_EVENT {
+        return FALSE;
+    }
+
+    let wakers = {
+        let mut waiting = WAITING.lock().expect("poisoned lock");
+        waiting.generation = waiting.generation.wrapping_add(1);
+        waiting
+            .wakers
+            .iter()
+            .map(|(_, waker)| waker.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // Waking a task of a Folo worker from a foreign thread wakes up the worker via its I/O
+    // completion port, so the task resumes promptly.
+    for waker in wakers {
+        waker.wake();
+    }
+
+    TRUE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        task::{noop_waker_ref, Context},
+        FutureExt,
+    };
+
+    #[test]
+    fn every_waiter_is_released_by_ctrl_c() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut first = ctrl_c().boxed();
+        let mut second = ctrl_c().boxed();
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+
+        // SAFETY: Nothing unsafe about simulating the console event.
+        assert_eq!(unsafe { on_console_ctrl(CTRL_C_EVENT) }, TRUE);
+
+        assert!(matches!(
+            first.poll_unpin(&mut cx),
+            task::Poll::Ready(Ok(()))
+        ));
+        assert!(matches!(
+            second.poll_unpin(&mut cx),
+            task::Poll::Ready(Ok(()))
+        ));
+
+        // Once nobody is waiting, the handler is unregistered.
+        assert_eq!(*REGISTRATIONS.lock().unwrap(), 0);
+    }
+}
@@ -1,11 +1,16 @@
 //! Top-level free functions that can be called to manipulate the Folo runtime.
 
 use super::SynchronousTaskType;
+use crate::io;
+use crate::net::ServerInfo;
 use crate::rt::{
     current_async_agent, current_runtime, ready_after_poll::ReadyAfterPoll, LocalJoinHandle,
-    RemoteJoinHandle,
+    NumaNodeId, RemoteJoinHandle, RuntimeHandle, RuntimeMetrics, SpawnOptions,
 };
+use crate::time::{Clock, Delay};
+use futures::future::{select, Either};
 use std::future::Future;
+use std::time::Duration;
 
 /// Spawns a task to execute a future on the current async worker thread.
 ///
@@ -20,10 +25,25 @@ where
     current_async_agent::with(|agent| agent.spawn(future))
 }
 
+/// Spawns a task to execute a future on the current async worker thread, with options that
+/// control how the task is scheduled. See `SpawnOptions` for the scheduling guarantees.
+///
+/// # Panics
+///
+/// Panics if the current thread is not an async worker thread owned by a Folo runtime.
+pub fn spawn_with_options<F, R>(options: SpawnOptions, future: F) -> LocalJoinHandle<R>
+where
+    F: Future<Output = R> + 'static,
+    R: 'static,
+{
+    current_async_agent::with(|agent| agent.spawn_with_options(options, future))
+}
+
 /// Spawns a task to execute a future on any worker thread owned by the same Folo runtime
 /// as the current thread. The future is provided by a closure.
 ///
-/// The future itself does not have to be thread-safe. However, the closure must be.
+/// The future itself does not have to be thread-safe. However, the closure must be. If the future
+/// is thread-safe, you can also use `spawn_future_on_any()` to skip the closure.
 ///
 /// # Panics
 ///
@@ -37,6 +57,82 @@ where
     current_runtime::with(|runtime| runtime.spawn_on_any(future_fn))
 }
 
+/// Spawns a task to execute a future on any worker thread owned by the same Folo runtime as the
+/// current thread, like `spawn_on_any()`, but instead of returning a join handle, calls
+/// `on_complete` with the result of the future. Use this for fire-and-forget tasks that still need
+/// to report their result somewhere, without having to keep a join handle around.
+///
+/// The callback is called on the worker thread that executed the task, as part of the task,
+/// immediately after the future completes. There is no ordering between the callbacks of different
+/// tasks, as tasks may run on different threads. If the task never completes (e.g. because the
+/// runtime is stopped first), the callback is never called.
+///
+/// # Panics
+///
+/// Panics if the current thread is not owned by a Folo runtime.
+pub fn spawn_with_callback<FN, F, R, C>(future_fn: FN, on_complete: C)
+where
+    FN: FnOnce() -> F + Send + 'static,
+    F: Future<Output = R> + 'static,
+    C: FnOnce(R) + Send + 'static,
+{
+    current_runtime::with(|runtime| runtime.spawn_with_callback(future_fn, on_complete))
+}
+
+/// Spawns a task to execute a future on a specific async worker thread owned by the same Folo
+/// runtime as the current thread. The future is provided by a closure.
+///
+/// Tasks spawned with the same worker index always run on the same thread. Valid worker indexes are
+/// `0..RuntimeClient::async_worker_count()`.
+///
+/// # Panics
+///
+/// Panics if the current thread is not owned by a Folo runtime or if the worker index is out of
+/// bounds.
+pub fn spawn_on_worker<FN, F, R>(worker_index: usize, future_fn: FN) -> RemoteJoinHandle<R>
+where
+    FN: FnOnce() -> F + Send + 'static,
+    F: Future<Output = R> + 'static,
+    R: Send + 'static,
+{
+    current_runtime::with(|runtime| runtime.spawn_on_worker(worker_index, future_fn))
+}
+
+/// Spawns a task to execute a future on any worker thread on the given NUMA node, owned by the same
+/// Folo runtime as the current thread, creating the future via closure. Use this to place
+/// latency-sensitive work near the memory it uses. Falls back to any worker if the runtime has no
+/// workers on the node.
+///
+/// # Panics
+///
+/// Panics if the current thread is not owned by a Folo runtime.
+pub fn spawn_on_numa_node<FN, F, R>(node: NumaNodeId, future_fn: FN) -> RemoteJoinHandle<R>
+where
+    FN: FnOnce() -> F + Send + 'static,
+    F: Future<Output = R> + 'static,
+    R: Send + 'static,
+{
+    current_runtime::with(|runtime| runtime.spawn_on_numa_node(node, future_fn))
+}
+
+/// Spawns a task to execute a thread-safe future on any worker thread owned by the same Folo
+/// runtime as the current thread. The future is moved to the worker thread that executes it.
+///
+/// Use this when the future is `Send`. If the future is not `Send` (e.g. it holds an `Rc` across
+/// an await point) but can be created by a `Send` closure, use `spawn_on_any()` instead, which
+/// creates the future on the worker thread that executes it.
+///
+/// # Panics
+///
+/// Panics if the current thread is not owned by a Folo runtime.
+pub fn spawn_future_on_any<F, R>(future: F) -> RemoteJoinHandle<R>
+where
+    F: Future<Output = R> + Send + 'static,
+    R: Send + 'static,
+{
+    current_runtime::with(|runtime| runtime.spawn_future_on_any(future))
+}
+
     /// Spawns a task to execute a future on every worker thread.
     ///
     /// There are two layers of callbacks involved here, with the overall sequence being:
@@ -69,9 +165,69 @@ where
     current_runtime::with(|runtime| runtime.spawn_sync(task_type, f))
 }
 
+/// Spawns a task on a synchronous worker thread like `spawn_sync()` but gives up waiting for the
+/// result once `timeout` has elapsed, failing with `io::Error::timed_out()`. This keeps the caller
+/// from being stuck forever on a blocking call that hangs (e.g. name resolution on a bad network).
+///
+/// Synchronous tasks cannot be preempted, so the timeout only frees the caller - the task keeps
+/// running and the worker thread remains occupied until the blocking call actually returns, at
+/// which point the result is discarded. A task that never returns permanently takes away one
+/// synchronous worker thread.
+pub fn spawn_sync_with_timeout<F, R>(
+    task_type: SynchronousTaskType,
+    timeout: Duration,
+    f: F,
+) -> impl Future<Output = io::Result<R>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    // The task is spawned right away, same as with `spawn_sync()`, not when the future is polled.
+    let join_handle = spawn_sync(task_type, f);
+    let delay = Delay::with_clock(&Clock::new(), timeout);
+
+    async move {
+        match select(join_handle, delay).await {
+            Either::Left((result, _)) => Ok(result),
+            Either::Right(_) => Err(io::Error::timed_out()),
+        }
+    }
+}
+
+/// Returns a handle to the Folo runtime that owns the current thread, or `None` if the current
+/// thread is not owned by a Folo runtime. Unlike the other functions here, the handle can be kept
+/// and sent to other threads, to interact with this runtime from code that runs elsewhere.
+pub fn current() -> Option<RuntimeHandle> {
+    current_runtime::try_get()
+}
+
+/// Lists the TCP servers of the current runtime that were built with
+/// `TcpServerBuilder::register_with_runtime()`. See `RuntimeClient::servers()`.
+pub fn servers() -> Vec<ServerInfo> {
+    current_runtime::with(|runtime| runtime.servers())
+}
+
+/// Takes a snapshot of the metrics of the current runtime. See `RuntimeClient::metrics()`.
+pub fn metrics() -> RuntimeMetrics {
+    current_runtime::with(|runtime| runtime.metrics())
+}
+
 /// Yields control back to the async task runtime to allow other tasks to run.
 /// There is no guarantee that other tasks will run in any particular order.
 /// Even the same task that called this may be scheduled again immediately.
+///
+/// The task wakes itself up before yielding, so it does not need anything else to wake it up -
+/// it is safe to call this in a loop that does nothing but wait for some condition to change.
 pub fn yield_now() -> impl Future<Output = ()> {
     ReadyAfterPoll::default()
 }
+
+/// Yields control back to the async task runtime `count` times in a row, giving other tasks more
+/// opportunities to run than a single `yield_now()` would. Useful for backoff loops and for testing
+/// the fairness of task scheduling. A count of zero completes without yielding.
+///
+/// As with `yield_now()`, the task wakes itself up each time, so it never waits for an external
+/// wakeup.
+pub fn yield_times(count: usize) -> impl Future<Output = ()> {
+    ReadyAfterPoll::ready_after(count)
+}
@@ -0,0 +1,58 @@
+use crate::trace::{event, Level};
+use core_affinity::CoreId;
+use windows::Win32::System::Threading::GetNumaProcessorNode;
+
+/// Identifies a NUMA node of the system, i.e. a group of processors together with the memory that
+/// is closest to them. Accessing memory on a different node is slower, so latency-sensitive work is
+/// best placed on a worker on the same node as its memory (see `spawn_on_numa_node()`).
+///
+/// The NUMA node of each processor is discovered via `GetNumaProcessorNode` when the runtime is
+/// built. On systems without NUMA, the operating system reports a flat topology in which every
+/// processor is on node 0, and the same is assumed for any processor whose node cannot be
+/// determined. With a flat topology, placement by NUMA node is equivalent to placement on any
+/// worker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NumaNodeId(u16);
+
+impl NumaNodeId {
+    pub const fn new(id: u16) -> Self {
+        Self(id)
+    }
+
+    pub const fn get(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Determines the NUMA node of a processor, falling back to node 0 (as in a flat topology) if the
+/// operating system cannot tell.
+pub(crate) fn numa_node_of(processor_id: CoreId) -> NumaNodeId {
+    // The processor IDs we work with are processor numbers within the processor group of the
+    // process, which is what GetNumaProcessorNode expects, though it can only take 256 of them.
+    let Ok(processor) = u8::try_from(processor_id.id) else {
+        event!(
+            Level::DEBUG,
+            message = "processor number out of range for NUMA query - assuming node 0",
+            processor = processor_id.id
+        );
+
+        return NumaNodeId(0);
+    };
+
+    let mut node: u8 = 0;
+
+    // SAFETY: Nothing unsafe here, just an FFI call with a valid pointer to a local.
+    match unsafe { GetNumaProcessorNode(processor, &mut node) } {
+        Ok(()) => NumaNodeId(node.into()),
+        Err(e) => {
+            event!(
+                Level::DEBUG,
+                message = "failed to query NUMA node of processor - assuming node 0",
+                processor,
+                error = e.to_string()
+            );
+
+            NumaNodeId(0)
+        }
+    }
+}
@@ -1,22 +1,102 @@
 use std::{future::Future, pin::Pin, task};
 
-/// A unit-result future that becomes ready immediately after the first time it is polled.
-/// We use this to implement yield_now().
-#[derive(Debug, Default)]
+/// A unit-result future that returns `Pending` for a given number of polls and becomes ready on the
+/// poll after that. We use this to implement yield_now() and yield_times().
+///
+/// Every time this returns `Pending`, it wakes its own waker before returning, so the task is
+/// scheduled to be polled again promptly. It does not rely on anything else waking up the task,
+/// so it is safe to use in loops that do nothing but yield (e.g. spinning while waiting for some
+/// condition) - the task will keep getting polled, just with other tasks getting a chance to run
+/// in between.
+#[derive(Debug)]
 pub(crate) struct ReadyAfterPoll {
-    first_poll_completed: bool,
+    remaining_pending_polls: usize,
+}
+
+impl ReadyAfterPoll {
+    /// Creates a future that returns `Pending` `polls` times before becoming ready. Zero means it
+    /// is ready on the first poll.
+    pub(crate) fn ready_after(polls: usize) -> Self {
+        Self {
+            remaining_pending_polls: polls,
+        }
+    }
+}
+
+impl Default for ReadyAfterPoll {
+    fn default() -> Self {
+        Self::ready_after(1)
+    }
 }
 
 impl Future for ReadyAfterPoll {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
-        if self.first_poll_completed {
+        if self.remaining_pending_polls == 0 {
             task::Poll::Ready(())
         } else {
-            self.first_poll_completed = true;
+            self.remaining_pending_polls -= 1;
             cx.waker().wake_by_ref();
             task::Poll::Pending
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::{waker, ArcWake};
+    use std::sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.wakes.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn wakes_on_every_pending_poll() {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = waker(Arc::clone(&counter));
+        let mut cx = task::Context::from_waker(&waker);
+
+        let mut future = ReadyAfterPoll::ready_after(3);
+
+        for _ in 0..3 {
+            assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        }
+
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+        assert_eq!(counter.wakes.load(atomic::Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn zero_polls_is_immediately_ready() {
+        let counter = Arc::new(CountingWaker::default());
+        let waker = waker(Arc::clone(&counter));
+        let mut cx = task::Context::from_waker(&waker);
+
+        let mut future = ReadyAfterPoll::ready_after(0);
+
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+        assert_eq!(counter.wakes.load(atomic::Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn default_yields_once() {
+        let mut future = ReadyAfterPoll::default();
+        let mut cx = task::Context::from_waker(futures::task::noop_waker_ref());
+
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+    }
+}
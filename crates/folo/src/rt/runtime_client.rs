@@ -2,10 +2,15 @@ use super::remote_result_box::RemoteResultBox;
 use super::sync_agent::SyncAgentCommand;
 use super::{current_async_agent, ErasedSyncTask};
 use crate::constants::{self, GENERAL_MILLISECONDS_BUCKETS};
-use crate::io::IoWaker;
+use crate::io::{self, pooled_buffer_bytes, CompletionCounters, IoWaker};
 use crate::metrics::{Event, EventBuilder};
-use crate::rt::{async_agent::AsyncAgentCommand, remote_task::RemoteTask, RemoteJoinHandle};
-use crate::util::LowPrecisionInstant;
+use crate::net::{ServerInfo, ServerRegistry};
+use crate::rt::{
+    async_agent::AsyncAgentCommand, remote_task::RemoteTask, waker::TaskCounters, NumaNodeId,
+    RemoteJoinHandle, RuntimeMetrics,
+};
+use crate::trace::{event, Level};
+use crate::util::{live_handles, LowPrecisionInstant};
 use core_affinity::CoreId;
 use crossbeam::channel;
 use crossbeam::queue::SegQueue;
@@ -15,7 +20,6 @@ use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{cell::Cell, future::Future, sync::Mutex, thread};
-use tracing::{event, Level};
 
 // TODO: In a real implementation we should split this up into multiple layers:
 // 1) Validation and input processing (what is the command, is it valid in context, etc).
@@ -62,6 +66,10 @@ pub struct RuntimeClient {
 
     processor_ids: Box<[CoreId]>,
 
+    // The indexes of the async workers on each NUMA node. With a flat topology, there is only one
+    // node and all the workers are on it.
+    workers_by_numa_node: HashMap<NumaNodeId, Box<[usize]>>,
+
     // This is None if `.wait()` has already been called - the field can be consumed only once,
     // typically done by the runtime client provided to the entry point thread.
     #[allow(clippy::type_complexity)] // One day we may refactor this but not today.
@@ -69,8 +77,32 @@ pub struct RuntimeClient {
 
     // This can be used by cleanup logic to detect that the runtime is not usable anymore.
     is_stopping: Arc<AtomicBool>,
+
+    // The TCP servers that opted into being listed by `servers()`.
+    servers: Arc<ServerRegistry>,
+
+    // Shared by the I/O drivers of all async workers, which count their completions here.
+    completion_counters: Arc<CompletionCounters>,
+
+    // Shared by the task engines of all async workers, which count spawned tasks and
+    // coalesced wake-ups here.
+    task_counters: Arc<TaskCounters>,
+
+    // If set, TCP servers throttle accepting connections once this many handles are live.
+    handle_limit: Option<usize>,
+
+    // If set, TCP servers throttle accepting connections once this many bytes of pooled buffers
+    // are in use.
+    max_buffer_memory: Option<usize>,
 }
 
+/// A cheap cloneable handle to a Folo runtime, obtained via `folo::rt::current()` from any thread
+/// owned by the runtime. It is the same runtime client that `RuntimeBuilder::build()` returns.
+///
+/// The handle is `Send`, so it can be captured into closures given to `spawn_on_any()` and
+/// friends, to spawn tasks back onto the originating runtime from another worker.
+pub type RuntimeHandle = RuntimeClient;
+
 impl RuntimeClient {
     #[allow(clippy::too_many_arguments)] // Ssssshhhhh, sleep little Clippy!
     pub(super) fn new(
@@ -82,8 +114,11 @@ impl RuntimeClient {
         sync_task_queues_by_processor: HashMap<CoreId, Arc<SegQueue<ErasedSyncTask>>>,
         sync_priority_task_queues_by_processor: HashMap<CoreId, Arc<SegQueue<ErasedSyncTask>>>,
         processor_ids: Box<[CoreId]>,
+        workers_by_numa_node: HashMap<NumaNodeId, Box<[usize]>>,
         join_handles: Box<[thread::JoinHandle<()>]>,
         is_stopping: Arc<AtomicBool>,
+        handle_limit: Option<usize>,
+        max_buffer_memory: Option<usize>,
     ) -> Self {
         let pending_sync_tasks_by_processor = processor_ids
             .iter()
@@ -105,8 +140,14 @@ impl RuntimeClient {
             pending_sync_tasks_by_processor,
             pending_sync_priority_tasks_by_processor,
             processor_ids,
+            workers_by_numa_node,
             join_handles: Arc::new(Mutex::new(Some(join_handles))),
             is_stopping,
+            servers: Arc::new(ServerRegistry::default()),
+            completion_counters: Arc::new(CompletionCounters::default()),
+            task_counters: Arc::new(TaskCounters::default()),
+            handle_limit,
+            max_buffer_memory,
         }
     }
 
@@ -117,6 +158,52 @@ impl RuntimeClient {
         F: Future<Output = R> + 'static,
         R: Send + 'static,
     {
+        self.spawn_on_worker(next_async_worker(self.async_command_txs.len()), future_fn)
+    }
+
+    /// Spawns a task to execute a future on any worker thread, creating the future via closure, and
+    /// calls `on_complete` with the result instead of returning a join handle. The callback is
+    /// called on the worker thread that executed the task, as part of the task.
+    pub fn spawn_with_callback<FN, F, R, C>(&self, future_fn: FN, on_complete: C)
+    where
+        FN: FnOnce() -> F + Send + 'static,
+        F: Future<Output = R> + 'static,
+        C: FnOnce(R) + Send + 'static,
+    {
+        // The result is delivered to the callback, so there is nothing for the join handle to
+        // return. Dropping the join handle does not affect the task.
+        _ = self.spawn_on_any(move || async move { on_complete(future_fn().await) });
+    }
+
+    /// The number of async worker threads owned by the runtime. Valid worker indexes for
+    /// `spawn_on_worker()` are `0..async_worker_count()`.
+    pub fn async_worker_count(&self) -> usize {
+        self.async_command_txs.len()
+    }
+
+    /// Spawns a task to execute a future on a specific async worker thread, creating the future via
+    /// closure. Spawning multiple tasks with the same worker index guarantees that they all run on
+    /// the same thread, so they can share thread-local state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the worker index is not less than `async_worker_count()`.
+    pub fn spawn_on_worker<FN, F, R>(
+        &self,
+        worker_index: usize,
+        future_fn: FN,
+    ) -> RemoteJoinHandle<R>
+    where
+        FN: FnOnce() -> F + Send + 'static,
+        F: Future<Output = R> + 'static,
+        R: Send + 'static,
+    {
+        assert!(
+            worker_index < self.async_command_txs.len(),
+            "worker index {worker_index} out of bounds - the runtime has {} async workers",
+            self.async_command_txs.len()
+        );
+
         let started = LowPrecisionInstant::now();
 
         // Just because we are spawning a future on another thread does not mean it has to be a
@@ -136,6 +223,83 @@ impl RuntimeClient {
         let task = RemoteTask::new(thread_safe_wrapper_future);
         let join_handle = task.join_handle(self.current_thread_io_waker());
 
+        // We ignore the return value because it is theoretically possible that something is trying
+        // to schedule new work when we are in the middle of a shutdown process.
+        _ = self.async_command_txs[worker_index].send(AsyncAgentCommand::EnqueueTask {
+            erased_task: Box::pin(task),
+        });
+
+        // Wake up the agent if it might be sleeping and waiting for I/O.
+        self.async_io_wakers[worker_index].wake();
+
+        join_handle
+    }
+
+    /// The NUMA nodes that the async workers of the runtime are on, in ascending order. With a flat
+    /// topology, this is only node 0. See `NumaNodeId`.
+    pub fn numa_nodes(&self) -> Vec<NumaNodeId> {
+        let mut nodes: Vec<_> = self.workers_by_numa_node.keys().copied().collect();
+        nodes.sort_unstable();
+        nodes
+    }
+
+    /// The indexes of the async workers on the given NUMA node, for use with `spawn_on_worker()`.
+    /// Empty if the runtime has no workers on the node.
+    pub fn numa_node_workers(&self, node: NumaNodeId) -> &[usize] {
+        self.workers_by_numa_node
+            .get(&node)
+            .map_or(&[], |workers| workers)
+    }
+
+    /// Spawns a task to execute a future on any worker thread on the given NUMA node, creating the
+    /// future via closure. Use this to place latency-sensitive work near the memory it uses.
+    ///
+    /// If the runtime has no workers on the node (e.g. because it was restricted to a different
+    /// node via `RuntimeBuilder::numa_node()`), the task is spawned on any worker instead.
+    pub fn spawn_on_numa_node<FN, F, R>(
+        &self,
+        node: NumaNodeId,
+        future_fn: FN,
+    ) -> RemoteJoinHandle<R>
+    where
+        FN: FnOnce() -> F + Send + 'static,
+        F: Future<Output = R> + 'static,
+        R: Send + 'static,
+    {
+        let workers = self.numa_node_workers(node);
+
+        if workers.is_empty() {
+            return self.spawn_on_any(future_fn);
+        }
+
+        // The round-robin counter is shared with `spawn_on_any()`, so it may be beyond the number
+        // of workers on the node.
+        let worker_index = workers[next_async_worker(self.async_command_txs.len()) % workers.len()];
+
+        self.spawn_on_worker(worker_index, future_fn)
+    }
+
+    /// Spawns a task to execute a thread-safe future on any worker thread.
+    ///
+    /// Unlike `spawn_on_any()`, this takes the future itself, which is moved to the target worker.
+    /// This is only possible if the future is `Send`. If the future is not `Send` (e.g. because it
+    /// holds an `Rc` across an await point), use `spawn_on_any()` with a thread-safe closure that
+    /// creates the future on the target worker instead.
+    pub fn spawn_future_on_any<F, R>(&self, future: F) -> RemoteJoinHandle<R>
+    where
+        F: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let started = LowPrecisionInstant::now();
+
+        // The future is thread-safe, so it can be the task directly - no need to create it on
+        // the target thread and chain join handles like in `spawn_on_any()`.
+        let task = RemoteTask::new(async move {
+            REMOTE_SPAWN_DELAY.with(|x| x.observe_millis(started.elapsed()));
+            future.await
+        });
+        let join_handle = task.join_handle(self.current_thread_io_waker());
+
         let worker_index = next_async_worker(self.async_command_txs.len());
 
         // We ignore the return value because it is theoretically possible that something is trying
@@ -152,7 +316,10 @@ impl RuntimeClient {
 
     /// Spawns a TCP connection dispatch task on the worker dedicated for connection dispatch,
     /// creating the future via closure.
-    pub fn spawn_tcp_dispatcher<FN, F, R>(&self, future_fn: FN) -> RemoteJoinHandle<R>
+    ///
+    /// Fails if the runtime is stopping or the dispatcher worker has already terminated, in which
+    /// case the task would never run.
+    pub fn spawn_tcp_dispatcher<FN, F, R>(&self, future_fn: FN) -> io::Result<RemoteJoinHandle<R>>
     where
         FN: FnOnce() -> F + Send + 'static,
         F: Future<Output = R> + 'static,
@@ -174,21 +341,32 @@ impl RuntimeClient {
             join_handle.await
         };
 
+        // The dispatcher drops tasks that arrive once it has been told to stop, so there is no
+        // point in sending one. The caller would be left waiting for a task that never runs.
+        if self.is_stopping() {
+            return Err(io::Error::LogicError(
+                "cannot spawn TCP dispatcher task - the runtime is stopping".to_string(),
+            ));
+        }
+
         let task = RemoteTask::new(thread_safe_wrapper_future);
         let join_handle = task.join_handle(self.current_thread_io_waker());
 
-        // We ignore the return value because it is theoretically possible that something is trying
-        // to schedule new work when we are in the middle of a shutdown process.
-        _ = self
-            .tcp_dispatcher_command_tx
+        self.tcp_dispatcher_command_tx
             .send(AsyncAgentCommand::EnqueueTask {
                 erased_task: Box::pin(task),
-            });
+            })
+            .map_err(|_| {
+                io::Error::LogicError(
+                    "cannot spawn TCP dispatcher task - the TCP dispatcher worker has terminated"
+                        .to_string(),
+                )
+            })?;
 
         // Wake up the agent if it might be sleeping and waiting for I/O.
         self.tcp_dispatcher_io_waker.wake();
 
-        join_handle
+        Ok(join_handle)
     }
 
     /// Spawns a task to execute a future on every worker thread.
@@ -436,6 +614,8 @@ impl RuntimeClient {
     ///
     /// This returns immediately. To wait for the runtime to stop, use `wait()`.
     pub fn stop(&self) {
+        self.is_stopping.store(true, Ordering::Relaxed);
+
         for tx in &self.async_command_txs {
             // We ignore the return value because if the worker has already stopped, the channel
             // may be closed in which case the send may simply fail.
@@ -505,6 +685,50 @@ impl RuntimeClient {
         }
     }
 
+    /// Lists the TCP servers of the runtime that were built with
+    /// `TcpServerBuilder::register_with_runtime()`, in the order they started. Servers that did not
+    /// opt in are not listed. A server is listed once it has started and until it stops.
+    ///
+    /// This is intended for debugging and administration (e.g. a page listing the servers of a
+    /// service) - the information is a snapshot that may be out of date by the time it is used.
+    pub fn servers(&self) -> Vec<ServerInfo> {
+        self.servers.snapshot()
+    }
+
+    pub(crate) fn server_registry(&self) -> &Arc<ServerRegistry> {
+        &self.servers
+    }
+
+    /// Takes a snapshot of the metrics of the runtime, accumulated since it started.
+    pub fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics {
+            immediate_completions: self.completion_counters.immediate.load(Ordering::Relaxed),
+            deferred_completions: self.completion_counters.deferred.load(Ordering::Relaxed),
+            coalesced_wakes: self.task_counters.coalesced_wakes.load(Ordering::Relaxed),
+            spawned_tasks: self.task_counters.spawned.load(Ordering::Relaxed),
+            live_handles: live_handles().map(|count| count as u64),
+            pooled_buffer_bytes: pooled_buffer_bytes().map(|bytes| bytes as u64),
+        }
+    }
+
+    /// The ceiling on live handles set via `RuntimeBuilder::handle_limit()`, if any.
+    pub(crate) fn handle_limit(&self) -> Option<usize> {
+        self.handle_limit
+    }
+
+    /// The budget for pooled buffer memory set via `RuntimeBuilder::max_buffer_memory()`, if any.
+    pub(crate) fn max_buffer_memory(&self) -> Option<usize> {
+        self.max_buffer_memory
+    }
+
+    pub(crate) fn completion_counters(&self) -> &Arc<CompletionCounters> {
+        &self.completion_counters
+    }
+
+    pub(crate) fn task_counters(&self) -> &Arc<TaskCounters> {
+        &self.task_counters
+    }
+
     fn current_thread_io_waker(&self) -> Option<IoWaker> {
         current_async_agent::try_with_io(|io| io.waker())
     }
@@ -546,6 +770,7 @@ impl fmt::Debug for RuntimeClient {
                     .sum::<usize>(),
             )
             .field("processor_ids", &self.processor_ids)
+            .field("workers_by_numa_node", &self.workers_by_numa_node)
             .field("join_handles", &self.join_handles)
             .field("is_stopping", &self.is_stopping)
             .finish()
@@ -0,0 +1,28 @@
+/// A snapshot of the metrics of a runtime, as returned by `RuntimeClient::metrics()`. The values
+/// are accumulated over all worker threads since the runtime started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeMetrics {
+    /// The number of I/O operations that completed immediately, without going through the I/O
+    /// completion port. This is the fast path enabled by `FILE_SKIP_COMPLETION_PORT_ON_SUCCESS`.
+    pub immediate_completions: u64,
+
+    /// The number of I/O operations whose completion was delivered via the I/O completion port.
+    /// A high ratio of these for small I/O operations suggests that the immediate completion fast
+    /// path is not engaging (e.g. because data is rarely already available when receiving).
+    pub deferred_completions: u64,
+
+    /// The number of times a task was woken up while it was already scheduled to be polled. Such
+    /// redundant wake-ups are coalesced - the task is still polled only once.
+    pub coalesced_wakes: u64,
+
+    /// The number of tasks spawned on async workers, including tasks that have since completed.
+    pub spawned_tasks: u64,
+
+    /// The number of handles (files, sockets and such) owned by Folo that are currently open in the
+    /// process, or `None` if handle accounting is not enabled (see `RuntimeBuilder::handle_limit()`).
+    pub live_handles: Option<u64>,
+
+    /// The number of bytes of pooled I/O buffers currently in use in the process, or `None` if
+    /// buffer accounting is not enabled (see `RuntimeBuilder::max_buffer_memory()`).
+    pub pooled_buffer_bytes: Option<u64>,
+}
@@ -0,0 +1,48 @@
+/// How urgently the async worker should poll a task relative to the other tasks on the same worker.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TaskPriority {
+    #[default]
+    Normal,
+
+    /// Whenever a high priority task is ready to make progress, the worker polls it before any
+    /// normal priority tasks that are ready at the same time.
+    High,
+}
+
+/// Options for spawning a task via `spawn_with_options()`.
+///
+/// The priority is a best-effort hint: a worker polls all ready high priority tasks before the
+/// ready normal priority tasks, but it never preempts a task that is being polled and it does not
+/// prevent normal priority tasks from running once the high priority tasks have yielded or are
+/// waiting for something. Priorities only affect ordering between tasks on the same worker.
+#[derive(Clone, Debug, Default)]
+pub struct SpawnOptions {
+    priority: TaskPriority,
+    name: Option<String>,
+}
+
+impl SpawnOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the priority of the task. Defaults to `TaskPriority::Normal`.
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets a name for the task, shown when inspecting the task for diagnostic purposes.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub(crate) fn priority_value(&self) -> TaskPriority {
+        self.priority
+    }
+
+    pub(crate) fn name_value(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
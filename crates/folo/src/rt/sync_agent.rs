@@ -1,11 +1,11 @@
 use super::ErasedSyncTask;
+use crate::trace::{event, Level};
 use crate::{
     constants::GENERAL_MILLISECONDS_BUCKETS,
     metrics::{self, Event, EventBuilder, Magnitude, ReportPage},
 };
 use crossbeam::{channel, queue::SegQueue};
 use std::{fmt::Debug, sync::Arc};
-use tracing::{event, Level};
 
 #[derive(Debug)]
 pub struct SyncAgent {
@@ -0,0 +1,327 @@
+use crate::{constants::POISONED_LOCK, time};
+use negative_impl::negative_impl;
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    fmt,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{self, Poll, Wake, Waker},
+    time::Duration,
+};
+
+type TestTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Creates a deterministic single-threaded runtime for testing async code. See `TestRuntime`.
+pub fn test_runtime() -> TestRuntime {
+    TestRuntime::new()
+}
+
+/// A runtime that executes tasks on the current thread, only when explicitly told to via `step()`
+/// or `run_until_stalled()`. There are no worker threads - tasks are polled in the order they were
+/// woken up, so the same test always executes the same way.
+///
+/// Time is virtual: creating the runtime pauses the clock of the current thread (see
+/// `folo::time::pause()`) until the runtime is dropped, so the time only moves forward when the
+/// test calls `advance_time()` and timers (e.g. `Delay` with a clock from `Clock::new()`) fire
+/// deterministically without the test having to wait in real time.
+///
+/// This runtime does not provide the services of the real Folo runtime - free functions like
+/// `folo::rt::spawn()` and I/O primitives require an async worker thread and are not available in
+/// tasks running here. To spawn more tasks from within a task, give it a clone of the runtime.
+///
+/// The runtime is a handle to shared state, so clones refer to the same runtime.
+#[derive(Clone)]
+pub struct TestRuntime {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    // Completed tasks leave their slot empty. Slots are never reused, so wake-ups meant for a
+    // completed task cannot reach a different task.
+    tasks: RefCell<Vec<Option<TestTask>>>,
+
+    // Indexes of the tasks that have been woken up and are waiting to be polled, in wake-up order.
+    // Wakers may be used from any thread, so this is shared via a lock.
+    ready: Arc<Mutex<VecDeque<usize>>>,
+
+    // Whether we paused the clock of the thread, in which case we resume it when dropped. If the
+    // test had already paused it, it remains paused.
+    paused_clock: bool,
+}
+
+impl TestRuntime {
+    pub fn new() -> Self {
+        let paused_clock = !time::is_paused();
+        time::pause();
+
+        Self {
+            inner: Rc::new(Inner {
+                tasks: RefCell::new(Vec::new()),
+                ready: Arc::new(Mutex::new(VecDeque::new())),
+                paused_clock,
+            }),
+        }
+    }
+
+    /// Spawns a task to execute a future. The task is first polled by the next `step()`.
+    pub fn spawn<F, R>(&self, future: F) -> TestJoinHandle<R>
+    where
+        F: Future<Output = R> + 'static,
+        R: 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let task = Box::pin(async move {
+            // We ignore the result because the join handle may have been dropped.
+            _ = result_tx.send(future.await);
+        });
+
+        let index = {
+            let mut tasks = self.inner.tasks.borrow_mut();
+            tasks.push(Some(task));
+            tasks.len() - 1
+        };
+
+        self.ready().push_back(index);
+
+        TestJoinHandle { rx: result_rx }
+    }
+
+    /// Polls every task that is ready at the start of the call once, in the order they were woken
+    /// up. Tasks that are woken up or spawned during the step are polled by the next step. Returns
+    /// the number of tasks polled.
+    pub fn step(&self) -> usize {
+        let ready = {
+            let mut ready = self.ready();
+            let mut seen = HashSet::new();
+
+            // A task may be woken up multiple times before it is polled but we poll it only once.
+            ready
+                .drain(..)
+                .filter(|x| seen.insert(*x))
+                .collect::<Vec<_>>()
+        };
+
+        let mut polled = 0;
+
+        for index in ready {
+            // We take the task out of its slot while polling, so it can spawn more tasks.
+            let Some(mut task) = self.inner.tasks.borrow_mut()[index].take() else {
+                // Completed already - this was a stale wake-up.
+                continue;
+            };
+
+            let waker = Waker::from(Arc::new(TaskWaker {
+                index,
+                ready: Arc::clone(&self.inner.ready),
+            }));
+
+            polled += 1;
+
+            if task
+                .as_mut()
+                .poll(&mut task::Context::from_waker(&waker))
+                .is_pending()
+            {
+                self.inner.tasks.borrow_mut()[index] = Some(task);
+            }
+        }
+
+        polled
+    }
+
+    /// Steps the runtime until no task is ready to be polled, returning the number of tasks polled.
+    /// Tasks waiting for a timer remain pending until `advance_time()` moves the clock past it.
+    ///
+    /// Never returns if some task keeps waking itself up (e.g. by yielding in a loop).
+    pub fn run_until_stalled(&self) -> usize {
+        let mut polled = 0;
+
+        loop {
+            match self.step() {
+                0 => return polled,
+                count => polled += count,
+            }
+        }
+    }
+
+    /// The number of tasks that have not yet completed.
+    pub fn pending_tasks(&self) -> usize {
+        self.inner.tasks.borrow().iter().flatten().count()
+    }
+
+    /// Moves the virtual time of the current thread forward via `folo::time::advance()`, waking up
+    /// the tasks whose timers have elapsed. The tasks are polled by the next `step()` or
+    /// `run_until_stalled()`.
+    pub fn advance_time(&self, duration: Duration) {
+        time::advance(duration);
+    }
+
+    fn ready(&self) -> std::sync::MutexGuard<'_, VecDeque<usize>> {
+        self.inner.ready.lock().expect(POISONED_LOCK)
+    }
+}
+
+impl Default for TestRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TestRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestRuntime")
+            .field("pending_tasks", &self.pending_tasks())
+            .finish()
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.paused_clock {
+            time::resume();
+        }
+    }
+}
+
+#[negative_impl]
+impl !Send for TestRuntime {}
+#[negative_impl]
+impl !Sync for TestRuntime {}
+
+struct TaskWaker {
+    index: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready
+            .lock()
+            .expect(POISONED_LOCK)
+            .push_back(self.index);
+    }
+}
+
+/// Allows the result of a task spawned on a `TestRuntime` to be awaited by another task on the
+/// same runtime, or inspected directly by the test via `try_result()`.
+///
+/// Awaiting this is optional - the task will continue even if you drop the join handle.
+#[derive(Debug)]
+pub struct TestJoinHandle<R> {
+    rx: oneshot::Receiver<R>,
+}
+
+impl<R> TestJoinHandle<R> {
+    /// Takes the result of the task if it has completed. The result can only be taken once.
+    pub fn try_result(&self) -> Option<R> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<R> Future for TestJoinHandle<R> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|result| result.expect("the task is never dropped before it completes"))
+    }
+}
+
+#[negative_impl]
+impl<R> !Send for TestJoinHandle<R> {}
+#[negative_impl]
+impl<R> !Sync for TestJoinHandle<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rt::yield_now,
+        time::{Clock, Delay},
+    };
+    use std::cell::Cell;
+
+    #[test]
+    fn step_polls_ready_tasks_once_in_order() {
+        let runtime = test_runtime();
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        for id in 0..3 {
+            let log = Rc::clone(&log);
+
+            runtime.spawn(async move {
+                log.borrow_mut().push((id, 1));
+                yield_now().await;
+                log.borrow_mut().push((id, 2));
+            });
+        }
+
+        assert_eq!(runtime.step(), 3);
+        assert_eq!(*log.borrow(), [(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(runtime.pending_tasks(), 3);
+
+        assert_eq!(runtime.step(), 3);
+        assert_eq!(
+            *log.borrow(),
+            [(0, 1), (1, 1), (2, 1), (0, 2), (1, 2), (2, 2)]
+        );
+        assert_eq!(runtime.pending_tasks(), 0);
+
+        assert_eq!(runtime.step(), 0);
+    }
+
+    #[test]
+    fn tasks_can_spawn_and_await_tasks() {
+        let runtime = test_runtime();
+        let runtime_clone = runtime.clone();
+
+        let outer = runtime.spawn(async move {
+            let inner = runtime_clone.spawn(async { 21 });
+            inner.await * 2
+        });
+
+        assert!(outer.try_result().is_none());
+
+        runtime.run_until_stalled();
+
+        assert_eq!(outer.try_result(), Some(42));
+    }
+
+    #[test]
+    fn delay_completes_only_after_time_is_advanced() {
+        let runtime = test_runtime();
+        let clock = Clock::new();
+        let done = Rc::new(Cell::new(false));
+        let done_clone = Rc::clone(&done);
+
+        runtime.spawn(async move {
+            Delay::with_clock(&clock, Duration::from_secs(10)).await;
+            done_clone.set(true);
+        });
+
+        runtime.run_until_stalled();
+        assert!(!done.get());
+
+        runtime.advance_time(Duration::from_secs(9));
+        runtime.run_until_stalled();
+        assert!(!done.get());
+
+        runtime.advance_time(Duration::from_secs(1));
+        runtime.run_until_stalled();
+        assert!(done.get());
+        assert_eq!(runtime.pending_tasks(), 0);
+
+        // The runtime paused the clock of the thread, so it also resumes it.
+        drop(runtime);
+        assert!(!time::is_paused());
+    }
+}
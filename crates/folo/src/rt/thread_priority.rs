@@ -0,0 +1,44 @@
+use std::{os::windows::io::AsRawHandle, thread::JoinHandle};
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::Threading::{
+        SetThreadPriority, THREAD_PRIORITY, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST,
+        THREAD_PRIORITY_NORMAL, THREAD_PRIORITY_TIME_CRITICAL,
+    },
+};
+
+/// Scheduling priority of the worker threads of a Folo runtime, relative to other threads of the
+/// same process. Maps directly to the Windows thread priority levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThreadPriority {
+    Lowest,
+    BelowNormal,
+    #[default]
+    Normal,
+    AboveNormal,
+    Highest,
+    TimeCritical,
+}
+
+impl ThreadPriority {
+    fn as_native(self) -> THREAD_PRIORITY {
+        match self {
+            Self::Lowest => THREAD_PRIORITY_LOWEST,
+            Self::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+            Self::Normal => THREAD_PRIORITY_NORMAL,
+            Self::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            Self::Highest => THREAD_PRIORITY_HIGHEST,
+            Self::TimeCritical => THREAD_PRIORITY_TIME_CRITICAL,
+        }
+    }
+
+    /// Applies the priority to a newly started thread. Failure is reported to the caller, so that a
+    /// runtime never silently runs at a priority other than the one requested.
+    pub(crate) fn apply_to<T>(self, thread: &JoinHandle<T>) -> std::io::Result<()> {
+        // SAFETY: The join handle keeps the thread handle open for the duration of the call.
+        unsafe { SetThreadPriority(HANDLE(thread.as_raw_handle()), self.as_native()) }?;
+
+        Ok(())
+    }
+}
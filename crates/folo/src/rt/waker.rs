@@ -5,7 +5,7 @@ use std::{
     collections::VecDeque,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, Mutex,
     },
     task::{RawWaker, RawWakerVTable, Waker},
@@ -49,6 +49,17 @@ pub(crate) struct WakeSignal {
     // needs to read each signal to identify what has woken up.
     probe_embedded_wake_signals: Arc<AtomicBool>,
 
+    // Shared by all the wake signals of a runtime, to report how many wake-ups were coalesced.
+    task_counters: Arc<TaskCounters>,
+
+    /// Whether a wake-up has been delivered since the task was last polled. Any further wake-ups
+    /// are redundant until the task is polled again, so we coalesce them instead of delivering
+    /// them, which would only waste space in the awakened queue and work in the task engine.
+    ///
+    /// AcqRel ordering on both sides - a coalesced wake-up must still make its memory writes
+    /// visible to the poll that it relies on.
+    queued: AtomicBool,
+
     /// Counts each waker we have created (both the initial one and any clones). The instance cannot
     /// be dropped until the clones are all gone because each clone holds a self-reference to the
     /// wake signal.
@@ -74,11 +85,14 @@ impl WakeSignal {
     pub(crate) fn new(
         awakened_queue: Arc<Mutex<VecDeque<*mut Task>>>,
         probe_embedded_wake_signals: Arc<AtomicBool>,
+        task_counters: Arc<TaskCounters>,
     ) -> Self {
         Self {
             task_ptr: std::ptr::null_mut(),
             awakened_queue,
             probe_embedded_wake_signals,
+            task_counters,
+            queued: AtomicBool::new(false),
             waker_count: AtomicUsize::new(0),
             awakened: AtomicBool::new(false),
             waker: UnsafeCell::new(None),
@@ -102,6 +116,12 @@ impl WakeSignal {
         self.awakened.load(Ordering::Relaxed) && self.awakened.swap(false, Ordering::Acquire)
     }
 
+    /// Marks the task as about to be polled. Wake-ups received from now on are no longer redundant,
+    /// so the next one is delivered to the task engine.
+    pub(crate) fn begin_poll(&self) {
+        self.queued.swap(false, Ordering::AcqRel);
+    }
+
     /// Returns whether the signal is inert, meaning that no wakers are currently active and it is
     /// safe to drop the signal.
     pub(crate) fn is_inert(&self) -> bool {
@@ -144,14 +164,24 @@ impl WakeSignal {
     }
 
     fn wake(&self) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            // A wake-up has already been delivered and the task has not been polled since, so it
+            // will be polled anyway - no need to deliver another one.
+            self.task_counters
+                .coalesced_wakes
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
         if let Ok(mut awakened_set) = self.awakened_queue.try_lock() {
             // We only add if we can do so without increasing capacity, because increasing capacity
             // from an arbitrary thread may require reallocation, which we do not want to do on a
             // different thread than the one that owns the set.
             if awakened_set.len() < awakened_set.capacity() {
-                // If we experienced spurious awakenings, we might push the same task multiple
-                // times. That is fine - it is up to the receiver of the notifications to deal
-                // with spurious notifications (which may arrive anyway through other means).
+                // Wake-ups are coalesced until the task is polled, so we push the same task at
+                // most once between polls. It may still be spurious (e.g. if the task completed
+                // in the meantime) - it is up to the receiver of the notifications to deal with
+                // that.
                 awakened_set.push_back(self.task_ptr);
                 return;
             }
@@ -166,6 +196,16 @@ impl WakeSignal {
     }
 }
 
+/// Counts task lifecycle events, shared by all the task engines and wake signals of a runtime.
+#[derive(Debug, Default)]
+pub(crate) struct TaskCounters {
+    /// Wake-ups that were not delivered because the task was already scheduled to be polled.
+    pub(crate) coalesced_wakes: AtomicU64,
+
+    /// Tasks that were enqueued for execution on any async worker.
+    pub(crate) spawned: AtomicU64,
+}
+
 impl Drop for WakeSignal {
     fn drop(&mut self) {
         debug_assert!(self.is_inert());
@@ -234,6 +274,7 @@ mod tests {
         let signal = WakeSignal::new(
             Arc::clone(&awakened_queue),
             Arc::clone(&probe_embedded_wake_signals),
+            Arc::new(TaskCounters::default()),
         );
         let signal = unsafe { Pin::new_unchecked(&signal) };
 
@@ -275,6 +316,7 @@ mod tests {
         let signal = WakeSignal::new(
             Arc::clone(&awakened_queue),
             Arc::clone(&probe_embedded_wake_signals),
+            Arc::new(TaskCounters::default()),
         );
         let signal = unsafe { Pin::new_unchecked(&signal) };
 
@@ -309,6 +351,38 @@ mod tests {
         assert!(signal.is_inert());
     }
 
+    #[test]
+    fn redundant_wakes_are_coalesced_until_poll() {
+        #[allow(clippy::arc_with_non_send_sync)]
+        let awakened_queue = Arc::new(Mutex::new(VecDeque::with_capacity(10)));
+        let probe_embedded_wake_signals = Arc::new(AtomicBool::new(false));
+        let task_counters = Arc::new(TaskCounters::default());
+
+        let signal = WakeSignal::new(
+            Arc::clone(&awakened_queue),
+            Arc::clone(&probe_embedded_wake_signals),
+            Arc::clone(&task_counters),
+        );
+        let signal = unsafe { Pin::new_unchecked(&signal) };
+
+        let waker = unsafe { signal.waker() };
+
+        for _ in 0..100 {
+            waker.wake_by_ref();
+        }
+
+        // Only the first wake-up was delivered, the rest were redundant.
+        assert_eq!(awakened_queue.lock().unwrap().len(), 1);
+        assert_eq!(task_counters.coalesced_wakes.load(Ordering::Relaxed), 99);
+
+        // Once the task is polled, the next wake-up is delivered again.
+        signal.begin_poll();
+        waker.wake_by_ref();
+
+        assert_eq!(awakened_queue.lock().unwrap().len(), 2);
+        assert_eq!(task_counters.coalesced_wakes.load(Ordering::Relaxed), 99);
+    }
+
     #[test]
     fn awaken_via_full_awakened_set() {
         // Capacity is 0 so the queue is not allowed to allocate (== is never used).
@@ -319,6 +393,7 @@ mod tests {
         let signal = WakeSignal::new(
             Arc::clone(&awakened_queue),
             Arc::clone(&probe_embedded_wake_signals),
+            Arc::new(TaskCounters::default()),
         );
         let signal = unsafe { Pin::new_unchecked(&signal) };
 
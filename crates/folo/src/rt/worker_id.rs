@@ -0,0 +1,36 @@
+use std::thread;
+use windows::Win32::System::Threading::GetCurrentProcessorNumber;
+
+/// Identifies the worker thread of a Folo runtime that some activity takes place on, together with
+/// the processor the thread was running on when the identity was captured.
+///
+/// Async and sync workers are pinned to their processor, so for them the processor never changes.
+/// The TCP dispatcher worker is not pinned and the operating system may move it to a different
+/// processor at any time, so for it the processor is only a point-in-time observation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkerId {
+    thread_name: String,
+    processor_id: u32,
+}
+
+impl WorkerId {
+    /// Captures the identity of the current thread.
+    pub(crate) fn current() -> Self {
+        Self {
+            thread_name: thread::current().name().unwrap_or_default().to_string(),
+            // SAFETY: Nothing unsafe here, just an FFI call without parameters.
+            processor_id: unsafe { GetCurrentProcessorNumber() },
+        }
+    }
+
+    /// The name of the worker thread, e.g. `folo-tcp-dispatcher`.
+    pub fn thread_name(&self) -> &str {
+        &self.thread_name
+    }
+
+    /// The processor (within the processor group of the thread) that the worker was running on
+    /// when the identity was captured.
+    pub fn processor_id(&self) -> u32 {
+        self.processor_id
+    }
+}
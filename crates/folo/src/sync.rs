@@ -1,3 +1,9 @@
+mod barrier;
+mod notify;
+mod rw_lock;
 mod semaphores;
 
-pub use semaphores::*;
\ No newline at end of file
+pub use barrier::*;
+pub use notify::*;
+pub use rw_lock::*;
+pub use semaphores::*;
@@ -0,0 +1,178 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{self, Waker},
+};
+
+/// Lets a fixed number of tasks wait for each other to reach the same point, releasing all of them
+/// once the last one arrives. The tasks may be running on different worker threads.
+///
+/// This is typically used for phased startup, e.g. to ensure that every worker has initialized its
+/// per-worker state before any of them starts serving requests. Share the barrier between the
+/// participants via `Arc`.
+///
+/// The barrier can be reused - once all participants have been released, the next `n` arrivals
+/// form a new group.
+#[derive(Debug)]
+pub struct Barrier {
+    participants: usize,
+    state: Mutex<BarrierState>,
+}
+
+#[derive(Debug)]
+struct BarrierState {
+    // How many participants of the current generation have arrived.
+    arrived: usize,
+
+    // Incremented every time a group of participants is released.
+    generation: u64,
+
+    // Wakers of the participants of the current generation that are waiting for the rest.
+    awaiting: Vec<Waker>,
+}
+
+impl Barrier {
+    /// Creates a barrier that releases waiting tasks once `participants` tasks have arrived.
+    ///
+    /// A barrier with 0 participants behaves the same as one with 1 participant - every task that
+    /// waits on it is released immediately.
+    pub fn new(participants: usize) -> Self {
+        Self {
+            participants,
+            state: Mutex::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+                awaiting: Vec::with_capacity(participants),
+            }),
+        }
+    }
+
+    /// Arrives at the barrier and waits until all participants have arrived.
+    ///
+    /// The arrival is registered when the returned future is first polled. Dropping the future
+    /// after that does not withdraw the arrival - the barrier will still release the other
+    /// participants once the required number of arrivals is reached.
+    pub fn wait(&self) -> impl Future<Output = BarrierWaitResult> + '_ {
+        BarrierWait {
+            barrier: self,
+            generation: None,
+        }
+    }
+}
+
+/// The outcome of waiting on a `Barrier`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Whether this participant was the last to arrive and therefore released the others. Exactly
+    /// one participant of each group is the leader, which is useful for performing a one-time
+    /// action after the rendezvous.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+}
+
+struct BarrierWait<'a> {
+    barrier: &'a Barrier,
+
+    // The generation we arrived in, once we have arrived.
+    generation: Option<u64>,
+}
+
+impl Future for BarrierWait<'_> {
+    type Output = BarrierWaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let mut state = self
+            .barrier
+            .state
+            .lock()
+            .expect("poisoned lock - cannot continue");
+
+        match self.generation {
+            Some(generation) if generation != state.generation => {
+                // Our group has been released.
+                task::Poll::Ready(BarrierWaitResult { is_leader: false })
+            }
+            Some(_) => {
+                // Still waiting. The task may have been moved to a different waker since last time.
+                if !state.awaiting.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.awaiting.push(cx.waker().clone());
+                }
+
+                task::Poll::Pending
+            }
+            None => {
+                state.arrived += 1;
+
+                if state.arrived >= self.barrier.participants {
+                    state.arrived = 0;
+                    state.generation = state.generation.wrapping_add(1);
+
+                    for waker in state.awaiting.drain(..) {
+                        waker.wake();
+                    }
+
+                    return task::Poll::Ready(BarrierWaitResult { is_leader: true });
+                }
+
+                let generation = state.generation;
+                state.awaiting.push(cx.waker().clone());
+                drop(state);
+
+                self.generation = Some(generation);
+                task::Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        task::{noop_waker_ref, Context},
+        FutureExt,
+    };
+
+    #[test]
+    fn releases_all_once_last_arrives() {
+        let barrier = Barrier::new(3);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut first = barrier.wait().boxed_local();
+        let mut second = barrier.wait().boxed_local();
+        let mut third = barrier.wait().boxed_local();
+
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+
+        let task::Poll::Ready(result) = third.poll_unpin(&mut cx) else {
+            panic!("last participant must be released immediately");
+        };
+        assert!(result.is_leader());
+
+        let task::Poll::Ready(result) = first.poll_unpin(&mut cx) else {
+            panic!("first participant must be released once all have arrived");
+        };
+        assert!(!result.is_leader());
+        assert!(second.poll_unpin(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn can_be_reused() {
+        let barrier = Barrier::new(2);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        for _ in 0..3 {
+            let mut first = barrier.wait().boxed_local();
+            assert!(first.poll_unpin(&mut cx).is_pending());
+            assert!(barrier.wait().boxed_local().poll_unpin(&mut cx).is_ready());
+            assert!(first.poll_unpin(&mut cx).is_ready());
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use negative_impl::negative_impl;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{self, Waker},
+};
+
+/// Wakes up tasks of the same worker that are waiting for something to happen, e.g. for an item to
+/// be added to a queue. Unlike a oneshot channel, a notification can be sent any number of times.
+///
+/// `notify_one()` wakes up the task that has been waiting the longest. If no task is waiting, it
+/// stores a permit instead, so the next task to wait returns immediately - a notification sent
+/// just before a task starts waiting is not lost. At most one permit is stored.
+///
+/// `notify_all()` wakes up every task that is waiting at that moment and does not store a permit.
+///
+/// As the notifier never leaves its thread, it uses no atomics or cross-thread synchronization.
+#[derive(Debug, Default)]
+pub struct LocalNotify {
+    state: RefCell<NotifyState>,
+}
+
+#[derive(Debug, Default)]
+struct NotifyState {
+    // Set by `notify_one()` when nobody is waiting, consumed by the next waiter.
+    permit: bool,
+
+    // Incremented by every `notify_all()`, releasing everyone who started waiting before it.
+    generation: u64,
+
+    next_id: u64,
+
+    // The tasks that are waiting, in arrival order.
+    waiting: VecDeque<(u64, Waker)>,
+
+    // Waiters that have been picked by `notify_one()` but have not yet been polled since.
+    notified: Vec<u64>,
+}
+
+impl LocalNotify {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits for a notification. The task starts waiting when the returned future is first polled,
+    /// so only notifications sent after that (or a stored permit) complete it.
+    ///
+    /// If the future is dropped after being picked by `notify_one()` but before completing, the
+    /// notification is passed on to the next waiting task.
+    pub fn notified(&self) -> impl Future<Output = ()> + '_ {
+        Notified {
+            notify: self,
+            state: NotifiedState::Initial,
+        }
+    }
+
+    /// Wakes up the task that has been waiting the longest or, if none is waiting, stores a permit
+    /// for the next task to wait.
+    pub fn notify_one(&self) {
+        let waker = {
+            let mut state = self.state.borrow_mut();
+
+            match state.waiting.pop_front() {
+                Some((id, waker)) => {
+                    state.notified.push(id);
+                    waker
+                }
+                None => {
+                    state.permit = true;
+                    return;
+                }
+            }
+        };
+
+        // We wake outside the borrow, in case the waker does anything with the notifier.
+        waker.wake();
+    }
+
+    /// Wakes up every task that is currently waiting.
+    pub fn notify_all(&self) {
+        let waiting = {
+            let mut state = self.state.borrow_mut();
+            state.generation = state.generation.wrapping_add(1);
+            state.waiting.drain(..).collect::<Vec<_>>()
+        };
+
+        for (_, waker) in waiting {
+            waker.wake();
+        }
+    }
+}
+
+#[negative_impl]
+impl !Send for LocalNotify {}
+#[negative_impl]
+impl !Sync for LocalNotify {}
+
+enum NotifiedState {
+    Initial,
+    Waiting { id: u64, generation: u64 },
+    Done,
+}
+
+struct Notified<'a> {
+    notify: &'a LocalNotify,
+    state: NotifiedState,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let this = &mut *self;
+        let mut state = this.notify.state.borrow_mut();
+
+        match this.state {
+            NotifiedState::Initial => {
+                if state.permit {
+                    state.permit = false;
+                    this.state = NotifiedState::Done;
+                    return task::Poll::Ready(());
+                }
+
+                let id = state.next_id;
+                state.next_id = state.next_id.wrapping_add(1);
+                state.waiting.push_back((id, cx.waker().clone()));
+
+                this.state = NotifiedState::Waiting {
+                    id,
+                    generation: state.generation,
+                };
+                task::Poll::Pending
+            }
+            NotifiedState::Waiting { id, generation } => {
+                if let Some(index) = state.notified.iter().position(|x| *x == id) {
+                    state.notified.swap_remove(index);
+                    this.state = NotifiedState::Done;
+                    return task::Poll::Ready(());
+                }
+
+                if state.generation != generation {
+                    this.state = NotifiedState::Done;
+                    return task::Poll::Ready(());
+                }
+
+                // Still waiting. The task may have been moved to a different waker since last time.
+                if let Some((_, waker)) = state.waiting.iter_mut().find(|(x, _)| *x == id) {
+                    waker.clone_from(cx.waker());
+                }
+
+                task::Poll::Pending
+            }
+            NotifiedState::Done => panic!("Notified polled after completion"),
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let NotifiedState::Waiting { id, .. } = self.state else {
+            return;
+        };
+
+        let passed_on = {
+            let mut state = self.notify.state.borrow_mut();
+            state.waiting.retain(|(x, _)| *x != id);
+
+            match state.notified.iter().position(|x| *x == id) {
+                Some(index) => {
+                    state.notified.swap_remove(index);
+                    true
+                }
+                None => false,
+            }
+        };
+
+        // We were picked by notify_one() but nobody will ever see it, so someone else should.
+        if passed_on {
+            self.notify.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        task::{noop_waker_ref, Context},
+        FutureExt,
+    };
+
+    #[test]
+    fn notify_one_before_waiting_stores_permit() {
+        let notify = LocalNotify::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        notify.notify_one();
+        notify.notify_one();
+
+        // Only one permit is stored, no matter how many notifications were sent.
+        assert!(notify
+            .notified()
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_ready());
+        assert!(notify
+            .notified()
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+    }
+
+    #[test]
+    fn notify_one_wakes_in_arrival_order() {
+        let notify = LocalNotify::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut first = notify.notified().boxed_local();
+        let mut second = notify.notified().boxed_local();
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+
+        notify.notify_one();
+        assert!(second.poll_unpin(&mut cx).is_pending());
+        assert!(first.poll_unpin(&mut cx).is_ready());
+
+        notify.notify_one();
+        assert!(second.poll_unpin(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn notify_all_wakes_all_without_permit() {
+        let notify = LocalNotify::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut first = notify.notified().boxed_local();
+        let mut second = notify.notified().boxed_local();
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+
+        notify.notify_all();
+        assert!(first.poll_unpin(&mut cx).is_ready());
+        assert!(second.poll_unpin(&mut cx).is_ready());
+
+        assert!(notify
+            .notified()
+            .boxed_local()
+            .poll_unpin(&mut cx)
+            .is_pending());
+    }
+
+    #[test]
+    fn dropped_waiter_passes_on_notification() {
+        let notify = LocalNotify::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let mut first = notify.notified().boxed_local();
+        let mut second = notify.notified().boxed_local();
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+
+        notify.notify_one();
+        drop(first);
+
+        assert!(second.poll_unpin(&mut cx).is_ready());
+    }
+}
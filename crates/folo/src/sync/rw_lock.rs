@@ -0,0 +1,581 @@
+use negative_impl::negative_impl;
+use std::{
+    cell::{RefCell, UnsafeCell},
+    collections::VecDeque,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::Mutex,
+    task::{self, Waker},
+};
+
+/// A reader-writer lock for state that lives on a single thread, shared between tasks of the same
+/// worker. Any number of readers or a single writer can hold the lock at the same time.
+///
+/// Access is granted in arrival order: once a writer is waiting, readers that arrive after it wait
+/// behind it, so a steady stream of readers cannot starve writers. Consecutive waiting readers are
+/// granted access together.
+///
+/// As the lock never leaves its thread, it uses no atomics or cross-thread synchronization. Use
+/// `RwLock` for state shared between worker threads.
+#[derive(Debug)]
+pub struct LocalRwLock<T> {
+    core: RefCell<RwLockCore>,
+    value: UnsafeCell<T>,
+}
+
+impl<T> LocalRwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            core: RefCell::new(RwLockCore::default()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Waits until shared read access is available.
+    pub fn read(&self) -> impl Future<Output = LocalRwLockReadGuard<'_, T>> {
+        LocalAcquire {
+            lock: self,
+            access: Access::Read,
+            id: None,
+            guard: |lock| LocalRwLockReadGuard { lock },
+        }
+    }
+
+    /// Waits until exclusive write access is available.
+    pub fn write(&self) -> impl Future<Output = LocalRwLockWriteGuard<'_, T>> {
+        LocalAcquire {
+            lock: self,
+            access: Access::Write,
+            id: None,
+            guard: |lock| LocalRwLockWriteGuard { lock },
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn release(&self, access: Access) {
+        let wakers = self.core.borrow_mut().release(access);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<T: Default> Default for LocalRwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[negative_impl]
+impl<T> !Send for LocalRwLock<T> {}
+#[negative_impl]
+impl<T> !Sync for LocalRwLock<T> {}
+
+/// Shared read access to the value of a `LocalRwLock`, released when dropped.
+#[derive(Debug)]
+pub struct LocalRwLockReadGuard<'a, T> {
+    lock: &'a LocalRwLock<T>,
+}
+
+impl<T> Deref for LocalRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock guarantees there is no writer while a read guard exists.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for LocalRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Read);
+    }
+}
+
+/// Exclusive write access to the value of a `LocalRwLock`, released when dropped.
+#[derive(Debug)]
+pub struct LocalRwLockWriteGuard<'a, T> {
+    lock: &'a LocalRwLock<T>,
+}
+
+impl<T> Deref for LocalRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock guarantees a write guard is the only guard in existence.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for LocalRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock guarantees a write guard is the only guard in existence.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for LocalRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Write);
+    }
+}
+
+struct LocalAcquire<'a, T, G> {
+    lock: &'a LocalRwLock<T>,
+    access: Access,
+
+    // Assigned once we have to wait in the queue.
+    id: Option<u64>,
+
+    // Creates the guard matching the requested access once access is granted.
+    guard: fn(&'a LocalRwLock<T>) -> G,
+}
+
+impl<T, G> Future for LocalAcquire<'_, T, G> {
+    type Output = G;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let this = &mut *self;
+
+        this.lock
+            .core
+            .borrow_mut()
+            .poll_acquire(this.access, &mut this.id, cx.waker())
+            .map(|()| (this.guard)(this.lock))
+    }
+}
+
+impl<T, G> Drop for LocalAcquire<'_, T, G> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let wakers = self.lock.core.borrow_mut().cancel(id, self.access);
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+/// A reader-writer lock for state shared between worker threads, such as a routing table or a
+/// configuration snapshot that is read for every request but updated rarely. Any number of readers
+/// or a single writer can hold the lock at the same time. Share the lock via `Arc`.
+///
+/// Access is granted in arrival order: once a writer is waiting, readers that arrive after it wait
+/// behind it, so a steady stream of readers cannot starve writers. Consecutive waiting readers are
+/// granted access together.
+///
+/// If the state is only used on a single thread, `LocalRwLock` avoids the synchronization cost.
+#[derive(Debug)]
+pub struct RwLock<T> {
+    core: Mutex<RwLockCore>,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: The value is only accessed through guards, with the lock guaranteeing that either one
+// writer (requiring `Send` to mutate from any thread) or many readers (requiring `Sync` to share
+// between threads) have access at the same time. Same bounds as `std::sync::RwLock`.
+unsafe impl<T: Send> Send for RwLock<T> {}
+// SAFETY: See above.
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            core: Mutex::new(RwLockCore::default()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Waits until shared read access is available.
+    pub fn read(&self) -> impl Future<Output = RwLockReadGuard<'_, T>> {
+        Acquire {
+            lock: self,
+            access: Access::Read,
+            id: None,
+            guard: |lock| RwLockReadGuard { lock },
+        }
+    }
+
+    /// Waits until exclusive write access is available.
+    pub fn write(&self) -> impl Future<Output = RwLockWriteGuard<'_, T>> {
+        Acquire {
+            lock: self,
+            access: Access::Write,
+            id: None,
+            guard: |lock| RwLockWriteGuard { lock },
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn core(&self) -> std::sync::MutexGuard<'_, RwLockCore> {
+        self.core.lock().expect("poisoned lock - cannot continue")
+    }
+
+    fn release(&self, access: Access) {
+        // We wake outside the lock, so woken tasks do not immediately contend for it.
+        let wakers = self.core().release(access);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Shared read access to the value of a `RwLock`, released when dropped.
+#[derive(Debug)]
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock guarantees there is no writer while a read guard exists.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Read);
+    }
+}
+
+/// Exclusive write access to the value of a `RwLock`, released when dropped.
+#[derive(Debug)]
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock guarantees a write guard is the only guard in existence.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock guarantees a write guard is the only guard in existence.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(Access::Write);
+    }
+}
+
+struct Acquire<'a, T, G> {
+    lock: &'a RwLock<T>,
+    access: Access,
+
+    // Assigned once we have to wait in the queue.
+    id: Option<u64>,
+
+    // Creates the guard matching the requested access once access is granted.
+    guard: fn(&'a RwLock<T>) -> G,
+}
+
+impl<T, G> Future for Acquire<'_, T, G> {
+    type Output = G;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
+        let this = &mut *self;
+
+        this.lock
+            .core()
+            .poll_acquire(this.access, &mut this.id, cx.waker())
+            .map(|()| (this.guard)(this.lock))
+    }
+}
+
+impl<T, G> Drop for Acquire<'_, T, G> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let wakers = self.lock.core().cancel(id, self.access);
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    access: Access,
+    waker: Waker,
+}
+
+/// The bookkeeping shared by both lock variants, which differ only in how they protect it.
+#[derive(Debug, Default)]
+struct RwLockCore {
+    // Number of read guards in existence (including granted but not yet collected ones).
+    readers: usize,
+
+    // Whether a write guard exists (or has been granted but not yet collected).
+    writer: bool,
+
+    // Waiting acquirers in arrival order.
+    queue: VecDeque<Waiter>,
+
+    // Waiters that have been granted access but have not yet been polled to collect it. The access
+    // is already accounted for in `readers`/`writer`.
+    granted: Vec<u64>,
+
+    next_id: u64,
+}
+
+impl RwLockCore {
+    fn is_available(&self, access: Access) -> bool {
+        match access {
+            Access::Read => !self.writer,
+            Access::Write => !self.writer && self.readers == 0,
+        }
+    }
+
+    fn take(&mut self, access: Access) {
+        match access {
+            Access::Read => self.readers += 1,
+            Access::Write => self.writer = true,
+        }
+    }
+
+    fn poll_acquire(
+        &mut self,
+        access: Access,
+        id: &mut Option<u64>,
+        waker: &Waker,
+    ) -> task::Poll<()> {
+        match *id {
+            None => {
+                // Newcomers only skip the queue if nobody is waiting, so writers are not starved.
+                if self.queue.is_empty() && self.is_available(access) {
+                    self.take(access);
+                    return task::Poll::Ready(());
+                }
+
+                let new_id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+
+                self.queue.push_back(Waiter {
+                    id: new_id,
+                    access,
+                    waker: waker.clone(),
+                });
+
+                *id = Some(new_id);
+                task::Poll::Pending
+            }
+            Some(existing_id) => {
+                if let Some(index) = self.granted.iter().position(|x| *x == existing_id) {
+                    self.granted.swap_remove(index);
+                    *id = None;
+                    return task::Poll::Ready(());
+                }
+
+                // Still waiting. The task may have been moved to a different waker since last time.
+                if let Some(waiter) = self.queue.iter_mut().find(|x| x.id == existing_id) {
+                    if !waiter.waker.will_wake(waker) {
+                        waiter.waker = waker.clone();
+                    }
+                }
+
+                task::Poll::Pending
+            }
+        }
+    }
+
+    /// Releases access held by a guard, returning the wakers of any waiters granted access as a
+    /// result. The caller is expected to wake them once the core is no longer borrowed.
+    #[must_use]
+    fn release(&mut self, access: Access) -> Vec<Waker> {
+        match access {
+            Access::Read => self.readers -= 1,
+            Access::Write => self.writer = false,
+        }
+
+        self.grant_waiting()
+    }
+
+    /// Withdraws a waiter whose future was dropped before it collected its access. If access was
+    /// already granted to it, the access is released.
+    #[must_use]
+    fn cancel(&mut self, id: u64, access: Access) -> Vec<Waker> {
+        if let Some(index) = self.granted.iter().position(|x| *x == id) {
+            self.granted.swap_remove(index);
+            return self.release(access);
+        }
+
+        self.queue.retain(|x| x.id != id);
+
+        // If we were the head of the queue, whoever is behind us may now be able to proceed.
+        self.grant_waiting()
+    }
+
+    fn grant_waiting(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+
+        while let Some(waiter) = self.queue.front() {
+            if !self.is_available(waiter.access) {
+                break;
+            }
+
+            let waiter = self.queue.pop_front().expect("we just peeked at it");
+            self.take(waiter.access);
+            self.granted.push(waiter.id);
+            wakers.push(waiter.waker);
+
+            if waiter.access == Access::Write {
+                break;
+            }
+        }
+
+        wakers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        task::{noop_waker_ref, Context},
+        FutureExt,
+    };
+
+    #[test]
+    fn readers_share_access() {
+        let lock = LocalRwLock::new(42);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let task::Poll::Ready(first) = lock.read().boxed_local().poll_unpin(&mut cx) else {
+            panic!("uncontended read must be granted immediately");
+        };
+        let task::Poll::Ready(second) = lock.read().boxed_local().poll_unpin(&mut cx) else {
+            panic!("reads must not exclude each other");
+        };
+
+        assert_eq!(*first + *second, 84);
+    }
+
+    #[test]
+    fn writer_waits_for_readers() {
+        let lock = LocalRwLock::new(1);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let task::Poll::Ready(reader) = lock.read().boxed_local().poll_unpin(&mut cx) else {
+            panic!("uncontended read must be granted immediately");
+        };
+
+        let mut write = lock.write().boxed_local();
+        assert!(write.poll_unpin(&mut cx).is_pending());
+
+        drop(reader);
+
+        let task::Poll::Ready(mut writer) = write.poll_unpin(&mut cx) else {
+            panic!("writer must be granted access once the reader is gone");
+        };
+        *writer = 2;
+        drop(writer);
+        drop(write);
+
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn waiting_writer_is_not_starved_by_new_readers() {
+        let lock = RwLock::new(());
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let task::Poll::Ready(reader) = lock.read().boxed().poll_unpin(&mut cx) else {
+            panic!("uncontended read must be granted immediately");
+        };
+
+        let mut write = lock.write().boxed();
+        assert!(write.poll_unpin(&mut cx).is_pending());
+
+        // A reader arriving after the writer must wait its turn, even though only readers hold
+        // the lock right now.
+        let mut late_read = lock.read().boxed();
+        assert!(late_read.poll_unpin(&mut cx).is_pending());
+
+        drop(reader);
+
+        let task::Poll::Ready(writer) = write.poll_unpin(&mut cx) else {
+            panic!("writer must be granted access before the late reader");
+        };
+        assert!(late_read.poll_unpin(&mut cx).is_pending());
+
+        drop(writer);
+        assert!(late_read.poll_unpin(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn waiting_readers_are_granted_together() {
+        let lock = RwLock::new(());
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let task::Poll::Ready(writer) = lock.write().boxed().poll_unpin(&mut cx) else {
+            panic!("uncontended write must be granted immediately");
+        };
+
+        let mut first = lock.read().boxed();
+        let mut second = lock.read().boxed();
+        let mut write = lock.write().boxed();
+        assert!(first.poll_unpin(&mut cx).is_pending());
+        assert!(second.poll_unpin(&mut cx).is_pending());
+        assert!(write.poll_unpin(&mut cx).is_pending());
+
+        drop(writer);
+
+        // Both readers were queued before the second writer, so they are granted together.
+        let task::Poll::Ready(first_guard) = first.poll_unpin(&mut cx) else {
+            panic!("first reader must be granted access");
+        };
+        let task::Poll::Ready(second_guard) = second.poll_unpin(&mut cx) else {
+            panic!("second reader must be granted access together with the first");
+        };
+        assert!(write.poll_unpin(&mut cx).is_pending());
+
+        drop(first_guard);
+        drop(second_guard);
+        assert!(write.poll_unpin(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn dropped_waiter_does_not_block_others() {
+        let lock = LocalRwLock::new(());
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let task::Poll::Ready(reader) = lock.read().boxed_local().poll_unpin(&mut cx) else {
+            panic!("uncontended read must be granted immediately");
+        };
+
+        let mut write = lock.write().boxed_local();
+        assert!(write.poll_unpin(&mut cx).is_pending());
+
+        let mut read = lock.read().boxed_local();
+        assert!(read.poll_unpin(&mut cx).is_pending());
+
+        // The writer gives up, so the reader behind it can join the existing reader.
+        drop(write);
+        assert!(read.poll_unpin(&mut cx).is_ready());
+
+        drop(reader);
+    }
+}
@@ -5,15 +5,21 @@ mod clock;
 mod clock_control;
 mod delay;
 mod error;
+mod interval;
 mod periodic_timer;
 mod stopwatch;
 mod timers;
+#[cfg(feature = "fakes")]
+mod virtual_clock;
 
 pub use clock::*;
 #[cfg(feature = "fakes")]
 pub use clock_control::*;
 pub use delay::*;
 pub use error::*;
+pub use interval::*;
 pub use periodic_timer::*;
 pub use stopwatch::*;
 pub(crate) use timers::*;
+#[cfg(feature = "fakes")]
+pub use virtual_clock::*;
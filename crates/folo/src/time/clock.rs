@@ -56,7 +56,7 @@ impl Clock {
         #[cfg(feature = "fakes")]
         fn now_core(clock: &Clock) -> SystemTime {
             match &clock.clock_control {
-                None => SystemTime::now(),
+                None => super::virtual_system_time().unwrap_or_else(SystemTime::now),
                 Some(control) => control.now(),
             }
         }
@@ -76,7 +76,7 @@ impl Clock {
         #[cfg(feature = "fakes")]
         fn now_core(clock: &Clock) -> Instant {
             match &clock.clock_control {
-                None => Instant::now(),
+                None => super::current_instant(),
                 Some(control) => control.instant_now(),
             }
         }
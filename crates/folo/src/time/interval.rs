@@ -0,0 +1,274 @@
+// Copyright (c) Microsoft Corporation.
+
+use std::future::{poll_fn, Future};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use negative_impl::negative_impl;
+
+use super::timers::TimerKey;
+use super::{Clock, TIMER_RESOLUTION};
+
+/// Creates an interval that ticks every `period`, with the first tick completing immediately.
+///
+/// The ticks are scheduled at fixed points in time (`start`, `start + period`, `start + 2 * period`
+/// and so on), so time spent between ticks does not cause the schedule to drift. What happens if
+/// the owner falls behind the schedule is controlled via [`MissedTickBehavior`].
+///
+/// The interval uses the timers of the current worker thread, so ticks are always delivered to the
+/// worker that owns the `Interval`.
+pub fn interval(period: Duration) -> Interval {
+    Interval::with_clock(&Clock::new(), period)
+}
+
+/// Defines what an [`Interval`] does when one or more ticks were missed because the owner did not
+/// call `tick()` in time (e.g. because the worker thread was busy).
+///
+/// Assuming a period of 10 ms, a start at 0 ms and a delay causing the owner to only poll at 35 ms:
+///
+/// | Behavior | Ticks after the delay |
+/// |----------|-----------------------|
+/// | `Burst`  | 35, 35, 35, 40, 50    |
+/// | `Delay`  | 35, 45, 55, 65        |
+/// | `Skip`   | 35, 40, 50, 60        |
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Missed ticks are delivered immediately, one after another, until the interval has caught up
+    /// with the original schedule.
+    #[default]
+    Burst,
+
+    /// The schedule is shifted so that the next tick happens one full period after the delayed
+    /// tick was delivered. The interval never catches up with the original schedule.
+    Delay,
+
+    /// Missed ticks are dropped and the next tick happens at the next point on the original
+    /// schedule.
+    Skip,
+}
+
+/// A timer that ticks at fixed points in time. Create one via [`interval()`] or
+/// [`Interval::with_clock()`] and call `tick().await` to wait for the next tick.
+#[derive(Debug)]
+pub struct Interval {
+    clock: Clock,
+    period: Duration,
+
+    // When the next tick is scheduled. None if the next tick would be so far in the future that
+    // it cannot be represented, in which case the interval never ticks again.
+    next_tick: Option<Instant>,
+
+    // Timer registered for `next_tick`. This value is not initialized before the interval is
+    // polled and is cleared every time a tick is delivered.
+    current_timer: Option<TimerKey>,
+
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+#[negative_impl]
+impl !Send for Interval {}
+#[negative_impl]
+impl !Sync for Interval {}
+
+impl Interval {
+    /// Creates an interval that ticks every `period`, with the first tick completing immediately.
+    pub fn with_clock(clock: &Clock, period: Duration) -> Self {
+        Self {
+            next_tick: Some(clock.instant_now()),
+            clock: clock.clone(),
+            period: period.max(TIMER_RESOLUTION),
+            current_timer: None,
+            missed_tick_behavior: MissedTickBehavior::default(),
+        }
+    }
+
+    /// The period between two consecutive ticks.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Restarts the schedule, so the next tick happens one full period from now.
+    pub fn reset(&mut self) {
+        self.unregister_timer();
+        self.next_tick = self.clock.instant_now().checked_add(self.period);
+    }
+
+    /// Completes when the next tick is due, returning the instant the tick was scheduled for.
+    pub fn tick(&mut self) -> impl Future<Output = Instant> + '_ {
+        poll_fn(|cx| self.poll_tick(cx))
+    }
+
+    /// Polls for the next tick. This is the poll-based equivalent of `tick()`, suitable for use in
+    /// manually implemented futures and streams.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        let Some(scheduled) = self.next_tick else {
+            // The next tick is beyond the maximum instant value, so we never tick again.
+            return Poll::Pending;
+        };
+
+        let now = self.clock.instant_now();
+
+        if scheduled > now {
+            // The registration is lazy, when someone polls the future. Once registered, the timer
+            // remains registered until the tick is delivered.
+            if self.current_timer.is_none() {
+                self.current_timer = Some(self.clock.register_timer(scheduled, cx.waker().clone()));
+            }
+
+            return Poll::Pending;
+        }
+
+        // Unregister timer, just in case this call was explicit and not due to timers advancing.
+        self.unregister_timer();
+
+        self.next_tick = match self.missed_tick_behavior {
+            MissedTickBehavior::Burst => scheduled.checked_add(self.period),
+            MissedTickBehavior::Delay => now.checked_add(self.period),
+            MissedTickBehavior::Skip => {
+                // We want the first point on the original schedule that is after `now`.
+                let behind = now.duration_since(scheduled).as_nanos();
+                let into_period = behind % self.period.as_nanos();
+
+                // The remainder is smaller than the period, so it always fits into u64 nanos.
+                now.checked_add(self.period - Duration::from_nanos(into_period as u64))
+            }
+        };
+
+        Poll::Ready(scheduled)
+    }
+
+    fn unregister_timer(&mut self) {
+        if let Some(key) = self.current_timer.take() {
+            self.clock.unregister_timer(key);
+        }
+    }
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        self.unregister_timer();
+    }
+}
+
+#[cfg(all(test, feature = "fakes"))]
+mod tests {
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+    use crate::time::ClockControl;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn poll(interval: &mut Interval) -> Poll<Instant> {
+        interval.poll_tick(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn first_tick_is_immediate() {
+        let control = ClockControl::new();
+        let mut interval = Interval::with_clock(&Clock::with_control(&control), PERIOD);
+
+        assert!(poll(&mut interval).is_ready());
+        assert!(poll(&mut interval).is_pending());
+    }
+
+    #[test]
+    fn ticks_at_fixed_points() {
+        let mut control = ClockControl::new();
+        let clock = Clock::with_control(&control);
+        let mut interval = Interval::with_clock(&clock, PERIOD);
+
+        let Poll::Ready(start) = poll(&mut interval) else {
+            panic!("first tick must be immediate");
+        };
+
+        control.advance(PERIOD + Duration::from_millis(3));
+        assert_eq!(poll(&mut interval), Poll::Ready(start + PERIOD));
+
+        // The 3 ms of lateness above must not have shifted the schedule.
+        control.advance(Duration::from_millis(7));
+        assert_eq!(poll(&mut interval), Poll::Ready(start + PERIOD * 2));
+        assert_eq!(control.timers_len(), 0);
+    }
+
+    #[test]
+    fn missed_ticks_burst() {
+        let mut control = ClockControl::new();
+        let mut interval = Interval::with_clock(&Clock::with_control(&control), PERIOD);
+        assert!(poll(&mut interval).is_ready());
+
+        control.advance(Duration::from_millis(35));
+
+        assert!(poll(&mut interval).is_ready());
+        assert!(poll(&mut interval).is_ready());
+        assert!(poll(&mut interval).is_ready());
+        assert!(poll(&mut interval).is_pending());
+    }
+
+    #[test]
+    fn missed_ticks_skip() {
+        let mut control = ClockControl::new();
+        let clock = Clock::with_control(&control);
+        let mut interval = Interval::with_clock(&clock, PERIOD);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let Poll::Ready(start) = poll(&mut interval) else {
+            panic!("first tick must be immediate");
+        };
+
+        control.advance(Duration::from_millis(35));
+
+        assert_eq!(poll(&mut interval), Poll::Ready(start + PERIOD));
+        assert!(poll(&mut interval).is_pending());
+
+        control.advance(Duration::from_millis(5));
+        assert_eq!(poll(&mut interval), Poll::Ready(start + PERIOD * 4));
+    }
+
+    #[test]
+    fn missed_ticks_delay() {
+        let mut control = ClockControl::new();
+        let clock = Clock::with_control(&control);
+        let mut interval = Interval::with_clock(&clock, PERIOD);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let Poll::Ready(start) = poll(&mut interval) else {
+            panic!("first tick must be immediate");
+        };
+
+        control.advance(Duration::from_millis(35));
+
+        assert_eq!(poll(&mut interval), Poll::Ready(start + PERIOD));
+        assert!(poll(&mut interval).is_pending());
+
+        control.advance(Duration::from_millis(5));
+        assert!(poll(&mut interval).is_pending());
+
+        control.advance(Duration::from_millis(5));
+        assert_eq!(
+            poll(&mut interval),
+            Poll::Ready(start + Duration::from_millis(45))
+        );
+    }
+
+    #[test]
+    fn drop_unregisters_timer() {
+        let control = ClockControl::new();
+        let mut interval = Interval::with_clock(&Clock::with_control(&control), PERIOD);
+
+        assert!(poll(&mut interval).is_ready());
+        assert!(poll(&mut interval).is_pending());
+        assert_eq!(control.timers_len(), 1);
+
+        drop(interval);
+        assert_eq!(control.timers_len(), 0);
+    }
+}
@@ -36,6 +36,20 @@ thread_local! {
     pub(super) static LOCAL_TIMERS: RefCell<Timers> = RefCell::new(Timers::new());
 }
 
+/// The current instant as seen by the timers of the current thread. This is the real time unless
+/// the clock of the current thread has been paused via `folo::time::pause()`.
+// This method is mutated, but cannot be tested due to tests
+// running with the "fakes" feature.
+#[cfg(not(feature = "fakes"))]
+pub(crate) fn current_instant() -> Instant {
+    Instant::now()
+}
+
+#[cfg(feature = "fakes")]
+pub(crate) fn current_instant() -> Instant {
+    super::virtual_instant().unwrap_or_else(Instant::now)
+}
+
 /// Processes all thread-local timers that are ready to fire.
 pub(crate) fn advance_local_timers(now: Instant) {
     LOCAL_TIMERS.with_borrow_mut(|timer_manager| timer_manager.advance_timers(now));
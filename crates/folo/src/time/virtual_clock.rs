@@ -0,0 +1,161 @@
+use super::advance_local_timers;
+use std::{
+    cell::Cell,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The point in time at which the clock of the current thread was paused, together with how far it
+/// has been advanced since then.
+#[derive(Clone, Copy, Debug)]
+struct VirtualTime {
+    instant: Instant,
+    system_time: SystemTime,
+    tick_count_millis: u64,
+    advanced: Duration,
+}
+
+thread_local! {
+    static VIRTUAL_TIME: Cell<Option<VirtualTime>> = const { Cell::new(None) };
+}
+
+/// Pauses the clock of the current thread, switching it to virtual time. While paused, time only
+/// moves forward via `advance()`, so tests of timeouts and intervals run instantly and
+/// deterministically instead of waiting for real time to pass.
+///
+/// The virtual time is used by the timers of the current thread (e.g. `Delay` and `Interval` with
+/// a clock from `Clock::new()`), by the runtime when it decides which of those timers have
+/// elapsed, and by the runtime's own low-precision time measurements taken on the current thread.
+/// Clocks created via `Clock::with_control()` keep following their own `ClockControl`.
+///
+/// This is intended for tests only, which is why it is only available with the `fakes` feature.
+/// Each thread has its own clock, so tests running in parallel do not affect each other. Call this
+/// from the async worker thread that runs the code under test (e.g. at the start of a
+/// `#[folo::test]` function). Pausing an already paused clock has no effect.
+pub fn pause() {
+    VIRTUAL_TIME.with(|virtual_time| {
+        if virtual_time.get().is_none() {
+            virtual_time.set(Some(VirtualTime {
+                instant: Instant::now(),
+                system_time: SystemTime::now(),
+                tick_count_millis: real_tick_count_millis(),
+                advanced: Duration::ZERO,
+            }));
+        }
+    });
+}
+
+/// Resumes following real time on the current thread. Timers that were registered while paused
+/// remain scheduled at their virtual deadlines, which are in the past or near future of real time.
+pub fn resume() {
+    VIRTUAL_TIME.with(|virtual_time| virtual_time.set(None));
+}
+
+/// Whether the clock of the current thread is paused via `pause()`.
+pub fn is_paused() -> bool {
+    VIRTUAL_TIME.with(|virtual_time| virtual_time.get().is_some())
+}
+
+/// Moves the paused clock of the current thread forward, immediately waking up the tasks whose
+/// timers have elapsed. The tasks run when the runtime next gets to them, e.g. after the current
+/// task yields.
+///
+/// # Panics
+///
+/// Panics if the clock of the current thread is not paused.
+pub fn advance(duration: Duration) {
+    let now = VIRTUAL_TIME.with(|virtual_time| {
+        let mut current = virtual_time
+            .get()
+            .expect("the clock can only be advanced while paused - call pause() first");
+
+        current.advanced = current
+            .advanced
+            .checked_add(duration)
+            .expect("advancing the clock past the maximum supported time range is not possible");
+
+        virtual_time.set(Some(current));
+
+        current.instant + current.advanced
+    });
+
+    advance_local_timers(now);
+}
+
+/// The virtual `Instant` of the current thread, if its clock is paused.
+pub(crate) fn virtual_instant() -> Option<Instant> {
+    VIRTUAL_TIME.with(|virtual_time| virtual_time.get().map(|x| x.instant + x.advanced))
+}
+
+/// The virtual `SystemTime` of the current thread, if its clock is paused.
+pub(crate) fn virtual_system_time() -> Option<SystemTime> {
+    VIRTUAL_TIME.with(|virtual_time| virtual_time.get().map(|x| x.system_time + x.advanced))
+}
+
+/// The virtual system tick count of the current thread in milliseconds, if its clock is paused.
+pub(crate) fn virtual_tick_count_millis() -> Option<u64> {
+    VIRTUAL_TIME.with(|virtual_time| {
+        virtual_time
+            .get()
+            .map(|x| x.tick_count_millis + x.advanced.as_millis() as u64)
+    })
+}
+
+fn real_tick_count_millis() -> u64 {
+    // SAFETY: Nothing unsafe about this, just an FFI call.
+    unsafe { windows::Win32::System::SystemInformation::GetTickCount64() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        time::{Clock, Delay},
+        util::LowPrecisionInstant,
+    };
+    use futures::task::noop_waker_ref;
+    use std::{future::Future, pin::pin, task::Context};
+
+    #[test]
+    fn delay_completes_after_advance() {
+        pause();
+
+        let mut delay = pin!(Delay::with_clock(&Clock::new(), Duration::from_secs(60)));
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        assert!(delay.as_mut().poll(&mut cx).is_pending());
+
+        advance(Duration::from_secs(59));
+        assert!(delay.as_mut().poll(&mut cx).is_pending());
+
+        advance(Duration::from_secs(1));
+        assert!(delay.as_mut().poll(&mut cx).is_ready());
+
+        resume();
+    }
+
+    #[test]
+    fn low_precision_instant_follows_virtual_time() {
+        pause();
+
+        let start = LowPrecisionInstant::now();
+        let clock = Clock::new();
+        let system_start = clock.now();
+
+        advance(Duration::from_secs(3600));
+
+        assert_eq!(start.elapsed(), Duration::from_secs(3600));
+        assert_eq!(
+            clock.now().duration_since(system_start).unwrap(),
+            Duration::from_secs(3600)
+        );
+
+        resume();
+        assert!(!is_paused());
+    }
+
+    #[test]
+    #[should_panic]
+    fn advance_without_pause_panics() {
+        advance(Duration::from_secs(1));
+    }
+}
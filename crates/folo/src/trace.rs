@@ -0,0 +1,74 @@
+//! Thin layer over the `tracing` crate, so the library can be built without it.
+//!
+//! With the `tracing` feature enabled (the default), this simply re-exports what we use from
+//! `tracing`. With it disabled, `event!` compiles to nothing, so there is no tracing overhead and
+//! no dependency on `tracing`.
+//!
+//! Every ERROR-level event has a counterpart that callers can observe without tracing, so nothing
+//! is lost by disabling it: a failed `build()` of the runtime or a server, an error returned by the
+//! affected operation (e.g. `TcpConnection::shutdown()` after a `BufferedWriter` lost data), a
+//! `ServerEvent` or a `ServerStats` counter. Log events only add detail.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{event, Level};
+
+// The arguments are type-checked but never evaluated, so values only used for logging still count
+// as used and the code compiles the same way with and without tracing.
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:expr, $($fields:tt)*) => {
+        if false {
+            let _ = &$level;
+            $crate::trace::discard_fields!($($fields)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! discard_fields {
+    () => {};
+    ($key:ident = ?$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+    ($key:ident = %$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+    (?$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+    (%$value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+    ($format:literal $(, $argument:expr)* $(,)?) => {
+        let _ = format_args!($format $(, $argument)*);
+    };
+    ($value:ident $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $($crate::trace::discard_fields!($($rest)*);)?
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use {discard_fields, event};
+
+/// Stand-in for `tracing::Level`, only used to type-check the arguments of `event!` when tracing is
+/// disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Level;
+
+#[cfg(not(feature = "tracing"))]
+impl Level {
+    pub(crate) const TRACE: Level = Level;
+    pub(crate) const DEBUG: Level = Level;
+    pub(crate) const INFO: Level = Level;
+    pub(crate) const WARN: Level = Level;
+    pub(crate) const ERROR: Level = Level;
+}
@@ -1,16 +1,24 @@
+mod arena;
 mod local_cell;
+mod local_futures_unordered;
 mod low_precision_instant;
 pub mod once_event;
+mod opt_in_counter;
 mod owned_handle;
+mod panic_message;
 mod pinned_slab;
 mod pinned_slab_chain;
 mod ptr_hash;
 mod slab_rc;
 mod thread_safe;
 
+pub use arena::*;
 pub use local_cell::*;
+pub use local_futures_unordered::*;
 pub use low_precision_instant::*;
+pub(crate) use opt_in_counter::*;
 pub use owned_handle::*;
+pub(crate) use panic_message::*;
 pub use pinned_slab::*;
 pub use pinned_slab_chain::*;
 pub use ptr_hash::*;
@@ -0,0 +1,231 @@
+use negative_impl::negative_impl;
+use std::{
+    alloc::Layout,
+    cell::{Cell, RefCell},
+    mem::{self, MaybeUninit},
+    ptr::{self, NonNull},
+};
+
+/// A bump allocator for many short-lived objects, such as the values produced while parsing a
+/// request. Create one when a connection is accepted, allocate into it while handling a request and
+/// `reset()` it once the response has been sent - the memory is then reused for the next request,
+/// avoiding heap allocation churn.
+///
+/// Allocated values live until the arena is reset or dropped, at which point their destructors are
+/// called. The arena is a single threaded type and stays on the worker that created it.
+#[derive(Debug)]
+pub struct Arena {
+    // The backing memory. Chunks are never freed or moved until the arena is dropped, so values
+    // allocated in them stay put even when more chunks are added.
+    chunks: RefCell<Vec<Box<[MaybeUninit<u8>]>>>,
+
+    // The chunk we are currently allocating from and how much of it is already in use.
+    current_chunk: Cell<usize>,
+    current_offset: Cell<usize>,
+
+    // Values that need to be dropped when the arena is reset or dropped, in allocation order.
+    drops: RefCell<Vec<PendingDrop>>,
+
+    chunk_size: usize,
+}
+
+#[derive(Debug)]
+struct PendingDrop {
+    ptr: *mut u8,
+    drop_fn: unsafe fn(*mut u8),
+}
+
+/// The default size of each block of memory the arena allocates from.
+pub const DEFAULT_ARENA_CHUNK_SIZE: usize = 16 * 1024;
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_ARENA_CHUNK_SIZE)
+    }
+
+    /// Creates an arena that allocates its memory in blocks of the given size. Values bigger than
+    /// a block get a block of their own.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        Self {
+            chunks: RefCell::new(Vec::new()),
+            current_chunk: Cell::new(0),
+            current_offset: Cell::new(0),
+            drops: RefCell::new(Vec::new()),
+            chunk_size: chunk_size.max(1),
+        }
+    }
+
+    /// Moves a value into the arena, returning a reference to it that lives as long as the arena
+    /// (or until the next `reset()`, which requires exclusive access and therefore cannot happen
+    /// while the reference is in use).
+    ///
+    /// The value must not borrow anything, as its destructor runs when the arena is reset or
+    /// dropped, by which time any borrowed data may already be gone.
+    #[allow(clippy::mut_from_ref)] // Each call hands out a reference to a distinct allocation.
+    pub fn alloc<T: 'static>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()).cast::<T>();
+
+        // SAFETY: The memory is freshly allocated for this value, properly aligned and big enough.
+        unsafe {
+            ptr.as_ptr().write(value);
+        }
+
+        if mem::needs_drop::<T>() {
+            self.drops.borrow_mut().push(PendingDrop {
+                ptr: ptr.as_ptr().cast(),
+                drop_fn: drop_in_place::<T>,
+            });
+        }
+
+        // SAFETY: The value was just initialized and nobody else has a reference to it. The memory
+        // remains valid until the arena is reset or dropped, both of which end the borrow of self.
+        unsafe { &mut *ptr.as_ptr() }
+    }
+
+    /// Drops all values allocated in the arena and makes its memory available for reuse. The
+    /// memory itself is kept, so an arena that is reset between requests stops allocating once it
+    /// has grown to fit the biggest request.
+    pub fn reset(&mut self) {
+        self.drop_values();
+
+        self.current_chunk.set(0);
+        self.current_offset.set(0);
+    }
+
+    /// The total amount of memory reserved by the arena, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().iter().map(|chunk| chunk.len()).sum()
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        if layout.size() == 0 {
+            // Zero-sized values need no memory, just a well-aligned pointer.
+            // SAFETY: The alignment is never zero, so this is never null.
+            return unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(layout.align())) };
+        }
+
+        let mut chunks = self.chunks.borrow_mut();
+
+        loop {
+            let index = self.current_chunk.get();
+
+            let Some(chunk) = chunks.get_mut(index) else {
+                // We have run out of chunks, so we need a new one. An oversized value gets a chunk
+                // of its own, with enough slack to align it.
+                let size = self.chunk_size.max(layout.size() + layout.align() - 1);
+                chunks.push(Box::new_uninit_slice(size));
+                continue;
+            };
+
+            let base = chunk.as_mut_ptr() as usize;
+            let start = (base + self.current_offset.get()).next_multiple_of(layout.align());
+            let end = start + layout.size();
+
+            if end <= base + chunk.len() {
+                self.current_offset.set(end - base);
+
+                // SAFETY: The address is within the chunk, so it is derived from a valid pointer.
+                return unsafe {
+                    NonNull::new_unchecked(chunk.as_mut_ptr().cast::<u8>().add(start - base))
+                };
+            }
+
+            // Does not fit - move on to the next chunk (which may need to be created).
+            self.current_chunk.set(index + 1);
+            self.current_offset.set(0);
+        }
+    }
+
+    fn drop_values(&mut self) {
+        for pending in self.drops.get_mut().drain(..) {
+            // SAFETY: Each pending drop refers to a live value of the matching type, which nobody
+            // can be referencing because we have exclusive access to the arena.
+            unsafe {
+                (pending.drop_fn)(pending.ptr);
+            }
+        }
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.drop_values();
+    }
+}
+
+#[negative_impl]
+impl !Send for Arena {}
+#[negative_impl]
+impl !Sync for Arena {}
+
+unsafe fn drop_in_place<T>(ptr: *mut u8) {
+    // SAFETY: Forwarding the safety requirements of the caller.
+    unsafe { ptr::drop_in_place(ptr.cast::<T>()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn memory_is_reused_after_reset() {
+        let mut arena = Arena::with_chunk_size(1024);
+
+        let first_round: Vec<usize> = (0..1000u64)
+            .map(|i| arena.alloc(i) as *mut u64 as usize)
+            .collect();
+        let capacity = arena.capacity();
+
+        arena.reset();
+
+        let second_round: Vec<usize> = (0..1000u64)
+            .map(|i| arena.alloc(i) as *mut u64 as usize)
+            .collect();
+
+        assert_eq!(first_round, second_round);
+        assert_eq!(arena.capacity(), capacity);
+    }
+
+    #[test]
+    fn values_are_aligned_and_distinct() {
+        let arena = Arena::with_chunk_size(64);
+
+        let byte = arena.alloc(1u8);
+        let big = arena.alloc([7u128; 8]);
+        let word = arena.alloc(2u64);
+
+        *byte += 1;
+        big[0] += 1;
+        *word += 1;
+
+        assert_eq!(*byte, 2);
+        assert_eq!(big[0], 8);
+        assert_eq!(*word, 3);
+        assert_eq!(big as *mut _ as usize % mem::align_of::<u128>(), 0);
+        assert_eq!(word as *mut _ as usize % mem::align_of::<u64>(), 0);
+    }
+
+    #[test]
+    fn values_are_dropped_on_reset() {
+        let tracker = Rc::new(());
+        let mut arena = Arena::new();
+
+        arena.alloc(Rc::clone(&tracker));
+        arena.alloc(Rc::clone(&tracker));
+        assert_eq!(Rc::strong_count(&tracker), 3);
+
+        arena.reset();
+        assert_eq!(Rc::strong_count(&tracker), 1);
+
+        arena.alloc(Rc::clone(&tracker));
+        drop(arena);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+}
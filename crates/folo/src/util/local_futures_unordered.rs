@@ -0,0 +1,379 @@
+use crate::util::PinnedSlabChain;
+use futures::Stream;
+use negative_impl::negative_impl;
+use pin_project::pin_project;
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    rc::Rc,
+    task::{self, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+/// A set of futures that run concurrently on the current thread, yielding their outputs in the
+/// order they complete. This is the single-threaded counterpart of
+/// `futures::stream::FuturesUnordered`: the futures do not need to be `Send` and the set itself
+/// never leaves its thread, which is how handlers on a Folo async worker can run many sub-tasks
+/// concurrently without spawning a task for each.
+///
+/// Futures are kept in a slab and only polled after they have been woken up. The set, its futures
+/// and the wakers given to them are all single-threaded, with no atomics or locks involved - waking
+/// up a future is just a push onto a queue owned by the set.
+///
+/// # Panics
+///
+/// The wakers given to the futures only work on the thread that owns the set. Cloning or waking
+/// one on another thread panics and dropping one there leaks it. This suits futures driven by the
+/// I/O of the current async worker and by single-threaded channels - futures that are woken up
+/// from other threads (e.g. results of `spawn_sync()`) must be spawned as tasks instead.
+///
+/// The stream ends (yields `None`) whenever the set is empty. More futures can be pushed after
+/// that, after which the stream continues to yield their outputs.
+pub struct LocalFuturesUnordered<F: Future> {
+    futures: PinnedSlabChain<FutureSlot<F>>,
+
+    // The generation of the future in each slot of the slab, or zero if the slot is vacant. Wakers
+    // of completed futures may outlive them, so we use this to ignore wake-ups meant for a previous
+    // occupant of a slot.
+    generations: Vec<u64>,
+    next_generation: u64,
+
+    // Wake-ups taken from the ready queue that have not yet been processed, because a future
+    // completed and we returned its output first.
+    polling: Vec<(usize, u64)>,
+
+    ready: Rc<ReadyQueue>,
+}
+
+#[pin_project]
+struct FutureSlot<F> {
+    #[pin]
+    future: F,
+    waker: Waker,
+}
+
+impl<F: Future> LocalFuturesUnordered<F> {
+    pub fn new() -> Self {
+        Self {
+            futures: PinnedSlabChain::new(),
+            generations: Vec::new(),
+            next_generation: 1,
+            polling: Vec::new(),
+            ready: Rc::new(ReadyQueue {
+                futures: RefCell::new(Vec::new()),
+                driver_waker: RefCell::new(None),
+                owning_thread: current_thread_marker(),
+            }),
+        }
+    }
+
+    /// The number of futures that have not yet completed.
+    pub fn len(&self) -> usize {
+        self.futures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+
+    /// Adds a future to the set. It is first polled the next time the stream is polled.
+    pub fn push(&mut self, future: F) {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        let inserter = self.futures.begin_insert();
+        let index = inserter.index();
+
+        let waker = SlotWaker::into_waker(Rc::new(SlotWaker {
+            index,
+            generation,
+            ready: Rc::clone(&self.ready),
+        }));
+
+        // Every future is polled once to get it started.
+        waker.wake_by_ref();
+
+        inserter.insert(FutureSlot { future, waker });
+
+        if self.generations.len() <= index {
+            self.generations.resize(index + 1, 0);
+        }
+
+        self.generations[index] = generation;
+    }
+}
+
+impl<F: Future> Default for LocalFuturesUnordered<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Future> Stream for LocalFuturesUnordered<F> {
+    type Item = F::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<F::Output>> {
+        // The futures are pinned by the slab, not by us, so we are free to move our own fields.
+        let this = self.get_mut();
+
+        {
+            let mut driver_waker = this.ready.driver_waker.borrow_mut();
+
+            if !driver_waker
+                .as_ref()
+                .is_some_and(|waker| waker.will_wake(cx.waker()))
+            {
+                *driver_waker = Some(cx.waker().clone());
+            }
+        }
+
+        // Futures woken up while we are polling are left for the next round, so a future that keeps
+        // waking itself up cannot starve the other tasks of the thread.
+        if this.polling.is_empty() {
+            this.polling = mem::take(&mut *this.ready.futures.borrow_mut());
+
+            // We process the wake-ups in the order they arrived.
+            this.polling.reverse();
+        }
+
+        while let Some((index, generation)) = this.polling.pop() {
+            if this.generations[index] != generation {
+                // Completed already - this is a leftover wake-up.
+                continue;
+            }
+
+            let slot = this.futures.get_mut(index).project();
+            let mut future_cx = task::Context::from_waker(slot.waker);
+
+            if let Poll::Ready(output) = slot.future.poll(&mut future_cx) {
+                this.futures.remove(index);
+                this.generations[index] = 0;
+
+                return Poll::Ready(Some(output));
+            }
+        }
+
+        if this.futures.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<F: Future> fmt::Debug for LocalFuturesUnordered<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalFuturesUnordered")
+            .field("len", &self.futures.len())
+            .finish()
+    }
+}
+
+#[negative_impl]
+impl<F: Future> !Send for LocalFuturesUnordered<F> {}
+#[negative_impl]
+impl<F: Future> !Sync for LocalFuturesUnordered<F> {}
+
+/// The futures that have been woken up and are waiting to be polled.
+struct ReadyQueue {
+    // Slab index and generation of each future.
+    futures: RefCell<Vec<(usize, u64)>>,
+
+    // The waker of the task that polls the set, woken up whenever one of the futures is.
+    driver_waker: RefCell<Option<Waker>>,
+
+    // The only thread on which the wakers of the set may be used.
+    owning_thread: usize,
+}
+
+/// Wakes up one future of the set. Shared by all the clones of the waker given to the future.
+struct SlotWaker {
+    index: usize,
+    generation: u64,
+    ready: Rc<ReadyQueue>,
+}
+
+impl SlotWaker {
+    fn into_waker(self: Rc<Self>) -> Waker {
+        // SAFETY: The vtable functions uphold the `RawWaker` contract for the `Rc` we give them.
+        unsafe { Waker::from_raw(RawWaker::new(Rc::into_raw(self).cast(), &VTABLE)) }
+    }
+
+    fn wake_by_ref(&self) {
+        self.ready
+            .futures
+            .borrow_mut()
+            .push((self.index, self.generation));
+
+        if let Some(driver_waker) = self.ready.driver_waker.borrow().as_ref() {
+            driver_waker.wake_by_ref();
+        }
+    }
+
+    fn is_on_owning_thread(&self) -> bool {
+        self.ready.owning_thread == current_thread_marker()
+    }
+
+    fn assert_owning_thread(&self) {
+        assert!(
+            self.is_on_owning_thread(),
+            "waker of a LocalFuturesUnordered used on a thread other than the one that owns the set"
+        );
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    waker_clone_waker,
+    waker_wake,
+    waker_wake_by_ref,
+    waker_drop_waker,
+);
+
+// The fields of a `SlotWaker` never change, so reading them from any thread is fine as long as the
+// waker keeps the value alive. Only the reference count is single-threaded, which is why we check
+// which thread we are on before touching it.
+
+fn waker_clone_waker(ptr: *const ()) -> RawWaker {
+    let slot_waker = unsafe { resurrect_slot_waker_ptr(ptr) };
+    slot_waker.assert_owning_thread();
+
+    // SAFETY: The pointer came from `Rc::into_raw()` and the waker we clone keeps it alive.
+    unsafe { Rc::increment_strong_count(ptr.cast::<SlotWaker>()) };
+
+    RawWaker::new(ptr, &VTABLE)
+}
+
+fn waker_wake(ptr: *const ()) {
+    let slot_waker = unsafe { resurrect_slot_waker_ptr(ptr) };
+    slot_waker.assert_owning_thread();
+
+    // This consumes the waker!
+    // SAFETY: The pointer came from `Rc::into_raw()` and we own the reference of the waker.
+    let slot_waker = unsafe { Rc::from_raw(ptr.cast::<SlotWaker>()) };
+    slot_waker.wake_by_ref();
+}
+
+fn waker_wake_by_ref(ptr: *const ()) {
+    let slot_waker = unsafe { resurrect_slot_waker_ptr(ptr) };
+    slot_waker.assert_owning_thread();
+
+    slot_waker.wake_by_ref();
+}
+
+fn waker_drop_waker(ptr: *const ()) {
+    let slot_waker = unsafe { resurrect_slot_waker_ptr(ptr) };
+
+    // Panicking here would abort if we are already unwinding, so we leak the waker instead.
+    if !slot_waker.is_on_owning_thread() {
+        return;
+    }
+
+    // SAFETY: The pointer came from `Rc::into_raw()` and we own the reference of the waker.
+    drop(unsafe { Rc::from_raw(ptr.cast::<SlotWaker>()) });
+}
+
+unsafe fn resurrect_slot_waker_ptr<'a>(ptr: *const ()) -> &'a SlotWaker {
+    &*(ptr as *const SlotWaker)
+}
+
+thread_local! {
+    static THREAD_MARKER: u8 = const { 0 };
+}
+
+/// Identifies the current thread without the reference counting of `thread::current()`. The
+/// address of a thread-local variable is unique among the threads that are alive.
+fn current_thread_marker() -> usize {
+    THREAD_MARKER.with(|marker| marker as *const u8 as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, task::noop_waker_ref, FutureExt, StreamExt};
+    use std::{cell::Cell, thread};
+
+    #[test]
+    fn yields_outputs_in_completion_order() {
+        let mut set = LocalFuturesUnordered::new();
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+
+        let (first_tx, first_rx) = oneshot::channel::<u32>();
+        let (second_tx, second_rx) = oneshot::channel::<u32>();
+
+        set.push(first_rx);
+        set.push(second_rx);
+
+        assert!(set.poll_next_unpin(&mut cx).is_pending());
+
+        second_tx.send(2).unwrap();
+        assert!(matches!(
+            set.poll_next_unpin(&mut cx),
+            Poll::Ready(Some(Ok(2)))
+        ));
+
+        first_tx.send(1).unwrap();
+        assert!(matches!(
+            set.poll_next_unpin(&mut cx),
+            Poll::Ready(Some(Ok(1)))
+        ));
+
+        assert!(matches!(set.poll_next_unpin(&mut cx), Poll::Ready(None)));
+    }
+
+    #[test]
+    fn stale_wake_does_not_poll_new_occupant() {
+        let mut set = LocalFuturesUnordered::new();
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+
+        let stale_waker = Rc::new(Cell::new(None));
+        let stale_waker_clone = Rc::clone(&stale_waker);
+
+        set.push(
+            future::poll_fn(move |cx| {
+                stale_waker_clone.set(Some(cx.waker().clone()));
+                Poll::Ready(())
+            })
+            .boxed_local(),
+        );
+        assert!(set.poll_next_unpin(&mut cx).is_ready());
+
+        let polls = Rc::new(Cell::new(0));
+        let polls_clone = Rc::clone(&polls);
+
+        // Takes over the slot of the first future.
+        set.push(
+            future::poll_fn(move |_| {
+                polls_clone.set(polls_clone.get() + 1);
+                Poll::<()>::Pending
+            })
+            .boxed_local(),
+        );
+        assert!(set.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(polls.get(), 1);
+
+        stale_waker.take().unwrap().wake();
+        assert!(set.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(polls.get(), 1);
+    }
+
+    #[test]
+    fn waking_on_another_thread_panics() {
+        let mut set = LocalFuturesUnordered::new();
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+
+        let waker = Rc::new(Cell::new(None));
+        let waker_clone = Rc::clone(&waker);
+
+        set.push(future::poll_fn(move |cx| {
+            waker_clone.set(Some(cx.waker().clone()));
+            Poll::<()>::Pending
+        }));
+        assert!(set.poll_next_unpin(&mut cx).is_pending());
+
+        let waker: Waker = waker.take().unwrap();
+
+        assert!(thread::spawn(move || waker.wake()).join().is_err());
+    }
+}
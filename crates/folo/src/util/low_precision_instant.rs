@@ -11,6 +11,9 @@ pub struct LowPrecisionInstant {
 }
 
 impl LowPrecisionInstant {
+    // This method is mutated, but cannot be tested due to tests
+    // running with the "fakes" feature.
+    #[cfg(not(feature = "fakes"))]
     pub fn now() -> Self {
         LowPrecisionInstant {
             // SAFETY: Nothing unsafe about this, just an FFI call.
@@ -18,8 +21,21 @@ impl LowPrecisionInstant {
         }
     }
 
+    /// Honors the virtual time of the current thread if its clock is paused via
+    /// `folo::time::pause()`.
+    #[cfg(feature = "fakes")]
+    pub fn now() -> Self {
+        LowPrecisionInstant {
+            value: crate::time::virtual_tick_count_millis()
+                // SAFETY: Nothing unsafe about this, just an FFI call.
+                .unwrap_or_else(|| unsafe { GetTickCount64() }),
+        }
+    }
+
+    /// The time elapsed from `earlier` to `self`, or zero if `earlier` is later. Instants taken on
+    /// different threads may disagree on the order if one of the threads uses virtual time.
     pub fn duration_since(&self, earlier: LowPrecisionInstant) -> std::time::Duration {
-        std::time::Duration::from_millis(self.value - earlier.value)
+        std::time::Duration::from_millis(self.value.saturating_sub(earlier.value))
     }
 
     pub fn elapsed(&self) -> std::time::Duration {
@@ -0,0 +1,50 @@
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+
+/// A process-wide total of some resource (e.g. handles or buffer bytes) that is only counted once
+/// a runtime opts in to it. Once enabled, counting stays enabled for the rest of the life of the
+/// process, as the resources are not specific to any runtime.
+///
+/// Whatever was acquired before counting was enabled is not counted, not even when it is released,
+/// so the owner of each resource must remember whether `add()` counted it.
+pub(crate) struct OptInCounter {
+    enabled: AtomicBool,
+    total: AtomicUsize,
+}
+
+impl OptInCounter {
+    pub(crate) const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, atomic::Ordering::Relaxed);
+    }
+
+    /// The current total, if counting has been enabled.
+    pub(crate) fn get(&self) -> Option<usize> {
+        if self.enabled.load(atomic::Ordering::Relaxed) {
+            Some(self.total.load(atomic::Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    /// Adds to the total if counting has been enabled, returning whether it was added.
+    pub(crate) fn add(&self, amount: usize) -> bool {
+        let counted = self.enabled.load(atomic::Ordering::Relaxed);
+
+        if counted {
+            self.total.fetch_add(amount, atomic::Ordering::Relaxed);
+        }
+
+        counted
+    }
+
+    /// Removes an amount that was previously added by `add()`.
+    pub(crate) fn sub(&self, amount: usize) {
+        self.total.fetch_sub(amount, atomic::Ordering::Relaxed);
+    }
+}
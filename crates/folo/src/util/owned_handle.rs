@@ -1,4 +1,4 @@
-use super::ThreadSafe;
+use super::{OptInCounter, ThreadSafe};
 use crate::rt::SynchronousTaskType;
 use std::mem;
 use std::ops::Deref;
@@ -7,6 +7,20 @@ use windows::{
     Win32::{Foundation::HANDLE, Networking::WinSock::SOCKET},
 };
 
+// Handle accounting is opt-in via `RuntimeBuilder::handle_limit()`.
+static LIVE_HANDLES: OptInCounter = OptInCounter::new();
+
+/// Starts counting the live `OwnedHandle`s of the process. Handles created before this are not
+/// counted, not even when they are dropped.
+pub(crate) fn enable_handle_accounting() {
+    LIVE_HANDLES.enable();
+}
+
+/// The number of live `OwnedHandle`s in the process, if handle accounting has been enabled.
+pub(crate) fn live_handles() -> Option<usize> {
+    LIVE_HANDLES.get()
+}
+
 /// An owned HANDLE/SOCKET or other type of reference from the `windows` crate, which we release on
 /// a background worker thread in case closing the handle incurs synchronous work due to flushing
 /// caches etc.
@@ -20,6 +34,9 @@ where
     T: Free + Copy + 'static,
 {
     inner: T,
+
+    // Whether the handle is included in `LIVE_HANDLES`, so we know whether to remove it from there.
+    counted: bool,
 }
 
 impl<T> OwnedHandle<T>
@@ -30,7 +47,27 @@ where
     ///
     /// The caller must ensure that the reference handle is valid to close from any thread.
     pub unsafe fn new(handle: T) -> Self {
-        Self { inner: handle }
+        Self::from_raw(handle)
+    }
+
+    fn from_raw(inner: T) -> Self {
+        let counted = LIVE_HANDLES.add(1);
+
+        Self { inner, counted }
+    }
+
+    /// Gives up ownership of the handle without closing it.
+    fn into_raw(self) -> T {
+        if self.counted {
+            LIVE_HANDLES.sub(1);
+        }
+
+        let inner = self.inner;
+
+        // Forget the value so that the handle is not closed on drop of the original.
+        mem::forget(self);
+
+        inner
     }
 }
 
@@ -39,7 +76,7 @@ where
     T: Free + Copy + 'static,
 {
     fn from(handle: T) -> Self {
-        Self { inner: handle }
+        Self::from_raw(handle)
     }
 }
 
@@ -73,6 +110,10 @@ where
     T: Free + Copy + 'static,
 {
     fn drop(&mut self) {
+        if self.counted {
+            LIVE_HANDLES.sub(1);
+        }
+
         // We require that this type is only used with thread-safe handles.
         let mut thread_safe = unsafe { ThreadSafe::new(self.inner) };
 
@@ -108,22 +149,12 @@ unsafe impl<T> Sync for OwnedHandle<T> where T: Free + Copy + 'static {}
 
 impl From<OwnedHandle<HANDLE>> for HANDLE {
     fn from(value: OwnedHandle<HANDLE>) -> HANDLE {
-        let inner = value.inner;
-
-        // Forget the value so that the handle is not closed on drop of the original.
-        mem::forget(value);
-
-        inner
+        value.into_raw()
     }
 }
 
 impl From<OwnedHandle<SOCKET>> for SOCKET {
     fn from(value: OwnedHandle<SOCKET>) -> SOCKET {
-        let inner = value.inner;
-
-        // Forget the value so that the handle is not closed on drop of the original.
-        mem::forget(value);
-
-        inner
+        value.into_raw()
     }
 }
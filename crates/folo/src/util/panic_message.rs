@@ -0,0 +1,12 @@
+use std::any::Any;
+
+/// Extracts the message from the payload of a caught panic, if it has one.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic payload is not a string".to_string()
+    }
+}
@@ -108,6 +108,24 @@ impl<T, const CAPACITY: usize> PinnedSlab<T, CAPACITY> {
         }
     }
 
+    /// Iterates over the occupied entries of the slab, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = Pin<&T>> {
+        (0..CAPACITY).filter_map(move |index| {
+            // SAFETY: We are operating within bounds and ensured in the ctor that every entry is
+            // initialized.
+            match unsafe {
+                self.ptr
+                    .add(index)
+                    .as_ref()
+                    .expect("we expect the resulting pointer to always be valid")
+            } {
+                // SAFETY: Items are always pinned - that is the point of this collection.
+                Entry::Occupied { value } => Some(unsafe { Pin::new_unchecked(value) }),
+                Entry::Vacant { .. } => None,
+            }
+        })
+    }
+
     pub fn begin_insert<'s, 'i>(&'s mut self) -> PinnedSlabInserter<'i, T, CAPACITY>
     where
         's: 'i,
@@ -418,6 +436,19 @@ mod tests {
         assert!(slab.is_full());
     }
 
+    #[test]
+    fn iter_skips_vacant_entries() {
+        let mut slab = PinnedSlab::<u32, 4>::new();
+
+        slab.insert(42);
+        let b = slab.insert(43);
+        slab.insert(44);
+        slab.remove(b);
+
+        let values: Vec<u32> = slab.iter().map(|x| *x).collect();
+        assert_eq!(values, vec![42, 44]);
+    }
+
     #[test]
     #[should_panic]
     fn panic_when_full() {
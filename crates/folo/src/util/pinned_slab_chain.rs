@@ -46,6 +46,11 @@ impl<T, const SLAB_SIZE: usize> PinnedSlabChain<T, SLAB_SIZE> {
             .expect("index was out of bounds of slab chain")
     }
 
+    /// Iterates over the items in the chain, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = Pin<&T>> {
+        self.slabs.iter().flat_map(|slab| slab.iter())
+    }
+
     pub fn begin_insert<'a, 'b>(&'a mut self) -> PinnedSlabChainInserter<'b, T, SLAB_SIZE>
     where
         'a: 'b,
@@ -0,0 +1,48 @@
+use folo::{rt::RuntimeBuilder, sync::Barrier};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+#[test]
+fn workers_rendezvous_at_barrier() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let worker_count = folo.async_worker_count();
+
+    let barrier = Arc::new(Barrier::new(worker_count));
+    let arrived = Arc::new(AtomicUsize::new(0));
+    let leaders = Arc::new(AtomicUsize::new(0));
+
+    let join_handles = folo.spawn_on_all(|| {
+        let barrier = Arc::clone(&barrier);
+        let arrived = Arc::clone(&arrived);
+        let leaders = Arc::clone(&leaders);
+
+        move || async move {
+            arrived.fetch_add(1, Ordering::SeqCst);
+
+            let result = barrier.wait().await;
+
+            // Nobody gets past the barrier before everyone has arrived.
+            assert_eq!(arrived.load(Ordering::SeqCst), worker_count);
+
+            if result.is_leader() {
+                leaders.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+
+    let folo_clone = folo.clone();
+    folo.spawn_on_any(move || async move {
+        for join_handle in join_handles {
+            join_handle.await;
+        }
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+
+    assert_eq!(arrived.load(Ordering::SeqCst), worker_count);
+    assert_eq!(leaders.load(Ordering::SeqCst), 1);
+}
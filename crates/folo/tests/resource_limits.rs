@@ -0,0 +1,253 @@
+use folo::io::{OperationResultExt, PinnedBuffer};
+use folo::net::{TcpConnection, TcpServerBuilder};
+use folo::rt::RuntimeBuilder;
+use std::{
+    io::Write,
+    mem,
+    net::{Ipv4Addr, TcpStream},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Resource accounting is process-wide, so tests that depend on the accounted totals must not run
+// side by side - the resources of one would count against the limits of the other.
+static ACCOUNTING_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn accepts_stall_at_handle_limit_until_handles_are_released() {
+    const HANDLE_LIMIT: usize = 32;
+    const CONNECTIONS: usize = 48;
+
+    let _guard = ACCOUNTING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // A single processor keeps the handles of the runtime itself well below the limit.
+    let folo = RuntimeBuilder::new()
+        .max_processors(1)
+        .handle_limit(NonZeroUsize::new(HANDLE_LIMIT).unwrap())
+        .build()
+        .unwrap();
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let (port_tx, port_rx) = mpsc::channel();
+
+    folo.spawn_on_any({
+        let accepted = Arc::clone(&accepted);
+
+        || async move {
+            let server = TcpServerBuilder::new()
+                .ephemeral_port()
+                .on_accept(move |mut connection: TcpConnection| {
+                    accepted.fetch_add(1, Ordering::Relaxed);
+
+                    // Every connection holds on to its socket until the client sends something.
+                    async move {
+                        connection
+                            .receive(PinnedBuffer::from_pool())
+                            .await
+                            .into_inner()?;
+                        Ok(())
+                    }
+                })
+                .build()
+                .await
+                .unwrap();
+
+            port_tx.send(server.local_port()).unwrap();
+        }
+    });
+
+    let port = port_rx.recv().unwrap();
+
+    // The operating system completes the connections even if the server does not accept them, so
+    // the ones over the limit wait in the listen backlog.
+    let mut clients: Vec<_> = (0..CONNECTIONS)
+        .map(|_| TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap())
+        .collect();
+
+    assert!(wait_for(|| accepted.load(Ordering::Relaxed) > 0));
+
+    // Give the server every chance to accept more than it should.
+    thread::sleep(Duration::from_secs(1));
+
+    assert!(accepted.load(Ordering::Relaxed) < CONNECTIONS);
+    assert!(folo.metrics().live_handles.unwrap() >= HANDLE_LIMIT as u64);
+
+    // Once the accepted connections complete and release their sockets, the rest get accepted.
+    for client in &mut clients {
+        client.write_all(b"x").unwrap();
+    }
+
+    assert!(wait_for(|| accepted.load(Ordering::Relaxed) == CONNECTIONS));
+
+    folo.stop();
+    folo.wait();
+}
+
+#[test]
+fn accepts_stall_at_buffer_budget_until_buffers_are_released() {
+    // Room for eight pooled buffers.
+    const BUFFER_BUDGET: usize = 8 * 64 * 1024;
+    const CONNECTIONS: usize = 24;
+
+    let _guard = ACCOUNTING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let folo = RuntimeBuilder::new()
+        .max_processors(1)
+        .max_buffer_memory(NonZeroUsize::new(BUFFER_BUDGET).unwrap())
+        .build()
+        .unwrap();
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let (port_tx, port_rx) = mpsc::channel();
+
+    folo.spawn_on_any({
+        let accepted = Arc::clone(&accepted);
+
+        || async move {
+            let server = TcpServerBuilder::new()
+                .ephemeral_port()
+                // Every accept operation in flight holds a pooled buffer, so we keep them few to
+                // leave the budget to the connections.
+                .adaptive_accepts(NonZeroUsize::new(2).unwrap(), NonZeroUsize::new(2).unwrap())
+                .on_accept(move |mut connection: TcpConnection| {
+                    accepted.fetch_add(1, Ordering::Relaxed);
+
+                    // Every connection holds on to a pooled buffer until the client sends something.
+                    async move {
+                        connection
+                            .receive(PinnedBuffer::from_pool())
+                            .await
+                            .into_inner()?;
+                        Ok(())
+                    }
+                })
+                .build()
+                .await
+                .unwrap();
+
+            port_tx.send(server.local_port()).unwrap();
+        }
+    });
+
+    let port = port_rx.recv().unwrap();
+
+    let mut clients: Vec<_> = (0..CONNECTIONS)
+        .map(|_| TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap())
+        .collect();
+
+    assert!(wait_for(|| accepted.load(Ordering::Relaxed) > 0));
+
+    // Give the server every chance to accept more than it should.
+    thread::sleep(Duration::from_secs(1));
+
+    assert!(accepted.load(Ordering::Relaxed) < CONNECTIONS);
+    assert!(folo.metrics().pooled_buffer_bytes.unwrap() >= BUFFER_BUDGET as u64);
+
+    // Once the accepted connections complete and release their buffers, the rest get accepted.
+    for client in &mut clients {
+        client.write_all(b"x").unwrap();
+    }
+
+    assert!(wait_for(|| accepted.load(Ordering::Relaxed) == CONNECTIONS));
+
+    folo.stop();
+    folo.wait();
+}
+
+#[test]
+fn shutdown_cancels_operations_on_leaked_sockets() {
+    const CONNECTIONS: usize = 100;
+
+    let _guard = ACCOUNTING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // The limits are only here to enable the accounting of handles and buffers.
+    let folo = RuntimeBuilder::new()
+        .handle_limit(NonZeroUsize::MAX)
+        .max_buffer_memory(NonZeroUsize::MAX)
+        .build()
+        .unwrap();
+
+    let folo_clone = folo.clone();
+    let baseline = folo.metrics();
+
+    let (port_tx, port_rx) = mpsc::channel();
+    let (accepted_tx, accepted_rx) = mpsc::channel();
+
+    folo.spawn_on_any(|| async move {
+        let server = TcpServerBuilder::new()
+            .ephemeral_port()
+            .on_accept(move |mut connection: TcpConnection| {
+                let accepted_tx = accepted_tx.clone();
+
+                async move {
+                    // The client never sends anything, so this receive stays in flight. We leak
+                    // the connection, so nothing ever closes its socket - shutdown must cancel the
+                    // receive by itself.
+                    drop(connection.receive(PinnedBuffer::from_pool()));
+                    mem::forget(connection);
+
+                    _ = accepted_tx.send(());
+                    Ok(())
+                }
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let port = server.local_port();
+        drop(server);
+
+        port_tx.send(port).unwrap();
+    });
+
+    let port = port_rx.recv().unwrap();
+
+    let clients: Vec<_> = (0..CONNECTIONS)
+        .map(|_| TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap())
+        .collect();
+
+    for _ in 0..CONNECTIONS {
+        accepted_rx.recv().unwrap();
+    }
+
+    folo.stop();
+
+    let (done_tx, done_rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        folo.wait();
+        _ = done_tx.send(());
+    });
+
+    done_rx
+        .recv_timeout(Duration::from_secs(30))
+        .expect("runtime did not shut down with operations in flight");
+
+    // The leaked connections keep their sockets but everything else must have been released, in
+    // particular the buffers of the canceled operations.
+    let metrics = folo_clone.metrics();
+    assert!(metrics.live_handles.unwrap() <= baseline.live_handles.unwrap() + CONNECTIONS as u64);
+    assert_eq!(metrics.pooled_buffer_bytes, baseline.pooled_buffer_bytes);
+
+    drop(clients);
+}
+
+/// Waits for up to 30 seconds for the condition to become true, returning whether it did.
+fn wait_for(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(30);
+
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
@@ -1,5 +1,25 @@
-use folo::rt::{spawn, spawn_on_any, yield_now, RuntimeBuilder};
-use std::rc::Rc;
+use folo::io::PinnedBuffer;
+use folo::net::{TcpConnection, TcpServerBuilder};
+use folo::rt::{
+    current, spawn, spawn_future_on_any, spawn_on_any, spawn_on_numa_node, spawn_on_worker,
+    spawn_sync, spawn_with_callback, spawn_with_options, yield_now, NumaNodeId, RuntimeBuilder,
+    SpawnOptions, SynchronousTaskType, TaskPriority, ThreadPriority,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    future,
+    io::Read,
+    net::{Ipv4Addr, TcpStream},
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::mpsc,
+    task::{Poll, Waker},
+    thread,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentThread, GetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL,
+};
 
 #[test]
 fn spawning() {
@@ -19,12 +39,454 @@ fn spawning() {
         spawn_on_any(thread_safe_logic).await.unwrap();
         spawn_on_any(single_threaded_logic).await.unwrap();
 
+        // Thread-safe futures can also be moved to another worker directly.
+        spawn_future_on_any(thread_safe_logic()).await.unwrap();
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn oversubscription_requires_opt_in() {
+    RuntimeBuilder::new()
+        .max_processors(1)
+        .worker_threads(2)
+        .build()
+        .unwrap_err();
+
+    let folo = RuntimeBuilder::new()
+        .max_processors(1)
+        .worker_threads(2)
+        .allow_oversubscription(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(folo.async_worker_count(), 2);
+
+    folo.stop();
+    folo.wait();
+}
+
+#[test]
+fn runtime_without_workers_is_rejected() {
+    let error = RuntimeBuilder::new().max_processors(0).build().unwrap_err();
+    assert!(matches!(error, folo::io::Error::InvalidOptions(_)));
+
+    let error = RuntimeBuilder::new().worker_threads(0).build().unwrap_err();
+    assert!(matches!(error, folo::io::Error::InvalidOptions(_)));
+}
+
+#[test]
+fn minimal_runtime_starts_tcp_server() {
+    let folo = RuntimeBuilder::new()
+        .max_processors(1)
+        .worker_threads(1)
+        .build()
+        .unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let mut server = TcpServerBuilder::new()
+            .ephemeral_port()
+            .on_accept(|_| async { Ok(()) })
+            .build()
+            .await
+            .unwrap();
+        server.stop();
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn pooled_buffer_bytes_are_counted_with_max_buffer_memory() {
+    let folo = RuntimeBuilder::new()
+        .max_buffer_memory(NonZeroUsize::new(64 * 1024 * 1024).unwrap())
+        .build()
+        .unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let buffer = PinnedBuffer::from_pool();
+
+        // Other tests may be using buffers at the same time, so we can only check a lower bound.
+        let pooled_buffer_bytes = folo_clone.metrics().pooled_buffer_bytes.unwrap();
+        assert!(pooled_buffer_bytes >= buffer.capacity() as u64);
+
+        drop(buffer);
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn buffer_memory_budget_smaller_than_one_buffer_is_rejected() {
+    let error = RuntimeBuilder::new()
+        .max_buffer_memory(NonZeroUsize::new(1024).unwrap())
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, folo::io::Error::InvalidOptions(_)));
+}
+
+#[test]
+fn spawn_with_callback_delivers_result_on_executing_thread() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let (result_tx, result_rx) = mpsc::channel();
+
+    folo.spawn_on_any(|| async move {
+        spawn_with_callback(
+            || async {
+                // The future does not have to be thread-safe, only the closure that creates it.
+                let value = Rc::new(21);
+                yield_now().await;
+                (thread::current().id(), *value * 2)
+            },
+            move |(task_thread_id, value)| {
+                result_tx
+                    .send((task_thread_id == thread::current().id(), value))
+                    .unwrap();
+            },
+        );
+    });
+
+    let (same_thread, value) = result_rx.recv().unwrap();
+    assert!(same_thread);
+    assert_eq!(value, 42);
+
+    folo.stop();
+    folo.wait();
+}
+
+#[test]
+fn redundant_wakes_are_coalesced() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let polls = Rc::new(Cell::new(0));
+        let waker_slot = Rc::new(RefCell::new(None::<Waker>));
+
+        let sleeper = spawn({
+            let polls = Rc::clone(&polls);
+            let waker_slot = Rc::clone(&waker_slot);
+
+            future::poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+
+                if polls.get() == 1 {
+                    *waker_slot.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+        });
+
+        // Wait for the sleeper to go to sleep after its first poll.
+        while waker_slot.borrow().is_none() {
+            yield_now().await;
+        }
+
+        let waker = waker_slot.borrow_mut().take().unwrap();
+
+        for _ in 0..100 {
+            waker.wake_by_ref();
+        }
+
+        sleeper.await;
+
+        assert_eq!(polls.get(), 2);
+        assert!(folo_clone.metrics().coalesced_wakes >= 99);
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn live_handles_are_counted_with_handle_limit() {
+    let folo = RuntimeBuilder::new()
+        .handle_limit(NonZeroUsize::new(100_000).unwrap())
+        .build()
+        .unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let mut server = TcpServerBuilder::new()
+            .ephemeral_port()
+            .on_accept(|_| async { Ok(()) })
+            .build()
+            .await
+            .unwrap();
+
+        // At least the listen socket of the server is counted.
+        assert!(folo_clone.metrics().live_handles.unwrap() > 0);
+
+        server.stop();
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn tcp_server_cannot_be_built_on_stopping_runtime() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    let (result_tx, result_rx) = mpsc::channel();
+
+    folo.spawn_on_any(|| async move {
+        folo_clone.stop();
+
+        // The build must fail right away instead of waiting for a dispatcher that never starts.
+        let result = TcpServerBuilder::new()
+            .ephemeral_port()
+            .on_accept(|_| async { Ok(()) })
+            .build()
+            .await;
+
+        result_tx.send(result.is_err()).unwrap();
+    });
+
+    folo.wait();
+
+    assert!(result_rx.recv().unwrap());
+}
+
+#[test]
+fn spawning_on_specific_worker() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let last_worker = folo_clone.async_worker_count() - 1;
+
+        let first = spawn_on_worker(last_worker, || async { thread::current().id() }).await;
+        let second = spawn_on_worker(last_worker, || async { thread::current().id() }).await;
+
+        assert_eq!(first, second);
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn worker_threads_are_named_and_prioritized() {
+    let folo = RuntimeBuilder::new()
+        .thread_name_prefix("custom")
+        .thread_priority(ThreadPriority::BelowNormal)
+        .build()
+        .unwrap();
+    let folo_clone = folo.clone();
+    let (result_tx, result_rx) = mpsc::channel();
+
+    folo.spawn_on_worker(0, move || async move {
+        let async_thread = current_thread_name_and_priority();
+        let sync_thread = spawn_sync(
+            SynchronousTaskType::Syscall,
+            current_thread_name_and_priority,
+        )
+        .await;
+
+        result_tx.send((async_thread, sync_thread)).unwrap();
+
+        folo_clone.stop();
+    });
+
+    let ((async_name, async_priority), (sync_name, sync_priority)) = result_rx.recv().unwrap();
+    folo.wait();
+
+    assert_eq!(async_name, "custom-async-0");
+    assert!(sync_name.starts_with("custom-sync-"));
+
+    assert_eq!(async_priority, THREAD_PRIORITY_BELOW_NORMAL.0);
+    assert_eq!(sync_priority, THREAD_PRIORITY_BELOW_NORMAL.0);
+}
+
+fn current_thread_name_and_priority() -> (String, i32) {
+    let name = thread::current().name().unwrap_or_default().to_string();
+
+    // SAFETY: Nothing unsafe here, just an FFI call on the pseudo-handle of the current thread.
+    let priority = unsafe { GetThreadPriority(GetCurrentThread()) };
+
+    (name, priority)
+}
+
+#[test]
+fn spawning_on_numa_node() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    // Every worker is on some NUMA node - with a flat topology, they are all on node 0.
+    let nodes = folo.numa_nodes();
+    assert!(!nodes.is_empty());
+    assert_eq!(
+        nodes
+            .iter()
+            .map(|node| folo.numa_node_workers(*node).len())
+            .sum::<usize>(),
+        folo.async_worker_count()
+    );
+
+    folo.spawn_on_any(|| async move {
+        for node in folo_clone.numa_nodes() {
+            let mut node_threads = HashSet::new();
+
+            for worker_index in folo_clone.numa_node_workers(node) {
+                node_threads.insert(
+                    spawn_on_worker(*worker_index, || async { thread::current().id() }).await,
+                );
+            }
+
+            for _ in 0..folo_clone.async_worker_count() {
+                let thread_id = spawn_on_numa_node(node, || async { thread::current().id() }).await;
+                assert!(node_threads.contains(&thread_id));
+            }
+        }
+
+        // A node without workers falls back to any worker.
+        spawn_on_numa_node(NumaNodeId::new(u16::MAX), || async {}).await;
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn runtime_restricted_to_numa_node() {
+    let first_node = {
+        let folo = RuntimeBuilder::new().build().unwrap();
+        let first_node = folo.numa_nodes()[0];
+        folo.stop();
+        folo.wait();
+        first_node
+    };
+
+    let folo = RuntimeBuilder::new().numa_node(first_node).build().unwrap();
+    assert_eq!(folo.numa_nodes(), [first_node]);
+    folo.stop();
+    folo.wait();
+
+    let error = RuntimeBuilder::new()
+        .numa_node(NumaNodeId::new(u16::MAX))
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, folo::io::Error::InvalidOptions(_)));
+}
+
+#[test]
+fn current_runtime_handle_spawns_back_from_another_worker() {
+    assert!(current().is_none());
+
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_worker(0, || async move {
+        let handle = current().expect("async workers are owned by the runtime");
+        let last_worker = handle.async_worker_count() - 1;
+        let origin = thread::current().id();
+
+        let spawned_back_on = spawn_on_worker(last_worker, move || async move {
+            handle
+                .spawn_on_worker(0, || async { thread::current().id() })
+                .await
+        })
+        .await;
+
+        assert_eq!(spawned_back_on, origin);
+
+        folo_clone.stop();
+    });
+
+    folo.wait();
+}
+
+#[test]
+fn high_priority_tasks_are_polled_first() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+    let folo_clone = folo.clone();
+
+    folo.spawn_on_any(|| async move {
+        let polled = Rc::new(RefCell::new(Vec::new()));
+
+        // Both tasks become ready at the same time, so the spawn order does not matter.
+        let normal = spawn({
+            let polled = Rc::clone(&polled);
+            async move { polled.borrow_mut().push("normal") }
+        });
+        let high = spawn_with_options(
+            SpawnOptions::new()
+                .priority(TaskPriority::High)
+                .name("high"),
+            {
+                let polled = Rc::clone(&polled);
+                async move { polled.borrow_mut().push("high") }
+            },
+        );
+
+        normal.await;
+        high.await;
+
+        assert_eq!(*polled.borrow(), vec!["high", "normal"]);
+
         folo_clone.stop();
     });
 
     folo.wait();
 }
 
+#[test]
+fn server_keeps_running_after_handle_drop_until_runtime_stops() {
+    let folo = RuntimeBuilder::new().build().unwrap();
+
+    let (port_tx, port_rx) = mpsc::channel();
+
+    folo.spawn_on_any(|| async move {
+        let server = TcpServerBuilder::new()
+            .ephemeral_port()
+            .on_accept(|mut connection: TcpConnection| async move {
+                connection.send_large(b"hello").await?;
+                connection.shutdown().await
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let port = server.local_port();
+        drop(server);
+
+        port_tx.send(port).unwrap();
+    });
+
+    // The handle is gone by the time we get the port but the server still accepts connections.
+    let port = port_rx.recv().unwrap();
+
+    let mut received = Vec::new();
+    TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .unwrap()
+        .read_to_end(&mut received)
+        .unwrap();
+    assert_eq!(received, b"hello");
+
+    // Stopping the runtime also stops the server, as nobody else can.
+    folo.stop();
+    folo.wait();
+
+    assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err());
+}
+
 async fn thread_safe_logic() -> Option<()> {
     yield_now().await;
     Some(())
@@ -0,0 +1,2248 @@
+use folo::{
+    io::{self, OperationResultExt, PinnedBuffer, ReadBuffer},
+    net::{
+        testing::{connect_loopback, connect_to, echo, echo_server},
+        Backpressure, Codec, LengthDelimitedCodec, LinesCodec, MessageServerBuilder, PrefixRoute,
+        ServerEvent, ServerState, TcpConnection, TcpServerBuilder, TcpServerHandle, TcpState,
+        TransferMode, MAX_DSCP,
+    },
+    rt::{
+        current, metrics, servers, spawn_on_worker, spawn_sync, spawn_sync_with_timeout, yield_now,
+        SynchronousTaskType,
+    },
+    time::{Clock, Delay},
+};
+use folo_testing::init_test_worker;
+use futures::{SinkExt, StreamExt};
+use std::{
+    cell::Cell,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    num::NonZeroUsize,
+    pin::pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use windows::{
+    core::PSTR,
+    Win32::Networking::WinSock::{
+        closesocket, getsockopt, recv, send, setsockopt, socket, WSAIoctl, AF_INET, IPPROTO_TCP,
+        RSS_SCALABILITY_INFO, SEND_RECV_FLAGS, SIO_QUERY_RSS_SCALABILITY_INFO, SOCK_STREAM,
+        SOL_SOCKET, SO_KEEPALIVE, WSAENOPROTOOPT,
+    },
+};
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn echo_round_trip() {
+    let mut server = echo_server().await.unwrap();
+    assert_echoes(server.local_port()).await;
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn buffer_is_reused_across_receives() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut buffer = PinnedBuffer::from_pool();
+    let storage = buffer.as_slice().as_ptr();
+
+    for message in [b"hello", b"world"] {
+        let mut send_buffer = PinnedBuffer::from_pool();
+        send_buffer
+            .as_mut_slice_with_len(message.len())
+            .copy_from_slice(message);
+        connection.send(send_buffer).await.into_inner().unwrap();
+
+        buffer = connection.receive(buffer).await.into_inner().unwrap();
+        assert_eq!(buffer.as_slice(), message);
+
+        buffer.reset();
+        assert_eq!(buffer.len(), buffer.capacity());
+        assert_eq!(buffer.as_slice().as_ptr(), storage);
+    }
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn receive_into_caller_owned_static_buffer() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let storage: &'static mut [u8] = Box::leak(vec![0; 64].into_boxed_slice());
+    let storage_ptr = storage.as_ptr();
+
+    connection.send_large(b"hello").await.unwrap();
+
+    // The data is received directly into the caller's storage, which is returned unchanged.
+    let received = connection
+        .receive(PinnedBuffer::from_static(storage))
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+
+    let storage = received.into_inner_static_slice();
+    assert_eq!(storage.as_ptr(), storage_ptr);
+    assert_eq!(storage.len(), 64);
+    assert_eq!(&storage[..5], b"hello");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn peek_does_not_consume() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    connection.send_large(b"hello").await.unwrap();
+
+    let peeked = connection
+        .peek(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(peeked.as_slice(), b"hello");
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn receive_up_to_leaves_rest_for_next_receive() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    connection.send_large(b"helloworld").await.unwrap();
+
+    // The echo may arrive in parts but never more than we asked for.
+    let mut header = Vec::new();
+
+    while header.len() < 5 {
+        let received = connection
+            .receive_up_to(PinnedBuffer::from_pool(), 5 - header.len())
+            .await
+            .into_inner()
+            .unwrap();
+        assert!(!received.is_empty());
+
+        header.extend_from_slice(received.as_slice());
+    }
+
+    assert_eq!(header, b"hello");
+
+    let mut rest = Vec::new();
+
+    while rest.len() < 5 {
+        let received = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+        assert!(!received.is_empty());
+
+        rest.extend_from_slice(received.as_slice());
+    }
+
+    assert_eq!(rest, b"world");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn receive_up_to_zero_bytes_is_rejected() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let error = connection
+        .receive_up_to(PinnedBuffer::from_pool(), 0)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.inner, io::Error::LogicError(_)));
+
+    // The rejected receive did not claim the connection, so it can still be used.
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn listener_handoff() {
+    let mut old_server = echo_server().await.unwrap();
+    let port = old_server.local_port();
+
+    let listener = old_server.stop_and_release_listener().await.unwrap();
+
+    let mut new_server = TcpServerBuilder::new()
+        .from_listener(listener)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(new_server.local_port(), port);
+
+    assert_echoes(port).await;
+    new_server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn lame_duck_closes_listener_but_keeps_serving_connections() {
+    let mut server = echo_server().await.unwrap();
+    let port = server.local_port();
+
+    let mut connection = connect_loopback(port).await.unwrap();
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    server.lame_duck();
+
+    // The listen socket is closed by the dispatcher shortly after it receives the signal.
+    assert!(wait_until_refused(port).await);
+
+    // The connection accepted before entering lame-duck mode is still being served.
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"again").await,
+        b"again"
+    );
+
+    connection.shutdown().await.unwrap();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn stats_count_connections_and_bytes() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    // The server tallies its side of the transfer as its own operations complete, which may be
+    // slightly after the client has seen the echo.
+    assert!(wait_until(|| server.stats().bytes_sent == 5).await);
+
+    let stats = server.stats();
+    assert_eq!(stats.connections_accepted, 1);
+    assert_eq!(stats.connections_active, 1);
+    assert_eq!(stats.bytes_received, 5);
+
+    connection.shutdown().await.unwrap();
+
+    assert!(wait_until(|| server.stats().connections_active == 0).await);
+    assert_eq!(server.stats().connections_failed, 0);
+
+    server.stop();
+}
+
+/// Polls the condition until it holds or a few seconds have passed, returning whether it held. For
+/// checking state that the server updates in the background, such as its counters.
+async fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let clock = Clock::new();
+
+    for _ in 0..500 {
+        if condition() {
+            return true;
+        }
+
+        Delay::with_clock(&clock, Duration::from_millis(10)).await;
+    }
+
+    false
+}
+
+/// Attempts to connect to the given port until a connection is refused or a few seconds have
+/// passed, returning whether it was refused. For checking that a server has closed its listener.
+async fn wait_until_refused(port: u16) -> bool {
+    let clock = Clock::new();
+
+    for _ in 0..500 {
+        if connect_loopback(port).await.is_err() {
+            return true;
+        }
+
+        Delay::with_clock(&clock, Duration::from_millis(10)).await;
+    }
+
+    false
+}
+
+/// Connects to the echo server on the given port, checks that it echoes back a message and
+/// disconnects.
+async fn assert_echoes(port: u16) {
+    let mut connection = connect_loopback(port).await.unwrap();
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+    connection.shutdown().await.unwrap();
+}
+
+async fn echo_round_trip_on(connection: &mut TcpConnection, data: &[u8]) -> Vec<u8> {
+    connection.send_large(data).await.unwrap();
+
+    let mut received = ReadBuffer::new();
+    while received.len() < data.len() {
+        assert_ne!(received.receive_into(connection).await.unwrap(), 0);
+    }
+
+    received.filled().to_vec()
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn extensions_are_keyed_by_type() {
+    #[derive(Debug, PartialEq)]
+    struct UserName(&'static str);
+
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    assert_eq!(connection.extension::<UserName>(), None);
+
+    assert_eq!(connection.set_extension(UserName("alice")), None);
+    assert_eq!(
+        connection.set_extension(UserName("bob")),
+        Some(UserName("alice"))
+    );
+
+    // Extensions do not need to be thread-safe.
+    let request_count = Rc::new(Cell::new(0));
+    connection.set_extension(Rc::clone(&request_count));
+    connection.extension::<Rc<Cell<u32>>>().unwrap().set(5);
+    assert_eq!(request_count.get(), 5);
+
+    connection.extension_mut::<UserName>().unwrap().0 = "carol";
+    assert_eq!(connection.extension(), Some(&UserName("carol")));
+
+    assert_eq!(connection.remove_extension(), Some(UserName("carol")));
+    assert_eq!(connection.extension::<UserName>(), None);
+    assert!(connection.extension::<Rc<Cell<u32>>>().is_some());
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn tcp_info_reports_established_connection() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    let info = connection.tcp_info().await.unwrap();
+    assert_eq!(info.state, TcpState::Established);
+    assert!(info.mss > 0);
+    assert!(info.bytes_sent >= 5);
+    assert!(info.bytes_received >= 5);
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn discard_input_skips_buffered_data() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Nothing has been received yet, so there is nothing to discard.
+    assert_eq!(connection.discard_input(100).await.unwrap(), 0);
+
+    connection.send_large(b"0123456789").await.unwrap();
+
+    // The echo may arrive in pieces, so we keep discarding until we have skipped enough.
+    let mut discarded = 0;
+
+    while discarded < 4 {
+        discarded += connection.discard_input(4 - discarded).await.unwrap();
+        Delay::with_clock(&Clock::new(), Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(discarded, 4);
+
+    // The rest of the data remains available to be received.
+    let mut rest = Vec::new();
+
+    while rest.len() < 6 {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+        rest.extend_from_slice(buffer.as_slice());
+    }
+
+    assert_eq!(rest, b"456789");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn concurrent_receive_is_rejected() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Nothing has been sent yet, so the first receive remains in progress.
+    let mut first = pin!(connection.receive(PinnedBuffer::from_pool()));
+    assert!(futures::poll!(first.as_mut()).is_pending());
+
+    let error = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .unwrap_err();
+    assert!(matches!(error.inner, io::Error::LogicError(_)));
+
+    let error = connection
+        .peek(PinnedBuffer::from_pool())
+        .await
+        .unwrap_err();
+    assert!(matches!(error.inner, io::Error::LogicError(_)));
+
+    connection.send_large(b"hello").await.unwrap();
+
+    let received = first.await.into_inner().unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+
+    // Once the first receive has completed, the next one is allowed.
+    connection.send_large(b"world").await.unwrap();
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"world");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn concurrent_send_is_rejected() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|mut connection: TcpConnection| async move {
+            // Discards everything until the client closes the connection.
+            while !connection
+                .receive(PinnedBuffer::from_pool())
+                .await
+                .into_inner()?
+                .is_empty()
+            {}
+
+            Ok(())
+        })
+        .build()
+        .await
+        .unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Far more than the socket buffers can hold, so the first send remains in progress.
+    let mut first = pin!(connection.send(PinnedBuffer::from_boxed_slice(
+        vec![0; 64 * 1024 * 1024].into_boxed_slice()
+    )));
+    assert!(futures::poll!(first.as_mut()).is_pending());
+
+    let error = connection
+        .send(PinnedBuffer::from_pool())
+        .await
+        .unwrap_err();
+    assert!(matches!(error.inner, io::Error::LogicError(_)));
+
+    first.await.into_inner().unwrap();
+
+    // Once the first send has completed, the next one is allowed.
+    connection.send_large(b"hello").await.unwrap();
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn buffer_length_reflects_bytes_transferred() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut buffer = PinnedBuffer::from_pool();
+    buffer.set_len(5);
+    buffer.as_mut_slice().copy_from_slice(b"hello");
+
+    let sent = connection.send(buffer).await.into_inner().unwrap();
+    assert_eq!(sent.len(), 5);
+
+    // The whole buffer is offered to the operating system but only the received bytes are valid.
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.len(), 5);
+    assert!(received.capacity() > received.len());
+    assert_eq!(received.as_slice(), b"hello");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn buffered_writes_are_coalesced() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut writer = connection.buffered_writer();
+    writer.write(b"hel").await.unwrap();
+    writer.write(b"lo").await.unwrap();
+    assert_eq!(writer.buffered_len(), 5);
+
+    writer.flush().await.unwrap();
+    assert_eq!(writer.buffered_len(), 0);
+    drop(writer);
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn shutdown_reports_unflushed_buffered_writes() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut writer = connection.buffered_writer();
+    writer.write(b"lost").await.unwrap();
+    drop(writer);
+
+    let error = connection.shutdown().await.unwrap_err();
+    assert!(matches!(error, io::Error::LogicError(_)));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connections_with_reused_sockets() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .reuse_accept_sockets(true)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    // Sequential connections give the server a chance to recycle the sockets in between.
+    for _ in 0..3 {
+        assert_echoes(server.local_port()).await;
+    }
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn send_and_disconnect_returns_socket_to_pool() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .reuse_accept_sockets(true)
+        .on_accept(|connection: TcpConnection| async move {
+            let mut buffer = PinnedBuffer::from_pool();
+            buffer.as_mut_slice_with_len(5).copy_from_slice(b"hello");
+            connection.send_and_disconnect(buffer).await
+        })
+        .build()
+        .await
+        .unwrap();
+
+    // The accepts in flight when the first connections arrive were started with new sockets, so it
+    // takes a few connections before an accept finds a disconnected socket in the pool.
+    for _ in 0..20 {
+        assert_eq!(request_reply(server.local_port(), b"").await, b"hello");
+
+        if server.stats().accept_sockets_reused > 0 {
+            break;
+        }
+    }
+
+    assert!(server.stats().accept_sockets_reused > 0);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn multiplexed_connections_are_served_concurrently() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .multiplex_connections(true)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    // The multiplexers were spawned when the server started, so serving connections must not
+    // spawn a task per connection.
+    let spawned_before = metrics().spawned_tasks;
+
+    // All connections stay open at the same time, so their handlers are driven side by side.
+    let mut connections = Vec::new();
+
+    for _ in 0..8 {
+        connections.push(connect_loopback(server.local_port()).await.unwrap());
+    }
+
+    for connection in &mut connections {
+        assert_eq!(echo_round_trip_on(connection, b"hello").await, b"hello");
+    }
+
+    assert!(metrics().spawned_tasks - spawned_before < connections.len() as u64);
+
+    for mut connection in connections {
+        connection.shutdown().await.unwrap();
+    }
+
+    server.stop();
+}
+
+async fn reply_and_close(mut connection: TcpConnection, reply: &'static [u8]) -> io::Result<()> {
+    connection.send_large(reply).await?;
+    connection.shutdown().await
+}
+
+async fn request_reply(port: u16, request: &[u8]) -> Vec<u8> {
+    let mut connection = connect_loopback(port).await.unwrap();
+    connection.send_large(request).await.unwrap();
+
+    let mut received = Vec::new();
+
+    loop {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        received.extend_from_slice(buffer.as_slice());
+    }
+
+    received
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connections_are_routed_by_prefix() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .route_by_prefix(vec![
+            PrefixRoute::new(*b"PING", |connection| reply_and_close(connection, b"pong")),
+            PrefixRoute::new(*b"GET ", |connection| reply_and_close(connection, b"http")),
+        ])
+        .prefix_sniff_timeout(Duration::from_millis(200))
+        .on_accept(|connection| reply_and_close(connection, b"default"))
+        .build()
+        .await
+        .unwrap();
+    let port = server.local_port();
+
+    assert_eq!(request_reply(port, b"PING 1").await, b"pong");
+    assert_eq!(request_reply(port, b"GET / HTTP/1.1").await, b"http");
+    assert_eq!(request_reply(port, b"\x16\x03\x01").await, b"default");
+
+    // A partial prefix that is never completed falls back to the default once the sniff times out.
+    assert_eq!(request_reply(port, b"PI").await, b"default");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn empty_route_prefix_is_rejected() {
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .route_by_prefix(vec![PrefixRoute::new(Vec::new(), echo)])
+        .on_accept(echo)
+        .build()
+        .await;
+
+    let Err(io::Error::InvalidOptions(message)) = result else {
+        panic!("expected invalid options error");
+    };
+
+    assert!(message.contains("route prefixes"));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connections_over_limit_go_to_overload_handler() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .max_connections(NonZeroUsize::new(1).unwrap())
+        .on_accept(echo)
+        .on_overload(|connection| reply_and_close(connection, b"busy"))
+        .build()
+        .await
+        .unwrap();
+    let port = server.local_port();
+
+    // The first connection stays open, taking up the only slot.
+    let mut connection = connect_loopback(port).await.unwrap();
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    assert_eq!(request_reply(port, b"hello").await, b"busy");
+
+    // The connection holding the slot is not affected by the rejection.
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"again").await,
+        b"again"
+    );
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn backpressure_is_engaged_and_relieved() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let reports_clone = Arc::clone(&reports);
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_backpressure(NonZeroUsize::new(2).unwrap(), 0, move |report| {
+            reports_clone.lock().unwrap().push(report);
+        })
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    let port = server.local_port();
+
+    // Both connections stay open until we close them, reaching the high watermark.
+    let mut first = connect_loopback(port).await.unwrap();
+    let mut second = connect_loopback(port).await.unwrap();
+    assert_eq!(echo_round_trip_on(&mut first, b"hello").await, b"hello");
+    assert_eq!(echo_round_trip_on(&mut second, b"hello").await, b"hello");
+
+    assert_eq!(
+        *reports.lock().unwrap(),
+        vec![Backpressure::Engaged {
+            active_connections: 2
+        }]
+    );
+
+    first.shutdown().await.unwrap();
+    second.shutdown().await.unwrap();
+
+    // The handlers finish in the background once they see the connections closed.
+    assert!(wait_until(|| reports.lock().unwrap().len() == 2).await);
+    assert_eq!(
+        reports.lock().unwrap()[1],
+        Backpressure::Relieved {
+            active_connections: 0
+        }
+    );
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn build_reports_all_problems() {
+    let result = TcpServerBuilder::new()
+        .on_accept(echo)
+        .on_overload(echo)
+        .build()
+        .await;
+
+    let Err(io::Error::InvalidOptions(message)) = result else {
+        panic!("expected invalid options error");
+    };
+
+    assert!(message.contains("port must be set"));
+    assert!(message.contains("on_overload requires max_connections"));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn nonexistent_interface_is_rejected() {
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .bind_interface("folo-test-no-such-interface")
+        .on_accept(echo)
+        .build()
+        .await;
+
+    let Err(io::Error::InvalidOptions(message)) = result else {
+        panic!("expected invalid options error");
+    };
+
+    assert!(message.contains("folo-test-no-such-interface"));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn bind_to_specific_address() {
+    let mut server = TcpServerBuilder::new()
+        .bind_addresses(vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 0))])
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes(server.local_port()).await;
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn bind_to_several_addresses() {
+    // The entire 127.0.0.0/8 range is loopback, so every machine has both of these addresses.
+    let first = Ipv4Addr::LOCALHOST;
+    let second = Ipv4Addr::new(127, 0, 0, 2);
+
+    let mut blocker = TcpServerBuilder::new()
+        .bind_addresses(vec![SocketAddr::from((second, 0))])
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    let port = blocker.local_port();
+
+    // The second address is taken, so the server fails to start.
+    let result = TcpServerBuilder::new()
+        .bind_addresses(vec![
+            SocketAddr::from((first, port)),
+            SocketAddr::from((second, port)),
+        ])
+        .on_accept(echo)
+        .build()
+        .await;
+    assert!(result.is_err());
+
+    // The socket already opened on the first address was closed again.
+    assert!(connect_to(SocketAddrV4::new(first, port)).await.is_err());
+
+    drop(blocker.stop_and_release_listener().await.unwrap());
+
+    let mut server = TcpServerBuilder::new()
+        .bind_addresses(vec![
+            SocketAddr::from((first, port)),
+            SocketAddr::from((second, port)),
+        ])
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    for address in [first, second] {
+        let mut connection = connect_to(SocketAddrV4::new(address, port)).await.unwrap();
+        assert_eq!(
+            echo_round_trip_on(&mut connection, b"hello").await,
+            b"hello"
+        );
+        connection.shutdown().await.unwrap();
+    }
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn declined_connections_are_closed_without_failing() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|connection: TcpConnection| async move {
+            // Stands in for async admission control (e.g. asking a rate limiter).
+            yield_now().await;
+            connection.decline()
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // The server closes the connection gracefully, so we see the end of the stream.
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert!(received.is_empty());
+
+    let stats = server.stats();
+    assert_eq!(stats.connections_accepted, 1);
+    assert_eq!(stats.connections_declined, 1);
+    assert_eq!(stats.connections_failed, 0);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn adaptive_accepts_start_at_minimum() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .adaptive_accepts(
+            NonZeroUsize::new(4).unwrap(),
+            NonZeroUsize::new(64).unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    assert_eq!(
+        echo_round_trip_on(&mut connection, b"hello").await,
+        b"hello"
+    );
+
+    // A single connection is neither saturation nor idleness, so the minimum is kept.
+    assert_eq!(server.accept_concurrency(), 4);
+    assert!(server.pending_accepts() <= 4);
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .adaptive_accepts(NonZeroUsize::new(8).unwrap(), NonZeroUsize::new(4).unwrap())
+        .build()
+        .await;
+    assert!(matches!(result, Err(io::Error::InvalidOptions(_))));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn adaptive_accepts_grow_under_burst_of_connections() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .adaptive_accepts(
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(64).unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(server.accept_concurrency(), 2);
+
+    // Connecting back to back uses up both accept operations many times over within a tuning
+    // window, which is saturation. The tuner only acts when a connection arrives, so we keep
+    // connecting until it has.
+    let mut connections = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    while server.accept_concurrency() == 2 && Instant::now() < deadline {
+        connections.push(connect_loopback(server.local_port()).await.unwrap());
+    }
+
+    assert!(server.accept_concurrency() > 2);
+
+    for mut connection in connections {
+        connection.shutdown().await.unwrap();
+    }
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn affinity_by_numa_node_falls_back_without_rss() {
+    // Loopback connections have no RSS information, so they are dispatched as usual.
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .affinity_by_numa_node()
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes(server.local_port()).await;
+    server.stop();
+
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .affinity_by_numa_node()
+        .affinity_by_peer()
+        .build()
+        .await;
+    assert!(matches!(result, Err(io::Error::InvalidOptions(_))));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn accept_filter_rejects_and_accepts_by_peer() {
+    let mut rejecting_server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .accept_filter(|_| false)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(connect_loopback(rejecting_server.local_port())
+        .await
+        .is_err());
+
+    // The rejection is counted by the dispatcher, which may not have gotten to it yet.
+    assert!(wait_until(|| rejecting_server.stats().connections_rejected >= 1).await);
+    assert_eq!(rejecting_server.stats().connections_accepted, 0);
+    rejecting_server.stop();
+
+    let mut accepting_server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .accept_filter(|peer| peer.ip().is_loopback())
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes(accepting_server.local_port()).await;
+    accepting_server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn large_send_arrives_in_full() {
+    const SIZE: usize = 1024 * 1024;
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|mut connection: TcpConnection| async move {
+            // Receive everything, then report a checksum so the client can verify the content.
+            let mut received = ReadBuffer::new();
+
+            while received.len() < SIZE {
+                if received.receive_into(&mut connection).await? == 0 {
+                    break;
+                }
+            }
+
+            let checksum = received
+                .filled()
+                .iter()
+                .fold(0u64, |sum, byte| sum.wrapping_add(*byte as u64));
+
+            connection.send_large(&checksum.to_le_bytes()).await
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let expected_checksum = data
+        .iter()
+        .fold(0u64, |sum, byte| sum.wrapping_add(*byte as u64));
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    connection.send_large(&data).await.unwrap();
+
+    let mut response = ReadBuffer::new();
+    while response.len() < 8 {
+        assert_ne!(response.receive_into(&mut connection).await.unwrap(), 0);
+    }
+
+    let checksum = u64::from_le_bytes(response.filled()[..8].try_into().unwrap());
+    assert_eq!(checksum, expected_checksum);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn bulk_transfer_mode_sends_with_caller_sized_buffers() {
+    const SIZE: usize = 4 * 1024 * 1024;
+    const BULK_BUFFER_SIZE: usize = 1024 * 1024;
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|mut connection: TcpConnection| async move {
+            connection.set_transfer_mode(TransferMode::Bulk {
+                buffer_size: NonZeroUsize::new(BULK_BUFFER_SIZE).unwrap(),
+            });
+
+            let mut total = 0;
+
+            while total < SIZE {
+                let buffer = connection.new_buffer();
+                assert_eq!(buffer.capacity(), BULK_BUFFER_SIZE);
+
+                let received = connection.receive(buffer).await.into_inner()?;
+                if received.is_empty() {
+                    break;
+                }
+
+                total += received.len();
+            }
+
+            connection.set_transfer_mode(TransferMode::Pooled);
+            connection.send_large(&(total as u64).to_le_bytes()).await
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    assert_eq!(connection.transfer_mode(), TransferMode::Pooled);
+
+    connection.set_transfer_mode(TransferMode::Bulk {
+        buffer_size: NonZeroUsize::new(BULK_BUFFER_SIZE).unwrap(),
+    });
+    connection.send_large(&vec![7; SIZE]).await.unwrap();
+
+    let mut response = ReadBuffer::new();
+    while response.len() < 8 {
+        assert_ne!(response.receive_into(&mut connection).await.unwrap(), 0);
+    }
+
+    let total = u64::from_le_bytes(response.filled()[..8].try_into().unwrap());
+    assert_eq!(total, SIZE as u64);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn first_connection_is_signaled() {
+    let mut server = echo_server().await.unwrap();
+    let first_connection = server.wait_for_first_connection();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    assert!(first_connection.await);
+
+    // Once signaled, the signal remains available to later callers.
+    assert!(server.wait_for_first_connection().await);
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn promptly_accepted_connection_is_not_backlog_pressure() {
+    let reports = Arc::new(AtomicUsize::new(0));
+    let reports_clone = Arc::clone(&reports);
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_backlog_pressure(Duration::from_secs(1), move |_| {
+            reports_clone.fetch_add(1, Ordering::Relaxed);
+        })
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes(server.local_port()).await;
+
+    assert_eq!(server.stats().connections_accepted, 1);
+    assert_eq!(server.stats().backlog_pressure, 0);
+    assert_eq!(reports.load(Ordering::Relaxed), 0);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connection_left_in_backlog_is_backlog_pressure() {
+    let reports = Arc::new(AtomicUsize::new(0));
+    let reports_clone = Arc::clone(&reports);
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_backlog_pressure(Duration::from_secs(1), move |waited| {
+            assert!(waited >= Duration::from_secs(1));
+            reports_clone.fetch_add(1, Ordering::Relaxed);
+        })
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes_after_backlog_wait(&server).await;
+
+    assert_eq!(server.stats().backlog_pressure, 1);
+    assert_eq!(reports.load(Ordering::Relaxed), 1);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn panicking_backlog_pressure_callback_does_not_stop_accepting() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_backlog_pressure(Duration::from_secs(1), |_| panic!("backlog pressure"))
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    assert_echoes_after_backlog_wait(&server).await;
+    assert_echoes(server.local_port()).await;
+
+    assert_eq!(server.stats().backlog_pressure, 1);
+
+    server.stop();
+}
+
+/// Makes a connection wait in the listen backlog for longer than a second before it is accepted,
+/// then checks that it is served normally.
+async fn assert_echoes_after_backlog_wait(server: &TcpServerHandle) {
+    // Pausing cancels the accept operations in flight, so wait for them to be in flight first.
+    assert!(wait_until(|| server.pending_accepts() > 0).await);
+    server.pause();
+    assert!(wait_until(|| server.pending_accepts() == 0).await);
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    connection.send_large(b"hello").await.unwrap();
+
+    // The connect time has a resolution of whole seconds, so leave some margin.
+    Delay::with_clock(&Clock::new(), Duration::from_millis(2500)).await;
+
+    server.resume();
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+
+    connection.shutdown().await.unwrap();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn sub_second_backlog_pressure_threshold_is_rejected() {
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_backlog_pressure(Duration::from_millis(500), |_| {})
+        .on_accept(echo)
+        .build()
+        .await;
+
+    let Err(io::Error::InvalidOptions(message)) = result else {
+        panic!("expected invalid options error");
+    };
+
+    assert!(message.contains("backlog pressure threshold"));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn slow_handlers_are_counted() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .slow_handler_threshold(Duration::from_millis(100))
+        .on_accept(|mut connection: TcpConnection| async move {
+            let buffer = connection
+                .receive(PinnedBuffer::from_pool())
+                .await
+                .into_inner()?;
+
+            if buffer.as_slice() == b"slow" {
+                Delay::with_clock(&Clock::new(), Duration::from_millis(300)).await;
+            }
+
+            Ok(())
+        })
+        .build()
+        .await
+        .unwrap();
+    let mut events = server.events();
+
+    for payload in [b"fast", b"slow"] {
+        let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+        connection.send_large(payload).await.unwrap();
+
+        // The handler is measured before the connection is reported as closed.
+        loop {
+            if let Some(ServerEvent::Closed { .. }) = events.next().await {
+                break;
+            }
+        }
+    }
+
+    assert_eq!(server.stats().slow_handlers, 1);
+
+    server.stop();
+
+    let result = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(echo)
+        .slow_handler_threshold(Duration::ZERO)
+        .build()
+        .await;
+    assert!(matches!(result, Err(io::Error::InvalidOptions(_))));
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn events_report_connection_lifecycle() {
+    let mut server = echo_server().await.unwrap();
+    let mut events = server.events();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    connection.shutdown().await.unwrap();
+
+    let Some(ServerEvent::Accepted {
+        id: accepted_id, ..
+    }) = events.next().await
+    else {
+        panic!("expected an accepted event first");
+    };
+    let Some(ServerEvent::Closed { id: closed_id }) = events.next().await else {
+        panic!("expected a closed event after the handler completed");
+    };
+    assert_eq!(accepted_id, closed_id);
+    assert_eq!(events.missed(), 0);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connection_can_be_closed_via_server_handle() {
+    let mut server = echo_server().await.unwrap();
+    let mut events = server.events();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let Some(ServerEvent::Accepted { id, .. }) = events.next().await else {
+        panic!("expected an accepted event first");
+    };
+
+    assert!(server.close_connection(id));
+
+    // The echo handler was waiting for data, so it is abandoned and the connection is closed.
+    let Some(ServerEvent::Closed { id: closed_id }) = events.next().await else {
+        panic!("expected a closed event after the handler was abandoned");
+    };
+    assert_eq!(closed_id, id);
+
+    // Depending on timing, the close is seen as either a graceful end of stream or a reset.
+    if let Ok(received) = connection.receive(PinnedBuffer::from_pool()).await {
+        assert!(received.is_empty());
+    }
+
+    // The connection is gone, so there is nothing to close any more.
+    assert!(!server.close_connection(id));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn aborted_connection_is_reset() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|connection: TcpConnection| async move { connection.abort() })
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // A graceful close would be seen as an end of stream - a reset is seen as an error.
+    let result = connection.receive(PinnedBuffer::from_pool()).await;
+    assert!(result.is_err());
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn stalled_handshake_is_reset_after_initial_read_deadline() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .initial_read_deadline(Duration::from_millis(100))
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+
+    // We never send anything, so the handshake deadline passes while the server is receiving.
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let result = connection.receive(PinnedBuffer::from_pool()).await;
+    assert!(result.is_err());
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn established_connection_outlives_initial_read_deadline() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .initial_read_deadline(Duration::from_millis(100))
+        .on_accept(|mut connection: TcpConnection| async move {
+            let buffer = connection
+                .receive(PinnedBuffer::from_pool())
+                .await
+                .into_inner()?;
+            connection.mark_established();
+
+            Delay::with_clock(&Clock::new(), Duration::from_millis(300)).await;
+
+            connection.send(buffer).await.into_inner()?;
+            connection.shutdown().await
+        })
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(request_reply(server.local_port(), b"hello").await, b"hello");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn send_and_disconnect_delivers_data_before_end_of_stream() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|connection: TcpConnection| async move {
+            let mut buffer = PinnedBuffer::from_pool();
+            buffer.as_mut_slice_with_len(5).copy_from_slice(b"hello");
+            connection.send_and_disconnect(buffer).await
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut received = Vec::new();
+
+    loop {
+        let buffer = connection
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+
+        // The end of the stream arrives only after all the data.
+        if buffer.is_empty() {
+            break;
+        }
+
+        received.extend_from_slice(buffer.as_slice());
+    }
+
+    assert_eq!(received, b"hello");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn panicking_handler_does_not_affect_other_connections() {
+    // All loopback connections share a peer address, so they are all handled on the same worker.
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .affinity_by_peer()
+        .on_accept(|mut connection: TcpConnection| async move {
+            let buffer = connection
+                .peek(PinnedBuffer::from_pool())
+                .await
+                .into_inner()?;
+
+            if buffer.as_slice() == b"panic" {
+                panic!("handler asked to panic");
+            }
+
+            echo(connection).await
+        })
+        .build()
+        .await
+        .unwrap();
+    let mut events = server.events();
+
+    let mut doomed = connect_loopback(server.local_port()).await.unwrap();
+    doomed.send_large(b"panic").await.unwrap();
+
+    loop {
+        match events.next().await {
+            Some(ServerEvent::HandlerPanicked { message, .. }) => {
+                assert_eq!(message, "handler asked to panic");
+                break;
+            }
+            Some(_) => continue,
+            None => panic!("server stopped before the handler panicked"),
+        }
+    }
+
+    // The panicking handler's connection was closed, either gracefully or with a reset.
+    if let Ok(received) = doomed.receive(PinnedBuffer::from_pool()).await {
+        assert!(received.is_empty());
+    }
+
+    assert_echoes(server.local_port()).await;
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn first_connection_wait_ends_when_server_stops() {
+    let mut server = echo_server().await.unwrap();
+    server.stop();
+
+    assert!(!server.wait_for_first_connection().await);
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn pending_accepts_follow_the_accept_loop() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Once a connection has been dispatched, the accept loop is up and running.
+    assert!(server.wait_for_first_connection().await);
+    connection.shutdown().await.unwrap();
+
+    // With no more connections arriving, the server tops up its accept operations and they all
+    // remain waiting.
+    assert!(server.accept_concurrency() > 0);
+    assert!(wait_until(|| server.pending_accepts() == server.accept_concurrency()).await);
+
+    // Releasing the listener waits for all accept operations to complete.
+    let listener = server.stop_and_release_listener().await.unwrap();
+    assert_eq!(server.pending_accepts(), 0);
+
+    drop(listener);
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn split_halves_send_and_receive_concurrently() {
+    const SIZE: usize = 1024 * 1024;
+
+    let mut server = echo_server().await.unwrap();
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+    let (mut reader, mut writer) = connection.split();
+    assert_eq!(reader.id(), writer.id());
+
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+
+    // The echo server sends the data back while we are still sending, so we need to receive
+    // concurrently with sending to avoid both sides waiting for the other to drain its buffers.
+    let send = async {
+        writer.send_large(&data).await.unwrap();
+    };
+
+    let receive = async {
+        let mut received = Vec::with_capacity(SIZE);
+
+        while received.len() < SIZE {
+            let buffer = reader
+                .receive(PinnedBuffer::from_pool())
+                .await
+                .into_inner()
+                .unwrap();
+            assert!(!buffer.is_empty());
+            received.extend_from_slice(buffer.as_slice());
+        }
+
+        received
+    };
+
+    let ((), received) = futures::join!(send, receive);
+    assert!(received == data);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn write_half_shutdown_ends_the_stream_for_the_peer() {
+    let mut server = echo_server().await.unwrap();
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+    let (mut reader, mut writer) = connection.split();
+
+    writer.send_large(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    // The echo server sees the end of the stream after the data, echoes the data and closes its
+    // end of the connection, which the read half sees as the end of the stream.
+    let mut received = Vec::new();
+
+    loop {
+        let buffer = reader
+            .receive(PinnedBuffer::from_pool())
+            .await
+            .into_inner()
+            .unwrap();
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        received.extend_from_slice(buffer.as_slice());
+    }
+
+    assert_eq!(received, b"hello");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn split_halves_can_be_reunited() {
+    let mut server = echo_server().await.unwrap();
+
+    let (first_reader, first_writer) = connect_loopback(server.local_port()).await.unwrap().split();
+    let (second_reader, second_writer) =
+        connect_loopback(server.local_port()).await.unwrap().split();
+
+    // Halves of different connections do not fit together.
+    let (first_reader, second_writer) = first_reader.reunite(second_writer).unwrap_err();
+
+    let mut first = first_reader.reunite(first_writer).unwrap();
+    let mut second = second_reader.reunite(second_writer).unwrap();
+    assert_ne!(first.id(), second.id());
+
+    for connection in [&mut first, &mut second] {
+        assert_eq!(echo_round_trip_on(connection, b"hello").await, b"hello");
+        connection.shutdown().await.unwrap();
+    }
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn stalled_receive_times_out_and_returns_buffer() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let buffer = PinnedBuffer::from_pool();
+    let capacity = buffer.capacity();
+
+    // We never send anything, so the echo server never responds and the receive stalls.
+    let (error, buffer) = connection
+        .receive_with_timeout(buffer, Duration::from_millis(50))
+        .await
+        .unwrap_err()
+        .into_inner_and_buffer();
+    assert!(error.is_timed_out());
+    assert_eq!(buffer.capacity(), capacity);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn receive_with_deadline_reports_timeout_without_error() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Nothing was sent, so nothing arrives before the deadline.
+    let received = connection
+        .receive_with_deadline(
+            PinnedBuffer::from_pool(),
+            Instant::now() + Duration::from_millis(50),
+        )
+        .await
+        .unwrap();
+    assert!(received.timed_out);
+    assert_eq!(received.buffer.len(), 0);
+
+    let mut buffer = received.buffer;
+    buffer.as_mut_slice_with_len(2).copy_from_slice(b"hi");
+    connection.send(buffer).await.into_inner().unwrap();
+
+    let received = connection
+        .receive_with_deadline(
+            PinnedBuffer::from_pool(),
+            Instant::now() + Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+    assert!(!received.timed_out);
+    assert_eq!(received.buffer.as_slice(), b"hi");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn vectored_receive_fills_buffers_in_order() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    connection.send_large(b"0123456789").await.unwrap();
+
+    let mut received = Vec::new();
+
+    while received.len() < 10 {
+        let buffers = (0..3)
+            .map(|_| {
+                let mut buffer = PinnedBuffer::from_pool();
+                buffer.set_len(4);
+                buffer
+            })
+            .collect();
+
+        let result = connection.receive_vectored(buffers).await.unwrap();
+        assert_eq!(result.buffers.len(), 3);
+        assert_ne!(result.bytes_transferred, 0);
+
+        let filled = result.filled();
+
+        // All but the last buffer holding data are full.
+        for buffer in &filled[..filled.len() - 1] {
+            assert_eq!(buffer.len(), 4);
+        }
+
+        for buffer in filled {
+            received.extend_from_slice(buffer.as_slice());
+        }
+    }
+
+    assert_eq!(received, b"0123456789");
+
+    let error = connection
+        .receive_vectored(Vec::new())
+        .await
+        .unwrap_err()
+        .into_inner();
+    assert!(matches!(error, io::Error::InvalidOptions(_)));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connection_migrates_to_worker() {
+    let mut server = echo_server().await.unwrap();
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+    let id = connection.id();
+
+    // Migrating to the current worker would prove nothing, so we pick any other one.
+    let current_thread = std::thread::current().id();
+    let mut target = None;
+
+    for worker_index in 0..current().unwrap().async_worker_count() {
+        let worker_thread =
+            spawn_on_worker(worker_index, || async { std::thread::current().id() }).await;
+
+        if worker_thread != current_thread {
+            target = Some((worker_index, worker_thread));
+            break;
+        }
+    }
+
+    let (target_index, target_thread) = target.expect("test requires at least two async workers");
+
+    let (migrated_id, migrated_thread) = connection
+        .migrate_to_worker(target_index, |mut connection| async move {
+            assert_eq!(
+                echo_round_trip_on(&mut connection, b"hello").await,
+                b"hello"
+            );
+
+            connection.shutdown().await.unwrap();
+
+            (connection.id(), std::thread::current().id())
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(migrated_id, id);
+    assert_eq!(migrated_thread, target_thread);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn operations_fail_after_deadline() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    connection
+        .set_deadline(Some(Instant::now() + Duration::from_millis(50)))
+        .unwrap();
+
+    // We never send anything, so the echo server never responds and the receive times out.
+    let error = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap_err();
+    assert!(error.is_timed_out());
+
+    // Operations started after the deadline fail immediately.
+    let error = connection
+        .send(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap_err();
+    assert!(error.is_timed_out());
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn raw_socket_can_be_used_for_blocking_io() {
+    let mut server = echo_server().await.unwrap();
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let socket = connection.into_raw_socket().unwrap();
+
+    // Blocking I/O must not happen on an async worker, which may also be running the echo server.
+    let received = spawn_sync(SynchronousTaskType::Syscall, move || {
+        // SAFETY: The socket is valid and the buffers are valid for the duration of the calls.
+        unsafe {
+            assert_eq!(send(*socket, b"hello", SEND_RECV_FLAGS(0)), 5);
+
+            let mut buffer = [0u8; 16];
+            let received = recv(*socket, &mut buffer, SEND_RECV_FLAGS(0));
+            buffer[..received as usize].to_vec()
+        }
+    })
+    .await;
+
+    assert_eq!(received, b"hello");
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn keepalive_is_enabled_on_accepted_connections() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .enable_keepalive(true)
+        .on_accept(report_keepalive)
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), [1]);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn rss_enabled_matches_system_configuration() {
+    let mut server = echo_server().await.unwrap();
+
+    // SAFETY: Nothing unsafe here, just FFI calls. The socket is closed before we return and the
+    // output pointer and size describe a valid value of the expected type.
+    let expected = unsafe {
+        let probe = socket(AF_INET.0 as i32, SOCK_STREAM, IPPROTO_TCP.0).unwrap();
+
+        let mut info = RSS_SCALABILITY_INFO::default();
+        let mut bytes_returned = 0;
+        let result = WSAIoctl(
+            probe,
+            SIO_QUERY_RSS_SCALABILITY_INFO,
+            None,
+            0,
+            Some(&mut info as *mut _ as *mut _),
+            size_of::<RSS_SCALABILITY_INFO>() as u32,
+            &mut bytes_returned,
+            None,
+            None,
+        );
+        closesocket(probe);
+
+        // If the system cannot tell, the server assumes there is no RSS.
+        result == 0 && info.RssEnabled.as_bool()
+    };
+
+    assert_eq!(server.rss_enabled(), expected);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn rss_affinity_is_only_queried_when_enabled() {
+    let mut server = echo_server().await.unwrap();
+    assert_echoes(server.local_port()).await;
+
+    // The query is opt-in, so the accept path must not spend a syscall on it by default.
+    assert_eq!(server.stats().rss_queries, 0);
+    server.stop();
+
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .query_rss_affinity(true)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    assert_echoes(server.local_port()).await;
+
+    // Without RSS on the system, there is nothing to query even if enabled.
+    assert_eq!(server.stats().rss_queries, u64::from(server.rss_enabled()));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn configure_socket_applies_to_accepted_connections() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .configure_socket(|socket| {
+            let enabled = 1u32;
+
+            // SAFETY: The socket is valid and the buffer is valid for the duration of the call.
+            let result = unsafe {
+                setsockopt(
+                    socket,
+                    SOL_SOCKET,
+                    SO_KEEPALIVE,
+                    Some(&enabled.to_ne_bytes()),
+                )
+            };
+            assert_eq!(result, 0);
+
+            Ok(())
+        })
+        .on_accept(report_keepalive)
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), [1]);
+
+    server.stop();
+}
+
+/// Reports to the client whether keepalive is enabled on the server end of the connection, as a
+/// single byte.
+async fn report_keepalive(connection: TcpConnection) -> io::Result<()> {
+    let socket = connection.into_raw_socket()?;
+
+    spawn_sync(SynchronousTaskType::Syscall, move || {
+        let mut enabled = 0u32;
+        let mut enabled_len = size_of::<u32>() as i32;
+
+        // SAFETY: The socket is valid and the buffers are valid for the duration of the calls.
+        unsafe {
+            assert_eq!(
+                getsockopt(
+                    *socket,
+                    SOL_SOCKET,
+                    SO_KEEPALIVE,
+                    PSTR::from_raw(&mut enabled as *mut u32 as *mut u8),
+                    &mut enabled_len,
+                ),
+                0
+            );
+            assert_eq!(send(*socket, &[enabled as u8], SEND_RECV_FLAGS(0)), 1);
+        }
+    })
+    .await;
+
+    Ok(())
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn message_server_replies_to_each_message() {
+    let mut server = MessageServerBuilder::new()
+        .ephemeral_port()
+        .on_message(|mut request: Vec<u8>| async move {
+            request.reverse();
+            request
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    let mut codec = LengthDelimitedCodec::new();
+
+    // Both requests are sent at once, so the server receives them in the same buffer.
+    let mut requests = Vec::new();
+    codec.encode(b"hello".to_vec(), &mut requests).unwrap();
+    codec.encode(b"world".to_vec(), &mut requests).unwrap();
+    connection.send_large(&requests).await.unwrap();
+
+    let mut received = ReadBuffer::new();
+    let mut responses = Vec::new();
+
+    while responses.len() < 2 {
+        match codec.decode(&mut received).unwrap() {
+            Some(response) => responses.push(response),
+            None => assert_ne!(received.receive_into(&mut connection).await.unwrap(), 0),
+        }
+    }
+
+    assert_eq!(responses, [b"olleh".to_vec(), b"dlrow".to_vec()]);
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn message_server_reports_truncated_message() {
+    let mut server = MessageServerBuilder::new()
+        .ephemeral_port()
+        .on_message(|request: Vec<u8>| async move { request })
+        .build()
+        .await
+        .unwrap();
+    let mut events = server.events();
+
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut request = Vec::new();
+    LengthDelimitedCodec::new()
+        .encode(b"hello".to_vec(), &mut request)
+        .unwrap();
+    request.truncate(request.len() - 2);
+    connection.send_large(&request).await.unwrap();
+
+    // The server gives up on the connection, so whether our shutdown is graceful does not matter.
+    _ = connection.shutdown().await;
+
+    let Some(ServerEvent::Accepted { .. }) = events.next().await else {
+        panic!("expected an accepted event first");
+    };
+    let Some(ServerEvent::HandlerError { error, .. }) = events.next().await else {
+        panic!("expected the handler to fail on the truncated message");
+    };
+    assert!(
+        matches!(&*error, io::Error::StdIo(e) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+    );
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn framed_lines_round_trip() {
+    let mut server = echo_server().await.unwrap();
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+
+    let mut framed = connection.framed(LinesCodec::new());
+
+    framed.feed("hello".to_string()).await.unwrap();
+    framed.feed("world".to_string()).await.unwrap();
+    framed.flush().await.unwrap();
+
+    assert_eq!(framed.next().await.unwrap().unwrap(), "hello");
+    assert_eq!(framed.next().await.unwrap().unwrap(), "world");
+
+    framed.into_inner().shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn framed_reports_truncated_frame() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .on_accept(|connection| reply_and_close(connection, b"no newline"))
+        .build()
+        .await
+        .unwrap();
+
+    let connection = connect_loopback(server.local_port()).await.unwrap();
+    let mut framed = connection.framed(LinesCodec::new());
+
+    let error = framed.next().await.unwrap().unwrap_err();
+    assert!(matches!(error, io::Error::StdIo(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn dscp_out_of_range_is_rejected() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    assert!(connection.set_dscp(MAX_DSCP + 1).is_err());
+
+    // Whether a valid value takes effect depends on privileges and policy, but it never fails
+    // just because the process is not permitted to set it.
+    connection.set_dscp(46).unwrap();
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn registered_server_is_listed_while_running() {
+    let mut server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .register_with_runtime(true)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    let port = server.local_port();
+
+    let listed = |state| {
+        servers()
+            .iter()
+            .any(|info| info.local_port == port && info.state == state)
+    };
+
+    assert!(listed(ServerState::Accepting));
+
+    // Pausing and stopping are processed by the dispatcher in the background.
+    server.pause();
+
+    while !listed(ServerState::Paused) {
+        yield_now().await;
+    }
+
+    server.stop();
+
+    while servers().iter().any(|info| info.local_port == port) {
+        yield_now().await;
+    }
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn dispatcher_worker_is_reported() {
+    let mut server = echo_server().await.unwrap();
+
+    assert!(server
+        .dispatcher_worker()
+        .thread_name()
+        .ends_with("-tcp-dispatcher"));
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connections_are_served_after_resume() {
+    let mut server = echo_server().await.unwrap();
+
+    server.pause();
+    server.resume();
+
+    assert_echoes(server.local_port()).await;
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn connection_made_while_paused_is_handled_after_resume() {
+    let mut server = echo_server().await.unwrap();
+
+    // Pausing cancels the accept operations in flight, so wait for them to be in flight first.
+    assert!(wait_until(|| server.pending_accepts() > 0).await);
+    server.pause();
+    assert!(wait_until(|| server.pending_accepts() == 0).await);
+
+    // The connection is established by the operating system and waits in the listen queue.
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+    connection.send_large(b"hello").await.unwrap();
+
+    Delay::with_clock(&Clock::new(), Duration::from_millis(300)).await;
+    assert_eq!(server.stats().connections_accepted, 0);
+
+    server.resume();
+
+    let received = connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+    assert_eq!(received.as_slice(), b"hello");
+    assert_eq!(server.stats().connections_accepted, 1);
+
+    connection.shutdown().await.unwrap();
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn server_stops_on_handle_drop() {
+    let server = TcpServerBuilder::new()
+        .ephemeral_port()
+        .stop_on_handle_drop(true)
+        .on_accept(echo)
+        .build()
+        .await
+        .unwrap();
+    let port = server.local_port();
+
+    assert_echoes(port).await;
+    drop(server);
+
+    // The server stops in the background, after which the port refuses connections.
+    assert!(wait_until_refused(port).await);
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn io_completions_are_counted_in_runtime_metrics() {
+    let before = metrics();
+
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    connection.send_large(b"hello").await.unwrap();
+    connection
+        .receive(PinnedBuffer::from_pool())
+        .await
+        .into_inner()
+        .unwrap();
+
+    let after = metrics();
+
+    // How each operation completes is up to the operating system but every one is counted.
+    assert!(
+        after.immediate_completions + after.deferred_completions
+            >= before.immediate_completions + before.deferred_completions + 2
+    );
+
+    server.stop();
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn hung_sync_task_times_out_for_awaiter() {
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+    let result = spawn_sync_with_timeout(
+        SynchronousTaskType::Syscall,
+        Duration::from_millis(50),
+        move || {
+            // Stands in for a blocking call that hangs until we release it.
+            _ = release_rx.recv();
+        },
+    )
+    .await;
+
+    assert!(result.unwrap_err().is_timed_out());
+
+    // Let the worker thread go, so it does not hold up the shutdown of the runtime.
+    release_tx.send(()).unwrap();
+
+    let result =
+        spawn_sync_with_timeout(SynchronousTaskType::Syscall, Duration::from_secs(10), || 42).await;
+
+    assert_eq!(result.unwrap(), 42);
+}
+
+#[folo::test(worker_init_fn = init_test_worker)]
+async fn low_water_marks_are_set_or_reported_unsupported() {
+    let mut server = echo_server().await.unwrap();
+    let mut connection = connect_loopback(server.local_port()).await.unwrap();
+
+    // Windows may not support these options for TCP, in which case we expect a specific error.
+    for result in [connection.set_recv_lowat(16), connection.set_send_lowat(16)] {
+        match result {
+            Ok(()) => {}
+            Err(io::Error::Winsock { detail, .. }) => assert_eq!(detail, WSAENOPROTOOPT),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    server.stop();
+}
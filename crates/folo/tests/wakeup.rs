@@ -0,0 +1,77 @@
+use folo::io::{post_wakeup, register_wakeup_handler, wait_for_object, wakeup_signal};
+use futures::{channel::mpsc, StreamExt};
+use std::{ffi::c_void, thread};
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::Threading::{CreateEventW, SetEvent},
+};
+
+#[folo::test]
+async fn wakeup_from_foreign_thread() {
+    let (sender, future) = wakeup_signal();
+
+    thread::spawn(move || sender.wake()).join().unwrap();
+
+    future.await.unwrap();
+}
+
+#[folo::test]
+async fn dropped_sender_fails_future() {
+    let (sender, future) = wakeup_signal();
+
+    thread::spawn(move || drop(sender)).join().unwrap();
+
+    assert!(future.await.is_err());
+}
+
+#[folo::test]
+async fn posted_wakeups_reach_registered_handler() {
+    let (data_tx, mut data_rx) = mpsc::unbounded();
+
+    let registration = register_wakeup_handler(0x5eed, move |data| {
+        data_tx.unbounded_send(data).unwrap();
+    })
+    .unwrap();
+    let target = registration.target();
+
+    thread::spawn(move || {
+        post_wakeup(&target, 1).unwrap();
+        post_wakeup(&target, usize::MAX).unwrap();
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(data_rx.next().await, Some(1));
+    assert_eq!(data_rx.next().await, Some(usize::MAX));
+}
+
+#[folo::test]
+async fn conflicting_wakeup_handler_keys_are_rejected() {
+    // Key 0 is used by Folo itself for all of its I/O operations.
+    assert!(register_wakeup_handler(0, |_| {}).is_err());
+
+    let registration = register_wakeup_handler(0xfeed, |_| {}).unwrap();
+    assert!(register_wakeup_handler(0xfeed, |_| {}).is_err());
+
+    // Once the registration is dropped, the key can be used again.
+    drop(registration);
+    register_wakeup_handler(0xfeed, |_| {}).unwrap();
+}
+
+#[folo::test]
+async fn wait_for_event() {
+    // SAFETY: No special requirements for creating an unnamed event.
+    let event = unsafe { CreateEventW(None, true, false, None) }.unwrap();
+    let event_for_thread = event.0 as usize;
+
+    thread::spawn(move || {
+        // SAFETY: The event remains open until the test completes.
+        unsafe { SetEvent(HANDLE(event_for_thread as *mut c_void)) }.unwrap();
+    });
+
+    // SAFETY: The event remains open until the wait completes.
+    unsafe { wait_for_object(event) }.await.unwrap();
+
+    // SAFETY: We own the handle and nothing is using it anymore.
+    unsafe { CloseHandle(event) }.unwrap();
+}